@@ -0,0 +1,154 @@
+use anyhow::{anyhow, Context, Result};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use crate::hashing::PasswordHasher;
+use crate::totp::DEFAULT_TOTP_ISSUER;
+use std::path::PathBuf;
+
+/// Lifetime of issued tokens unless configured otherwise
+pub const DEFAULT_EXPIRATION_HOURS: i64 = 24;
+
+/// Lifetime of refresh tokens unless configured otherwise
+pub const DEFAULT_REFRESH_EXPIRATION_DAYS: i64 = 30;
+
+/// How `AuthService` signs and verifies tokens
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    /// Shared secret for the HMAC algorithms (HS256/HS384/HS512)
+    pub secret: String,
+    pub algorithm: Algorithm,
+    pub expiration_hours: i64,
+    /// Lifetime of refresh tokens; each use rotates the token
+    pub refresh_expiration_days: i64,
+    /// Algorithm for new password hashes; existing hashes verify regardless
+    pub password_hasher: PasswordHasher,
+    /// PEM private key used to sign tokens with RSA or ECDSA algorithms
+    pub private_key_path: Option<PathBuf>,
+    /// PEM public key used to verify tokens with RSA or ECDSA algorithms
+    pub public_key_path: Option<PathBuf>,
+    /// AES-256 key encrypting stored TOTP secrets; two-factor authentication
+    /// can't be enabled without one
+    pub totp_encryption_key: Option<[u8; 32]>,
+    /// Issuer shown in authenticator apps
+    pub totp_issuer: String,
+}
+
+impl AuthConfig {
+    /// HS256 with `secret` and the default token lifetime
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self {
+            secret: secret.into(),
+            algorithm: Algorithm::HS256,
+            expiration_hours: DEFAULT_EXPIRATION_HOURS,
+            refresh_expiration_days: DEFAULT_REFRESH_EXPIRATION_DAYS,
+            password_hasher: PasswordHasher::default(),
+            private_key_path: None,
+            public_key_path: None,
+            totp_encryption_key: None,
+            totp_issuer: DEFAULT_TOTP_ISSUER.to_string(),
+        }
+    }
+
+    /// HS256 with the secret from `JWT_SECRET`, the one the gateway verifies tokens with
+    pub fn from_env() -> Result<Self> {
+        Ok(Self::new(kagi_shared::jwt_secret_from_env()?))
+    }
+
+    /// Sign with an asymmetric `algorithm` (e.g. RS256, ES256) using PEM key files
+    pub fn with_pem_keys(
+        algorithm: Algorithm,
+        private_key_path: impl Into<PathBuf>,
+        public_key_path: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            secret: String::new(),
+            algorithm,
+            expiration_hours: DEFAULT_EXPIRATION_HOURS,
+            refresh_expiration_days: DEFAULT_REFRESH_EXPIRATION_DAYS,
+            password_hasher: PasswordHasher::default(),
+            private_key_path: Some(private_key_path.into()),
+            public_key_path: Some(public_key_path.into()),
+            totp_encryption_key: None,
+            totp_issuer: DEFAULT_TOTP_ISSUER.to_string(),
+        }
+    }
+
+    pub fn with_expiration_hours(mut self, hours: i64) -> Self {
+        self.expiration_hours = hours;
+        self
+    }
+
+    pub fn with_refresh_expiration_days(mut self, days: i64) -> Self {
+        self.refresh_expiration_days = days;
+        self
+    }
+
+    pub fn with_password_hasher(mut self, hasher: PasswordHasher) -> Self {
+        self.password_hasher = hasher;
+        self
+    }
+
+    /// Allow two-factor authentication, encrypting TOTP secrets with `key`
+    pub fn with_totp_encryption_key(mut self, key: [u8; 32]) -> Self {
+        self.totp_encryption_key = Some(key);
+        self
+    }
+
+    pub fn with_totp_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.totp_issuer = issuer.into();
+        self
+    }
+
+    /// Load the signing and verification keys for the configured algorithm
+    pub fn keys(&self) -> Result<(EncodingKey, DecodingKey)> {
+        if self.expiration_hours <= 0 {
+            return Err(anyhow!("Token expiration must be positive, got {} hours", self.expiration_hours));
+        }
+        if self.refresh_expiration_days <= 0 {
+            return Err(anyhow!("Refresh token expiration must be positive, got {} days", self.refresh_expiration_days));
+        }
+
+        match self.algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                if self.secret.is_empty() {
+                    return Err(anyhow!("{:?} requires a non-empty secret", self.algorithm));
+                }
+                Ok((
+                    EncodingKey::from_secret(self.secret.as_bytes()),
+                    DecodingKey::from_secret(self.secret.as_bytes()),
+                ))
+            },
+            Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512
+            | Algorithm::PS256 | Algorithm::PS384 | Algorithm::PS512 => {
+                let (private, public) = self.read_pem_keys()?;
+                Ok((
+                    EncodingKey::from_rsa_pem(&private).context("Invalid RSA private key")?,
+                    DecodingKey::from_rsa_pem(&public).context("Invalid RSA public key")?,
+                ))
+            },
+            Algorithm::ES256 | Algorithm::ES384 => {
+                let (private, public) = self.read_pem_keys()?;
+                Ok((
+                    EncodingKey::from_ec_pem(&private).context("Invalid EC private key")?,
+                    DecodingKey::from_ec_pem(&public).context("Invalid EC public key")?,
+                ))
+            },
+            Algorithm::EdDSA => {
+                let (private, public) = self.read_pem_keys()?;
+                Ok((
+                    EncodingKey::from_ed_pem(&private).context("Invalid Ed25519 private key")?,
+                    DecodingKey::from_ed_pem(&public).context("Invalid Ed25519 public key")?,
+                ))
+            },
+        }
+    }
+
+    fn read_pem_keys(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        let read = |path: &Option<PathBuf>, which: &str| -> Result<Vec<u8>> {
+            let path = path
+                .as_ref()
+                .ok_or_else(|| anyhow!("{:?} requires a {} key path", self.algorithm, which))?;
+            std::fs::read(path).with_context(|| format!("Failed to read {} key {}", which, path.display()))
+        };
+        Ok((read(&self.private_key_path, "private")?, read(&self.public_key_path, "public")?))
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::{anyhow, Result};
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString};
+use argon2::{Argon2, Params};
+
+/// How new passwords are hashed.
+///
+/// Verification looks at the stored hash rather than this setting, so hashes
+/// written by one backend keep verifying after switching to the other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasswordHasher {
+    Bcrypt { cost: u32 },
+    /// Argon2id; memory is in KiB
+    Argon2 { memory_kib: u32, iterations: u32, parallelism: u32 },
+}
+
+impl Default for PasswordHasher {
+    fn default() -> Self {
+        Self::Bcrypt { cost: bcrypt::DEFAULT_COST }
+    }
+}
+
+impl PasswordHasher {
+    /// Argon2id with the crate's recommended parameters
+    pub fn argon2() -> Self {
+        Self::Argon2 {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String> {
+        match self {
+            Self::Bcrypt { cost } => Ok(bcrypt::hash(password.as_bytes(), *cost)?),
+            Self::Argon2 { memory_kib, iterations, parallelism } => {
+                let params = Params::new(*memory_kib, *iterations, *parallelism, None)
+                    .map_err(|e| anyhow!("Invalid Argon2 parameters: {}", e))?;
+                let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+                let salt = SaltString::generate(&mut OsRng);
+                argon2
+                    .hash_password(password.as_bytes(), &salt)
+                    .map(|hash| hash.to_string())
+                    .map_err(|e| anyhow!("Failed to hash password: {}", e))
+            }
+        }
+    }
+
+    /// Check `password` against a bcrypt (`$2a$`/`$2b$`/`$2y$`) or Argon2 (`$argon2…`) hash
+    pub fn verify(password: &str, hash: &str) -> Result<bool> {
+        if hash.starts_with("$argon2") {
+            let parsed = PasswordHash::new(hash).map_err(|e| anyhow!("Malformed Argon2 hash: {}", e))?;
+            // Parameters come from the hash itself, so the default instance verifies any of them
+            Ok(Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+        } else if hash.starts_with("$2") {
+            Ok(bcrypt::verify(password.as_bytes(), hash)?)
+        } else {
+            Err(anyhow!("Unrecognized password hash format"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters keep the tests fast; the format is the same at any cost
+    fn backends() -> [PasswordHasher; 2] {
+        [
+            PasswordHasher::Bcrypt { cost: 4 },
+            PasswordHasher::Argon2 { memory_kib: 1024, iterations: 1, parallelism: 1 },
+        ]
+    }
+
+    #[test]
+    fn each_backend_hashes_and_verifies() {
+        for hasher in backends() {
+            let hash = hasher.hash("correct horse").unwrap();
+            assert!(PasswordHasher::verify("correct horse", &hash).unwrap(), "{:?}", hasher);
+            assert!(!PasswordHasher::verify("wrong horse", &hash).unwrap(), "{:?}", hasher);
+        }
+    }
+
+    #[test]
+    fn hashes_are_recognised_by_format_whatever_the_current_backend() {
+        let [bcrypt, argon2] = backends();
+        let old = bcrypt.hash("correct horse").unwrap();
+        let new = argon2.hash("correct horse").unwrap();
+
+        assert!(old.starts_with("$2"));
+        assert!(new.starts_with("$argon2id$"));
+        assert!(PasswordHasher::verify("correct horse", &old).unwrap());
+        assert!(PasswordHasher::verify("correct horse", &new).unwrap());
+        assert!(PasswordHasher::verify("correct horse", "plaintext").is_err());
+    }
+}
@@ -1,41 +1,115 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use bcrypt::{hash, verify, DEFAULT_COST};
 use chrono::{DateTime, Duration, Utc};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use kagi_macros::{action, init, service};
+use kagi_shared::{to_result, unknown_operation, ActionParams, ActionService, DomainEvent, EventBus, ServiceError};
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+pub mod config;
+pub mod hashing;
+pub mod password;
+pub mod revocation;
+pub mod store;
+pub mod token_cache;
+pub mod totp;
+
+pub use config::AuthConfig;
+pub use hashing::PasswordHasher;
+pub use store::{InMemoryUserStore, UserStore};
+use password::{CompromisedPasswordChecker, PasswordPolicy, PasswordRule, WeakPassword};
+use revocation::{RevocationList, DEFAULT_PRUNE_INTERVAL};
+use token_cache::{TokenCache, DEFAULT_TOKEN_CACHE_TTL};
+use totp::{StoredTotp, TotpCipher, TotpSetup};
+
+/// Tenant used when a deployment is not partitioned
+pub const DEFAULT_TENANT: &str = "default";
+
+fn default_tenant() -> String {
+    DEFAULT_TENANT.to_string()
+}
+
+/// Role every user gets on registration
+pub const DEFAULT_ROLE: &str = "user";
+
+/// Role allowed to manage other users' roles
+pub const ADMIN_ROLE: &str = "admin";
+
+fn default_roles() -> Vec<String> {
+    vec![DEFAULT_ROLE.to_string()]
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: Uuid,
+    pub tenant_id: String,
     pub username: String,
     pub email: String,
     #[serde(skip_serializing)]
     password_hash: String,
+    #[serde(default = "default_roles")]
+    pub roles: Vec<String>,
+    /// Bumped each time all of the user's sessions are revoked; tokens from
+    /// an older version are rejected
+    #[serde(default)]
+    pub session_version: u64,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Largest page `list_users` returns
+pub const MAX_PAGE_LIMIT: usize = 100;
+
+/// One page of users plus the total across all pages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaginatedUsers {
+    pub items: Vec<User>,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: Uuid,
+    pub tenant_id: String,
     pub exp: i64,
     pub iat: i64,
+    /// Unique token id, used to revoke the token before it expires
+    pub jti: Uuid,
+    /// Roles the user held when the token was issued
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// OAuth-style scopes such as `invoices:read`; `invoices:*` grants every invoice scope
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// The user's `session_version` when the token was issued
+    #[serde(default)]
+    pub session_version: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoginRequest {
+    /// Stamped by the gateway from the request host, never taken from the client
+    #[serde(default = "default_tenant")]
+    pub tenant_id: String,
     pub username: String,
     pub password: String,
+    /// Current code from the user's authenticator; required once 2FA is enabled
+    #[serde(default)]
+    pub totp_code: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RegisterRequest {
+    /// Stamped by the gateway from the request host, never taken from the client
+    #[serde(default = "default_tenant")]
+    pub tenant_id: String,
     pub username: String,
     pub email: String,
     pub password: String,
@@ -44,151 +118,1544 @@ pub struct RegisterRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthResponse {
     pub user: User,
+    /// Short-lived access token (JWT)
     pub token: String,
+    /// Opaque single-use token exchanged for a new pair via `refresh`
+    pub refresh_token: String,
+}
+
+/// An issued refresh token; removed from the store as soon as it is used
+#[derive(Debug, Clone)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub tenant_id: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A pending password reset, stored under a hash of the token sent to the user
+#[derive(Debug, Clone)]
+pub struct PasswordReset {
+    pub user_id: Uuid,
+    pub tenant_id: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// How long a password reset token stays usable
+pub const PASSWORD_RESET_TTL_MINUTES: i64 = 30;
+
+/// Users whose emails normalize to the same address
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateCluster {
+    pub normalized_email: String,
+    pub users: Vec<User>,
+}
+
+/// Payload of the `users_merged` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsersMerged {
+    pub tenant_id: String,
+    pub keep_id: Uuid,
+    pub merge_id: Uuid,
+}
+
+impl DomainEvent for UsersMerged {
+    const TOPIC: &'static str = EVENT_TOPIC;
+    const KIND: &'static str = USERS_MERGED_EVENT;
 }
 
-const JWT_SECRET: &[u8] = b"your-secret-key"; // In production, use environment variable
-const TOKEN_EXPIRATION_HOURS: i64 = 24;
+/// Payload of the `user_registered` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserRegistered {
+    pub tenant_id: String,
+    pub user_id: Uuid,
+    pub username: String,
+}
 
-#[service]
-pub struct AuthService {
-    users: Arc<RwLock<HashMap<Uuid, User>>>,
-    username_index: Arc<RwLock<HashMap<String, Uuid>>>,
-    email_index: Arc<RwLock<HashMap<String, Uuid>>>,
+impl DomainEvent for UserRegistered {
+    const TOPIC: &'static str = EVENT_TOPIC;
+    const KIND: &'static str = USER_REGISTERED_EVENT;
+}
+
+/// Payload of the `user_deleted` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserDeleted {
+    pub tenant_id: String,
+    pub user_id: Uuid,
+}
+
+impl DomainEvent for UserDeleted {
+    const TOPIC: &'static str = EVENT_TOPIC;
+    const KIND: &'static str = USER_DELETED_EVENT;
+}
+
+/// Topic auth events are published on
+pub const EVENT_TOPIC: &str = "auth";
+
+/// Event published once a user has registered
+pub const USER_REGISTERED_EVENT: &str = "user_registered";
+
+/// Event published once a duplicate user has been merged into another
+pub const USERS_MERGED_EVENT: &str = "users_merged";
+
+/// Event published once a user has deleted their account
+pub const USER_DELETED_EVENT: &str = "user_deleted";
+
+/// Canonical form of an email address used to detect duplicates
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Hex SHA-256 of a password reset token, the form it is stored in
+fn reset_token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+static USERNAME_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z0-9_]{3,32}$").unwrap());
+
+// Deliberately loose: one `@`, no whitespace, and a dot in the domain
+static EMAIL_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^[^\s@]+@[^\s@]+\.[^\s@]+$").unwrap()
+});
+
+/// Reject empty usernames and anything outside `[a-zA-Z0-9_]{3,32}`
+pub fn validate_username(username: &str) -> Result<(), ServiceError> {
+    if username.is_empty() {
+        return Err(ServiceError::validation("Username is required"));
+    }
+    if !USERNAME_PATTERN.is_match(username) {
+        return Err(ServiceError::validation(
+            "Username must be 3-32 characters of letters, digits or underscores",
+        ));
+    }
+    Ok(())
+}
+
+pub fn validate_email(email: &str) -> Result<(), ServiceError> {
+    if email.is_empty() {
+        return Err(ServiceError::validation("Email is required"));
+    }
+    if !EMAIL_PATTERN.is_match(email) {
+        return Err(ServiceError::validation("Email address is not valid"));
+    }
+    Ok(())
+}
+
+/// Checks that a token carries a role, so other services can guard admin-only
+/// actions without depending on which `UserStore` the auth service runs on
+#[async_trait]
+pub trait RoleChecker: Send + Sync {
+    async fn require_role(&self, token: &str, role: &str) -> Result<Claims>;
+}
+
+#[async_trait]
+impl<S: UserStore> RoleChecker for AuthService<S> {
+    async fn require_role(&self, token: &str, role: &str) -> Result<Claims> {
+        AuthService::require_role(self, token, role).await
+    }
+}
+
+#[service(name = "auth", description = "User accounts, sessions and tokens")]
+pub struct AuthService<S: UserStore = InMemoryUserStore> {
+    store: Arc<S>,
+    events: EventBus,
+    algorithm: jsonwebtoken::Algorithm,
+    expiration_hours: i64,
+    refresh_expiration_days: i64,
+    refresh_tokens: RwLock<HashMap<Uuid, RefreshToken>>,
+    // Keyed by `reset_token_hash` so a leaked map doesn't leak usable tokens
+    password_resets: RwLock<HashMap<String, PasswordReset>>,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    password_hasher: PasswordHasher,
+    password_policy: PasswordPolicy,
+    password_checker: Option<Arc<dyn CompromisedPasswordChecker>>,
+    token_cache: Arc<TokenCache>,
+    revoked: Arc<RevocationList>,
+    default_scopes: Vec<String>,
+    totp_cipher: Option<TotpCipher>,
+    totp_issuer: String,
 }
 
 #[init]
 impl AuthService {
-    pub async fn new() -> Result<Self> {
+    pub async fn new(config: AuthConfig) -> Result<Self> {
+        Self::with_store(config, InMemoryUserStore::new()).await
+    }
+}
+
+impl<S: UserStore> AuthService<S> {
+    /// Keep users in `store` instead of process memory
+    pub async fn with_store(config: AuthConfig, store: S) -> Result<Self> {
+        let (encoding_key, decoding_key) = config.keys()?;
+        let revoked = Arc::new(RevocationList::new());
+        RevocationList::spawn_pruner(&revoked, DEFAULT_PRUNE_INTERVAL);
         Ok(Self {
-            users: Arc::new(RwLock::new(HashMap::new())),
-            username_index: Arc::new(RwLock::new(HashMap::new())),
-            email_index: Arc::new(RwLock::new(HashMap::new())),
+            store: Arc::new(store),
+            events: EventBus::new(),
+            algorithm: config.algorithm,
+            expiration_hours: config.expiration_hours,
+            refresh_expiration_days: config.refresh_expiration_days,
+            refresh_tokens: RwLock::new(HashMap::new()),
+            password_resets: RwLock::new(HashMap::new()),
+            encoding_key,
+            decoding_key,
+            password_hasher: config.password_hasher,
+            password_policy: PasswordPolicy::default(),
+            password_checker: None,
+            token_cache: Arc::new(TokenCache::new(DEFAULT_TOKEN_CACHE_TTL)),
+            revoked,
+            default_scopes: Vec::new(),
+            totp_cipher: config.totp_encryption_key.as_ref().map(TotpCipher::new),
+            totp_issuer: config.totp_issuer,
         })
     }
-}
 
-impl AuthService {
-    async fn create_token(&self, user_id: Uuid) -> Result<String> {
+    /// HS256 with a fixed, well-known secret; only for tests
+    #[cfg(test)]
+    pub async fn new_with_default_secret() -> Result<Self> {
+        Self::new(AuthConfig::new("test-secret-key")).await
+    }
+
+    /// Publish events onto a bus shared with other services
+    pub fn with_event_bus(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Require new passwords to satisfy `policy`
+    pub fn with_password_policy(mut self, policy: PasswordPolicy) -> Self {
+        self.password_policy = policy;
+        self
+    }
+
+    /// Reject new passwords that `checker` reports as common or breached
+    pub fn with_password_checker(mut self, checker: Arc<dyn CompromisedPasswordChecker>) -> Self {
+        self.password_checker = Some(checker);
+        self
+    }
+
+    /// Reuse decoded claims for `ttl`; a zero TTL disables the cache
+    pub fn with_token_cache_ttl(mut self, ttl: std::time::Duration) -> Self {
+        self.token_cache = Arc::new(TokenCache::new(ttl));
+        self
+    }
+
+    /// Scopes granted to tokens issued on login and registration
+    pub fn with_default_scopes(mut self, scopes: Vec<String>) -> Self {
+        self.default_scopes = scopes;
+        self
+    }
+
+    /// Check a new password against the policy and the compromised-password checker.
+    ///
+    /// Every password set through this service goes through here so the rules
+    /// can't drift between registration and later password changes.
+    async fn check_new_password(&self, password: &str) -> Result<()> {
+        let mut unmet = self.password_policy.unmet(password);
+        if let Some(checker) = &self.password_checker {
+            if checker.is_compromised(password).await? {
+                unmet.push(PasswordRule::NotCompromised);
+            }
+        }
+
+        if unmet.is_empty() {
+            Ok(())
+        } else {
+            Err(ServiceError::validation(WeakPassword { unmet }.to_string()).into())
+        }
+    }
+
+    fn totp_cipher(&self) -> Result<&TotpCipher> {
+        self.totp_cipher
+            .as_ref()
+            .ok_or_else(|| ServiceError::validation("Two-factor authentication is not available").into())
+    }
+
+    /// Check a TOTP code against the user's stored secret
+    fn verify_totp(&self, user: &User, stored: &StoredTotp, code: &str) -> Result<bool> {
+        let secret = self.totp_cipher()?.decrypt(&stored.encrypted_secret)?;
+        let totp = totp::totp(secret, &self.totp_issuer, &user.username)?;
+        totp::verify_code(&totp, code)
+    }
+
+    async fn create_token(&self, user: &User, scopes: &[String]) -> Result<String> {
         let now = Utc::now();
-        let exp = now + Duration::hours(TOKEN_EXPIRATION_HOURS);
+        let exp = now + Duration::hours(self.expiration_hours);
         
         let claims = Claims {
-            sub: user_id,
+            sub: user.id,
+            tenant_id: user.tenant_id.clone(),
             exp: exp.timestamp(),
             iat: now.timestamp(),
+            jti: Uuid::new_v4(),
+            roles: user.roles.clone(),
+            scopes: scopes.to_vec(),
+            session_version: user.session_version,
         };
 
         encode(
-            &Header::default(),
+            &Header::new(self.algorithm),
             &claims,
-            &EncodingKey::from_secret(JWT_SECRET),
+            &self.encoding_key,
         )
         .map_err(|e| anyhow!("Failed to create token: {}", e))
     }
 
+    /// Issue an access token and a fresh refresh token for `user`
+    async fn issue_tokens(&self, user: User) -> Result<AuthResponse> {
+        let token = self.create_token(&user, &self.default_scopes).await?;
+
+        let now = Utc::now();
+        let refresh = RefreshToken {
+            id: Uuid::new_v4(),
+            user_id: user.id,
+            tenant_id: user.tenant_id.clone(),
+            expires_at: now + Duration::days(self.refresh_expiration_days),
+        };
+        let refresh_token = refresh.id.to_string();
+
+        let mut refresh_tokens = self.refresh_tokens.write().await;
+        // Drop expired tokens as we go so the map doesn't grow unbounded
+        refresh_tokens.retain(|_, t| t.expires_at > now);
+        refresh_tokens.insert(refresh.id, refresh);
+
+        Ok(AuthResponse { user, token, refresh_token })
+    }
+
+    /// Reject every refresh token issued to `user_id`, and every access token
+    /// from a session version older than `current_version`
+    async fn revoke_sessions(&self, user_id: Uuid, current_version: u64) {
+        let expires_at = Utc::now() + Duration::hours(self.expiration_hours);
+        self.revoked.revoke_user(user_id, current_version, expires_at.timestamp()).await;
+        self.token_cache.invalidate_user(user_id).await;
+        self.refresh_tokens.write().await.retain(|_, t| t.user_id != user_id);
+    }
+
     async fn verify_token(&self, token: &str) -> Result<Claims> {
-        let token_data = decode::<Claims>(
-            token,
-            &DecodingKey::from_secret(JWT_SECRET),
-            &Validation::default(),
-        )
-        .map_err(|e| anyhow!("Invalid token: {}", e))?;
+        let claims = match self.token_cache.get(token).await {
+            Some(claims) => claims,
+            None => {
+                let token_data = decode::<Claims>(
+                    token,
+                    &self.decoding_key,
+                    &Validation::new(self.algorithm),
+                )
+                .map_err(|e| ServiceError::unauthorized(format!("Invalid token: {}", e)))?;
 
-        Ok(token_data.claims)
+                self.token_cache.insert(token, token_data.claims.clone()).await;
+                token_data.claims
+            }
+        };
+
+        if self.revoked.is_revoked(&claims).await {
+            return Err(ServiceError::unauthorized("Token has been revoked").into());
+        }
+        Ok(claims)
+    }
+
+    /// Verify `token` and require that it carries `role`
+    pub async fn require_role(&self, token: &str, role: &str) -> Result<Claims> {
+        let claims = self.verify_token(token).await?;
+        if !claims.roles.iter().any(|r| r == role) {
+            return Err(ServiceError::forbidden(format!("Requires the '{}' role", role)).into());
+        }
+        Ok(claims)
+    }
+
+    /// Verify `token` and require that it was issued to `user_id` in `tenant_id`
+    pub async fn require_owner(&self, token: &str, tenant_id: &str, user_id: Uuid) -> Result<Claims> {
+        let claims = self.verify_token(token).await?;
+        if claims.sub != user_id || claims.tenant_id != tenant_id {
+            return Err(ServiceError::forbidden("Token was issued to another user").into());
+        }
+        Ok(claims)
     }
 }
 
 #[async_trait]
-impl AuthService {
+impl<S: UserStore> AuthService<S> {
     #[action]
     pub async fn register(&self, req: RegisterRequest) -> Result<AuthResponse> {
         // Validate input
-        if req.password.len() < 8 {
-            return Err(anyhow!("Password must be at least 8 characters long"));
-        }
+        validate_username(&req.username)?;
+        validate_email(&req.email)?;
+        self.check_new_password(&req.password).await?;
 
-        // Check if username or email already exists
-        {
-            let username_index = self.username_index.read().await;
-            let email_index = self.email_index.read().await;
-            
-            if username_index.contains_key(&req.username) {
-                return Err(anyhow!("Username already exists"));
-            }
-            if email_index.contains_key(&req.email) {
-                return Err(anyhow!("Email already exists"));
-            }
+        // Check if username or email already exists before paying for the hash;
+        // the store enforces uniqueness again on insert
+        if self.store.find_by_username(&req.tenant_id, &req.username).await?.is_some() {
+            return Err(ServiceError::conflict("Username already exists").into());
+        }
+        if self.store.find_by_email(&req.tenant_id, &req.email).await?.is_some() {
+            return Err(ServiceError::conflict("Email already exists").into());
         }
 
         let now = Utc::now();
         let user = User {
             id: Uuid::new_v4(),
+            tenant_id: req.tenant_id.clone(),
             username: req.username.clone(),
             email: req.email.clone(),
-            password_hash: hash(req.password.as_bytes(), DEFAULT_COST)?,
+            password_hash: self.password_hasher.hash(&req.password)?,
+            roles: default_roles(),
+            session_version: 0,
             created_at: now,
             updated_at: now,
         };
 
-        // Update indexes and store user
-        {
-            let mut users = self.users.write().await;
-            let mut username_index = self.username_index.write().await;
-            let mut email_index = self.email_index.write().await;
+        self.store.insert_user(user.clone()).await?;
 
-            username_index.insert(user.username.clone(), user.id);
-            email_index.insert(user.email.clone(), user.id);
-            users.insert(user.id, user.clone());
-        }
+        self.events.emit(&UserRegistered {
+            tenant_id: user.tenant_id.clone(),
+            user_id: user.id,
+            username: user.username.clone(),
+        })?;
 
-        let token = self.create_token(user.id).await?;
-        Ok(AuthResponse { user, token })
+        self.issue_tokens(user).await
     }
 
     #[action]
     pub async fn login(&self, req: LoginRequest) -> Result<AuthResponse> {
-        let user_id = {
-            let username_index = self.username_index.read().await;
-            username_index
-                .get(&req.username)
-                .ok_or_else(|| anyhow!("Invalid username or password"))?
-                .clone()
-        };
+        let user = self.store
+            .find_by_username(&req.tenant_id, &req.username)
+            .await?
+            .ok_or_else(|| ServiceError::unauthorized("Invalid username or password"))?;
 
-        let user = {
-            let users = self.users.read().await;
-            users
-                .get(&user_id)
-                .ok_or_else(|| anyhow!("User not found"))?
-                .clone()
-        };
+        if !PasswordHasher::verify(&req.password, &user.password_hash)? {
+            return Err(ServiceError::unauthorized("Invalid username or password").into());
+        }
+
+        if let Some(stored) = self.store.find_totp(user.id).await?.filter(|t| t.enabled) {
+            let code = req.totp_code
+                .as_deref()
+                .ok_or_else(|| ServiceError::unauthorized("Two-factor code required"))?;
+            if !self.verify_totp(&user, &stored, code)? {
+                return Err(ServiceError::unauthorized("Invalid two-factor code").into());
+            }
+        }
+
+        self.issue_tokens(user).await
+    }
 
-        if !verify(req.password.as_bytes(), &user.password_hash)? {
-            return Err(anyhow!("Invalid username or password"));
+    /// Exchange a refresh token for a new access token and refresh token.
+    ///
+    /// Refresh tokens are single-use: the presented token is invalidated whether
+    /// or not the exchange succeeds, so a replayed token is always rejected.
+    #[action]
+    pub async fn refresh(&self, tenant_id: String, refresh_token: String) -> Result<AuthResponse> {
+        let invalid = || ServiceError::unauthorized("Invalid refresh token");
+
+        let id = Uuid::parse_str(&refresh_token).map_err(|_| invalid())?;
+        let stored = self.refresh_tokens.write().await.remove(&id).ok_or_else(invalid)?;
+        if stored.tenant_id != tenant_id {
+            return Err(invalid().into());
+        }
+        if stored.expires_at <= Utc::now() {
+            return Err(ServiceError::unauthorized("Refresh token has expired").into());
         }
 
-        let token = self.create_token(user.id).await?;
-        Ok(AuthResponse { user, token })
+        let user = self.get_user(tenant_id, stored.user_id).await
+            .map_err(|_| invalid())?;
+        self.issue_tokens(user).await
     }
 
+    /// Validate a token issued for `tenant_id`; tokens from other tenants are rejected
     #[action]
-    pub async fn validate_token(&self, token: String) -> Result<User> {
+    pub async fn validate_token(&self, tenant_id: String, token: String) -> Result<User> {
         let claims = self.verify_token(&token).await?;
+        if claims.tenant_id != tenant_id {
+            return Err(ServiceError::unauthorized("Invalid token: tenant mismatch").into());
+        }
         
-        let users = self.users.read().await;
-        users
-            .get(&claims.sub)
-            .cloned()
-            .ok_or_else(|| anyhow!("User not found"))
+        let user = self.get_user(tenant_id, claims.sub).await?;
+        // The stored version also covers revocations from before a restart
+        if claims.session_version < user.session_version {
+            return Err(ServiceError::unauthorized("Token has been revoked").into());
+        }
+        Ok(user)
     }
 
+    /// Change a user's password after checking the current one.
+    ///
+    /// Unless `revoke_tokens` is `false`, every token issued to the user
+    /// before the change stops working, including ones from the same second.
     #[action]
-    pub async fn get_user(&self, user_id: Uuid) -> Result<User> {
-        let users = self.users.read().await;
-        users
-            .get(&user_id)
-            .cloned()
-            .ok_or_else(|| anyhow!("User not found"))
+    pub async fn change_password(
+        &self,
+        tenant_id: String,
+        user_id: Uuid,
+        old_password: String,
+        new_password: String,
+        revoke_tokens: Option<bool>,
+    ) -> Result<()> {
+        let mut user = self.get_user(tenant_id, user_id).await?;
+        if !PasswordHasher::verify(&old_password, &user.password_hash)? {
+            return Err(ServiceError::unauthorized("Invalid username or password").into());
+        }
+        self.check_new_password(&new_password).await?;
+
+        let revoke_tokens = revoke_tokens.unwrap_or(true);
+        user.password_hash = self.password_hasher.hash(&new_password)?;
+        user.updated_at = Utc::now();
+        if revoke_tokens {
+            user.session_version += 1;
+        }
+        let session_version = user.session_version;
+        self.store.update_user(user).await?;
+
+        if revoke_tokens {
+            self.revoke_sessions(user_id, session_version).await;
+        }
+        Ok(())
+    }
+
+    /// Issue a single-use token for resetting the password of the account
+    /// registered with `email`.
+    ///
+    /// A token comes back whether or not the account exists, so callers can't
+    /// probe for registered emails; one for an unknown email is never accepted.
+    /// A real deployment emails the token instead of returning it.
+    #[action]
+    pub async fn request_password_reset(&self, tenant_id: String, email: String) -> Result<String> {
+        let token = Uuid::new_v4().to_string();
+        let user = match self.store.find_by_email(&tenant_id, &email).await? {
+            Some(user) => user,
+            None => return Ok(token),
+        };
+
+        let now = Utc::now();
+        let mut resets = self.password_resets.write().await;
+        // Only the latest token works, and expired ones are dropped as we go
+        resets.retain(|_, r| r.user_id != user.id && r.expires_at > now);
+        resets.insert(reset_token_hash(&token), PasswordReset {
+            user_id: user.id,
+            tenant_id,
+            expires_at: now + Duration::minutes(PASSWORD_RESET_TTL_MINUTES),
+        });
+        Ok(token)
+    }
+
+    /// Set a new password with a token from `request_password_reset`.
+    ///
+    /// The token is used up once the password is changed, and every session
+    /// the user had is revoked.
+    #[action]
+    pub async fn reset_password(&self, tenant_id: String, token: String, new_password: String) -> Result<()> {
+        let invalid = || ServiceError::unauthorized("Invalid or expired reset token");
+
+        // Check the password before using up the token so a rejected one can be retried
+        self.check_new_password(&new_password).await?;
+
+        let reset = self.password_resets
+            .write()
+            .await
+            .remove(&reset_token_hash(&token))
+            .ok_or_else(invalid)?;
+        if reset.tenant_id != tenant_id || reset.expires_at <= Utc::now() {
+            return Err(invalid().into());
+        }
+
+        let mut user = self.get_user(tenant_id, reset.user_id).await.map_err(|_| invalid())?;
+        user.password_hash = self.password_hasher.hash(&new_password)?;
+        user.updated_at = Utc::now();
+        user.session_version += 1;
+        let session_version = user.session_version;
+        self.store.update_user(user).await?;
+
+        self.revoke_sessions(reset.user_id, session_version).await;
+        Ok(())
+    }
+
+    /// Start enabling two-factor authentication with a fresh TOTP secret.
+    ///
+    /// Only the user themselves can do this, with their own `token`. Login
+    /// doesn't ask for codes until one is confirmed with `confirm_totp`;
+    /// calling this again before then replaces the pending secret.
+    #[action]
+    pub async fn enable_totp(&self, tenant_id: String, token: String, user_id: Uuid) -> Result<TotpSetup> {
+        self.require_owner(&token, &tenant_id, user_id).await?;
+        let user = self.get_user(tenant_id, user_id).await?;
+        if self.store.find_totp(user.id).await?.map_or(false, |t| t.enabled) {
+            return Err(ServiceError::conflict("Two-factor authentication is already enabled").into());
+        }
+
+        let secret = totp::generate_secret()?;
+        let encrypted_secret = self.totp_cipher()?.encrypt(&secret)?;
+        let totp = totp::totp(secret, &self.totp_issuer, &user.username)?;
+        self.store.set_totp(user.id, Some(StoredTotp { encrypted_secret, enabled: false })).await?;
+
+        Ok(TotpSetup {
+            secret: totp.get_secret_base32(),
+            uri: totp.get_url(),
+        })
+    }
+
+    /// Turn on two-factor authentication once the user, signed in with their own
+    /// `token`, proves their authenticator works
+    #[action]
+    pub async fn confirm_totp(&self, tenant_id: String, token: String, user_id: Uuid, code: String) -> Result<()> {
+        self.require_owner(&token, &tenant_id, user_id).await?;
+        let user = self.get_user(tenant_id, user_id).await?;
+        let mut stored = self.store
+            .find_totp(user.id)
+            .await?
+            .ok_or_else(|| ServiceError::validation("Two-factor authentication has not been set up"))?;
+        if stored.enabled {
+            return Err(ServiceError::conflict("Two-factor authentication is already enabled").into());
+        }
+        if !self.verify_totp(&user, &stored, &code)? {
+            return Err(ServiceError::unauthorized("Invalid two-factor code").into());
+        }
+
+        stored.enabled = true;
+        self.store.set_totp(user.id, Some(stored)).await
+    }
+
+    /// Revoke an access token so it is rejected until it would have expired
+    #[action]
+    pub async fn logout(&self, token: String) -> Result<()> {
+        let claims = self.verify_token(&token).await?;
+        self.revoked.revoke(claims.jti, claims.exp).await;
+        self.token_cache.invalidate(&token).await;
+        Ok(())
+    }
+
+    /// Delete a user's account, freeing their username and email.
+    ///
+    /// `token` must belong to the user themselves or to an admin in their tenant.
+    /// Every token issued to the user stops working; services owning per-user
+    /// data remove it on the `user_deleted` event.
+    #[action]
+    pub async fn delete_user(&self, tenant_id: String, token: String, user_id: Uuid) -> Result<()> {
+        let caller = match self.require_owner(&token, &tenant_id, user_id).await {
+            Ok(claims) => claims,
+            Err(_) => self.require_role(&token, ADMIN_ROLE).await?,
+        };
+        if caller.tenant_id != tenant_id {
+            return Err(ServiceError::forbidden("Token was issued in another tenant").into());
+        }
+
+        // Check the tenant first so users in other tenants can't be removed
+        let user = self.get_user(tenant_id, user_id).await?;
+        self.store
+            .remove_user(user.id)
+            .await?
+            .ok_or_else(|| ServiceError::not_found("User not found"))?;
+
+        // No tokens can be issued to a removed user, so every version is stale
+        self.revoke_sessions(user_id, u64::MAX).await;
+        self.password_resets.write().await.retain(|_, r| r.user_id != user_id);
+
+        self.events.emit(&UserDeleted {
+            tenant_id: user.tenant_id,
+            user_id,
+        })?;
+        Ok(())
+    }
+
+    /// Admin: find users in the admin's tenant whose emails collide once normalized.
+    ///
+    /// Useful for accounts created before emails were normalized on registration.
+    #[action]
+    pub async fn find_duplicate_users(&self, token: String) -> Result<Vec<DuplicateCluster>> {
+        let admin = self.require_role(&token, ADMIN_ROLE).await?;
+
+        let mut by_email: HashMap<String, Vec<User>> = HashMap::new();
+        for user in self.store.list_users(&admin.tenant_id).await? {
+            by_email
+                .entry(normalize_email(&user.email))
+                .or_default()
+                .push(user);
+        }
+
+        let mut clusters: Vec<DuplicateCluster> = by_email
+            .into_iter()
+            .filter(|(_, users)| users.len() > 1)
+            .map(|(normalized_email, mut users)| {
+                users.sort_by_key(|u| u.created_at);
+                DuplicateCluster { normalized_email, users }
+            })
+            .collect();
+        clusters.sort_by(|a, b| a.normalized_email.cmp(&b.normalized_email));
+
+        Ok(clusters)
+    }
+
+    /// Admin: merge `merge_id` into `keep_id`, both in the admin's tenant.
+    ///
+    /// The duplicate account is removed here; services owning per-user data
+    /// (profiles, invoices) reassign it on the `users_merged` event.
+    #[action]
+    pub async fn merge_users(&self, token: String, keep_id: Uuid, merge_id: Uuid) -> Result<User> {
+        let tenant_id = self.require_role(&token, ADMIN_ROLE).await?.tenant_id;
+        if keep_id == merge_id {
+            return Err(ServiceError::validation("Cannot merge a user into itself").into());
+        }
+
+        let kept = self.store
+            .find_by_id(keep_id)
+            .await?
+            .filter(|u| u.tenant_id == tenant_id)
+            .ok_or_else(|| ServiceError::not_found("User to keep not found"))?;
+        if !self.store.find_by_id(merge_id).await?.map_or(false, |u| u.tenant_id == tenant_id) {
+            return Err(ServiceError::not_found("User to merge not found").into());
+        }
+        self.store.remove_user(merge_id).await?;
+        // The merged account is gone, so none of its sessions may outlive it
+        self.revoke_sessions(merge_id, u64::MAX).await;
+
+        self.events.emit(&UsersMerged {
+            tenant_id,
+            keep_id,
+            merge_id,
+        })?;
+
+        Ok(kept)
+    }
+
+    /// Admin: grant `role` to a user in the admin's tenant.
+    ///
+    /// Tokens already issued to the user keep their old roles until they expire.
+    #[action]
+    pub async fn assign_role(&self, token: String, user_id: Uuid, role: String) -> Result<User> {
+        let admin = self.require_role(&token, ADMIN_ROLE).await?;
+
+        let role = role.trim().to_string();
+        if role.is_empty() {
+            return Err(ServiceError::validation("Role must not be empty").into());
+        }
+
+        let mut user = self.get_user(admin.tenant_id, user_id).await?;
+        if !user.roles.contains(&role) {
+            user.roles.push(role);
+            user.updated_at = Utc::now();
+            self.store.update_user(user.clone()).await?;
+        }
+        Ok(user)
     }
-} 
\ No newline at end of file
+
+    /// Admin: page through the users of the admin's tenant, oldest first.
+    ///
+    /// `limit` is capped at `MAX_PAGE_LIMIT`; an `offset` past the end yields an empty page.
+    #[action]
+    pub async fn list_users(&self, token: String, offset: usize, limit: usize) -> Result<PaginatedUsers> {
+        let admin = self.require_role(&token, ADMIN_ROLE).await?;
+        let limit = limit.min(MAX_PAGE_LIMIT);
+
+        let mut users = self.store.list_users(&admin.tenant_id).await?;
+        // Tie-break on id so pages stay stable when timestamps collide
+        users.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+
+        let total = users.len();
+        let items = users.into_iter().skip(offset).take(limit).collect();
+
+        Ok(PaginatedUsers { items, total })
+    }
+
+    #[action]
+    pub async fn get_user(&self, tenant_id: String, user_id: Uuid) -> Result<User> {
+        self.store
+            .find_by_id(user_id)
+            .await?
+            .filter(|user| user.tenant_id == tenant_id)
+            .ok_or_else(|| ServiceError::not_found("User not found").into())
+    }
+
+    /// Look a user up by username, matched case-insensitively as on registration
+    #[action]
+    pub async fn get_user_by_username(&self, tenant_id: String, username: String) -> Result<User> {
+        self.store
+            .find_by_username(&tenant_id, &username)
+            .await?
+            .ok_or_else(|| ServiceError::not_found("User not found").into())
+    }
+}
+
+/// Actions by operation name, for registration and gateway dispatch
+#[async_trait]
+impl<S: UserStore> ActionService for AuthService<S> {
+    fn name(&self) -> &str {
+        "auth"
+    }
+
+    fn description(&self) -> &str {
+        "User accounts, sessions and tokens"
+    }
+
+    fn operations(&self) -> Vec<String> {
+        [
+            "register",
+            "login",
+            "refresh",
+            "validate_token",
+            "change_password",
+            "request_password_reset",
+            "reset_password",
+            "enable_totp",
+            "confirm_totp",
+            "logout",
+            "delete_user",
+            "find_duplicate_users",
+            "merge_users",
+            "assign_role",
+            "list_users",
+            "get_user",
+            "get_user_by_username",
+        ]
+        .iter()
+        .map(|op| op.to_string())
+        .collect()
+    }
+
+    async fn call(&self, operation: &str, params: ActionParams) -> Result<serde_json::Value> {
+        match operation {
+            "register" => to_result(self.register(params.parse()?).await?),
+            "login" => to_result(self.login(params.parse()?).await?),
+            "refresh" => to_result(
+                self.refresh(tenant_param(&params)?, params.get_string("refresh_token")?).await?,
+            ),
+            "validate_token" => to_result(
+                self.validate_token(tenant_param(&params)?, params.get_string("token")?).await?,
+            ),
+            "change_password" => to_result(
+                self.change_password(
+                    tenant_param(&params)?,
+                    params.get_json("user_id")?,
+                    params.get_string("old_password")?,
+                    params.get_string("new_password")?,
+                    params.get_json_optional("revoke_tokens")?,
+                )
+                .await?,
+            ),
+            "request_password_reset" => to_result(
+                self.request_password_reset(tenant_param(&params)?, params.get_string("email")?).await?,
+            ),
+            "reset_password" => to_result(
+                self.reset_password(
+                    tenant_param(&params)?,
+                    params.get_string("token")?,
+                    params.get_string("new_password")?,
+                )
+                .await?,
+            ),
+            "enable_totp" => to_result(
+                self.enable_totp(tenant_param(&params)?, params.get_string("token")?, params.get_json("user_id")?).await?,
+            ),
+            "confirm_totp" => to_result(
+                self.confirm_totp(
+                    tenant_param(&params)?,
+                    params.get_string("token")?,
+                    params.get_json("user_id")?,
+                    params.get_string("code")?,
+                )
+                .await?,
+            ),
+            "logout" => to_result(self.logout(params.get_string("token")?).await?),
+            "delete_user" => to_result(
+                self.delete_user(tenant_param(&params)?, params.get_string("token")?, params.get_json("user_id")?)
+                    .await?,
+            ),
+            "find_duplicate_users" => to_result(self.find_duplicate_users(params.get_string("token")?).await?),
+            "merge_users" => to_result(
+                self.merge_users(
+                    params.get_string("token")?,
+                    params.get_json("keep_id")?,
+                    params.get_json("merge_id")?,
+                )
+                .await?,
+            ),
+            "assign_role" => to_result(
+                self.assign_role(
+                    params.get_string("token")?,
+                    params.get_json("user_id")?,
+                    params.get_string("role")?,
+                )
+                .await?,
+            ),
+            "list_users" => to_result(
+                self.list_users(
+                    params.get_string("token")?,
+                    params.get_json_optional("offset")?.unwrap_or(0),
+                    params.get_json_optional("limit")?.unwrap_or(MAX_PAGE_LIMIT),
+                )
+                .await?,
+            ),
+            "get_user" => to_result(self.get_user(tenant_param(&params)?, params.get_json("user_id")?).await?),
+            "get_user_by_username" => to_result(
+                self.get_user_by_username(tenant_param(&params)?, params.get_string("username")?).await?,
+            ),
+            _ => Err(unknown_operation(self, operation)),
+        }
+    }
+}
+
+/// The call's `tenant_id`, defaulting like the request structs do
+fn tenant_param(params: &ActionParams) -> Result<String> {
+    Ok(params.get_string_optional("tenant_id")?.unwrap_or_else(default_tenant))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::totp::StoredTotp;
+    use jsonwebtoken::Algorithm;
+
+    /// Store predating email normalization: it lets duplicate emails in
+    #[derive(Default)]
+    struct LegacyUserStore {
+        users: RwLock<Vec<User>>,
+    }
+
+    #[async_trait]
+    impl UserStore for LegacyUserStore {
+        async fn insert_user(&self, user: User) -> Result<()> {
+            self.users.write().await.push(user);
+            Ok(())
+        }
+
+        async fn find_by_id(&self, id: Uuid) -> Result<Option<User>> {
+            Ok(self.users.read().await.iter().find(|u| u.id == id).cloned())
+        }
+
+        async fn find_by_username(&self, tenant_id: &str, username: &str) -> Result<Option<User>> {
+            Ok(self.users.read().await
+                .iter()
+                .find(|u| u.tenant_id == tenant_id && u.username.eq_ignore_ascii_case(username))
+                .cloned())
+        }
+
+        async fn find_by_email(&self, tenant_id: &str, email: &str) -> Result<Option<User>> {
+            Ok(self.users.read().await
+                .iter()
+                .find(|u| u.tenant_id == tenant_id && normalize_email(&u.email) == normalize_email(email))
+                .cloned())
+        }
+
+        async fn update_user(&self, user: User) -> Result<()> {
+            let mut users = self.users.write().await;
+            let stored = users
+                .iter_mut()
+                .find(|u| u.id == user.id)
+                .ok_or_else(|| ServiceError::not_found("User not found"))?;
+            *stored = user;
+            Ok(())
+        }
+
+        async fn list_users(&self, tenant_id: &str) -> Result<Vec<User>> {
+            Ok(self.users.read().await.iter().filter(|u| u.tenant_id == tenant_id).cloned().collect())
+        }
+
+        async fn remove_user(&self, id: Uuid) -> Result<Option<User>> {
+            let mut users = self.users.write().await;
+            let position = users.iter().position(|u| u.id == id);
+            Ok(position.map(|position| users.remove(position)))
+        }
+
+        async fn set_totp(&self, _user_id: Uuid, _totp: Option<StoredTotp>) -> Result<()> {
+            Ok(())
+        }
+
+        async fn find_totp(&self, _user_id: Uuid) -> Result<Option<StoredTotp>> {
+            Ok(None)
+        }
+    }
+
+    fn user(username: &str, email: &str, roles: &[&str], age_days: i64) -> User {
+        let created_at = Utc::now() - Duration::days(age_days);
+        User {
+            id: Uuid::new_v4(),
+            tenant_id: DEFAULT_TENANT.to_string(),
+            username: username.to_string(),
+            email: email.to_string(),
+            password_hash: String::new(),
+            roles: roles.iter().map(|r| r.to_string()).collect(),
+            session_version: 0,
+            created_at,
+            updated_at: created_at,
+        }
+    }
+
+    async fn service_with(users: &[&User]) -> AuthService<LegacyUserStore> {
+        let store = LegacyUserStore::default();
+        for user in users {
+            store.insert_user((*user).clone()).await.unwrap();
+        }
+        AuthService::with_store(AuthConfig::new("test-secret-key"), store).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn admin_finds_and_merges_a_duplicate_cluster() {
+        let admin = user("admin", "admin@example.com", &[ADMIN_ROLE], 30);
+        let original = user("alice", "Alice@Example.com", &[DEFAULT_ROLE], 20);
+        let duplicate = user("alice2", " alice@example.com", &[DEFAULT_ROLE], 10);
+        let events = EventBus::new();
+        let service = service_with(&[&admin, &duplicate, &original]).await.with_event_bus(events.clone());
+        let mut merged = events.subscribe(EVENT_TOPIC);
+        let token = service.create_token(&admin, &[]).await.unwrap();
+        let duplicate_token = service.create_token(&duplicate, &[]).await.unwrap();
+        service.verify_token(&duplicate_token).await.unwrap();
+
+        let clusters = service.find_duplicate_users(token.clone()).await.unwrap();
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].normalized_email, "alice@example.com");
+        let ids: Vec<Uuid> = clusters[0].users.iter().map(|u| u.id).collect();
+        assert_eq!(ids, vec![original.id, duplicate.id]);
+
+        let kept = service.merge_users(token.clone(), original.id, duplicate.id).await.unwrap();
+        assert_eq!(kept.id, original.id);
+        assert!(service.store.find_by_id(duplicate.id).await.unwrap().is_none());
+        assert!(service.find_duplicate_users(token).await.unwrap().is_empty());
+        let err = service.verify_token(&duplicate_token).await.unwrap_err();
+        assert_eq!(ServiceError::from_anyhow(&err), ServiceError::unauthorized("Token has been revoked"));
+
+        let event: UsersMerged = merged.recv().await.unwrap().parse().unwrap().unwrap();
+        assert_eq!(event.keep_id, original.id);
+        assert_eq!(event.merge_id, duplicate.id);
+        assert_eq!(event.tenant_id, DEFAULT_TENANT);
+    }
+
+    #[tokio::test]
+    async fn non_admin_cannot_find_or_merge_duplicates() {
+        let original = user("alice", "alice@example.com", &[DEFAULT_ROLE], 20);
+        let duplicate = user("alice2", "ALICE@example.com", &[DEFAULT_ROLE], 10);
+        let service = service_with(&[&original, &duplicate]).await;
+        let token = service.create_token(&original, &[]).await.unwrap();
+
+        let err = service.find_duplicate_users(token.clone()).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Forbidden(_)));
+
+        let err = service.merge_users(token, original.id, duplicate.id).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Forbidden(_)));
+        assert!(service.store.find_by_id(duplicate.id).await.unwrap().is_some());
+    }
+
+    const PASSWORD: &str = "correct horse battery";
+
+    async fn register(service: &AuthService, tenant_id: &str, username: &str) -> AuthResponse {
+        service
+            .register(RegisterRequest {
+                tenant_id: tenant_id.to_string(),
+                username: username.to_string(),
+                email: format!("{}@example.com", username),
+                password: PASSWORD.to_string(),
+            })
+            .await
+            .unwrap()
+    }
+
+    fn login_request(tenant_id: &str, username: &str) -> LoginRequest {
+        LoginRequest {
+            tenant_id: tenant_id.to_string(),
+            username: username.to_string(),
+            password: PASSWORD.to_string(),
+            totp_code: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn same_username_coexists_across_tenants() {
+        let service = AuthService::new_with_default_secret().await.unwrap();
+        let acme = register(&service, "acme", "alice").await;
+        let globex = register(&service, "globex", "alice").await;
+        assert_ne!(acme.user.id, globex.user.id);
+
+        let logged_in = service.login(login_request("globex", "alice")).await.unwrap();
+        assert_eq!(logged_in.user.id, globex.user.id);
+        assert_eq!(logged_in.user.tenant_id, "globex");
+    }
+
+    #[tokio::test]
+    async fn cross_tenant_reads_fail() {
+        let service = AuthService::new_with_default_secret().await.unwrap();
+        let acme = register(&service, "acme", "alice").await;
+
+        let err = service.get_user("globex".to_string(), acme.user.id).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::NotFound(_)));
+        assert!(service.get_user_by_username("globex".to_string(), "alice".to_string()).await.is_err());
+        assert!(service.login(login_request("globex", "alice")).await.is_err());
+        let err = service.validate_token("globex".to_string(), acme.token).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Unauthorized(_)));
+    }
+
+    #[tokio::test]
+    async fn registration_reports_every_failed_password_rule() {
+        let policy = PasswordPolicy {
+            min_length: 12,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_symbol: true,
+        };
+        let service = AuthService::new_with_default_secret()
+            .await
+            .unwrap()
+            .with_password_policy(policy)
+            .with_password_checker(Arc::new(password::CommonPasswords::new(["Letmein-2024!"])));
+        let request = |password: &str| RegisterRequest {
+            tenant_id: "acme".to_string(),
+            username: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            password: password.to_string(),
+        };
+
+        let err = service.register(request("letmein")).await.unwrap_err();
+        match ServiceError::from_anyhow(&err) {
+            ServiceError::Validation(message) => {
+                assert!(message.contains("at least 12"), "{}", message);
+                assert!(message.contains("uppercase") && message.contains("digit") && message.contains("symbol"), "{}", message);
+            },
+            other => panic!("expected Validation, got {:?}", other),
+        }
+
+        let err = service.register(request("Letmein-2024!")).await.unwrap_err();
+        assert!(ServiceError::from_anyhow(&err).message().contains("common or breached"));
+
+        service.register(request("Correct-Horse-42")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cached_claims_are_reused_within_the_ttl() {
+        let service = AuthService::new_with_default_secret().await.unwrap();
+        let auth = register(&service, "acme", "alice").await;
+        let claims = service.verify_token(&auth.token).await.unwrap();
+
+        // Not a JWT at all, so only a cache hit can validate it
+        service.token_cache.insert("not-a-jwt", claims.clone()).await;
+        assert_eq!(service.verify_token("not-a-jwt").await.unwrap().jti, claims.jti);
+
+        let uncached = AuthService::new_with_default_secret()
+            .await
+            .unwrap()
+            .with_token_cache_ttl(std::time::Duration::ZERO);
+        uncached.token_cache.insert("not-a-jwt", claims).await;
+        assert!(uncached.verify_token("not-a-jwt").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn revoking_a_token_evicts_its_cached_claims() {
+        let service = AuthService::new_with_default_secret().await.unwrap();
+        let auth = register(&service, "acme", "alice").await;
+        service.validate_token("acme".to_string(), auth.token.clone()).await.unwrap();
+        assert!(service.token_cache.get(&auth.token).await.is_some());
+
+        service.logout(auth.token.clone()).await.unwrap();
+
+        assert!(service.token_cache.get(&auth.token).await.is_none());
+        assert!(service.validate_token("acme".to_string(), auth.token).await.is_err());
+
+        let session = service.login(login_request("acme", "alice")).await.unwrap();
+        service.validate_token("acme".to_string(), session.token.clone()).await.unwrap();
+        service
+            .change_password("acme".to_string(), auth.user.id, PASSWORD.to_string(), "a brand new passphrase".to_string(), None)
+            .await
+            .unwrap();
+        assert!(service.token_cache.get(&session.token).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn tokens_are_signed_with_the_configured_secret_and_algorithm() {
+        let config = AuthConfig {
+            algorithm: Algorithm::HS384,
+            ..AuthConfig::new("a-configured-secret")
+        };
+        let service = AuthService::new(config).await.unwrap();
+        let auth = register(&service, "acme", "alice").await;
+
+        assert_eq!(jsonwebtoken::decode_header(&auth.token).unwrap().alg, Algorithm::HS384);
+        service.validate_token("acme".to_string(), auth.token.clone()).await.unwrap();
+
+        // Neither another secret nor the test default accepts it
+        let other = AuthService::new(AuthConfig { algorithm: Algorithm::HS384, ..AuthConfig::new("another-secret") })
+            .await
+            .unwrap();
+        assert!(other.verify_token(&auth.token).await.is_err());
+        let default = AuthService::new_with_default_secret().await.unwrap();
+        assert!(default.verify_token(&auth.token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unusable_key_configuration_is_rejected_up_front() {
+        assert!(AuthService::new(AuthConfig::new("")).await.is_err());
+        let missing_keys = AuthConfig::with_pem_keys(Algorithm::RS256, "/nonexistent/private.pem", "/nonexistent/public.pem");
+        assert!(AuthService::new(missing_keys).await.is_err());
+        assert!(AuthService::new(AuthConfig::new("secret").with_expiration_hours(0)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn refresh_tokens_rotate_and_cannot_be_reused() {
+        let service = AuthService::new_with_default_secret().await.unwrap();
+        let auth = register(&service, "acme", "alice").await;
+
+        let renewed = service.refresh("acme".to_string(), auth.refresh_token.clone()).await.unwrap();
+        assert_ne!(renewed.refresh_token, auth.refresh_token);
+        assert_eq!(renewed.user.id, auth.user.id);
+        service.validate_token("acme".to_string(), renewed.token).await.unwrap();
+
+        let err = service.refresh("acme".to_string(), auth.refresh_token).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Unauthorized(_)));
+
+        // A token presented to the wrong tenant is spent as well
+        let err = service.refresh("globex".to_string(), renewed.refresh_token.clone()).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Unauthorized(_)));
+        assert!(service.refresh("acme".to_string(), renewed.refresh_token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn logged_out_token_no_longer_validates() {
+        let service = AuthService::new_with_default_secret().await.unwrap();
+        let auth = register(&service, "acme", "alice").await;
+        let other_session = service.login(login_request("acme", "alice")).await.unwrap();
+
+        service.logout(auth.token.clone()).await.unwrap();
+
+        let err = service.validate_token("acme".to_string(), auth.token.clone()).await.unwrap_err();
+        match ServiceError::from_anyhow(&err) {
+            ServiceError::Unauthorized(message) => assert!(message.contains("revoked"), "{}", message),
+            other => panic!("expected Unauthorized, got {:?}", other),
+        }
+        assert!(service.logout(auth.token).await.is_err());
+        // Only the token that logged out is revoked
+        service.validate_token("acme".to_string(), other_session.token).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_admins_can_assign_roles() {
+        let admin = user("admin", "admin@example.com", &[ADMIN_ROLE], 30);
+        let alice = user("alice", "alice@example.com", &[DEFAULT_ROLE], 10);
+        let service = service_with(&[&admin, &alice]).await;
+        let alice_token = service.create_token(&alice, &[]).await.unwrap();
+
+        let err = service.assign_role(alice_token.clone(), alice.id, ADMIN_ROLE.to_string()).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Forbidden(_)));
+        let unchanged = service.get_user(DEFAULT_TENANT.to_string(), alice.id).await.unwrap();
+        assert_eq!(unchanged.roles, vec![DEFAULT_ROLE.to_string()]);
+
+        let admin_token = service.create_token(&admin, &[]).await.unwrap();
+        let promoted = service.assign_role(admin_token, alice.id, "billing".to_string()).await.unwrap();
+        assert!(promoted.roles.contains(&"billing".to_string()));
+        assert!(service.require_role(&alice_token, DEFAULT_ROLE).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn registration_rejects_each_invalid_input_with_its_own_message() {
+        let service = AuthService::new_with_default_secret().await.unwrap();
+        register(&service, "acme", "alice").await;
+        let attempt = |username: &str, email: &str| RegisterRequest {
+            tenant_id: "acme".to_string(),
+            username: username.to_string(),
+            email: email.to_string(),
+            password: PASSWORD.to_string(),
+        };
+
+        let cases = [
+            (attempt("", "new@example.com"), "Username is required"),
+            (attempt("al", "new@example.com"), "Username must be 3-32"),
+            (attempt("bob smith", "new@example.com"), "Username must be 3-32"),
+            (attempt(&"b".repeat(33), "new@example.com"), "Username must be 3-32"),
+            (attempt("bob", ""), "Email is required"),
+            (attempt("bob", "bob at example.com"), "Email address is not valid"),
+            (attempt("bob", "bob@localhost"), "Email address is not valid"),
+        ];
+        for (request, expected) in cases {
+            let label = format!("{:?} / {:?}", request.username, request.email);
+            let err = service.register(request).await.unwrap_err();
+            match ServiceError::from_anyhow(&err) {
+                ServiceError::Validation(message) => assert!(message.starts_with(expected), "{}: {}", label, message),
+                other => panic!("{}: expected Validation, got {:?}", label, other),
+            }
+        }
+
+        // Usernames and emails are unique regardless of case
+        let err = service.register(attempt("ALICE", "other@example.com")).await.unwrap_err();
+        assert_eq!(ServiceError::from_anyhow(&err).message(), "Username already exists");
+        let err = service.register(attempt("bob", "Alice@Example.COM")).await.unwrap_err();
+        assert_eq!(ServiceError::from_anyhow(&err).message(), "Email already exists");
+    }
+
+    #[tokio::test]
+    async fn username_lookup_ignores_case_and_stays_in_the_tenant() {
+        let service = AuthService::new_with_default_secret().await.unwrap();
+        let registered = register(&service, "acme", "Alice").await;
+
+        for username in ["Alice", "alice", "ALICE", " alice "] {
+            let found = service.get_user_by_username("acme".to_string(), username.to_string()).await.unwrap();
+            assert_eq!(found.id, registered.user.id, "{:?}", username);
+        }
+
+        for (tenant, username) in [("acme", "bob"), ("globex", "alice")] {
+            let err = service.get_user_by_username(tenant.to_string(), username.to_string()).await.unwrap_err();
+            assert_eq!(ServiceError::from_anyhow(&err), ServiceError::not_found("User not found"));
+        }
+    }
+
+    #[tokio::test]
+    async fn deleted_user_frees_their_username_and_email_for_re_registration() {
+        let service = AuthService::new_with_default_secret().await.unwrap();
+        let alice = register(&service, "acme", "alice").await;
+
+        // Another tenant's admin can't reach the account
+        let mut globex_admin = user("admin", "admin@example.com", &[ADMIN_ROLE], 30);
+        globex_admin.tenant_id = "globex".to_string();
+        let globex_token = service.create_token(&globex_admin, &[]).await.unwrap();
+        let err = service.delete_user("globex".to_string(), globex_token, alice.user.id).await.unwrap_err();
+        assert_eq!(ServiceError::from_anyhow(&err), ServiceError::not_found("User not found"));
+
+        service.delete_user("acme".to_string(), alice.token.clone(), alice.user.id).await.unwrap();
+
+        assert!(service.store.find_by_username("acme", "alice").await.unwrap().is_none());
+        assert!(service.store.find_by_email("acme", "alice@example.com").await.unwrap().is_none());
+        assert!(service.validate_token("acme".to_string(), alice.token.clone()).await.is_err());
+        let again = register(&service, "acme", "alice").await;
+        assert_ne!(again.user.id, alice.user.id);
+        assert_eq!(service.store.find_by_email("acme", "alice@example.com").await.unwrap().unwrap().id, again.user.id);
+    }
+
+    /// The code an authenticator set up from `setup` shows `offset_secs` from now
+    fn totp_code(setup: &TotpSetup, offset_secs: i64) -> String {
+        let secret = totp_rs::Secret::Encoded(setup.secret.clone()).to_bytes().unwrap();
+        let generator = totp::totp(secret, totp::DEFAULT_TOTP_ISSUER, "alice").unwrap();
+        generator.generate((Utc::now().timestamp() + offset_secs) as u64)
+    }
+
+    async fn service_with_totp() -> AuthService {
+        AuthService::new(AuthConfig::new("test-secret-key").with_totp_encryption_key([7; 32])).await.unwrap()
+    }
+
+    fn totp_login(code: Option<String>) -> LoginRequest {
+        LoginRequest { totp_code: code, ..login_request("acme", "alice") }
+    }
+
+    #[tokio::test]
+    async fn confirming_totp_needs_a_current_code() {
+        let service = service_with_totp().await;
+        let alice = register(&service, "acme", "alice").await;
+        let setup = service.enable_totp("acme".to_string(), alice.token.clone(), alice.user.id).await.unwrap();
+        assert!(setup.uri.starts_with("otpauth://totp/"), "{}", setup.uri);
+
+        // Stored encrypted, never as the secret itself
+        let stored = service.store.find_totp(alice.user.id).await.unwrap().unwrap();
+        let raw = totp_rs::Secret::Encoded(setup.secret.clone()).to_bytes().unwrap();
+        assert!(!stored.encrypted_secret.windows(raw.len()).any(|window| window == raw.as_slice()));
+        // Not enforced until confirmed
+        service.login(totp_login(None)).await.unwrap();
+
+        let expired = totp_code(&setup, -120);
+        let err = service.confirm_totp("acme".to_string(), alice.token.clone(), alice.user.id, expired).await.unwrap_err();
+        assert_eq!(ServiceError::from_anyhow(&err), ServiceError::unauthorized("Invalid two-factor code"));
+
+        service.confirm_totp("acme".to_string(), alice.token.clone(), alice.user.id, totp_code(&setup, 0)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn login_with_totp_enabled_checks_the_code() {
+        let service = service_with_totp().await;
+        let alice = register(&service, "acme", "alice").await;
+        let setup = service.enable_totp("acme".to_string(), alice.token.clone(), alice.user.id).await.unwrap();
+        service.confirm_totp("acme".to_string(), alice.token.clone(), alice.user.id, totp_code(&setup, 0)).await.unwrap();
+
+        let err = service.login(totp_login(None)).await.unwrap_err();
+        assert_eq!(ServiceError::from_anyhow(&err), ServiceError::unauthorized("Two-factor code required"));
+
+        let err = service.login(totp_login(Some(totp_code(&setup, -120)))).await.unwrap_err();
+        assert_eq!(ServiceError::from_anyhow(&err), ServiceError::unauthorized("Invalid two-factor code"));
+
+        let logged_in = service.login(totp_login(Some(totp_code(&setup, 0)))).await.unwrap();
+        assert_eq!(logged_in.user.id, alice.user.id);
+        // One step of clock drift is tolerated
+        service.login(totp_login(Some(totp_code(&setup, -30)))).await.unwrap();
+    }
+
+    const NEW_PASSWORD: &str = "staple battery horse";
+
+    #[tokio::test]
+    async fn reset_token_sets_a_new_password_once() {
+        let service = AuthService::new_with_default_secret().await.unwrap();
+        let alice = register(&service, "acme", "alice").await;
+        let token = service.request_password_reset("acme".to_string(), "alice@example.com".to_string()).await.unwrap();
+
+        // A password the policy rejects leaves the token usable
+        let err = service.reset_password("acme".to_string(), token.clone(), "short".to_string()).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Validation(_)));
+
+        // Issued immediately before the reset, so usually within the same second
+        let session = service.login(login_request("acme", "alice")).await.unwrap();
+        service.reset_password("acme".to_string(), token.clone(), NEW_PASSWORD.to_string()).await.unwrap();
+
+        assert!(service.login(login_request("acme", "alice")).await.is_err());
+        let login = LoginRequest { password: NEW_PASSWORD.to_string(), ..login_request("acme", "alice") };
+        let fresh = service.login(login).await.unwrap();
+        assert_eq!(fresh.user.id, alice.user.id);
+        for token in [alice.token, session.token] {
+            let err = service.validate_token("acme".to_string(), token).await.unwrap_err();
+            assert_eq!(ServiceError::from_anyhow(&err), ServiceError::unauthorized("Token has been revoked"));
+        }
+        service.validate_token("acme".to_string(), fresh.token).await.unwrap();
+        assert_eq!(service.store.find_by_id(alice.user.id).await.unwrap().unwrap().session_version, 1);
+
+        let err = service.reset_password("acme".to_string(), token, "another new password".to_string()).await.unwrap_err();
+        assert_eq!(ServiceError::from_anyhow(&err), ServiceError::unauthorized("Invalid or expired reset token"));
+    }
+
+    #[tokio::test]
+    async fn expired_and_unknown_email_reset_tokens_are_rejected() {
+        let service = AuthService::new_with_default_secret().await.unwrap();
+        register(&service, "acme", "alice").await;
+        let invalid = ServiceError::unauthorized("Invalid or expired reset token");
+
+        let expired = service.request_password_reset("acme".to_string(), "alice@example.com".to_string()).await.unwrap();
+        for reset in service.password_resets.write().await.values_mut() {
+            reset.expires_at = Utc::now() - Duration::minutes(1);
+        }
+        let err = service.reset_password("acme".to_string(), expired, NEW_PASSWORD.to_string()).await.unwrap_err();
+        assert_eq!(ServiceError::from_anyhow(&err), invalid);
+
+        // Unknown emails get a token too, so they can't be told apart, but it never works
+        let unknown = service.request_password_reset("acme".to_string(), "nobody@example.com".to_string()).await.unwrap();
+        let err = service.reset_password("acme".to_string(), unknown, NEW_PASSWORD.to_string()).await.unwrap_err();
+        assert_eq!(ServiceError::from_anyhow(&err), invalid);
+
+        service.login(login_request("acme", "alice")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn admin_lists_users_oldest_first_one_page_at_a_time() {
+        let admin = user("admin", "admin@example.com", &[ADMIN_ROLE], 30);
+        let carol = user("carol", "carol@example.com", &[DEFAULT_ROLE], 20);
+        let alice = user("alice", "alice@example.com", &[DEFAULT_ROLE], 10);
+        let bob = user("bob", "bob@example.com", &[DEFAULT_ROLE], 5);
+        let service = service_with(&[&bob, &admin, &alice, &carol]).await;
+        let token = service.create_token(&admin, &[]).await.unwrap();
+
+        let page = service.list_users(token.clone(), 1, 2).await.unwrap();
+
+        assert_eq!(page.total, 4);
+        let usernames: Vec<&str> = page.items.iter().map(|u| u.username.as_str()).collect();
+        assert_eq!(usernames, ["carol", "alice"]);
+        let json = serde_json::to_value(&page).unwrap();
+        assert!(json["items"][0].get("password_hash").is_none());
+        assert!(service.list_users(token, 4, 2).await.unwrap().items.is_empty());
+    }
+
+    #[tokio::test]
+    async fn list_users_caps_the_page_size() {
+        let admin = user("admin", "admin@example.com", &[ADMIN_ROLE], 1);
+        let others: Vec<User> = (0..MAX_PAGE_LIMIT + 5)
+            .map(|n| user(&format!("user{}", n), &format!("user{}@example.com", n), &[DEFAULT_ROLE], 2))
+            .collect();
+        let mut users: Vec<&User> = others.iter().collect();
+        users.push(&admin);
+        let service = service_with(&users).await;
+        let token = service.create_token(&admin, &[]).await.unwrap();
+
+        let page = service.list_users(token, 0, 1_000).await.unwrap();
+
+        assert_eq!(page.items.len(), MAX_PAGE_LIMIT);
+        assert_eq!(page.total, MAX_PAGE_LIMIT + 6);
+    }
+
+    #[tokio::test]
+    async fn non_admins_cannot_list_users() {
+        let admin = user("admin", "admin@example.com", &[ADMIN_ROLE], 30);
+        let alice = user("alice", "alice@example.com", &[DEFAULT_ROLE], 10);
+        let service = service_with(&[&admin, &alice]).await;
+        let alice_token = service.create_token(&alice, &[]).await.unwrap();
+
+        let err = service.list_users(alice_token, 0, 10).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Forbidden(_)));
+
+        let err = service.list_users("not-a-token".to_string(), 0, 10).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Unauthorized(_)));
+    }
+
+    #[tokio::test]
+    async fn changing_the_password_ends_sessions_from_the_same_second() {
+        let service = AuthService::new_with_default_secret().await.unwrap();
+        let alice = register(&service, "acme", "alice").await;
+        let session = service.login(login_request("acme", "alice")).await.unwrap();
+        let kept = service.login(login_request("acme", "alice")).await.unwrap();
+
+        // Keeping tokens on request doesn't end any session
+        service
+            .change_password("acme".to_string(), alice.user.id, PASSWORD.to_string(), NEW_PASSWORD.to_string(), Some(false))
+            .await
+            .unwrap();
+        service.validate_token("acme".to_string(), kept.token.clone()).await.unwrap();
+
+        service
+            .change_password("acme".to_string(), alice.user.id, NEW_PASSWORD.to_string(), "a brand new passphrase".to_string(), None)
+            .await
+            .unwrap();
+
+        for token in [alice.token, session.token, kept.token] {
+            let err = service.validate_token("acme".to_string(), token).await.unwrap_err();
+            assert_eq!(ServiceError::from_anyhow(&err), ServiceError::unauthorized("Token has been revoked"));
+        }
+        assert!(service.refresh("acme".to_string(), session.refresh_token).await.is_err());
+        let login = LoginRequest { password: "a brand new passphrase".to_string(), ..login_request("acme", "alice") };
+        let fresh = service.login(login).await.unwrap();
+        service.validate_token("acme".to_string(), fresh.token).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn wrong_old_password_is_rejected_like_a_failed_login() {
+        let service = AuthService::new_with_default_secret().await.unwrap();
+        let alice = register(&service, "acme", "alice").await;
+
+        let err = service
+            .change_password("acme".to_string(), alice.user.id, "not my password".to_string(), NEW_PASSWORD.to_string(), None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(ServiceError::from_anyhow(&err), ServiceError::unauthorized("Invalid username or password"));
+        service.login(login_request("acme", "alice")).await.unwrap();
+        service.validate_token("acme".to_string(), alice.token).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_the_account_owner_can_set_up_totp() {
+        let service = service_with_totp().await;
+        let alice = register(&service, "acme", "alice").await;
+        let bob = register(&service, "acme", "bob").await;
+        let alice_elsewhere = register(&service, "globex", "alice").await;
+
+        for token in [bob.token.clone(), alice_elsewhere.token] {
+            let err = service.enable_totp("acme".to_string(), token, alice.user.id).await.unwrap_err();
+            assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Forbidden(_)));
+        }
+        let err = service.enable_totp("acme".to_string(), "not-a-token".to_string(), alice.user.id).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Unauthorized(_)));
+        assert!(service.store.find_totp(alice.user.id).await.unwrap().is_none());
+
+        // A pending setup can't be confirmed by someone else either
+        let setup = service.enable_totp("acme".to_string(), alice.token.clone(), alice.user.id).await.unwrap();
+        let err = service
+            .confirm_totp("acme".to_string(), bob.token, alice.user.id, totp_code(&setup, 0))
+            .await
+            .unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Forbidden(_)));
+        service.login(totp_login(None)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_the_user_or_an_admin_can_delete_an_account() {
+        let service = AuthService::new_with_default_secret().await.unwrap();
+        let alice = register(&service, "acme", "alice").await;
+        let bob = register(&service, "acme", "bob").await;
+
+        let err = service.delete_user("acme".to_string(), bob.token, alice.user.id).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Forbidden(_)));
+        let err = service.delete_user("acme".to_string(), "not-a-token".to_string(), alice.user.id).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Unauthorized(_)));
+        assert!(service.store.find_by_username("acme", "alice").await.unwrap().is_some());
+
+        let mut admin = user("admin", "admin@example.com", &[ADMIN_ROLE], 30);
+        admin.tenant_id = "acme".to_string();
+        let admin_token = service.create_token(&admin, &[]).await.unwrap();
+        service.delete_user("acme".to_string(), admin_token.clone(), alice.user.id).await.unwrap();
+        assert!(service.store.find_by_username("acme", "alice").await.unwrap().is_none());
+
+        let err = service.delete_user("acme".to_string(), admin_token, alice.user.id).await.unwrap_err();
+        assert_eq!(ServiceError::from_anyhow(&err), ServiceError::not_found("User not found"));
+    }
+}
@@ -0,0 +1,184 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+/// A single password requirement
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum PasswordRule {
+    MinLength { min: usize },
+    Uppercase,
+    Lowercase,
+    Digit,
+    Symbol,
+    /// The password appears in a list of common or breached passwords
+    NotCompromised,
+}
+
+impl fmt::Display for PasswordRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MinLength { min } => write!(f, "must be at least {} characters long", min),
+            Self::Uppercase => f.write_str("must contain an uppercase letter"),
+            Self::Lowercase => f.write_str("must contain a lowercase letter"),
+            Self::Digit => f.write_str("must contain a digit"),
+            Self::Symbol => f.write_str("must contain a symbol"),
+            Self::NotCompromised => f.write_str("must not be a common or breached password"),
+        }
+    }
+}
+
+/// Every rule a password failed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WeakPassword {
+    pub unmet: Vec<PasswordRule>,
+}
+
+impl fmt::Display for WeakPassword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rules: Vec<String> = self.unmet.iter().map(|rule| rule.to_string()).collect();
+        write!(f, "Password {}", rules.join("; "))
+    }
+}
+
+impl std::error::Error for WeakPassword {}
+
+/// Character-class and length requirements for new passwords
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    #[serde(default)]
+    pub require_uppercase: bool,
+    #[serde(default)]
+    pub require_lowercase: bool,
+    #[serde(default)]
+    pub require_digit: bool,
+    #[serde(default)]
+    pub require_symbol: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            require_uppercase: false,
+            require_lowercase: false,
+            require_digit: false,
+            require_symbol: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Rules `password` fails, in a stable order
+    pub fn unmet(&self, password: &str) -> Vec<PasswordRule> {
+        let mut unmet = Vec::new();
+        if password.chars().count() < self.min_length {
+            unmet.push(PasswordRule::MinLength { min: self.min_length });
+        }
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            unmet.push(PasswordRule::Uppercase);
+        }
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            unmet.push(PasswordRule::Lowercase);
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            unmet.push(PasswordRule::Digit);
+        }
+        if self.require_symbol && !password.chars().any(|c| !c.is_alphanumeric() && !c.is_whitespace()) {
+            unmet.push(PasswordRule::Symbol);
+        }
+        unmet
+    }
+}
+
+/// Source of known-bad passwords, e.g. a breach corpus lookup
+#[async_trait]
+pub trait CompromisedPasswordChecker: Send + Sync {
+    async fn is_compromised(&self, password: &str) -> anyhow::Result<bool>;
+}
+
+/// Checker rejecting a fixed list of passwords, compared case-insensitively
+#[derive(Debug, Clone, Default)]
+pub struct CommonPasswords {
+    passwords: HashSet<String>,
+}
+
+impl CommonPasswords {
+    pub fn new<I, S>(passwords: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            passwords: passwords.into_iter().map(|p| p.as_ref().to_lowercase()).collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl CompromisedPasswordChecker for CommonPasswords {
+    async fn is_compromised(&self, password: &str) -> anyhow::Result<bool> {
+        Ok(self.passwords.contains(&password.to_lowercase()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn only(policy: PasswordPolicy) -> PasswordPolicy {
+        PasswordPolicy { min_length: 0, ..policy }
+    }
+
+    #[test]
+    fn each_rule_applies_only_when_enabled() {
+        let off = only(PasswordPolicy::default());
+        // (policy with one rule on, password failing it, password meeting it, rule)
+        let cases = [
+            (PasswordPolicy { min_length: 10, ..PasswordPolicy::default() }, "short", "long enough!", PasswordRule::MinLength { min: 10 }),
+            (only(PasswordPolicy { require_uppercase: true, ..PasswordPolicy::default() }), "lower", "Upper", PasswordRule::Uppercase),
+            (only(PasswordPolicy { require_lowercase: true, ..PasswordPolicy::default() }), "UPPER", "lOWER", PasswordRule::Lowercase),
+            (only(PasswordPolicy { require_digit: true, ..PasswordPolicy::default() }), "letters", "letters1", PasswordRule::Digit),
+            (only(PasswordPolicy { require_symbol: true, ..PasswordPolicy::default() }), "plain 1", "plain!", PasswordRule::Symbol),
+        ];
+
+        for (policy, failing, passing, rule) in cases {
+            assert_eq!(policy.unmet(failing), vec![rule.clone()], "{}", rule);
+            assert!(policy.unmet(passing).is_empty(), "{}", rule);
+            assert!(off.unmet(failing).is_empty(), "{} applied while off", rule);
+        }
+    }
+
+    #[test]
+    fn every_unmet_rule_is_reported_and_a_compliant_password_passes() {
+        let strict = PasswordPolicy {
+            min_length: 12,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_symbol: true,
+        };
+
+        let unmet = strict.unmet("abc");
+        assert_eq!(unmet, vec![
+            PasswordRule::MinLength { min: 12 },
+            PasswordRule::Uppercase,
+            PasswordRule::Digit,
+            PasswordRule::Symbol,
+        ]);
+        let message = WeakPassword { unmet }.to_string();
+        assert!(message.contains("at least 12 characters") && message.contains("a symbol"), "{}", message);
+
+        assert!(strict.unmet("Correct-Horse-42").is_empty());
+    }
+
+    #[tokio::test]
+    async fn common_passwords_are_matched_case_insensitively() {
+        let checker = CommonPasswords::new(["password123", "letmein"]);
+
+        assert!(checker.is_compromised("Password123").await.unwrap());
+        assert!(!checker.is_compromised("Correct-Horse-42").await.unwrap());
+    }
+}
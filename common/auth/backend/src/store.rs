@@ -0,0 +1,175 @@
+use crate::totp::StoredTotp;
+use crate::{normalize_email, User};
+use anyhow::Result;
+use async_trait::async_trait;
+use kagi_shared::ServiceError;
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// Persistence for `AuthService` users.
+///
+/// Usernames and emails are unique per tenant, compared case-insensitively;
+/// implementations own whatever indexes they need to enforce that and to
+/// serve the lookups below.
+#[async_trait]
+pub trait UserStore: Send + Sync + 'static {
+    /// Store a new user; fails with `ServiceError::Conflict` if the username
+    /// or email is already taken in the user's tenant
+    async fn insert_user(&self, user: User) -> Result<()>;
+
+    async fn find_by_id(&self, id: Uuid) -> Result<Option<User>>;
+
+    async fn find_by_username(&self, tenant_id: &str, username: &str) -> Result<Option<User>>;
+
+    async fn find_by_email(&self, tenant_id: &str, email: &str) -> Result<Option<User>>;
+
+    /// Replace a stored user; fails with `ServiceError::NotFound` if it doesn't exist
+    async fn update_user(&self, user: User) -> Result<()>;
+
+    /// Every user in `tenant_id`, in no particular order
+    async fn list_users(&self, tenant_id: &str) -> Result<Vec<User>>;
+
+    /// Remove a user, returning it if it existed; their TOTP secret goes with them
+    async fn remove_user(&self, id: Uuid) -> Result<Option<User>>;
+
+    /// Set or clear a user's TOTP secret; the secret arrives already encrypted
+    async fn set_totp(&self, user_id: Uuid, totp: Option<StoredTotp>) -> Result<()>;
+
+    async fn find_totp(&self, user_id: Uuid) -> Result<Option<StoredTotp>>;
+}
+
+// Index keys are case-insensitive so `Alice` and `alice` collide
+fn username_key(tenant_id: &str, username: &str) -> (String, String) {
+    (tenant_id.to_string(), username.trim().to_lowercase())
+}
+
+fn email_key(tenant_id: &str, email: &str) -> (String, String) {
+    (tenant_id.to_string(), normalize_email(email))
+}
+
+#[derive(Debug, Default)]
+struct Tables {
+    users: HashMap<Uuid, User>,
+    username_index: HashMap<(String, String), Uuid>,
+    email_index: HashMap<(String, String), Uuid>,
+    totp: HashMap<Uuid, StoredTotp>,
+}
+
+/// Process-local store; everything is lost on restart
+#[derive(Debug, Default)]
+pub struct InMemoryUserStore {
+    tables: RwLock<Tables>,
+}
+
+impl InMemoryUserStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl UserStore for InMemoryUserStore {
+    async fn insert_user(&self, user: User) -> Result<()> {
+        let mut tables = self.tables.write().await;
+        let username = username_key(&user.tenant_id, &user.username);
+        let email = email_key(&user.tenant_id, &user.email);
+
+        if tables.username_index.contains_key(&username) {
+            return Err(ServiceError::conflict("Username already exists").into());
+        }
+        if tables.email_index.contains_key(&email) {
+            return Err(ServiceError::conflict("Email already exists").into());
+        }
+
+        tables.username_index.insert(username, user.id);
+        tables.email_index.insert(email, user.id);
+        tables.users.insert(user.id, user);
+        Ok(())
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> Result<Option<User>> {
+        Ok(self.tables.read().await.users.get(&id).cloned())
+    }
+
+    async fn find_by_username(&self, tenant_id: &str, username: &str) -> Result<Option<User>> {
+        let tables = self.tables.read().await;
+        Ok(tables
+            .username_index
+            .get(&username_key(tenant_id, username))
+            .and_then(|id| tables.users.get(id))
+            .cloned())
+    }
+
+    async fn find_by_email(&self, tenant_id: &str, email: &str) -> Result<Option<User>> {
+        let tables = self.tables.read().await;
+        Ok(tables
+            .email_index
+            .get(&email_key(tenant_id, email))
+            .and_then(|id| tables.users.get(id))
+            .cloned())
+    }
+
+    async fn update_user(&self, user: User) -> Result<()> {
+        let mut tables = self.tables.write().await;
+        let old = tables
+            .users
+            .get(&user.id)
+            .cloned()
+            .ok_or_else(|| ServiceError::not_found("User not found"))?;
+
+        let username = username_key(&user.tenant_id, &user.username);
+        let email = email_key(&user.tenant_id, &user.email);
+        if tables.username_index.get(&username).map_or(false, |id| *id != user.id) {
+            return Err(ServiceError::conflict("Username already exists").into());
+        }
+        if tables.email_index.get(&email).map_or(false, |id| *id != user.id) {
+            return Err(ServiceError::conflict("Email already exists").into());
+        }
+
+        tables.username_index.remove(&username_key(&old.tenant_id, &old.username));
+        tables.email_index.remove(&email_key(&old.tenant_id, &old.email));
+        tables.username_index.insert(username, user.id);
+        tables.email_index.insert(email, user.id);
+        tables.users.insert(user.id, user);
+        Ok(())
+    }
+
+    async fn list_users(&self, tenant_id: &str) -> Result<Vec<User>> {
+        let tables = self.tables.read().await;
+        Ok(tables
+            .users
+            .values()
+            .filter(|user| user.tenant_id == tenant_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn remove_user(&self, id: Uuid) -> Result<Option<User>> {
+        let mut tables = self.tables.write().await;
+        let user = match tables.users.remove(&id) {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        tables.username_index.remove(&username_key(&user.tenant_id, &user.username));
+        tables.email_index.remove(&email_key(&user.tenant_id, &user.email));
+        tables.totp.remove(&id);
+        Ok(Some(user))
+    }
+
+    async fn set_totp(&self, user_id: Uuid, totp: Option<StoredTotp>) -> Result<()> {
+        let mut tables = self.tables.write().await;
+        if !tables.users.contains_key(&user_id) {
+            return Err(ServiceError::not_found("User not found").into());
+        }
+        match totp {
+            Some(totp) => tables.totp.insert(user_id, totp),
+            None => tables.totp.remove(&user_id),
+        };
+        Ok(())
+    }
+
+    async fn find_totp(&self, user_id: Uuid) -> Result<Option<StoredTotp>> {
+        Ok(self.tables.read().await.totp.get(&user_id).cloned())
+    }
+}
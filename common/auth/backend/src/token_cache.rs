@@ -0,0 +1,68 @@
+use crate::Claims;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// How long decoded claims are reused before the token is decoded again
+pub const DEFAULT_TOKEN_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Most tokens remembered at once
+const MAX_CACHED_TOKENS: usize = 10_000;
+
+/// Short-lived memo of decoded token claims, keyed by the raw token.
+///
+/// Entries never outlive the token's own expiry, and anything that revokes a
+/// token or a user's sessions must evict it here as well.
+#[derive(Debug)]
+pub struct TokenCache {
+    ttl: Duration,
+    entries: RwLock<HashMap<String, (Claims, Instant)>>,
+}
+
+impl TokenCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Cached claims for `token`, if still fresh and unexpired
+    pub async fn get(&self, token: &str) -> Option<Claims> {
+        let entries = self.entries.read().await;
+        let (claims, cached_at) = entries.get(token)?;
+        if cached_at.elapsed() >= self.ttl || claims.exp <= Utc::now().timestamp() {
+            return None;
+        }
+        Some(claims.clone())
+    }
+
+    pub async fn insert(&self, token: &str, claims: Claims) {
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        let mut entries = self.entries.write().await;
+        if entries.len() >= MAX_CACHED_TOKENS {
+            let now = Utc::now().timestamp();
+            let ttl = self.ttl;
+            entries.retain(|_, (claims, cached_at)| cached_at.elapsed() < ttl && claims.exp > now);
+            if entries.len() >= MAX_CACHED_TOKENS {
+                entries.clear();
+            }
+        }
+        entries.insert(token.to_string(), (claims, Instant::now()));
+    }
+
+    /// Forget a single token
+    pub async fn invalidate(&self, token: &str) {
+        self.entries.write().await.remove(token);
+    }
+
+    /// Forget every token issued to `user_id`
+    pub async fn invalidate_user(&self, user_id: Uuid) {
+        self.entries.write().await.retain(|_, (claims, _)| claims.sub != user_id);
+    }
+}
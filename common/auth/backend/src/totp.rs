@@ -0,0 +1,101 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+/// Issuer shown next to the account in authenticator apps
+pub const DEFAULT_TOTP_ISSUER: &str = "Kagi";
+
+const TOTP_DIGITS: usize = 6;
+
+/// Codes from one step either side of the current one are accepted to allow for clock drift
+const TOTP_SKEW: u8 = 1;
+
+const TOTP_STEP_SECS: u64 = 30;
+
+const NONCE_LEN: usize = 12;
+
+/// A user's TOTP secret as the store keeps it: encrypted, and only enforced
+/// on login once the user has confirmed a code
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredTotp {
+    /// Nonce followed by the AES-256-GCM ciphertext of the raw secret
+    pub encrypted_secret: Vec<u8>,
+    pub enabled: bool,
+}
+
+/// Returned by `enable_totp` for the user to add to an authenticator app
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpSetup {
+    /// Base32 secret for manual entry
+    pub secret: String,
+    /// `otpauth://` URI, usually shown as a QR code
+    pub uri: String,
+}
+
+/// Encrypts TOTP secrets before they reach the store
+#[derive(Clone)]
+pub struct TotpCipher {
+    cipher: Aes256Gcm,
+}
+
+impl std::fmt::Debug for TotpCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TotpCipher").finish_non_exhaustive()
+    }
+}
+
+impl TotpCipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+        }
+    }
+
+    pub fn encrypt(&self, secret: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher
+            .encrypt(&nonce, secret)
+            .map_err(|_| anyhow!("Failed to encrypt TOTP secret"))?;
+        let mut encrypted = nonce.to_vec();
+        encrypted.extend_from_slice(&ciphertext);
+        Ok(encrypted)
+    }
+
+    pub fn decrypt(&self, encrypted: &[u8]) -> Result<Vec<u8>> {
+        if encrypted.len() < NONCE_LEN {
+            return Err(anyhow!("Encrypted TOTP secret is truncated"));
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Failed to decrypt TOTP secret"))
+    }
+}
+
+/// A fresh random secret
+pub fn generate_secret() -> Result<Vec<u8>> {
+    Secret::generate_secret()
+        .to_bytes()
+        .map_err(|e| anyhow!("Failed to generate TOTP secret: {:?}", e))
+}
+
+/// The TOTP generator for `secret`, labelled with the user's account name
+pub fn totp(secret: Vec<u8>, issuer: &str, account_name: &str) -> Result<TOTP> {
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW,
+        TOTP_STEP_SECS,
+        secret,
+        Some(issuer.to_string()),
+        account_name.to_string(),
+    )
+    .map_err(|e| anyhow!("Invalid TOTP parameters: {}", e))
+}
+
+/// Check `code` against the current time step, allowing `TOTP_SKEW` steps of drift
+pub fn verify_code(totp: &TOTP, code: &str) -> Result<bool> {
+    Ok(totp.check_current(code.trim())?)
+}
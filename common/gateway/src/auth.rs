@@ -0,0 +1,231 @@
+use crate::routing::MatchedRoute;
+use crate::scopes::json_error;
+use crate::{read_recover, Gateway, Middleware, Next, ROUTES};
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::{header, Body, Request, Response, StatusCode};
+use kagi_shared::ServiceError;
+use once_cell::sync::OnceCell;
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
+
+/// Name in `GatewayConfig::middleware` that turns on authentication
+pub const AUTH_MIDDLEWARE: &str = "auth";
+
+/// Name in `RouteInfo::middleware` that exempts a route from authentication
+pub const PUBLIC_ROUTE: &str = "public";
+
+/// Resolves a bearer token to the user it was issued to
+#[async_trait]
+pub trait TokenValidator: Send + Sync {
+    async fn validate_token(&self, tenant_id: &str, token: &str) -> Result<serde_json::Value>;
+}
+
+/// Any `async fn(tenant_id, token) -> Result<Value>` closure is a validator
+#[async_trait]
+impl<F, Fut> TokenValidator for F
+where
+    F: Fn(String, String) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<serde_json::Value>> + Send,
+{
+    async fn validate_token(&self, tenant_id: &str, token: &str) -> Result<serde_json::Value> {
+        self(tenant_id.to_string(), token.to_string()).await
+    }
+}
+
+/// Validates tokens by calling the auth service's `validate_token` action through the gateway
+pub struct ServiceTokenValidator {
+    gateway: Arc<dyn Gateway + Send + Sync>,
+}
+
+impl ServiceTokenValidator {
+    pub fn new(gateway: Arc<dyn Gateway + Send + Sync>) -> Self {
+        Self { gateway }
+    }
+}
+
+#[async_trait]
+impl TokenValidator for ServiceTokenValidator {
+    async fn validate_token(&self, tenant_id: &str, token: &str) -> Result<serde_json::Value> {
+        let params = serde_json::json!({ "tenant_id": tenant_id, "token": token });
+        self.gateway.dispatch("auth", "validate_token", params).await
+    }
+}
+
+/// The caller resolved by `AuthMiddleware`
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub token: String,
+    /// The user as returned by the token validator
+    pub user: serde_json::Value,
+}
+
+/// Request extension `AuthMiddleware` fills in for downstream handlers.
+///
+/// Middleware only sees `&Request`, so the gateway inserts an empty slot
+/// before running the chain and authentication sets it once.
+#[derive(Debug, Clone, Default)]
+pub struct Authentication(Arc<OnceCell<AuthenticatedUser>>);
+
+impl Authentication {
+    /// The authenticated caller, if the request went through `AuthMiddleware`
+    pub fn user(&self) -> Option<&AuthenticatedUser> {
+        self.0.get()
+    }
+}
+
+/// Token from an `Authorization: Bearer` header, if there is a non-empty one
+pub(crate) fn bearer_token(headers: &hyper::HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+/// Require a valid bearer token on every route not marked `public`.
+///
+/// Missing or rejected tokens get `401` with a JSON error body.
+pub struct AuthMiddleware {
+    validator: Arc<dyn TokenValidator>,
+    /// (METHOD, path pattern) of routes that opted out
+    public_routes: HashSet<(String, String)>,
+}
+
+impl AuthMiddleware {
+    pub fn new(validator: Arc<dyn TokenValidator>) -> Self {
+        let public_routes = read_recover(&ROUTES)
+            .iter()
+            .filter(|route| route.middleware.iter().flatten().any(|name| *name == PUBLIC_ROUTE))
+            .map(|route| (route.method.to_uppercase(), route.path.to_string()))
+            .collect();
+        
+        Self { validator, public_routes }
+    }
+}
+
+#[async_trait]
+impl Middleware for AuthMiddleware {
+    async fn process(&self, req: &Request<Body>, next: Next<'_>) -> Result<Response<Body>> {
+        let is_public = req.extensions()
+            .get::<MatchedRoute>()
+            .map_or(false, |route| self.public_routes.contains(&(route.method.clone(), route.pattern.clone())));
+        if is_public {
+            return next.run(req).await;
+        }
+        
+        let token = match bearer_token(req.headers()) {
+            Some(token) => token,
+            None => return json_error(StatusCode::UNAUTHORIZED, "Missing bearer token"),
+        };
+        
+        let tenant_id = crate::resolve_tenant(req);
+        let user = match self.validator.validate_token(&tenant_id, token).await {
+            Ok(user) => user,
+            Err(e) => match ServiceError::from_anyhow(&e) {
+                // Only the validator failing to run is a server error
+                ServiceError::Internal(_) | ServiceError::Unavailable(_) => return Err(e),
+                rejected => return json_error(StatusCode::UNAUTHORIZED, rejected.message()),
+            },
+        };
+        
+        if let Some(slot) = req.extensions().get::<Authentication>() {
+            let _ = slot.0.set(AuthenticatedUser { token: token.to_string(), user });
+        }
+        
+        next.run(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{register_route, HandlerFn, RouteInfo};
+    
+    /// Accepts only `good-token`; a token of `broken` makes the validator itself fail
+    fn validator() -> Arc<dyn TokenValidator> {
+        Arc::new(|_tenant_id: String, token: String| async move {
+            match token.as_str() {
+                "good-token" => Ok(serde_json::json!({ "username": "alice" })),
+                "broken" => Err(anyhow::Error::from(ServiceError::unavailable("Auth service is down"))),
+                _ => Err(anyhow::Error::from(ServiceError::unauthorized("Invalid token"))),
+            }
+        })
+    }
+    
+    /// Echoes the authenticated username, or `anonymous`
+    fn whoami_handler() -> Box<HandlerFn> {
+        Box::new(|req: &Request<Body>| {
+            let username = req.extensions()
+                .get::<Authentication>()
+                .and_then(Authentication::user)
+                .map_or("anonymous".to_string(), |auth| auth.user["username"].as_str().unwrap().to_string());
+            Box::pin(async move { Ok(Response::new(Body::from(username))) })
+        })
+    }
+    
+    fn request(path: &str, token: Option<&str>) -> Request<Body> {
+        let mut req = Request::get(path).body(Body::empty()).unwrap();
+        if let Some(token) = token {
+            req.headers_mut().insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+        }
+        req.extensions_mut().insert(Authentication::default());
+        req.extensions_mut().insert(MatchedRoute { method: "GET".to_string(), pattern: path.to_string() });
+        req
+    }
+    
+    async fn run(middleware: &AuthMiddleware, req: Request<Body>) -> (StatusCode, String) {
+        let handler = whoami_handler();
+        let response = middleware.process(&req, Next::new(&[], handler.as_ref())).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+    
+    #[tokio::test]
+    async fn valid_token_reaches_the_handler_with_the_user_attached() {
+        let middleware = AuthMiddleware::new(validator());
+        
+        let (status, body) = run(&middleware, request("/auth-tests/me", Some("good-token"))).await;
+        
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "alice");
+    }
+    
+    #[tokio::test]
+    async fn missing_or_rejected_tokens_get_a_json_401() {
+        let middleware = AuthMiddleware::new(validator());
+        
+        for token in [None, Some("forged-token")] {
+            let (status, body) = run(&middleware, request("/auth-tests/me", token)).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{:?}", token);
+            let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert!(error["error"].is_string(), "{}", body);
+        }
+        
+        // A validator that can't run is a server error, not a rejection
+        let req = request("/auth-tests/me", Some("broken"));
+        let handler = whoami_handler();
+        assert!(middleware.process(&req, Next::new(&[], handler.as_ref())).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn public_routes_skip_authentication() {
+        register_route(RouteInfo {
+            method: "GET",
+            path: "/auth-tests/health",
+            handler_name: "authtest.health",
+            middleware: Some(vec![PUBLIC_ROUTE.to_string()]),
+        });
+        let middleware = AuthMiddleware::new(validator());
+        
+        let (status, body) = run(&middleware, request("/auth-tests/health", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "anonymous");
+        
+        let (status, _) = run(&middleware, request("/auth-tests/me", None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::auth::Authentication;
+use crate::forwarding::{self, RequestBody, RouteTarget};
+use crate::routing::MatchedRoute;
+use crate::{logging, read_recover, GatewaySettings, GatewayState, HandlerFn, Next, METHOD_OVERRIDE_HEADER, ROUTES};
+use futures::future::join_all;
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use hyper::http::request::Parts;
+use hyper::{Body, Request, Response, StatusCode};
+use kagi_shared::{BulkResult, ServiceError};
+use serde::Deserialize;
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Path of the batch endpoint
+pub const BATCH_PATH: &str = "/batch";
+
+/// Headers describing the carrying request's own body or conditions, which calls don't inherit
+const NOT_INHERITED: [HeaderName; 6] = [
+    header::CONTENT_LENGTH,
+    header::CONTENT_TYPE,
+    header::TRANSFER_ENCODING,
+    header::ACCEPT_ENCODING,
+    header::IF_MATCH,
+    header::IF_NONE_MATCH,
+];
+
+/// A single call within a batch request
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchCall {
+    pub service: String,
+    pub action: String,
+    #[serde(default)]
+    pub params: Option<Value>,
+}
+
+/// What each call inherits from the request that carried it: the credentials,
+/// host (and so the tenant) and client address
+#[derive(Debug, Clone)]
+pub(crate) struct Caller {
+    headers: HeaderMap,
+    remote_addr: Option<SocketAddr>,
+}
+
+impl Caller {
+    pub(crate) fn from_parts(parts: &Parts) -> Self {
+        let mut headers = HeaderMap::new();
+        for (name, value) in parts.headers.iter() {
+            if !NOT_INHERITED.contains(name) && *name != METHOD_OVERRIDE_HEADER {
+                headers.append(name, value.clone());
+            }
+        }
+        
+        Self {
+            headers,
+            remote_addr: parts.extensions.get::<SocketAddr>().copied(),
+        }
+    }
+    
+    /// The request `params` would have been as a direct call to the route `method pattern`
+    fn request(&self, method: &str, pattern: &str, params: Option<Value>) -> Result<Request<Body>, ServiceError> {
+        let body = Bytes::from(params.unwrap_or_else(|| serde_json::json!({})).to_string());
+        let mut req = Request::builder()
+            .method(method)
+            .uri(pattern)
+            .body(Body::from(body.clone()))
+            .map_err(|e| ServiceError::internal(format!("Invalid route {} {}: {}", method, pattern, e)))?;
+        
+        *req.headers_mut() = self.headers.clone();
+        req.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        if let Some(remote_addr) = self.remote_addr {
+            req.extensions_mut().insert(remote_addr);
+        }
+        req.extensions_mut().insert(RequestBody(body));
+        req.extensions_mut().insert(MatchedRoute {
+            method: method.to_string(),
+            pattern: pattern.to_string(),
+        });
+        req.extensions_mut().insert(Authentication::default());
+        let request_id = logging::RequestId::for_request(&req);
+        req.extensions_mut().insert(request_id);
+        Ok(req)
+    }
+}
+
+/// Method and path pattern of the route exposing `service.action`
+fn exposing_route(call: &BatchCall) -> Option<(String, String)> {
+    let handler_name = format!("{}.{}", call.service, call.action);
+    read_recover(&ROUTES)
+        .iter()
+        .find(|route| route.handler_name == handler_name)
+        .map(|route| (route.method.to_uppercase(), route.path.to_string()))
+}
+
+/// The structured error matching a middleware or handler's error response
+pub(crate) fn error_for_status(status: StatusCode, message: String) -> ServiceError {
+    match status {
+        StatusCode::BAD_REQUEST | StatusCode::PAYLOAD_TOO_LARGE => ServiceError::Validation(message),
+        StatusCode::UNAUTHORIZED => ServiceError::Unauthorized(message),
+        StatusCode::FORBIDDEN => ServiceError::Forbidden(message),
+        StatusCode::NOT_FOUND => ServiceError::NotFound(message),
+        StatusCode::CONFLICT => ServiceError::Conflict(message),
+        StatusCode::PRECONDITION_FAILED => ServiceError::PreconditionFailed(message),
+        StatusCode::GONE => ServiceError::Gone(message),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT => {
+            ServiceError::Unavailable(message)
+        },
+        _ => ServiceError::Internal(message),
+    }
+}
+
+/// Turn the response to one call back into its result
+async fn call_result(response: Response<Body>) -> Result<Value, ServiceError> {
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| ServiceError::internal(format!("Failed to read response: {}", e)))?;
+    if status.is_success() {
+        return Ok(serde_json::from_slice(&body).unwrap_or(Value::Null));
+    }
+    
+    let message = serde_json::from_slice::<Value>(&body)
+        .ok()
+        .and_then(|body| body.get("error").and_then(Value::as_str).map(str::to_string))
+        .unwrap_or_else(|| status.canonical_reason().unwrap_or("Call failed").to_string());
+    Err(error_for_status(status, message))
+}
+
+/// Run one call through the middleware chain of the route exposing its action.
+///
+/// The call is authenticated, scope-checked, rate limited and replay-checked
+/// exactly as a direct request to that route would be. Actions no route exposes
+/// can't be called this way.
+pub(crate) async fn execute_call(
+    state: &GatewayState,
+    settings: &GatewaySettings,
+    caller: &Caller,
+    call: BatchCall,
+) -> Result<Value, ServiceError> {
+    let gateway = state.gateway.clone()
+        .ok_or_else(|| ServiceError::internal("No gateway available to dispatch calls"))?;
+    let (method, pattern) = exposing_route(&call)
+        .ok_or_else(|| ServiceError::not_found(format!("No route exposes {}.{}", call.service, call.action)))?;
+    let req = caller.request(&method, &pattern, call.params)?;
+    
+    let target = RouteTarget {
+        service: call.service,
+        action: call.action,
+    };
+    let metrics = state.metrics.clone();
+    let retry = settings.config.retry.clone().map(Arc::new);
+    let handler: Box<HandlerFn> = Box::new(move |req: &Request<Body>| {
+        forwarding::forward(gateway.clone(), metrics.clone(), retry.clone(), target.clone(), req)
+    });
+    
+    let route_middlewares = settings.route_middlewares
+        .get(&(method, pattern))
+        .map(Vec::as_slice)
+        .unwrap_or(&[]);
+    match Next::with_route(&settings.middlewares, route_middlewares, handler.as_ref()).run(&req).await {
+        Ok(response) => call_result(response).await,
+        Err(e) => Err(ServiceError::from_anyhow(&e)),
+    }
+}
+
+/// Run every call concurrently, reporting outcomes in input order
+pub(crate) async fn execute_batch(
+    state: &GatewayState,
+    settings: &GatewaySettings,
+    caller: &Caller,
+    calls: Vec<Value>,
+) -> BulkResult<Value> {
+    join_all(calls.into_iter().map(|call| async move {
+        let call: BatchCall = serde_json::from_value(call)
+            .map_err(|e| ServiceError::validation(format!("Invalid batch call: {}", e)))?;
+        execute_call(state, settings, caller, call).await
+    }))
+    .await
+    .into_iter()
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metrics;
+    use crate::{register_route, route_http_request, Gateway, GatewayConfig, RouteInfo};
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use serde_json::json;
+    
+    /// Echoes `echo` calls and refuses everything else
+    struct StubGateway;
+    
+    #[async_trait]
+    impl Gateway for StubGateway {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn dispatch(&self, service: &str, action: &str, params: Value) -> Result<Value> {
+            match action {
+                "echo" => Ok(params),
+                _ => Err(ServiceError::conflict(format!("{}.{} refused", service, action)).into()),
+            }
+        }
+    }
+    
+    fn register_batch_routes() {
+        for (path, handler_name) in [("/batch-tests/echo", "batchtest.echo"), ("/batch-tests/refuse", "batchtest.refuse")] {
+            register_route(RouteInfo {
+                method: "POST",
+                path,
+                handler_name,
+                middleware: None,
+            });
+        }
+    }
+    
+    async fn post_batch(config: &GatewayConfig, calls: Value) -> (StatusCode, Value) {
+        register_batch_routes();
+        let state = Arc::new(GatewayState::new(Arc::new(StubGateway), config, Arc::new(Metrics::new())).unwrap());
+        let req = Request::post(BATCH_PATH)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(calls.to_string()))
+            .unwrap();
+        
+        let response = route_http_request(req, state).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+    
+    #[tokio::test]
+    async fn mixed_batch_reports_each_outcome_at_its_input_index() {
+        let calls = json!([
+            { "service": "batchtest", "action": "echo", "params": { "n": 1 } },
+            { "service": "batchtest", "action": "refuse" },
+            { "service": "batchtest" },
+            { "service": "batchtest", "action": "unrouted" },
+            { "service": "batchtest", "action": "echo", "params": { "n": 5 } },
+        ]);
+        let (status, body) = post_batch(&GatewayConfig::default(), calls).await;
+        
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["succeeded"], 2);
+        assert_eq!(body["failed"], 3);
+        let items = body["items"].as_array().unwrap();
+        assert_eq!(items.len(), 5);
+        for (position, item) in items.iter().enumerate() {
+            assert_eq!(item["index"], position);
+        }
+        
+        assert_eq!(items[0]["status"], "ok");
+        assert_eq!(items[0]["value"]["n"], 1);
+        assert_eq!(items[0]["value"]["tenant_id"], crate::DEFAULT_TENANT);
+        assert_eq!(items[1]["status"], "error");
+        assert_eq!(items[1]["error"]["kind"], "Conflict");
+        assert_eq!(items[2]["error"]["kind"], "Validation");
+        assert_eq!(items[3]["error"]["kind"], "NotFound");
+        assert_eq!(items[4]["status"], "ok");
+        assert_eq!(items[4]["value"]["n"], 5);
+    }
+    
+    #[tokio::test]
+    async fn batch_over_the_configured_size_is_rejected() {
+        let config = GatewayConfig::builder().max_batch_size(2).build();
+        let call = json!({ "service": "batchtest", "action": "echo" });
+        
+        let (status, body) = post_batch(&config, json!([call, call, call])).await;
+        
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("at most 2"));
+    }
+    
+    #[tokio::test]
+    async fn calls_go_through_the_middleware_chain() {
+        // Without a bearer token the auth middleware rejects every call
+        let config = GatewayConfig::builder().middleware(crate::auth::AUTH_MIDDLEWARE).build();
+        let calls = json!([{ "service": "batchtest", "action": "echo" }]);
+        
+        let (status, body) = post_batch(&config, calls).await;
+        
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["items"][0]["error"]["kind"], "Unauthorized");
+    }
+}
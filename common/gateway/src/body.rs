@@ -0,0 +1,89 @@
+use hyper::body::{Bytes, HttpBody};
+use hyper::{header, Body, HeaderMap, StatusCode};
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// Why a request body was rejected
+#[derive(Debug)]
+pub enum BodyError {
+    /// Larger than the configured limit, in bytes
+    TooLarge(usize),
+    Unreadable(hyper::Error),
+    InvalidJson(serde_json::Error),
+    NotAnObject,
+    /// One part of a multipart form is larger than the per-part limit, in bytes
+    PartTooLarge(usize),
+    InvalidMultipart(String),
+}
+
+impl BodyError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            BodyError::TooLarge(_) | BodyError::PartTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::TooLarge(limit) => write!(f, "Request body exceeds the {} byte limit", limit),
+            BodyError::Unreadable(e) => write!(f, "Failed to read body: {}", e),
+            BodyError::InvalidJson(e) => write!(f, "Invalid JSON body at line {} column {}: {}", e.line(), e.column(), e),
+            BodyError::NotAnObject => write!(f, "Request body must be a JSON object"),
+            BodyError::PartTooLarge(limit) => write!(f, "Form part exceeds the {} byte limit", limit),
+            BodyError::InvalidMultipart(e) => write!(f, "Invalid multipart body: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+/// Buffer a request body, giving up as soon as it grows past `limit` bytes
+pub async fn read_limited(headers: &HeaderMap, mut body: Body, limit: usize) -> Result<Bytes, BodyError> {
+    // Refuse up front when the client announces an oversized body
+    let announced = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if announced.map_or(false, |length| length > limit as u64) {
+        return Err(BodyError::TooLarge(limit));
+    }
+    
+    let mut buffer = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(BodyError::Unreadable)?;
+        if buffer.len() + chunk.len() > limit {
+            return Err(BodyError::TooLarge(limit));
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+    
+    Ok(Bytes::from(buffer))
+}
+
+/// Whether the request declares a JSON body (`application/json` or a `+json` type)
+pub fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|mime| {
+            let mime = mime.trim().to_ascii_lowercase();
+            mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+        })
+        .unwrap_or(false)
+}
+
+/// Fields of a JSON object body; `None` when the body is empty or not declared as JSON
+pub fn json_fields(headers: &HeaderMap, body: &[u8]) -> Result<Option<Map<String, Value>>, BodyError> {
+    if body.is_empty() || !is_json(headers) {
+        return Ok(None);
+    }
+    
+    match serde_json::from_slice(body).map_err(BodyError::InvalidJson)? {
+        Value::Object(fields) => Ok(Some(fields)),
+        _ => Err(BodyError::NotAnObject),
+    }
+}
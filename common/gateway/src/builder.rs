@@ -0,0 +1,325 @@
+use crate::cache::CacheConfig;
+use crate::circuit_breaker::CircuitBreakerConfig;
+use crate::compression::CompressionConfig;
+use crate::connections::ConnectionLimitConfig;
+use crate::nonce::NonceConfig;
+use crate::retry::RetryConfig;
+use crate::static_files::StaticFiles;
+use crate::{AuthConfig, CorsConfig, ErrorBodyTemplate, GatewayConfig, RateLimitConfig, SslConfig};
+use hyper::StatusCode;
+use kagi_shared::EventBus;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Fluent builder for `GatewayConfig`.
+///
+/// Anything not set keeps the value from `GatewayConfig::default()`:
+///
+/// ```ignore
+/// let config = GatewayConfig::builder()
+///     .host("0.0.0.0")
+///     .port(8443)
+///     .allow_origin("https://app.example.com")
+///     .enable_tls("cert.pem", "key.pem")
+///     .build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct GatewayConfigBuilder {
+    config: GatewayConfig,
+}
+
+impl GatewayConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Address to bind to
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.config.host = host.into();
+        self
+    }
+    
+    /// Port to listen on
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+    
+    /// Also listen on `addr` (`host:port`), serving the same routes
+    pub fn listen_on(mut self, addr: impl Into<String>) -> Self {
+        self.config.additional_listeners.push(addr.into());
+        self
+    }
+    
+    /// Add a backend service the gateway depends on
+    pub fn service(mut self, name: impl Into<String>) -> Self {
+        self.config.services.push(name.into());
+        self
+    }
+    
+    /// Replace the CORS configuration
+    pub fn with_cors(mut self, cors: CorsConfig) -> Self {
+        self.config.cors = cors;
+        self
+    }
+    
+    /// Allow an additional CORS origin
+    pub fn allow_origin(mut self, origin: impl Into<String>) -> Self {
+        self.config.cors.allowed_origins.push(origin.into());
+        self
+    }
+    
+    /// Set the default rate limit (requests per second) and burst size
+    pub fn with_rate_limit(mut self, rate: u32, burst: u32) -> Self {
+        self.config.rate_limit.default_rate = rate;
+        self.config.rate_limit.default_burst = burst;
+        self
+    }
+    
+    /// Rate limit requests from `proxy` by the client in its `X-Forwarded-For` header
+    pub fn trust_proxy(mut self, proxy: std::net::IpAddr) -> Self {
+        self.config.rate_limit.trusted_proxies.push(proxy);
+        self
+    }
+    
+    /// Set the JWT secret and token lifetime in seconds
+    pub fn with_auth(mut self, jwt_secret: impl Into<String>, expiration: u32) -> Self {
+        self.config.auth = AuthConfig {
+            jwt_secret: jwt_secret.into(),
+            expiration,
+        };
+        self
+    }
+    
+    /// Verify scoped tokens with `auth`, e.g. `AuthConfig::from_env()`
+    pub fn with_auth_config(mut self, auth: AuthConfig) -> Self {
+        self.config.auth = auth;
+        self
+    }
+    
+    /// Serve HTTPS using the given PEM certificate chain and private key
+    pub fn enable_tls(mut self, cert_file: impl Into<String>, key_file: impl Into<String>) -> Self {
+        self.config.ssl = SslConfig {
+            enabled: true,
+            cert_file: Some(cert_file.into()),
+            key_file: Some(key_file.into()),
+        };
+        self
+    }
+    
+    /// Enable a named middleware
+    pub fn middleware(mut self, name: impl Into<String>) -> Self {
+        self.config.middleware.push(name.into());
+        self
+    }
+    
+    /// File the configuration was (or will be re-)loaded from
+    pub fn config_file(mut self, path: impl Into<String>) -> Self {
+        self.config.config_file = Some(path.into());
+        self
+    }
+    
+    /// Hard ceiling on processing a request
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.config.request_timeout_ms = Some(timeout.as_millis() as u64);
+        self
+    }
+    
+    /// Timeout for each backend probe made by the readiness endpoint
+    pub fn readiness_timeout(mut self, timeout: Duration) -> Self {
+        self.config.readiness_timeout_ms = timeout.as_millis() as u64;
+        self
+    }
+    
+    /// Close WebSocket connections idle for longer than this
+    pub fn websocket_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.config.websocket_idle_timeout_secs = timeout.as_secs();
+        self
+    }
+    
+    /// Use a custom body for error responses with the given status
+    pub fn error_body(mut self, status: StatusCode, template: ErrorBodyTemplate) -> Self {
+        self.config.error_bodies.insert(status.as_u16(), template);
+        self
+    }
+    
+    /// Reject requests to `path` that reuse an `X-Request-Nonce`
+    pub fn protect_from_replay(mut self, path: impl Into<String>) -> Self {
+        self.config
+            .replay_protection
+            .get_or_insert_with(NonceConfig::default)
+            .routes
+            .push(path.into());
+        self
+    }
+    
+    /// Sign forwarded calls with an internal token backend services can verify
+    pub fn internal_auth_secret(mut self, secret: impl Into<String>) -> Self {
+        self.config.internal_auth_secret = Some(secret.into());
+        self
+    }
+    
+    /// Trace this fraction of requests, from 0.0 to 1.0
+    pub fn trace_sample_rate(mut self, rate: f64) -> Self {
+        self.config.trace_sample_rate = rate;
+        self
+    }
+    
+    /// Reject request bodies larger than `bytes`
+    pub fn max_body_size(mut self, bytes: usize) -> Self {
+        self.config.max_body_bytes = bytes;
+        self
+    }
+    
+    /// Reject `multipart/form-data` bodies with a part larger than `bytes`
+    pub fn max_part_size(mut self, bytes: usize) -> Self {
+        self.config.max_part_bytes = bytes;
+        self
+    }
+    
+    /// Reject `POST /batch` requests carrying more than `max_calls` calls
+    pub fn max_batch_size(mut self, max_calls: usize) -> Self {
+        self.config.max_batch_size = max_calls;
+        self
+    }
+    
+    /// Compress responses of at least `min_size` bytes for clients that accept gzip or brotli
+    pub fn compress_responses(mut self, min_size: usize) -> Self {
+        self.config.compression = Some(CompressionConfig { min_size });
+        self
+    }
+    
+    /// Answer repeated GETs from an in-memory cache for `ttl`
+    pub fn cache_responses(mut self, ttl: Duration) -> Self {
+        self.config.response_cache = Some(CacheConfig {
+            ttl_ms: ttl.as_millis() as u64,
+            ..CacheConfig::default()
+        });
+        self
+    }
+    
+    /// Serve at most `max_connections` at once; connections over the cap wait up to
+    /// `queue_timeout` for a slot before getting `503`
+    pub fn max_connections(mut self, max_connections: usize, queue_timeout: Duration) -> Self {
+        self.config.connection_limit = Some(ConnectionLimitConfig {
+            max_connections,
+            queue_timeout_ms: queue_timeout.as_millis() as u64,
+        });
+        self
+    }
+    
+    /// Serve the files under `root` for GET requests below `mount` that no route matches
+    pub fn static_files(mut self, mount: &str, root: impl Into<PathBuf>) -> Self {
+        self.config.static_files.push(StaticFiles {
+            mount: mount.to_string(),
+            root: root.into(),
+        });
+        self
+    }
+    
+    /// Time in-flight requests get to finish after shutdown
+    pub fn shutdown_grace_period(mut self, grace: Duration) -> Self {
+        self.config.shutdown_grace_period_ms = grace.as_millis() as u64;
+        self
+    }
+    
+    /// Fail calls to a service fast for `cooldown` after `failure_threshold` consecutive failures
+    pub fn circuit_breaker(mut self, failure_threshold: u32, cooldown: Duration) -> Self {
+        self.config.circuit_breaker = Some(CircuitBreakerConfig {
+            failure_threshold,
+            cooldown_ms: cooldown.as_millis() as u64,
+        });
+        self
+    }
+    
+    /// Retry forwarded GET, PUT and DELETE requests up to `max_attempts` times in total,
+    /// backing off exponentially from `initial_backoff` up to `max_backoff`
+    pub fn retry(mut self, max_attempts: u32, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        self.config.retry = Some(RetryConfig {
+            max_attempts,
+            initial_backoff_ms: initial_backoff.as_millis() as u64,
+            max_backoff_ms: max_backoff.as_millis() as u64,
+        });
+        self
+    }
+    
+    /// Push events published on `topics` of `bus` to WebSocket clients subscribed to them
+    pub fn push_events(mut self, bus: EventBus, topics: &[&str]) -> Self {
+        self.config.event_bus = Some(bus);
+        self.config.event_topics = topics.iter().map(|topic| topic.to_string()).collect();
+        self
+    }
+    
+    pub fn build(self) -> GatewayConfig {
+        self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RateLimitConfig;
+    
+    // `GatewayConfig` holds an `EventBus` and so can't be compared directly;
+    // its serialized form covers every other field
+    fn same(a: &GatewayConfig, b: &GatewayConfig) -> bool {
+        serde_json::to_value(a).unwrap() == serde_json::to_value(b).unwrap()
+    }
+    
+    #[test]
+    fn builder_matches_a_hand_built_config() {
+        let built = GatewayConfig::builder()
+            .host("0.0.0.0")
+            .port(8443)
+            .allow_origin("https://app.example.com")
+            .with_rate_limit(10, 20)
+            .with_auth("secret", 600)
+            .enable_tls("cert.pem", "key.pem")
+            .middleware("auth")
+            .request_timeout(Duration::from_secs(5))
+            .build();
+        
+        let mut cors = CorsConfig::default();
+        cors.allowed_origins.push("https://app.example.com".to_string());
+        let by_hand = GatewayConfig {
+            host: "0.0.0.0".to_string(),
+            port: 8443,
+            cors,
+            rate_limit: RateLimitConfig {
+                default_rate: 10,
+                default_burst: 20,
+                trusted_proxies: Vec::new(),
+            },
+            auth: AuthConfig {
+                jwt_secret: "secret".to_string(),
+                expiration: 600,
+            },
+            ssl: SslConfig {
+                enabled: true,
+                cert_file: Some("cert.pem".to_string()),
+                key_file: Some("key.pem".to_string()),
+            },
+            middleware: vec!["auth".to_string()],
+            request_timeout_ms: Some(5000),
+            ..GatewayConfig::default()
+        };
+        
+        assert!(same(&built, &by_hand), "{:?}\n!=\n{:?}", built, by_hand);
+    }
+    
+    #[test]
+    fn omitted_parts_keep_their_defaults() {
+        let built = GatewayConfig::builder().port(9000).build();
+        let defaults = GatewayConfig::default();
+        
+        assert_eq!(built.port, 9000);
+        assert_eq!(built.host, defaults.host);
+        assert!(!built.ssl.enabled);
+        assert_eq!(built.rate_limit.default_rate, defaults.rate_limit.default_rate);
+        assert_eq!(built.cors.allowed_methods, defaults.cors.allowed_methods);
+        assert_eq!(built.max_body_bytes, defaults.max_body_bytes);
+        assert!(same(&built, &GatewayConfig { port: 9000, ..defaults }));
+        assert!(same(&GatewayConfig::builder().build(), &GatewayConfig::default()));
+        built.validate().unwrap();
+    }
+}
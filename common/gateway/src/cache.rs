@@ -0,0 +1,307 @@
+use crate::{conditional, resolve_tenant, Middleware, Next};
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Header telling whether a GET was answered from the cache (`HIT`) or not (`MISS`)
+pub const CACHE_HEADER: &str = "x-cache";
+
+/// Response caching settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheConfig {
+    /// Milliseconds a cached response is served before the handler runs again
+    #[serde(default = "default_ttl_ms")]
+    pub ttl_ms: u64,
+    /// Responses kept at most; the least recently used is evicted first
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+    /// Responses with larger bodies than this many bytes are never cached
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+}
+
+fn default_ttl_ms() -> u64 {
+    5_000
+}
+
+fn default_max_entries() -> usize {
+    1024
+}
+
+fn default_max_body_bytes() -> usize {
+    256 * 1024
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_ms: default_ttl_ms(),
+            max_entries: default_max_entries(),
+            max_body_bytes: default_max_body_bytes(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CachedResponse {
+    headers: HeaderMap,
+    body: Bytes,
+    expires_at: Instant,
+    /// Position in `ResponseCache::recency`
+    tick: u64,
+}
+
+/// Least-recently-used map of successful responses
+#[derive(Debug, Default)]
+struct ResponseCache {
+    entries: HashMap<String, CachedResponse>,
+    /// Keys ordered from least to most recently used
+    recency: BTreeMap<u64, String>,
+    next_tick: u64,
+}
+
+impl ResponseCache {
+    fn get(&mut self, key: &str, now: Instant) -> Option<CachedResponse> {
+        let tick = self.next_tick;
+        let entry = self.entries.get_mut(key)?;
+        if entry.expires_at <= now {
+            let stale = entry.tick;
+            self.entries.remove(key);
+            self.recency.remove(&stale);
+            return None;
+        }
+        
+        self.recency.remove(&entry.tick);
+        self.recency.insert(tick, key.to_string());
+        entry.tick = tick;
+        self.next_tick += 1;
+        Some(entry.clone())
+    }
+    
+    fn insert(&mut self, key: String, mut entry: CachedResponse, max_entries: usize) {
+        entry.tick = self.next_tick;
+        self.next_tick += 1;
+        self.recency.insert(entry.tick, key.clone());
+        if let Some(replaced) = self.entries.insert(key, entry) {
+            self.recency.remove(&replaced.tick);
+        }
+        
+        while self.entries.len() > max_entries {
+            match self.recency.pop_first() {
+                Some((_, oldest)) => {
+                    self.entries.remove(&oldest);
+                },
+                None => break,
+            }
+        }
+    }
+}
+
+/// Cache key for a GET: method, path and query with its parameters sorted.
+///
+/// The tenant and a hash of the `Authorization` header are part of the key too,
+/// so one caller's response is never served to another.
+pub fn cache_key(req: &Request<Body>) -> String {
+    let mut query: Vec<(String, String)> = req.uri().query()
+        .and_then(|query| serde_urlencoded::from_str(query).ok())
+        .unwrap_or_default();
+    query.sort();
+    let query = serde_urlencoded::to_string(&query).unwrap_or_default();
+    
+    let mut credentials = DefaultHasher::new();
+    req.headers().get(header::AUTHORIZATION).map(HeaderValue::as_bytes).hash(&mut credentials);
+    
+    format!(
+        "{} {}?{} tenant={} auth={:x}",
+        req.method(),
+        req.uri().path(),
+        query,
+        resolve_tenant(req),
+        credentials.finish()
+    )
+}
+
+fn has_no_store(headers: &HeaderMap) -> bool {
+    headers.get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-store"))
+}
+
+/// Middleware answering repeated GETs from an in-memory cache until their TTL passes.
+///
+/// Requests sending `Cache-Control: no-store` bypass the cache entirely. Writes
+/// don't invalidate cached responses, so the TTL bounds how stale they get.
+pub struct CacheMiddleware {
+    ttl: Duration,
+    max_entries: usize,
+    max_body_bytes: usize,
+    cache: Mutex<ResponseCache>,
+}
+
+impl CacheMiddleware {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            ttl: Duration::from_millis(config.ttl_ms),
+            max_entries: config.max_entries.max(1),
+            max_body_bytes: config.max_body_bytes,
+            cache: Mutex::new(ResponseCache::default()),
+        }
+    }
+    
+    /// Number of responses currently cached, including expired ones not yet evicted
+    pub fn len(&self) -> usize {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner()).entries.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    fn hit(req: &Request<Body>, cached: CachedResponse) -> Response<Body> {
+        let etag = cached.headers.get(header::ETAG).and_then(|v| v.to_str().ok());
+        let mut response = match etag {
+            Some(etag) if conditional::if_none_match_matches(req.headers(), etag) => Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header(header::ETAG, etag)
+                .body(Body::empty())
+                .unwrap(),
+            _ => {
+                let mut response = Response::new(Body::from(cached.body));
+                *response.headers_mut() = cached.headers;
+                response
+            },
+        };
+        response.headers_mut().insert(CACHE_HEADER, HeaderValue::from_static("HIT"));
+        response
+    }
+    
+    fn cacheable(&self, response: &Response<Body>) -> bool {
+        response.status() == StatusCode::OK
+            && !response.headers().contains_key(header::SET_COOKIE)
+            && !has_no_store(response.headers())
+            // Streamed bodies have no exact size and are never buffered
+            && matches!(response.body().size_hint().exact(), Some(size) if size as usize <= self.max_body_bytes)
+    }
+}
+
+#[async_trait]
+impl Middleware for CacheMiddleware {
+    async fn process(&self, req: &Request<Body>, next: Next<'_>) -> Result<Response<Body>> {
+        if req.method() != Method::GET || has_no_store(req.headers()) {
+            return next.run(req).await;
+        }
+        
+        let key = cache_key(req);
+        let cached = self.cache.lock().unwrap_or_else(|e| e.into_inner()).get(&key, Instant::now());
+        if let Some(cached) = cached {
+            debug!("Serving {} from the response cache", key);
+            return Ok(Self::hit(req, cached));
+        }
+        
+        let mut response = next.run(req).await?;
+        if self.cacheable(&response) {
+            let (parts, body) = response.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            let entry = CachedResponse {
+                headers: parts.headers.clone(),
+                body: body.clone(),
+                expires_at: Instant::now() + self.ttl,
+                tick: 0,
+            };
+            self.cache.lock().unwrap_or_else(|e| e.into_inner()).insert(key, entry, self.max_entries);
+            response = Response::from_parts(parts, Body::from(body));
+        }
+        
+        response.headers_mut().insert(CACHE_HEADER, HeaderValue::from_static("MISS"));
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HandlerFn;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    
+    /// Handler answering with `body`, counting how often it runs
+    fn counting_handler(body: &'static str, calls: Arc<AtomicUsize>) -> Box<HandlerFn> {
+        Box::new(move |_: &Request<Body>| {
+            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            Box::pin(async move {
+                Ok(Response::builder()
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header("x-handler-run", n)
+                    .body(Body::from(body))
+                    .unwrap())
+            })
+        })
+    }
+    
+    async fn get(middleware: &CacheMiddleware, handler: &HandlerFn, req: Request<Body>) -> (String, String, String) {
+        let response = middleware.process(&req, Next::new(&[], handler)).await.unwrap();
+        let cache = response.headers()[CACHE_HEADER].to_str().unwrap().to_string();
+        let run = response.headers()["x-handler-run"].to_str().unwrap().to_string();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (cache, run, String::from_utf8(body.to_vec()).unwrap())
+    }
+    
+    fn invoice_get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+    
+    #[tokio::test]
+    async fn second_identical_get_is_a_hit() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = counting_handler("{\"id\":\"inv-1\",\"total\":110.0}", calls.clone());
+        let middleware = CacheMiddleware::new(&CacheConfig::default());
+        
+        let first = get(&middleware, handler.as_ref(), invoice_get("/invoices/inv-1?a=1&b=2")).await;
+        // Same parameters in another order share the cache entry
+        let second = get(&middleware, handler.as_ref(), invoice_get("/invoices/inv-1?b=2&a=1")).await;
+        
+        assert_eq!(first.0, "MISS");
+        assert_eq!(second.0, "HIT");
+        assert_eq!(second.1, "1");
+        assert_eq!(second.2, first.2);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(middleware.len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn no_store_expired_and_oversized_responses_run_the_handler() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = counting_handler("{\"id\":\"inv-1\"}", calls.clone());
+        let middleware = CacheMiddleware::new(&CacheConfig { ttl_ms: 30, ..CacheConfig::default() });
+        
+        get(&middleware, handler.as_ref(), invoice_get("/invoices/inv-1")).await;
+        let no_store = Request::get("/invoices/inv-1")
+            .header(header::CACHE_CONTROL, "no-cache, no-store")
+            .body(Body::empty())
+            .unwrap();
+        let response = middleware.process(&no_store, Next::new(&[], handler.as_ref())).await.unwrap();
+        assert!(!response.headers().contains_key(CACHE_HEADER));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let (cache, run, _) = get(&middleware, handler.as_ref(), invoice_get("/invoices/inv-1")).await;
+        assert_eq!((cache.as_str(), run.as_str()), ("MISS", "3"));
+        
+        let tiny = CacheMiddleware::new(&CacheConfig { max_body_bytes: 4, ..CacheConfig::default() });
+        get(&tiny, handler.as_ref(), invoice_get("/invoices/inv-1")).await;
+        let (cache, _, _) = get(&tiny, handler.as_ref(), invoice_get("/invoices/inv-1")).await;
+        assert_eq!(cache, "MISS");
+        assert!(tiny.is_empty());
+    }
+}
@@ -0,0 +1,314 @@
+use crate::{read_recover, streaming, write_recover, Gateway};
+use anyhow::Result;
+use async_trait::async_trait;
+use kagi_shared::ServiceError;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// When a backend's circuit opens and how long it stays open
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failed calls that open a service's circuit
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Milliseconds an open circuit fails fast before letting a trial call through
+    #[serde(default = "default_cooldown_ms")]
+    pub cooldown_ms: u64,
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_cooldown_ms() -> u64 {
+    30_000
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_failure_threshold(),
+            cooldown_ms: default_cooldown_ms(),
+        }
+    }
+}
+
+/// Where a service's circuit stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through; counts consecutive failures
+    Closed { failures: u32 },
+    /// Calls fail fast until `until`
+    Open { until: Instant },
+    /// One trial call decides whether the circuit closes again
+    HalfOpen { trial_in_flight: bool },
+}
+
+/// Whether a failed call says anything about the backend's health.
+///
+/// Rejections such as validation errors or missing entities mean the service
+/// answered, so only internal and unavailable errors count.
+pub(crate) fn is_backend_failure(err: &anyhow::Error) -> bool {
+    matches!(
+        ServiceError::from_anyhow(err),
+        ServiceError::Internal(_) | ServiceError::Unavailable(_)
+    )
+}
+
+/// One circuit per backend service
+#[derive(Debug)]
+pub struct CircuitBreakers {
+    failure_threshold: u32,
+    cooldown: Duration,
+    circuits: RwLock<HashMap<String, CircuitState>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            failure_threshold: config.failure_threshold.max(1),
+            cooldown: Duration::from_millis(config.cooldown_ms),
+            circuits: RwLock::new(HashMap::new()),
+        }
+    }
+    
+    /// Current state of `service`'s circuit
+    pub fn state(&self, service: &str) -> CircuitState {
+        read_recover(&self.circuits)
+            .get(service)
+            .copied()
+            .unwrap_or(CircuitState::Closed { failures: 0 })
+    }
+    
+    /// Ask to call `service`, failing with `ServiceError::Unavailable` while its circuit is open.
+    ///
+    /// Once the cooldown has passed exactly one caller gets a trial permit; the
+    /// others keep failing fast until that trial completes.
+    pub fn acquire<'a>(&'a self, service: &str) -> Result<Permit<'a>, ServiceError> {
+        let mut circuits = write_recover(&self.circuits);
+        let state = circuits
+            .entry(service.to_string())
+            .or_insert(CircuitState::Closed { failures: 0 });
+        
+        let trial = match *state {
+            CircuitState::Closed { .. } => false,
+            CircuitState::Open { until } if Instant::now() >= until => {
+                info!("Circuit for {} is half-open; letting one trial call through", service);
+                *state = CircuitState::HalfOpen { trial_in_flight: true };
+                true
+            },
+            CircuitState::HalfOpen { trial_in_flight: false } => {
+                *state = CircuitState::HalfOpen { trial_in_flight: true };
+                true
+            },
+            CircuitState::Open { .. } | CircuitState::HalfOpen { trial_in_flight: true } => {
+                return Err(ServiceError::unavailable(format!(
+                    "Service {} is unavailable; try again later", service
+                )));
+            },
+        };
+        
+        Ok(Permit {
+            breakers: self,
+            service: service.to_string(),
+            trial,
+            finished: false,
+        })
+    }
+    
+    fn record(&self, service: &str, success: bool) {
+        let mut circuits = write_recover(&self.circuits);
+        let state = circuits
+            .entry(service.to_string())
+            .or_insert(CircuitState::Closed { failures: 0 });
+        
+        *state = match (*state, success) {
+            (CircuitState::HalfOpen { .. }, true) => {
+                info!("Circuit for {} closed after a successful trial call", service);
+                CircuitState::Closed { failures: 0 }
+            },
+            (_, true) => CircuitState::Closed { failures: 0 },
+            (CircuitState::Closed { failures }, false) if failures + 1 < self.failure_threshold => {
+                CircuitState::Closed { failures: failures + 1 }
+            },
+            // A call admitted before the circuit opened doesn't push the deadline back
+            (CircuitState::Open { until }, false) => CircuitState::Open { until },
+            (_, false) => {
+                warn!("Opening circuit for {} for {:?}", service, self.cooldown);
+                CircuitState::Open { until: Instant::now() + self.cooldown }
+            },
+        };
+    }
+    
+    /// Free the half-open slot of a trial call that was abandoned before finishing
+    fn release_trial(&self, service: &str) {
+        let mut circuits = write_recover(&self.circuits);
+        if let Some(state) = circuits.get_mut(service) {
+            if matches!(state, CircuitState::HalfOpen { .. }) {
+                *state = CircuitState::HalfOpen { trial_in_flight: false };
+            }
+        }
+    }
+}
+
+/// Permission to make one call, reporting its outcome back to the breaker
+#[derive(Debug)]
+pub struct Permit<'a> {
+    breakers: &'a CircuitBreakers,
+    service: String,
+    trial: bool,
+    finished: bool,
+}
+
+impl Permit<'_> {
+    /// Record the outcome of the call
+    pub fn finish<T>(mut self, result: &Result<T>) {
+        let success = match result {
+            Ok(_) => true,
+            Err(e) => !is_backend_failure(e),
+        };
+        self.breakers.record(&self.service, success);
+        self.finished = true;
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        // A cancelled call says nothing about the backend, but mustn't hold the trial slot forever
+        if !self.finished && self.trial {
+            self.breakers.release_trial(&self.service);
+        }
+    }
+}
+
+/// Gateway wrapper failing calls fast with `503` while their service's circuit is open
+pub struct CircuitBreaking {
+    inner: Arc<dyn Gateway + Send + Sync>,
+    breakers: Arc<CircuitBreakers>,
+}
+
+impl CircuitBreaking {
+    pub fn new(inner: Arc<dyn Gateway + Send + Sync>, breakers: Arc<CircuitBreakers>) -> Self {
+        Self { inner, breakers }
+    }
+}
+
+#[async_trait]
+impl Gateway for CircuitBreaking {
+    async fn run(&self) -> Result<()> {
+        self.inner.run().await
+    }
+    
+    async fn dispatch(&self, service: &str, action: &str, params: serde_json::Value) -> Result<serde_json::Value> {
+        let permit = self.breakers.acquire(service)?;
+        let result = self.inner.dispatch(service, action, params).await;
+        permit.finish(&result);
+        result
+    }
+    
+    async fn dispatch_stream(&self, service: &str, action: &str, params: serde_json::Value) -> Result<streaming::JsonStream> {
+        // Only opening the stream counts; errors mid-stream are the consumer's to handle
+        let permit = self.breakers.acquire(service)?;
+        let result = self.inner.dispatch_stream(service, action, params).await;
+        permit.finish(&result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status_for_error;
+    use hyper::StatusCode;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    
+    /// Backend that fails with an internal error until it is marked healthy
+    #[derive(Default)]
+    struct FlakyBackend {
+        healthy: AtomicBool,
+        calls: AtomicUsize,
+    }
+    
+    #[async_trait]
+    impl Gateway for FlakyBackend {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn dispatch(&self, _service: &str, _action: &str, _params: serde_json::Value) -> Result<serde_json::Value> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.healthy.load(Ordering::SeqCst) {
+                Ok(serde_json::json!({ "ok": true }))
+            } else {
+                Err(ServiceError::internal("database connection lost").into())
+            }
+        }
+    }
+    
+    fn breakers(failure_threshold: u32, cooldown_ms: u64) -> Arc<CircuitBreakers> {
+        Arc::new(CircuitBreakers::new(&CircuitBreakerConfig { failure_threshold, cooldown_ms }))
+    }
+    
+    #[tokio::test]
+    async fn breaker_trips_fails_fast_then_recovers_after_the_cooldown() {
+        let backend = Arc::new(FlakyBackend::default());
+        let breakers = breakers(3, 50);
+        let gateway = CircuitBreaking::new(backend.clone(), breakers.clone());
+        let call = || gateway.dispatch("invoice", "list", serde_json::json!({}));
+        
+        for _ in 0..3 {
+            assert!(call().await.is_err());
+        }
+        assert!(matches!(breakers.state("invoice"), CircuitState::Open { .. }));
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 3);
+        
+        // Open: the backend isn't called and the caller sees a 503
+        let err = call().await.unwrap_err();
+        assert_eq!(status_for_error(&err), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 3);
+        // Other services keep their own circuit
+        assert_eq!(breakers.state("profile"), CircuitState::Closed { failures: 0 });
+        
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        backend.healthy.store(true, Ordering::SeqCst);
+        call().await.unwrap();
+        assert_eq!(breakers.state("invoice"), CircuitState::Closed { failures: 0 });
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 4);
+    }
+    
+    #[tokio::test]
+    async fn half_open_circuit_admits_a_single_trial() {
+        let breakers = breakers(1, 20);
+        breakers.acquire("invoice").unwrap().finish::<()>(&Err(ServiceError::unavailable("down").into()));
+        assert!(breakers.acquire("invoice").is_err());
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        
+        let trial = breakers.acquire("invoice").unwrap();
+        assert_eq!(breakers.state("invoice"), CircuitState::HalfOpen { trial_in_flight: true });
+        assert!(breakers.acquire("invoice").is_err());
+        
+        // A failed trial opens the circuit again
+        trial.finish::<()>(&Err(ServiceError::internal("still down").into()));
+        assert!(matches!(breakers.state("invoice"), CircuitState::Open { .. }));
+        
+        // An abandoned trial frees the slot for the next caller
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        drop(breakers.acquire("invoice").unwrap());
+        assert_eq!(breakers.state("invoice"), CircuitState::HalfOpen { trial_in_flight: false });
+        breakers.acquire("invoice").unwrap().finish(&Ok(()));
+        assert_eq!(breakers.state("invoice"), CircuitState::Closed { failures: 0 });
+    }
+    
+    #[test]
+    fn rejections_from_a_healthy_backend_do_not_count() {
+        let breakers = breakers(1, 60_000);
+        
+        breakers.acquire("invoice").unwrap().finish::<()>(&Err(ServiceError::not_found("Invoice not found").into()));
+        breakers.acquire("invoice").unwrap().finish::<()>(&Err(ServiceError::validation("Bad tax rate").into()));
+        
+        assert_eq!(breakers.state("invoice"), CircuitState::Closed { failures: 0 });
+    }
+}
@@ -0,0 +1,247 @@
+use crate::{Middleware, Next};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use hyper::body::HttpBody;
+use hyper::{header, Body, Request, Response};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// Content types that are already compressed and gain nothing from another pass
+const COMPRESSED_CONTENT_TYPES: [&str; 10] = [
+    "image/",
+    "video/",
+    "audio/",
+    "font/woff",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-bzip2",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+];
+
+/// Response compression settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressionConfig {
+    /// Bodies smaller than this many bytes are sent as is
+    #[serde(default = "default_min_size")]
+    pub min_size: usize,
+}
+
+fn default_min_size() -> usize {
+    1024
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            min_size: default_min_size(),
+        }
+    }
+}
+
+/// Encodings the gateway can compress with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+    
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                writer.write_all(data)?;
+                Ok(writer.into_inner())
+            },
+            Encoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            },
+        }
+    }
+}
+
+/// Pick the encoding for an `Accept-Encoding` value, preferring brotli.
+///
+/// Encodings listed with `q=0` are refused; `*` stands for gzip.
+pub fn preferred_encoding(accept_encoding: &str) -> Option<Encoding> {
+    let mut gzip = false;
+    for entry in accept_encoding.split(',') {
+        let mut parts = entry.split(';').map(str::trim);
+        let coding = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let refused = parts
+            .filter_map(|param| param.strip_prefix("q="))
+            .any(|q| q.parse::<f32>().map(|q| q <= 0.0).unwrap_or(false));
+        if refused {
+            continue;
+        }
+        
+        match coding.as_str() {
+            "br" => return Some(Encoding::Brotli),
+            "gzip" | "x-gzip" | "*" => gzip = true,
+            _ => {},
+        }
+    }
+    
+    gzip.then_some(Encoding::Gzip)
+}
+
+fn is_compressible(response: &Response<Body>) -> bool {
+    let content_type = response.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    
+    !COMPRESSED_CONTENT_TYPES.iter().any(|compressed| content_type.starts_with(compressed))
+}
+
+/// Middleware compressing response bodies for clients that accept gzip or brotli
+pub struct CompressionMiddleware {
+    min_size: usize,
+}
+
+impl CompressionMiddleware {
+    pub fn new(config: &CompressionConfig) -> Self {
+        Self {
+            min_size: config.min_size,
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for CompressionMiddleware {
+    async fn process(&self, req: &Request<Body>, next: Next<'_>) -> Result<Response<Body>> {
+        let encoding = req.headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .and_then(preferred_encoding);
+        
+        let mut response = next.run(req).await?;
+        // The body depends on Accept-Encoding even when this response isn't compressed
+        response.headers_mut().append(header::VARY, header::HeaderValue::from_static("accept-encoding"));
+        
+        let encoding = match encoding {
+            Some(encoding) => encoding,
+            None => return Ok(response),
+        };
+        if response.headers().contains_key(header::CONTENT_ENCODING) || !is_compressible(&response) {
+            return Ok(response);
+        }
+        
+        // Streamed bodies have no exact size and are passed through rather than buffered
+        match response.body().size_hint().exact() {
+            Some(size) if size as usize >= self.min_size => {},
+            _ => return Ok(response),
+        }
+        
+        let (mut parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        let compressed = tokio::task::spawn_blocking({
+            let body = body.clone();
+            move || encoding.compress(&body)
+        })
+        .await
+        .map_err(|e| anyhow!("Compression task failed: {}", e))??;
+        
+        if compressed.len() >= body.len() {
+            return Ok(Response::from_parts(parts, Body::from(body)));
+        }
+        
+        debug!("Compressed response with {} from {} to {} bytes", encoding.as_str(), body.len(), compressed.len());
+        parts.headers.insert(header::CONTENT_ENCODING, header::HeaderValue::from_static(encoding.as_str()));
+        parts.headers.insert(header::CONTENT_LENGTH, header::HeaderValue::from(compressed.len()));
+        Ok(Response::from_parts(parts, Body::from(compressed)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HandlerFn;
+    use std::io::Read;
+    
+    /// A large invoice list, well over the default threshold
+    fn invoice_list() -> String {
+        let invoices: Vec<_> = (0..200)
+            .map(|n| serde_json::json!({ "id": format!("inv-{}", n), "customer_name": "Acme Ltd", "total": 110.0 }))
+            .collect();
+        serde_json::Value::Array(invoices).to_string()
+    }
+    
+    fn handler_returning(body: String, content_type: &'static str) -> Box<HandlerFn> {
+        Box::new(move |_: &Request<Body>| {
+            let body = body.clone();
+            Box::pin(async move {
+                Ok(Response::builder().header(header::CONTENT_TYPE, content_type).body(Body::from(body)).unwrap())
+            })
+        })
+    }
+    
+    async fn fetch(accept_encoding: &str, handler: &HandlerFn) -> (Option<String>, Vec<u8>) {
+        let middleware = CompressionMiddleware::new(&CompressionConfig::default());
+        let req = Request::get("/invoices").header(header::ACCEPT_ENCODING, accept_encoding).body(Body::empty()).unwrap();
+        let response = middleware.process(&req, Next::new(&[], handler)).await.unwrap();
+        let encoding = response.headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|v| v.to_str().unwrap().to_string());
+        let length = response.headers()
+            .get(header::CONTENT_LENGTH)
+            .map(|v| v.to_str().unwrap().to_string());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec();
+        // A rewritten body must carry its new length
+        if let Some(length) = length {
+            assert_eq!(length, body.len().to_string());
+        }
+        (encoding, body)
+    }
+    
+    #[tokio::test]
+    async fn large_json_comes_back_gzipped_and_round_trips() {
+        let json = invoice_list();
+        let handler = handler_returning(json.clone(), "application/json");
+        
+        let (encoding, body) = fetch("gzip", handler.as_ref()).await;
+        
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+        assert!(body.len() < json.len());
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(body.as_slice()).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, json);
+    }
+    
+    #[tokio::test]
+    async fn brotli_is_preferred_when_both_are_offered() {
+        let json = invoice_list();
+        let handler = handler_returning(json.clone(), "application/json");
+        
+        let (encoding, body) = fetch("gzip, deflate, br", handler.as_ref()).await;
+        
+        assert_eq!(encoding.as_deref(), Some("br"));
+        let mut decoded = String::new();
+        brotli::Decompressor::new(body.as_slice(), 4096).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, json);
+    }
+    
+    #[tokio::test]
+    async fn small_and_already_compressed_bodies_are_left_alone() {
+        let small = handler_returning("{\"ok\":true}".to_string(), "application/json");
+        assert_eq!(fetch("gzip", small.as_ref()).await, (None, b"{\"ok\":true}".to_vec()));
+        
+        let image = handler_returning("x".repeat(4096), "image/png");
+        let (encoding, body) = fetch("gzip", image.as_ref()).await;
+        assert_eq!(encoding, None);
+        assert_eq!(body.len(), 4096);
+    }
+}
@@ -0,0 +1,138 @@
+use hyper::{header, Body, HeaderMap, Response, StatusCode};
+
+/// Parameter carrying the `If-Match` version into the forwarded request
+pub const IF_MATCH_PARAM: &str = "if_match_version";
+
+/// Entity tag for a resource version
+pub fn etag_for(version: u64) -> String {
+    format!("\"v{}\"", version)
+}
+
+/// Parse a version out of an entity tag produced by `etag_for`, ignoring weak prefixes
+pub fn parse_etag(value: &str) -> Option<u64> {
+    let value = value.trim().trim_start_matches("W/");
+    value
+        .strip_prefix("\"v")
+        .and_then(|v| v.strip_suffix('"'))
+        .and_then(|v| v.parse().ok())
+}
+
+/// Version required by the `If-Match` header, if present
+pub fn if_match_version(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::IF_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_etag)
+}
+
+/// Whether `If-None-Match` lists the given entity tag (or `*`)
+pub fn if_none_match_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag))
+        .unwrap_or(false)
+}
+
+/// Version carried in a JSON response body, if it is a versioned resource
+pub fn body_version(body: &serde_json::Value) -> Option<u64> {
+    body.get("version").and_then(|v| v.as_u64())
+}
+
+/// Build a `304 Not Modified` response for a versioned body matching `If-None-Match`
+pub fn not_modified(headers: &HeaderMap, body: &serde_json::Value) -> Option<Response<Body>> {
+    let etag = etag_for(body_version(body)?);
+    if !if_none_match_matches(headers, &etag) {
+        return None;
+    }
+    
+    Some(Response::builder()
+        .status(StatusCode::NOT_MODIFIED)
+        .header(header::ETAG, etag)
+        .body(Body::empty())
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metrics;
+    use crate::{register_route, route_http_request, Gateway, GatewayConfig, GatewayState, RouteInfo};
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use hyper::Request;
+    use kagi_shared::ServiceError;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    
+    /// One invoice at version 3
+    struct VersionedInvoice;
+    
+    #[async_trait]
+    impl Gateway for VersionedInvoice {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn dispatch(&self, _service: &str, action: &str, params: Value) -> Result<Value> {
+            match (action, params.get(IF_MATCH_PARAM).and_then(Value::as_u64)) {
+                ("get", _) => Ok(json!({ "id": "inv-1", "version": 3 })),
+                ("update", Some(3)) => Ok(json!({ "id": "inv-1", "version": 4 })),
+                ("update", Some(version)) => {
+                    Err(ServiceError::precondition_failed(format!("expected version {}, current version 3", version)).into())
+                },
+                _ => Err(ServiceError::validation("expected_version is required").into()),
+            }
+        }
+    }
+    
+    async fn send(req: Request<Body>) -> Response<Body> {
+        for (method, handler_name) in [("GET", "conditionaltest.get"), ("PUT", "conditionaltest.update")] {
+            register_route(RouteInfo {
+                method,
+                path: "/conditional-tests/:id",
+                handler_name,
+                middleware: None,
+            });
+        }
+        let state = GatewayState::new(Arc::new(VersionedInvoice), &GatewayConfig::default(), Arc::new(Metrics::new()));
+        route_http_request(req, Arc::new(state.unwrap())).await.unwrap()
+    }
+    
+    #[tokio::test]
+    async fn unchanged_fetch_is_304() {
+        let fresh = send(Request::get("/conditional-tests/inv-1").body(Body::empty()).unwrap()).await;
+        assert_eq!(fresh.status(), StatusCode::OK);
+        assert_eq!(fresh.headers()[header::ETAG], "\"v3\"");
+        
+        let req = Request::get("/conditional-tests/inv-1")
+            .header(header::IF_NONE_MATCH, "\"v3\"")
+            .body(Body::empty())
+            .unwrap();
+        let unchanged = send(req).await;
+        assert_eq!(unchanged.status(), StatusCode::NOT_MODIFIED);
+        assert!(hyper::body::to_bytes(unchanged.into_body()).await.unwrap().is_empty());
+        
+        let req = Request::get("/conditional-tests/inv-1")
+            .header(header::IF_NONE_MATCH, "\"v2\"")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(req).await.status(), StatusCode::OK);
+    }
+    
+    #[tokio::test]
+    async fn stale_if_match_update_is_412() {
+        let update = |etag: &'static str| {
+            Request::put("/conditional-tests/inv-1")
+                .header(header::IF_MATCH, etag)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from("{}"))
+                .unwrap()
+        };
+        
+        assert_eq!(send(update("\"v2\"")).await.status(), StatusCode::PRECONDITION_FAILED);
+        let current = send(update("\"v3\"")).await;
+        assert_eq!(current.status(), StatusCode::OK);
+        assert_eq!(current.headers()[header::ETAG], "\"v4\"");
+    }
+}
@@ -0,0 +1,167 @@
+use crate::metrics::Metrics;
+use crate::{error_response, GatewayConfig};
+use hyper::{header, Body, Response, StatusCode};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Gauge tracking open HTTP connections
+pub const CONNECTIONS_ACTIVE_GAUGE: &str = "gateway_connections_active";
+
+/// How many connections the gateway serves at once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionLimitConfig {
+    /// Connections served concurrently, across all listeners
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+    /// Milliseconds a connection over the limit waits for a slot before it gets `503`
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+}
+
+fn default_max_connections() -> usize {
+    10_000
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    1_000
+}
+
+impl Default for ConnectionLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: default_max_connections(),
+            queue_timeout_ms: default_queue_timeout_ms(),
+        }
+    }
+}
+
+/// Whether a new connection may be served
+#[derive(Debug)]
+pub enum Admission {
+    /// No limit is configured
+    Unlimited,
+    /// Holds one slot until the connection closes
+    Admitted(ConnectionPermit),
+    /// Every request on the connection gets `503 Service Unavailable`
+    Rejected,
+}
+
+impl Admission {
+    pub fn is_rejected(&self) -> bool {
+        matches!(self, Admission::Rejected)
+    }
+}
+
+/// Semaphore with one permit per connection slot
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    slots: Arc<Semaphore>,
+    max_connections: usize,
+    queue_timeout: Duration,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(config: &ConnectionLimitConfig) -> Self {
+        let max_connections = config.max_connections.max(1);
+        Self {
+            slots: Arc::new(Semaphore::new(max_connections)),
+            max_connections,
+            queue_timeout: Duration::from_millis(config.queue_timeout_ms),
+            metrics: None,
+        }
+    }
+    
+    /// Report the open connection count as `CONNECTIONS_ACTIVE_GAUGE`
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+    
+    /// Connections currently holding a slot
+    pub fn active(&self) -> usize {
+        self.max_connections - self.slots.available_permits()
+    }
+    
+    /// Wait up to the queue timeout for a free slot
+    pub async fn admit(self: &Arc<Self>) -> Admission {
+        let permit = match tokio::time::timeout(self.queue_timeout, self.slots.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => permit,
+            _ => {
+                warn!("Rejecting connection: all {} connection slots are in use", self.max_connections);
+                return Admission::Rejected;
+            },
+        };
+        
+        self.report();
+        Admission::Admitted(ConnectionPermit {
+            limiter: self.clone(),
+            permit: Some(permit),
+        })
+    }
+    
+    fn report(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.set_gauge(CONNECTIONS_ACTIVE_GAUGE, self.active() as i64);
+        }
+    }
+}
+
+/// A connection's slot, freed when the connection is dropped
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        // Release the slot before reporting so the gauge doesn't count this connection
+        self.permit.take();
+        self.limiter.report();
+    }
+}
+
+/// Admit a connection against `limiter`, if one is configured
+pub async fn admit(limiter: Option<&Arc<ConnectionLimiter>>) -> Admission {
+    match limiter {
+        Some(limiter) => limiter.admit().await,
+        None => Admission::Unlimited,
+    }
+}
+
+/// Response to every request on a rejected connection; asks the client to reconnect later
+pub fn rejected_response(config: &GatewayConfig) -> Response<Body> {
+    let mut response = error_response(config, StatusCode::SERVICE_UNAVAILABLE, "Too many connections");
+    response.headers_mut().insert(header::CONNECTION, header::HeaderValue::from_static("close"));
+    response.headers_mut().insert(header::RETRY_AFTER, header::HeaderValue::from_static("1"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[tokio::test]
+    async fn permits_are_counted_and_released_on_drop() {
+        let metrics = Arc::new(Metrics::new());
+        let config = ConnectionLimitConfig { max_connections: 2, queue_timeout_ms: 20 };
+        let limiter = Arc::new(ConnectionLimiter::new(&config).with_metrics(metrics.clone()));
+        
+        let first = limiter.admit().await;
+        let second = limiter.admit().await;
+        assert_eq!(limiter.active(), 2);
+        assert_eq!(metrics.gauge(CONNECTIONS_ACTIVE_GAUGE), Some(2));
+        assert!(limiter.admit().await.is_rejected());
+        
+        drop(first);
+        assert_eq!(limiter.active(), 1);
+        assert_eq!(metrics.gauge(CONNECTIONS_ACTIVE_GAUGE), Some(1));
+        assert!(!limiter.admit().await.is_rejected());
+        drop(second);
+        assert!(matches!(admit(None).await, Admission::Unlimited));
+    }
+}
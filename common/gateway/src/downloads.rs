@@ -0,0 +1,215 @@
+use crate::metrics::{dispatch_measured, Metrics};
+use crate::{error_response, error_response_for, stamp_tenant, Gateway, GatewayConfig};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::{header, Body, Response, StatusCode};
+use mime_guess::mime::Mime;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Deserialize;
+
+/// Prefix of the signed download endpoint (`/downloads/:service/:id`)
+pub const DOWNLOADS_PREFIX: &str = "/downloads/";
+
+/// Query parameters carried by a signed download link
+#[derive(Debug, Deserialize)]
+struct DownloadQuery {
+    expires: i64,
+    signature: String,
+}
+
+/// File returned by a service's `download` action
+#[derive(Debug, Deserialize)]
+struct DownloadPayload {
+    content_type: String,
+    filename: String,
+    data: String,
+}
+
+/// Bytes left unencoded in an RFC 5987 `filename*` value
+const FILENAME_ATTR_CHARS: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// `Content-Disposition` offering the payload's file under `filename`.
+///
+/// The quoted `filename` is an ASCII fallback with quotes, backslashes and
+/// control characters replaced, so a service can't break out of the header;
+/// `filename*` carries the exact name percent-encoded.
+fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| if c == ' ' || (c.is_ascii_graphic() && c != '"' && c != '\\') { c } else { '_' })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        utf8_percent_encode(filename, FILENAME_ATTR_CHARS)
+    )
+}
+
+/// Split `/downloads/:service/:id` into its service and resource id
+fn parse_path(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix(DOWNLOADS_PREFIX)?;
+    let (service, id) = rest.split_once('/')?;
+    if service.is_empty() || id.is_empty() || id.contains('/') {
+        return None;
+    }
+    Some((service, id))
+}
+
+/// Serve a signed download link by handing it to the owning service's `download` action.
+///
+/// The service checks the signature and expiry; the gateway only decodes the file it returns.
+pub async fn download_response(
+    gateway: Option<&dyn Gateway>,
+    metrics: &Metrics,
+    config: &GatewayConfig,
+    tenant_id: &str,
+    path: &str,
+    query: Option<&str>,
+) -> Response<Body> {
+    let (service, id) = match parse_path(path) {
+        Some(parts) => parts,
+        None => return error_response(config, StatusCode::NOT_FOUND, "Route not found"),
+    };
+    let query: DownloadQuery = match serde_urlencoded::from_str(query.unwrap_or("")) {
+        Ok(query) => query,
+        Err(e) => return error_response(config, StatusCode::BAD_REQUEST, &format!("Invalid download link: {}", e)),
+    };
+    let gateway = match gateway {
+        Some(gateway) => gateway,
+        None => return error_response(config, StatusCode::SERVICE_UNAVAILABLE, "No gateway available to dispatch calls"),
+    };
+    
+    let params = serde_json::json!({
+        "id": id,
+        "expires": query.expires,
+        "signature": query.signature,
+    });
+    let payload = match dispatch_measured(gateway, metrics, service, "download", stamp_tenant(Some(params), tenant_id)).await {
+        Ok(value) => value,
+        Err(e) => return error_response_for(config, &e),
+    };
+    let payload: DownloadPayload = match serde_json::from_value(payload) {
+        Ok(payload) => payload,
+        Err(e) => return error_response(config, StatusCode::BAD_GATEWAY, &format!("Invalid download payload: {}", e)),
+    };
+    let bytes = match STANDARD.decode(payload.data) {
+        Ok(bytes) => bytes,
+        Err(e) => return error_response(config, StatusCode::BAD_GATEWAY, &format!("Invalid download payload: {}", e)),
+    };
+    let content_type = match payload.content_type.parse::<Mime>() {
+        Ok(content_type) => content_type,
+        Err(e) => return error_response(config, StatusCode::BAD_GATEWAY, &format!("Invalid download content type: {}", e)),
+    };
+    if payload.filename.trim().is_empty() {
+        return error_response(config, StatusCode::BAD_GATEWAY, "Invalid download payload: empty filename");
+    }
+    
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type.as_ref())
+        .header(header::CONTENT_DISPOSITION, content_disposition(&payload.filename))
+        .body(Body::from(bytes));
+    match response {
+        Ok(response) => response,
+        Err(e) => error_response(config, StatusCode::BAD_GATEWAY, &format!("Invalid download payload: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use kagi_shared::ServiceError;
+    use serde_json::{json, Value};
+    
+    /// Serves one file whose name and type depend on the requested id, after
+    /// checking the link like a real service would
+    struct StubDownloads;
+    
+    #[async_trait]
+    impl Gateway for StubDownloads {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn dispatch(&self, _service: &str, _action: &str, params: Value) -> Result<Value> {
+            if params["signature"] != "valid" {
+                return Err(ServiceError::forbidden("Invalid download signature").into());
+            }
+            if params["expires"].as_i64() < Some(chrono::Utc::now().timestamp()) {
+                return Err(ServiceError::gone("Download link has expired").into());
+            }
+            let (content_type, filename) = match params["id"].as_str() {
+                Some("hostile-name") => ("application/pdf", "a\"; x=\"y\r\nSet-Cookie: z\\é.pdf"),
+                Some("hostile-type") => ("text/html\r\nSet-Cookie: z", "report.pdf"),
+                _ => ("application/pdf", "invoice-7.pdf"),
+            };
+            Ok(json!({ "content_type": content_type, "filename": filename, "data": STANDARD.encode("%PDF-1.4") }))
+        }
+    }
+    
+    async fn download(id: &str, query: &str) -> Response<Body> {
+        download_response(
+            Some(&StubDownloads),
+            &Metrics::new(),
+            &GatewayConfig::default(),
+            crate::DEFAULT_TENANT,
+            &format!("{}files/{}", DOWNLOADS_PREFIX, id),
+            Some(query),
+        )
+        .await
+    }
+    
+    fn future_query(signature: &str) -> String {
+        format!("expires={}&signature={}", chrono::Utc::now().timestamp() + 60, signature)
+    }
+    
+    #[tokio::test]
+    async fn valid_link_serves_the_file() {
+        let response = download("7", &future_query("valid")).await;
+        
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/pdf");
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"invoice-7.pdf\"; filename*=UTF-8''invoice-7.pdf"
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"%PDF-1.4");
+    }
+    
+    #[tokio::test]
+    async fn tampered_and_expired_links_are_refused() {
+        assert_eq!(download("7", &future_query("forged")).await.status(), StatusCode::FORBIDDEN);
+        
+        let expired = format!("expires={}&signature=valid", chrono::Utc::now().timestamp() - 60);
+        assert_eq!(download("7", &expired).await.status(), StatusCode::GONE);
+    }
+    
+    #[tokio::test]
+    async fn payload_headers_cannot_be_injected() {
+        let response = download("hostile-name", &future_query("valid")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"a_; x=_y__Set-Cookie: z__.pdf\"; filename*=UTF-8''a%22%3B%20x%3D%22y%0D%0ASet-Cookie%3A%20z%5C%C3%A9.pdf"
+        );
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+        
+        let response = download("hostile-type", &future_query("valid")).await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::body::json_fields;
+use crate::metrics::{dispatch_measured, Metrics};
+use crate::query::parse_query;
+use crate::routing::PathParams;
+use crate::retry::{is_idempotent, RetryConfig};
+use crate::{conditional, effective_method, resolve_tenant, stamp_tenant, Gateway, RouteInfo};
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use hyper::body::Bytes;
+use hyper::{header, Body, Request, Response, StatusCode};
+use kagi_shared::ServiceError;
+use std::sync::Arc;
+
+/// Request body buffered by the gateway before running middleware, stored in request extensions.
+///
+/// Only JSON bodies (by `Content-Type`) are merged into the call arguments.
+#[derive(Debug, Clone, Default)]
+pub struct RequestBody(pub Bytes);
+
+/// Fields of a `multipart/form-data` body, parsed by the gateway after buffering it
+/// and stored in request extensions
+#[derive(Debug, Clone, Default)]
+pub struct FormFields(pub serde_json::Map<String, serde_json::Value>);
+
+/// The service action a route forwards to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteTarget {
+    pub service: String,
+    pub action: String,
+}
+
+impl RouteTarget {
+    /// Parse a `RouteInfo::handler_name` of the form `service.action`
+    pub fn parse(handler_name: &str) -> Option<Self> {
+        let (service, action) = handler_name.split_once('.')?;
+        if service.is_empty() || action.is_empty() || action.contains('.') {
+            return None;
+        }
+        Some(Self {
+            service: service.to_string(),
+            action: action.to_string(),
+        })
+    }
+    
+    pub fn from_route(route: &RouteInfo) -> Result<Self> {
+        Self::parse(route.handler_name).ok_or_else(|| anyhow!(
+            "Route {} {} has handler '{}'; expected 'service.action'",
+            route.method,
+            route.path,
+            route.handler_name
+        ))
+    }
+}
+
+/// Merge the request's arguments into one JSON object.
+///
+/// Query parameters are overridden by body fields, which are overridden by
+/// path parameters, so a client can't smuggle a different id in the body.
+pub fn request_params(req: &Request<Body>) -> Result<serde_json::Value> {
+    let mut params = req.uri().query().map(parse_query).unwrap_or_default();
+    
+    if let Some(RequestBody(body)) = req.extensions().get::<RequestBody>() {
+        let fields = json_fields(req.headers(), body).map_err(|e| ServiceError::validation(e.to_string()))?;
+        params.extend(fields.unwrap_or_default());
+    }
+    if let Some(FormFields(fields)) = req.extensions().get::<FormFields>() {
+        params.extend(fields.clone());
+    }
+    
+    if let Some(PathParams(path_params)) = req.extensions().get::<PathParams>() {
+        for (name, value) in path_params {
+            params.insert(name.clone(), serde_json::Value::String(value.clone()));
+        }
+    }
+    
+    Ok(serde_json::Value::Object(params))
+}
+
+/// Call `target` with the request's arguments and turn its result into an HTTP response.
+///
+/// Errors are returned rather than rendered so the gateway maps them to a status
+/// code and error body the same way it does for every other failure.
+///
+/// Idempotent requests are retried after transient failures when `retry` is set.
+pub fn forward(
+    gateway: Arc<dyn Gateway + Send + Sync>,
+    metrics: Arc<Metrics>,
+    retry: Option<Arc<RetryConfig>>,
+    target: RouteTarget,
+    req: &Request<Body>,
+) -> BoxFuture<'static, Result<Response<Body>>> {
+    let retry = retry.filter(|_| is_idempotent(&effective_method(req)));
+    let tenant_id = resolve_tenant(req);
+    let headers = req.headers().clone();
+    let params = request_params(req);
+    
+    Box::pin(async move {
+        let mut params = stamp_tenant(Some(params?), &tenant_id);
+        if let Some(version) = conditional::if_match_version(&headers) {
+            params[conditional::IF_MATCH_PARAM] = serde_json::json!(version);
+        }
+        
+        let dispatch = || dispatch_measured(gateway.as_ref(), &metrics, &target.service, &target.action, params.clone());
+        let body = match &retry {
+            Some(retry) => retry.run(&format!("{}.{}", target.service, target.action), dispatch).await?,
+            None => dispatch().await?,
+        };
+        if let Some(response) = conditional::not_modified(&headers, &body) {
+            return Ok(response);
+        }
+        
+        let mut response = Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(version) = conditional::body_version(&body) {
+            response = response.header(header::ETAG, conditional::etag_for(version));
+        }
+        Ok(response.body(Body::from(body.to_string()))?)
+    })
+}
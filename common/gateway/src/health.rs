@@ -0,0 +1,179 @@
+use crate::Gateway;
+use futures::future::join_all;
+use hyper::{header, Body, Response, StatusCode};
+use log::warn;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Path of the readiness endpoint
+pub const READINESS_PATH: &str = "/readyz";
+
+/// Alternative path of the readiness endpoint
+pub const READY_PATH: &str = "/ready";
+
+/// Path of the liveness endpoint
+pub const HEALTH_PATH: &str = "/health";
+
+/// Version reported by the health endpoints of gateways started with `start_gateway`
+pub const GATEWAY_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Whether `path` is served by the readiness endpoint
+pub fn is_readiness_path(path: &str) -> bool {
+    path == READINESS_PATH || path == READY_PATH
+}
+
+/// Build the `/health` response: `200` whenever the server is up to answer it
+pub fn liveness_response(version: &str) -> Response<Body> {
+    json_response(StatusCode::OK, serde_json::json!({
+        "status": "ok",
+        "version": version,
+    }))
+}
+
+/// Health of a single backend service
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceHealth {
+    /// Service name
+    pub service: String,
+    /// Whether the service answered its ping in time
+    pub healthy: bool,
+    /// Reason the probe failed, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Probe a single service by invoking its `ping` action
+async fn probe_service(gateway: Option<&dyn Gateway>, service: &str, timeout: Duration) -> ServiceHealth {
+    let result = match gateway {
+        Some(gateway) => {
+            match tokio::time::timeout(timeout, gateway.dispatch(service, "ping", serde_json::json!({}))).await {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(_) => Err(format!("Timed out after {}ms", timeout.as_millis())),
+            }
+        },
+        None => Err("No gateway available to dispatch probes".to_string()),
+    };
+    
+    match result {
+        Ok(()) => ServiceHealth {
+            service: service.to_string(),
+            healthy: true,
+            error: None,
+        },
+        Err(error) => {
+            warn!("Readiness probe failed for {}: {}", service, error);
+            ServiceHealth {
+                service: service.to_string(),
+                healthy: false,
+                error: Some(error),
+            }
+        }
+    }
+}
+
+/// Probe every configured backend service concurrently
+pub async fn probe_services(gateway: Option<&dyn Gateway>, services: &[String], timeout: Duration) -> Vec<ServiceHealth> {
+    join_all(services.iter().map(|service| probe_service(gateway, service, timeout))).await
+}
+
+/// Build the readiness response: `200` once routes are initialized and every
+/// service is healthy, else `503`
+pub async fn readiness_response(
+    gateway: Option<&dyn Gateway>,
+    services: &[String],
+    timeout: Duration,
+    version: &str,
+    routes_initialized: bool,
+) -> Response<Body> {
+    if !routes_initialized {
+        return json_response(StatusCode::SERVICE_UNAVAILABLE, serde_json::json!({
+            "status": "initializing",
+            "version": version,
+        }));
+    }
+    
+    let results = probe_services(gateway, services, timeout).await;
+    let unhealthy: Vec<&str> = results
+        .iter()
+        .filter(|r| !r.healthy)
+        .map(|r| r.service.as_str())
+        .collect();
+    
+    let (status, body) = if unhealthy.is_empty() {
+        (StatusCode::OK, serde_json::json!({
+            "status": "ready",
+            "version": version,
+            "services": results,
+        }))
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, serde_json::json!({
+            "status": "unavailable",
+            "version": version,
+            "unhealthy": unhealthy,
+            "services": results,
+        }))
+    };
+    
+    json_response(status, body)
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Result};
+    use async_trait::async_trait;
+    use serde_json::Value;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    
+    /// Answers pings, except for `billing` while it is down
+    struct FlakyBackends {
+        billing_up: AtomicBool,
+    }
+    
+    #[async_trait]
+    impl Gateway for FlakyBackends {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn dispatch(&self, service: &str, _action: &str, _params: Value) -> Result<Value> {
+            if service == "billing" && !self.billing_up.load(Ordering::SeqCst) {
+                return Err(anyhow!("billing is down"));
+            }
+            Ok(Value::from("pong"))
+        }
+    }
+    
+    async fn readiness(gateway: &FlakyBackends) -> (StatusCode, Value) {
+        let services = vec!["invoice".to_string(), "billing".to_string()];
+        let response = readiness_response(Some(gateway), &services, Duration::from_secs(1), "test", true).await;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+    
+    #[tokio::test]
+    async fn unhealthy_service_keeps_readiness_at_503_until_it_recovers() {
+        let gateway = FlakyBackends { billing_up: AtomicBool::new(false) };
+        
+        let (status, body) = readiness(&gateway).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["unhealthy"], serde_json::json!(["billing"]));
+        assert_eq!(body["services"][0]["healthy"], true);
+        assert_eq!(body["services"][1]["error"], "billing is down");
+        
+        gateway.billing_up.store(true, Ordering::SeqCst);
+        let (status, body) = readiness(&gateway).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{streaming, Gateway};
+use anyhow::Result;
+use async_trait::async_trait;
+use kagi_shared::InternalAuth;
+use std::sync::Arc;
+
+/// Gateway wrapper stamping a short-lived internal token on every forwarded call.
+///
+/// Services configured with the same secret reject calls without a valid token,
+/// so they only accept traffic that came through the gateway.
+pub struct InternallyAuthenticated {
+    inner: Arc<dyn Gateway + Send + Sync>,
+    auth: InternalAuth,
+}
+
+impl InternallyAuthenticated {
+    pub fn new(inner: Arc<dyn Gateway + Send + Sync>, auth: InternalAuth) -> Self {
+        Self { inner, auth }
+    }
+}
+
+#[async_trait]
+impl Gateway for InternallyAuthenticated {
+    async fn run(&self) -> Result<()> {
+        self.inner.run().await
+    }
+    
+    async fn dispatch(&self, service: &str, action: &str, params: serde_json::Value) -> Result<serde_json::Value> {
+        self.inner.dispatch(service, action, self.auth.stamp(service, params)).await
+    }
+    
+    async fn dispatch_stream(&self, service: &str, action: &str, params: serde_json::Value) -> Result<streaming::JsonStream> {
+        self.inner.dispatch_stream(service, action, self.auth.stamp(service, params)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kagi_shared::{ServiceError, INTERNAL_TOKEN_PARAM};
+    use serde_json::{json, Value};
+    
+    const SECRET: &str = "shared-internal-secret";
+    
+    /// Backend accepting only calls carrying a valid internal token
+    struct GuardedService {
+        auth: InternalAuth,
+    }
+    
+    #[async_trait]
+    impl Gateway for GuardedService {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn dispatch(&self, service: &str, _action: &str, params: Value) -> Result<Value> {
+            let token = params.get(INTERNAL_TOKEN_PARAM).and_then(Value::as_str)
+                .ok_or_else(|| ServiceError::unauthorized("Missing internal token"))?;
+            let tenant_id = params.get("tenant_id").and_then(Value::as_str).unwrap_or("");
+            self.auth.verify(token, service, tenant_id)?;
+            Ok(json!("accepted"))
+        }
+    }
+    
+    #[tokio::test]
+    async fn forwarded_calls_are_accepted_and_direct_calls_rejected() {
+        let backend: Arc<dyn Gateway + Send + Sync> = Arc::new(GuardedService { auth: InternalAuth::new(SECRET) });
+        let gateway = InternallyAuthenticated::new(backend.clone(), InternalAuth::new(SECRET));
+        let params = json!({ "tenant_id": "acme", "invoice_id": "inv-1" });
+        
+        assert_eq!(gateway.dispatch("invoice", "get", params.clone()).await.unwrap(), json!("accepted"));
+        
+        let direct = backend.dispatch("invoice", "get", params.clone()).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&direct), ServiceError::Unauthorized(_)));
+        
+        // A token minted with another secret, or for another service, doesn't pass either
+        let forged = InternalAuth::new("not-the-secret").stamp("invoice", params.clone());
+        assert!(backend.dispatch("invoice", "get", forged).await.is_err());
+        let misdirected = InternalAuth::new(SECRET).stamp("profile", params);
+        assert!(backend.dispatch("invoice", "get", misdirected).await.is_err());
+    }
+}
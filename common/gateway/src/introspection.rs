@@ -0,0 +1,74 @@
+use std::sync::Mutex;
+
+/// Response header listing each middleware's decision when debug mode is on
+pub const DEBUG_MIDDLEWARE_HEADER: &str = "x-debug-middleware";
+
+/// One middleware's part in handling a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MiddlewareStep {
+    pub name: &'static str,
+    /// Whether it handed the request on down the chain
+    pub passed: bool,
+    /// Status of the response it returned, if it returned one
+    pub status: Option<u16>,
+}
+
+/// Record of the middleware a request went through, filled in by `Next`
+#[derive(Debug, Default)]
+pub struct MiddlewareTrace {
+    steps: Mutex<Vec<MiddlewareStep>>,
+}
+
+impl MiddlewareTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub(crate) fn enter(&self, name: &'static str) -> usize {
+        let mut steps = self.steps.lock().unwrap_or_else(|e| e.into_inner());
+        steps.push(MiddlewareStep { name, passed: false, status: None });
+        steps.len() - 1
+    }
+    
+    pub(crate) fn mark_passed(&self, index: usize) {
+        if let Some(step) = self.steps.lock().unwrap_or_else(|e| e.into_inner()).get_mut(index) {
+            step.passed = true;
+        }
+    }
+    
+    pub(crate) fn finish(&self, index: usize, status: Option<u16>) {
+        if let Some(step) = self.steps.lock().unwrap_or_else(|e| e.into_inner()).get_mut(index) {
+            step.status = status;
+        }
+    }
+    
+    /// Steps in the order the request reached them
+    pub fn steps(&self) -> Vec<MiddlewareStep> {
+        self.steps.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+    
+    /// Step that produced the response without passing the request on
+    pub fn terminating_step(&self) -> Option<MiddlewareStep> {
+        self.steps().into_iter().find(|step| !step.passed)
+    }
+    
+    /// Render as `X-Debug-Middleware`, e.g. `CorsMiddleware=pass, ScopeMiddleware=401`
+    pub fn header_value(&self) -> String {
+        self.steps()
+            .iter()
+            .map(|step| match (step.passed, step.status) {
+                (true, _) => format!("{}=pass", step.name),
+                (false, Some(status)) => format!("{}={}", step.name, status),
+                (false, None) => format!("{}=error", step.name),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Last path segment of a type name, e.g. `CorsMiddleware` for `kagi_gateway::CorsMiddleware`
+pub(crate) fn short_type_name<T: ?Sized>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
+}
@@ -0,0 +1,388 @@
+use crate::auth::Authentication;
+use crate::batch::{self, BatchCall, Caller};
+use crate::forwarding::RequestBody;
+use crate::routing::MatchedRoute;
+use crate::{body, error_response, error_response_for, logging, resolve_tenant, GatewaySettings, GatewayState, HandlerFn, Next};
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use kagi_shared::ServiceError;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::{RwLock, Semaphore};
+use uuid::Uuid;
+
+/// Path accepting job submissions; a job's status lives at `/jobs/:id`
+pub const JOBS_PATH: &str = "/jobs";
+
+/// Route pattern of a job's status, as seen by middleware
+const JOB_PATTERN: &str = "/jobs/:id";
+
+/// How long finished jobs stay available to poll
+const FINISHED_JOB_RETENTION_MINUTES: i64 = 60;
+
+/// Most jobs tracked at once, finished or not
+const MAX_TRACKED_JOBS: usize = 10_000;
+
+/// Lifecycle of a background job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// Who submitted a job; nobody else can poll it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobOwner {
+    pub tenant_id: String,
+    /// Id of the authenticated user, or their token when the validator returns no id.
+    /// `None` when the gateway doesn't authenticate requests.
+    pub user_id: Option<String>,
+}
+
+impl JobOwner {
+    /// The owner of a request that has been through the middleware chain
+    pub fn of(req: &Request<Body>) -> Self {
+        let user_id = req.extensions()
+            .get::<Authentication>()
+            .and_then(Authentication::user)
+            .map(|user| match user.user.get("id") {
+                Some(Value::String(id)) => id.clone(),
+                Some(id) if !id.is_null() => id.to_string(),
+                _ => user.token.clone(),
+            });
+        
+        Self {
+            tenant_id: resolve_tenant(req),
+            user_id,
+        }
+    }
+}
+
+/// A service call running in the background
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: String,
+    #[serde(skip)]
+    pub owner: JobOwner,
+    pub service: String,
+    pub action: String,
+    pub status: JobStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ServiceError>,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Tracks background jobs and bounds how many run at once
+pub struct JobStore {
+    jobs: RwLock<HashMap<String, Job>>,
+    permits: Arc<Semaphore>,
+    max_tracked: usize,
+}
+
+impl JobStore {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            jobs: RwLock::new(HashMap::new()),
+            permits: Arc::new(Semaphore::new(max_concurrent.max(1))),
+            max_tracked: MAX_TRACKED_JOBS,
+        }
+    }
+    
+    /// Queue `run` as a job calling `service.action`, returning the pending job.
+    ///
+    /// The job waits for a free slot before running, so a burst of submissions
+    /// never runs more than the configured number of calls at once. When the
+    /// store is full the submission fails with `Unavailable`.
+    pub async fn submit<F>(
+        self: &Arc<Self>,
+        owner: JobOwner,
+        service: String,
+        action: String,
+        run: F,
+    ) -> Result<Job, ServiceError>
+    where
+        F: Future<Output = Result<Value, ServiceError>> + Send + 'static,
+    {
+        let job = Job {
+            id: Uuid::new_v4().to_string(),
+            owner,
+            service,
+            action,
+            status: JobStatus::Pending,
+            result: None,
+            error: None,
+            created_at: Utc::now(),
+            finished_at: None,
+        };
+        
+        {
+            let mut jobs = self.jobs.write().await;
+            let cutoff = Utc::now() - Duration::minutes(FINISHED_JOB_RETENTION_MINUTES);
+            jobs.retain(|_, job| job.finished_at.map_or(true, |finished| finished > cutoff));
+            if jobs.len() >= self.max_tracked {
+                return Err(ServiceError::unavailable("Too many jobs in progress; try again later"));
+            }
+            jobs.insert(job.id.clone(), job.clone());
+        }
+        
+        let store = self.clone();
+        let job_id = job.id.clone();
+        tokio::spawn(async move {
+            // The semaphore lives as long as the store, so acquiring can't fail
+            let _permit = store.permits.clone().acquire_owned().await;
+            store.update(&job_id, |job| job.status = JobStatus::Running).await;
+            
+            let result = run.await;
+            store.update(&job_id, |job| {
+                match result {
+                    Ok(value) => {
+                        job.status = JobStatus::Succeeded;
+                        job.result = Some(value);
+                    },
+                    Err(e) => {
+                        job.status = JobStatus::Failed;
+                        job.error = Some(e);
+                    },
+                }
+                job.finished_at = Some(Utc::now());
+            }).await;
+        });
+        
+        Ok(job)
+    }
+    
+    async fn update(&self, job_id: &str, apply: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.jobs.write().await.get_mut(job_id) {
+            apply(job);
+        }
+    }
+    
+    /// Look up a job submitted by `owner`
+    pub async fn get(&self, owner: &JobOwner, job_id: &str) -> Option<Job> {
+        self.jobs
+            .read()
+            .await
+            .get(job_id)
+            .filter(|job| job.owner == *owner)
+            .cloned()
+    }
+}
+
+/// Id in a `/jobs/:id` path
+fn job_id(path: &str) -> Option<&str> {
+    path.strip_prefix(JOBS_PATH)?
+        .strip_prefix('/')
+        .filter(|id| !id.is_empty() && !id.contains('/'))
+}
+
+/// Whether a request is for `POST /jobs` or `GET /jobs/:id`
+pub(crate) fn is_jobs_request(method: &Method, path: &str) -> bool {
+    (*method == Method::POST && path == JOBS_PATH) || (*method == Method::GET && job_id(path).is_some())
+}
+
+fn json_response(status: StatusCode, job: &Job) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::json!(job).to_string()))
+        .unwrap()
+}
+
+/// Queue the call in `body`; it later runs through the middleware chain of the
+/// route exposing it, with the submitter's credentials
+async fn submit(
+    state: Arc<GatewayState>,
+    settings: Arc<GatewaySettings>,
+    caller: Caller,
+    owner: JobOwner,
+    body: &[u8],
+) -> Result<Response<Body>> {
+    let call: BatchCall = serde_json::from_slice(body)
+        .map_err(|e| ServiceError::validation(format!("Invalid job: {}", e)))?;
+    let (service, action) = (call.service.clone(), call.action.clone());
+    let jobs = state.jobs.clone();
+    let run = async move { batch::execute_call(&state, &settings, &caller, call).await };
+    
+    let job = jobs.submit(owner, service, action, run).await?;
+    let mut response = json_response(StatusCode::ACCEPTED, &job);
+    if let Ok(location) = header::HeaderValue::from_str(&format!("{}/{}", JOBS_PATH, job.id)) {
+        response.headers_mut().insert(header::LOCATION, location);
+    }
+    Ok(response)
+}
+
+/// Serve `POST /jobs` and `GET /jobs/:id` behind the global middleware chain.
+///
+/// A job belongs to the tenant and authenticated user that submitted it, and
+/// polling anyone else's job answers `404`.
+pub(crate) async fn jobs_response(
+    state: Arc<GatewayState>,
+    settings: Arc<GatewaySettings>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let (parts, body) = req.into_parts();
+    let body = match body::read_limited(&parts.headers, body, settings.config.max_body_bytes).await {
+        Ok(body) => body,
+        Err(e) => return error_response(&settings.config, e.status(), &e.to_string()),
+    };
+    let caller = Caller::from_parts(&parts);
+    let pattern = if parts.method == Method::POST { JOBS_PATH } else { JOB_PATTERN };
+    let method = parts.method.to_string();
+    
+    let mut req = Request::from_parts(parts, Body::from(body.clone()));
+    req.extensions_mut().insert(RequestBody(body));
+    req.extensions_mut().insert(MatchedRoute {
+        method,
+        pattern: pattern.to_string(),
+    });
+    req.extensions_mut().insert(Authentication::default());
+    let request_id = logging::RequestId::for_request(&req);
+    req.extensions_mut().insert(request_id);
+    
+    let handler: Box<HandlerFn> = {
+        let (state, settings) = (state.clone(), settings.clone());
+        Box::new(move |req: &Request<Body>| {
+            let (state, settings, caller) = (state.clone(), settings.clone(), caller.clone());
+            let owner = JobOwner::of(req);
+            let body = req.extensions().get::<RequestBody>().map(|body| body.0.clone()).unwrap_or_default();
+            let polled = job_id(req.uri().path()).map(str::to_string);
+            Box::pin(async move {
+                match polled {
+                    None => submit(state, settings, caller, owner, &body).await,
+                    Some(job_id) => match state.jobs.get(&owner, &job_id).await {
+                        Some(job) => Ok(json_response(StatusCode::OK, &job)),
+                        None => Err(ServiceError::not_found("Job not found").into()),
+                    },
+                }
+            })
+        })
+    };
+    
+    match Next::new(&settings.middlewares, handler.as_ref()).run(&req).await {
+        Ok(response) => response,
+        Err(e) => error_response_for(&settings.config, &e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metrics;
+    use crate::{register_route, route_http_request, Gateway, GatewayConfig, RouteInfo};
+    use async_trait::async_trait;
+    use serde_json::json;
+    
+    struct EchoGateway;
+    
+    #[async_trait]
+    impl Gateway for EchoGateway {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn dispatch(&self, _service: &str, _action: &str, params: Value) -> Result<Value> {
+            Ok(params)
+        }
+    }
+    
+    fn state() -> Arc<GatewayState> {
+        register_route(RouteInfo {
+            method: "POST",
+            path: "/jobs-tests/echo",
+            handler_name: "jobtest.echo",
+            middleware: None,
+        });
+        Arc::new(GatewayState::new(Arc::new(EchoGateway), &GatewayConfig::default(), Arc::new(Metrics::new())).unwrap())
+    }
+    
+    async fn send(state: &Arc<GatewayState>, req: Request<Body>) -> (StatusCode, Option<String>, Value) {
+        let response = route_http_request(req, state.clone()).await.unwrap();
+        let status = response.status();
+        let location = response.headers()
+            .get(header::LOCATION)
+            .map(|v| v.to_str().unwrap().to_string());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, location, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+    
+    fn poll(location: &str, host: &str) -> Request<Body> {
+        Request::get(location).header(header::HOST, host).body(Body::empty()).unwrap()
+    }
+    
+    #[tokio::test]
+    async fn submitted_job_can_be_polled_until_it_has_a_result() {
+        let state = state();
+        let submission = Request::post(JOBS_PATH)
+            .header(header::HOST, "acme.api.example.com")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "service": "jobtest", "action": "echo", "params": { "n": 7 } }).to_string()))
+            .unwrap();
+        
+        let (status, location, job) = send(&state, submission).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(job["status"], "pending");
+        let location = location.expect("submission should point at the job");
+        
+        let mut polled = Value::Null;
+        for _ in 0..100 {
+            let (status, _, job) = send(&state, poll(&location, "acme.api.example.com")).await;
+            assert_eq!(status, StatusCode::OK);
+            if job["status"] == "succeeded" {
+                polled = job;
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(polled["result"]["n"], 7);
+        assert_eq!(polled["result"]["tenant_id"], "acme");
+        
+        // Another tenant can't see the job
+        let (status, _, _) = send(&state, poll(&location, "other.api.example.com")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+    
+    #[tokio::test]
+    async fn full_store_is_unavailable() {
+        let store = Arc::new(JobStore {
+            max_tracked: 1,
+            ..JobStore::new(1)
+        });
+        let owner = JobOwner {
+            tenant_id: "acme".to_string(),
+            user_id: None,
+        };
+        let never = futures::future::pending::<Result<Value, ServiceError>>();
+        store.submit(owner.clone(), "jobtest".into(), "echo".into(), never).await.unwrap();
+        
+        let rejected = store.submit(owner, "jobtest".into(), "echo".into(), async { Ok(Value::Null) }).await;
+        assert!(matches!(rejected, Err(ServiceError::Unavailable(_))));
+    }
+    
+    #[tokio::test]
+    async fn jobs_are_private_to_the_submitting_user() {
+        let store = Arc::new(JobStore::new(1));
+        let alice = JobOwner {
+            tenant_id: "acme".to_string(),
+            user_id: Some("alice".to_string()),
+        };
+        let bob = JobOwner {
+            user_id: Some("bob".to_string()),
+            ..alice.clone()
+        };
+        let job = store.submit(alice.clone(), "jobtest".into(), "echo".into(), async { Ok(Value::Null) }).await.unwrap();
+        
+        assert!(store.get(&alice, &job.id).await.is_some());
+        assert!(store.get(&bob, &job.id).await.is_none());
+    }
+}
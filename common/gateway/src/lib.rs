@@ -7,33 +7,57 @@ use hyper::{
 };
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::Infallible;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock};
+use std::sync::{Arc, PoisonError};
+use tokio::sync::{mpsc, Mutex, RwLock};
 use futures::{SinkExt, StreamExt};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
 use tokio_tungstenite::{
     tungstenite::protocol::Message, WebSocketStream,
 };
-use std::time::Duration;
-use tower;
+use std::time::{Duration, Instant};
+use crate::metrics::Metrics;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame, Role};
+use futures::stream::SplitSink;
+use kagi_shared::{Event, EventBus, ServiceError};
+use once_cell::sync::Lazy;
 
 // Re-exports
 pub use hyper;
 
-// Routes vector - replace distributed_slice with a simple static Vec
-pub static mut ROUTES: Vec<RouteInfo> = Vec::new();
+// Routes registry - replace distributed_slice with a lock-guarded static Vec
+pub static ROUTES: Lazy<std::sync::RwLock<Vec<RouteInfo>>> = Lazy::new(|| std::sync::RwLock::new(Vec::new()));
 
 // Helper function to register routes safely
 pub fn register_route(route: RouteInfo) {
-    unsafe {
-        ROUTES.push(route);
-    }
+    write_recover(&ROUTES).push(route);
+}
+
+/// Acquire a read guard, recovering it if a panicking writer poisoned the lock.
+///
+/// Route tables are always replaced wholesale, so the data behind a poisoned
+/// lock is still consistent and routing can safely carry on.
+pub(crate) fn read_recover<T>(lock: &std::sync::RwLock<T>) -> std::sync::RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|poisoned: PoisonError<_>| {
+        warn!("Recovering poisoned lock for reading");
+        poisoned.into_inner()
+    })
+}
+
+/// Acquire a write guard, recovering it if a panicking writer poisoned the lock
+pub(crate) fn write_recover<T>(lock: &std::sync::RwLock<T>) -> std::sync::RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|poisoned: PoisonError<_>| {
+        warn!("Recovering poisoned lock for writing");
+        lock.clear_poison();
+        poisoned.into_inner()
+    })
 }
 
 /// Information about a route
+#[derive(Debug, Clone)]
 pub struct RouteInfo {
     pub method: &'static str,
     pub path: &'static str, 
@@ -45,10 +69,27 @@ pub struct RouteInfo {
 #[async_trait]
 pub trait Gateway: Send + Sync {
     async fn run(&self) -> Result<()>;
+    
+    /// Call an action on a backend service
+    async fn dispatch(&self, service: &str, action: &str, _params: serde_json::Value) -> Result<serde_json::Value> {
+        Err(anyhow!("Service dispatch not supported: {}.{}", service, action))
+    }
+    
+    /// Call a list action, yielding its items one at a time.
+    ///
+    /// The default buffers the result of `dispatch`; gateways backed by a
+    /// streaming source should override this to yield items as they are produced.
+    async fn dispatch_stream(&self, service: &str, action: &str, params: serde_json::Value) -> Result<streaming::JsonStream> {
+        let items = match self.dispatch(service, action, params).await? {
+            serde_json::Value::Array(items) => items,
+            other => vec![other],
+        };
+        Ok(futures::stream::iter(items.into_iter().map(Ok)).boxed())
+    }
 }
 
 /// SSL configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SslConfig {
     pub enabled: bool,
     pub cert_file: Option<String>,
@@ -60,6 +101,43 @@ pub struct SslConfig {
 pub struct CorsConfig {
     pub allowed_origins: Vec<String>,
     pub allow_credentials: bool,
+    /// Methods listed in `Access-Control-Allow-Methods` on preflight responses
+    #[serde(default = "default_cors_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Headers listed in `Access-Control-Allow-Headers` on preflight responses
+    #[serde(default = "default_cors_headers")]
+    pub allowed_headers: Vec<String>,
+    /// Echo the preflight's `Access-Control-Request-Headers` instead of `allowed_headers`
+    #[serde(default)]
+    pub reflect_request_headers: bool,
+    /// Seconds browsers may cache a preflight response
+    #[serde(default = "default_cors_max_age_secs")]
+    pub max_age_secs: u64,
+}
+
+fn default_cors_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "DELETE", "OPTIONS"].iter().map(|m| m.to_string()).collect()
+}
+
+fn default_cors_headers() -> Vec<String> {
+    vec!["Content-Type".to_string(), "Authorization".to_string()]
+}
+
+fn default_cors_max_age_secs() -> u64 {
+    86_400
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allow_credentials: false,
+            allowed_methods: default_cors_methods(),
+            allowed_headers: default_cors_headers(),
+            reflect_request_headers: false,
+            max_age_secs: default_cors_max_age_secs(),
+        }
+    }
 }
 
 /// Rate limiting configuration
@@ -67,6 +145,19 @@ pub struct CorsConfig {
 pub struct RateLimitConfig {
     pub default_rate: u32,
     pub default_burst: u32,
+    /// Proxies whose `X-Forwarded-For` is believed; other peers are limited by their own address
+    #[serde(default)]
+    pub trusted_proxies: Vec<std::net::IpAddr>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            default_rate: 100,
+            default_burst: 200,
+            trusted_proxies: Vec::new(),
+        }
+    }
 }
 
 /// Authentication configuration
@@ -76,6 +167,55 @@ pub struct AuthConfig {
     pub expiration: u32,
 }
 
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            jwt_secret: String::new(),
+            expiration: 3600,
+        }
+    }
+}
+
+impl AuthConfig {
+    /// The secret from `JWT_SECRET`, the one `AuthService` signs tokens with
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            jwt_secret: kagi_shared::jwt_secret_from_env()?,
+            ..Self::default()
+        })
+    }
+}
+
+/// Response body used in place of the default JSON error for a status code
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorBodyTemplate {
+    /// Content type of the rendered body
+    #[serde(default = "default_error_content_type")]
+    pub content_type: String,
+    /// Body template; `{status}` and `{message}` are substituted when rendered
+    pub body: String,
+}
+
+fn default_error_content_type() -> String {
+    "application/json".to_string()
+}
+
+impl ErrorBodyTemplate {
+    /// Render the template, JSON-escaping the message when the body is JSON
+    pub fn render(&self, status: StatusCode, message: &str) -> String {
+        let message = if self.content_type.starts_with("application/json") {
+            let quoted = serde_json::Value::String(message.to_string()).to_string();
+            quoted[1..quoted.len() - 1].to_string()
+        } else {
+            message.to_string()
+        };
+        
+        self.body
+            .replace("{status}", status.as_str())
+            .replace("{message}", &message)
+    }
+}
+
 /// Gateway configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GatewayConfig {
@@ -88,66 +228,441 @@ pub struct GatewayConfig {
     pub auth: AuthConfig,
     pub middleware: Vec<String>,
     pub config_file: Option<String>,
+    /// Timeout in milliseconds for each backend probe made by `/readyz`
+    #[serde(default = "default_readiness_timeout_ms")]
+    pub readiness_timeout_ms: u64,
+    /// Custom error bodies keyed by HTTP status code
+    #[serde(default)]
+    pub error_bodies: HashMap<u16, ErrorBodyTemplate>,
+    /// Seconds without activity after which a WebSocket connection is closed
+    #[serde(default = "default_websocket_idle_timeout_secs")]
+    pub websocket_idle_timeout_secs: u64,
+    /// Hard ceiling in milliseconds on processing a request, including all middleware
+    #[serde(default)]
+    pub request_timeout_ms: Option<u64>,
+    /// Reject replayed requests to the listed routes
+    #[serde(default)]
+    pub replay_protection: Option<nonce::NonceConfig>,
+    /// Largest request body accepted, in bytes; larger ones get `413 Payload Too Large`
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+    /// Largest single part of a `multipart/form-data` body, in bytes
+    #[serde(default = "default_max_part_bytes")]
+    pub max_part_bytes: usize,
+    /// Compress responses for clients that send `Accept-Encoding`
+    #[serde(default)]
+    pub compression: Option<compression::CompressionConfig>,
+    /// Answer repeated GETs from an in-memory cache
+    #[serde(default)]
+    pub response_cache: Option<cache::CacheConfig>,
+    /// Directories served for GET requests no route matches
+    #[serde(default)]
+    pub static_files: Vec<static_files::StaticFiles>,
+    /// Cap concurrent connections; connections over the cap wait briefly, then get `503`
+    #[serde(default)]
+    pub connection_limit: Option<connections::ConnectionLimitConfig>,
+    /// Fraction of requests traced, from 0.0 to 1.0
+    #[serde(default = "default_trace_sample_rate")]
+    pub trace_sample_rate: f64,
+    /// Extra `host:port` addresses served alongside `host`/`port`, e.g. an internal admin port or `[::]:8080`
+    #[serde(default)]
+    pub additional_listeners: Vec<String>,
+    /// Bearer token required by `POST /_admin/reload`; the endpoint is disabled when unset
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// Secret shared with backend services to sign internal tokens on forwarded calls
+    #[serde(default)]
+    pub internal_auth_secret: Option<String>,
+    /// Most calls accepted in one `POST /batch`; larger batches get `400 Bad Request`
+    #[serde(default = "default_max_batch_size")]
+    pub max_batch_size: usize,
+    /// Most background jobs (`POST /jobs`) dispatching at once
+    #[serde(default = "default_max_concurrent_jobs")]
+    pub max_concurrent_jobs: usize,
+    /// Report each middleware's decision in an `X-Debug-Middleware` response header
+    #[serde(default)]
+    pub debug_middleware: bool,
+    /// Milliseconds in-flight requests get to finish after shutdown before their connections are closed
+    #[serde(default = "default_shutdown_grace_period_ms")]
+    pub shutdown_grace_period_ms: u64,
+    /// Fail calls to a backend fast with `503` after repeated failures
+    #[serde(default)]
+    pub circuit_breaker: Option<circuit_breaker::CircuitBreakerConfig>,
+    /// Retry forwarded GET, PUT and DELETE requests after transient backend failures
+    #[serde(default)]
+    pub retry: Option<retry::RetryConfig>,
+    /// Bus topics whose events are pushed to subscribed WebSocket clients
+    #[serde(default)]
+    pub event_topics: Vec<String>,
+    /// Bus the services publish on; events are only pushed when one is set
+    #[serde(skip)]
+    pub event_bus: Option<EventBus>,
+}
+
+fn default_max_body_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_max_part_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_shutdown_grace_period_ms() -> u64 {
+    30_000
+}
+
+fn default_max_batch_size() -> usize {
+    100
+}
+
+fn default_max_concurrent_jobs() -> usize {
+    16
+}
+
+fn default_trace_sample_rate() -> f64 {
+    1.0
+}
+
+fn default_websocket_idle_timeout_secs() -> u64 {
+    300
+}
+
+fn default_readiness_timeout_ms() -> u64 {
+    2000
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            services: Vec::new(),
+            ssl: SslConfig::default(),
+            cors: CorsConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            auth: AuthConfig::default(),
+            middleware: Vec::new(),
+            config_file: None,
+            readiness_timeout_ms: default_readiness_timeout_ms(),
+            error_bodies: HashMap::new(),
+            websocket_idle_timeout_secs: default_websocket_idle_timeout_secs(),
+            request_timeout_ms: None,
+            replay_protection: None,
+            max_body_bytes: default_max_body_bytes(),
+            max_part_bytes: default_max_part_bytes(),
+            compression: None,
+            response_cache: None,
+            connection_limit: None,
+            static_files: Vec::new(),
+            trace_sample_rate: default_trace_sample_rate(),
+            additional_listeners: Vec::new(),
+            admin_token: None,
+            internal_auth_secret: None,
+            max_batch_size: default_max_batch_size(),
+            max_concurrent_jobs: default_max_concurrent_jobs(),
+            debug_middleware: false,
+            shutdown_grace_period_ms: default_shutdown_grace_period_ms(),
+            circuit_breaker: None,
+            retry: None,
+            event_topics: Vec::new(),
+            event_bus: None,
+        }
+    }
+}
+
+impl GatewayConfig {
+    /// Load a JSON configuration file, remembering where it came from
+    pub fn from_file(path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read config file {}: {}", path, e))?;
+        let mut config: GatewayConfig = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid config file {}: {}", path, e))?;
+        config.config_file = Some(path.to_string());
+        config.validate()?;
+        Ok(config)
+    }
+    
+    /// Reject settings the gateway could not run with
+    pub fn validate(&self) -> Result<()> {
+        self.listen_addresses()?;
+        if self.rate_limit.default_rate == 0 || self.rate_limit.default_burst == 0 {
+            return Err(anyhow!("Rate limit rate and burst must be greater than zero"));
+        }
+        if let Some(origin) = self.cors.allowed_origins.iter().find(|o| o.trim().is_empty()) {
+            return Err(anyhow!("Invalid CORS origin: {:?}", origin));
+        }
+        if let Some(method) = self.cors.allowed_methods.iter().find(|m| Method::from_bytes(m.as_bytes()).is_err()) {
+            return Err(anyhow!("Invalid CORS method: {:?}", method));
+        }
+        if let Some(name) = self.cors.allowed_headers.iter().find(|h| header::HeaderName::from_bytes(h.as_bytes()).is_err()) {
+            return Err(anyhow!("Invalid CORS header: {:?}", name));
+        }
+        if !(0.0..=1.0).contains(&self.trace_sample_rate) {
+            return Err(anyhow!("trace_sample_rate must be between 0.0 and 1.0"));
+        }
+        if self.request_timeout_ms == Some(0) {
+            return Err(anyhow!("request_timeout_ms must be greater than zero"));
+        }
+        Ok(())
+    }
+    
+    /// Every address the gateway listens on, the primary `host`/`port` first
+    pub fn listen_addresses(&self) -> Result<Vec<SocketAddr>> {
+        // Parse the host on its own so bare IPv6 hosts like `::1` work
+        let host = self.host
+            .parse::<std::net::IpAddr>()
+            .map_err(|e| anyhow!("Invalid address {}: {}", self.host, e))?;
+        let mut addresses = vec![SocketAddr::new(host, self.port)];
+        
+        for listener in &self.additional_listeners {
+            let addr = listener
+                .parse::<SocketAddr>()
+                .map_err(|e| anyhow!("Invalid address {}: {}", listener, e))?;
+            if !addresses.contains(&addr) {
+                addresses.push(addr);
+            }
+        }
+        
+        Ok(addresses)
+    }
+    
+    /// Start building a configuration from the defaults
+    pub fn builder() -> builder::GatewayConfigBuilder {
+        builder::GatewayConfigBuilder::new()
+    }
 }
 
 /// Middleware for processing HTTP requests
 #[async_trait]
 pub trait Middleware: Send + Sync {
     async fn process(&self, request: &Request<Body>, next: Next<'_>) -> Result<Response<Body>>;
+    
+    /// Name shown in middleware debug output
+    fn name(&self) -> &'static str {
+        introspection::short_type_name::<Self>()
+    }
 }
 
+/// Final handler of a middleware chain.
+///
+/// It copies what it needs from the request before returning, so the future
+/// it hands back doesn't borrow the request.
+pub type HandlerFn = dyn Fn(&Request<Body>) -> futures::future::BoxFuture<'static, Result<Response<Body>>> + Send + Sync;
+
 /// Next middleware in the chain
 pub struct Next<'a> {
     middlewares: &'a [Box<dyn Middleware>],
-    handler: &'a (dyn Fn(&Request<Body>) -> Result<Response<Body>> + Send + Sync),
+    /// Middleware declared on the matched route, run after the global chain
+    route_middlewares: &'a [Box<dyn Middleware>],
+    handler: &'a HandlerFn,
+    /// Debug record of each middleware's decision, when enabled
+    trace: Option<&'a introspection::MiddlewareTrace>,
+    /// Trace step of the middleware that is calling `run` on this `Next`
+    caller: Option<usize>,
 }
 
 impl<'a> Next<'a> {
     pub fn new(
         middlewares: &'a [Box<dyn Middleware>],
-        handler: &'a (dyn Fn(&Request<Body>) -> Result<Response<Body>> + Send + Sync),
+        handler: &'a HandlerFn,
+    ) -> Self {
+        Self::with_route(middlewares, &[], handler)
+    }
+    
+    /// Chain running the global middleware, then the route's own, then the handler
+    pub fn with_route(
+        middlewares: &'a [Box<dyn Middleware>],
+        route_middlewares: &'a [Box<dyn Middleware>],
+        handler: &'a HandlerFn,
     ) -> Self {
         Self {
             middlewares,
+            route_middlewares,
             handler,
+            trace: None,
+            caller: None,
         }
     }
+    
+    /// Record each middleware's decision into `trace`
+    pub fn with_trace(mut self, trace: &'a introspection::MiddlewareTrace) -> Self {
+        self.trace = Some(trace);
+        self
+    }
 
     pub async fn run(self, req: &Request<Body>) -> Result<Response<Body>> {
-        if let Some((current, rest)) = self.middlewares.split_first() {
-            let next = Next {
-                middlewares: rest,
-                handler: self.handler,
-            };
-            current.process(req, next).await
+        if let (Some(trace), Some(caller)) = (self.trace, self.caller) {
+            trace.mark_passed(caller);
+        }
+        
+        let (current, middlewares, route_middlewares) = if let Some((current, rest)) = self.middlewares.split_first() {
+            (current, rest, self.route_middlewares)
+        } else if let Some((current, rest)) = self.route_middlewares.split_first() {
+            (current, &[][..], rest)
         } else {
-            (self.handler)(req)
+            return (self.handler)(req).await;
+        };
+        
+        let step = self.trace.map(|trace| trace.enter(current.name()));
+        let next = Next {
+            middlewares,
+            route_middlewares,
+            handler: self.handler,
+            trace: self.trace,
+            caller: step,
+        };
+        let result = current.process(req, next).await;
+        
+        if let (Some(trace), Some(step)) = (self.trace, step) {
+            trace.finish(step, result.as_ref().ok().map(|response| response.status().as_u16()));
         }
+        result
     }
 }
 
+/// Gauge tracking open WebSocket connections
+pub const WS_ACTIVE_GAUGE: &str = "gateway_websocket_connections_active";
+/// Gauge tracking WebSocket connections quiet for longer than one heartbeat
+pub const WS_IDLE_GAUGE: &str = "gateway_websocket_connections_idle";
+
+/// Heartbeats a peer may leave unanswered before its connection is closed
+pub const MAX_MISSED_HEARTBEATS: u32 = 2;
+
+/// Frames a connection may have queued before it counts as too slow and is dropped
+pub const WS_OUTBOX_CAPACITY: usize = 64;
+
+/// WebSocket stream over a connection upgraded by hyper
+pub type WsStream = WebSocketStream<hyper::upgrade::Upgraded>;
+
 /// WebSocket connection wrapper
 pub struct WebSocketConnection {
     id: String,
-    socket: WebSocketStream<TcpStream>,
+    /// Tenant resolved from the upgrade request; only its events are pushed here
+    tenant_id: String,
+    /// User the upgrade request's token was issued to; anonymous connections can't subscribe
+    user_id: Option<String>,
+    /// Event topics the peer asked to receive
+    topics: HashSet<String>,
+    /// Distinguishes this socket from a later one registered under the same id
+    session: uuid::Uuid,
+    /// Frames waiting for the connection's writer task, see `spawn_writer`
+    outbox: mpsc::Sender<Message>,
+    last_activity: Instant,
+    /// When the peer last answered a heartbeat
+    last_pong: Instant,
 }
 
 impl WebSocketConnection {
-    pub fn new(id: String, socket: WebSocketStream<TcpStream>) -> Self {
-        Self { id, socket }
+    pub fn new(id: String, tenant_id: String, outbox: mpsc::Sender<Message>) -> Self {
+        Self {
+            id,
+            tenant_id,
+            user_id: None,
+            topics: HashSet::new(),
+            session: uuid::Uuid::new_v4(),
+            outbox,
+            last_activity: Instant::now(),
+            last_pong: Instant::now(),
+        }
+    }
+    
+    /// Mark the connection as opened by `user_id`
+    pub fn authenticated_as(mut self, user_id: String) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+    
+    /// Queue a frame without waiting on the socket; fails when the outbox is
+    /// full because the peer isn't keeping up, or closed because it is gone
+    fn queue(&self, message: Message) -> Result<()> {
+        self.outbox.try_send(message).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => anyhow!("WebSocket connection {} is not keeping up", self.id),
+            mpsc::error::TrySendError::Closed(_) => anyhow!("WebSocket connection {} is closed", self.id),
+        })
     }
 
-    pub async fn send(&mut self, data: serde_json::Value) -> Result<()> {
-        let message = Message::Text(data.to_string());
-        self.socket.send(message).await.map_err(|e| anyhow!("WebSocket send error: {}", e))?;
-        Ok(())
+    pub fn send(&self, data: serde_json::Value) -> Result<()> {
+        self.queue(Message::Text(data.to_string()))
+    }
+    
+    /// Record that the peer was active just now
+    pub fn touch(&mut self) {
+        self.last_activity = Instant::now();
+    }
+    
+    /// Time since the peer was last active
+    pub fn idle_for(&self) -> Duration {
+        self.last_activity.elapsed()
+    }
+    
+    /// Record that the peer answered a heartbeat just now.
+    ///
+    /// Answering heartbeats proves the peer is alive, not that it is in use,
+    /// so this doesn't count as activity for idle reaping.
+    pub fn record_pong(&mut self) {
+        self.last_pong = Instant::now();
+    }
+    
+    /// Whether the peer has left `MAX_MISSED_HEARTBEATS` heartbeats in a row unanswered
+    pub fn missed_heartbeats(&self, heartbeat: Duration) -> bool {
+        self.last_pong.elapsed() >= heartbeat * MAX_MISSED_HEARTBEATS
+    }
+    
+    /// Send a ping frame; the peer's pong is recorded by the read loop
+    pub fn ping(&self) -> Result<()> {
+        self.queue(Message::Ping(Vec::new()))
+    }
+    
+    /// Send a close frame with the given reason
+    pub fn close(&self, code: CloseCode, reason: &str) -> Result<()> {
+        let frame = CloseFrame {
+            code,
+            reason: reason.to_string().into(),
+        };
+        self.queue(Message::Close(Some(frame)))
     }
 }
 
+/// Write a connection's queued frames to its socket.
+///
+/// Runs until the connection is dropped, a close frame has gone out or the
+/// socket fails, so a slow peer only ever holds up its own frames.
+fn spawn_writer(mut sink: SplitSink<WsStream, Message>, mut outbox: mpsc::Receiver<Message>) {
+    tokio::spawn(async move {
+        while let Some(message) = outbox.recv().await {
+            let closing = matches!(message, Message::Close(_));
+            if let Err(e) = sink.send(message).await {
+                debug!("WebSocket write failed: {}", e);
+                return;
+            }
+            if closing {
+                return;
+            }
+        }
+        let _ = sink.close().await;
+    });
+}
+
+/// What to do when a connection arrives with an id that is already connected.
+///
+/// Ids come from the client's `?id=` on the upgrade request, so replacing is
+/// only safe where every client is trusted not to take over another's id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateIdPolicy {
+    /// Close the new connection and keep the existing one
+    #[default]
+    Reject,
+    /// Close the existing connection, then register the new one in its place
+    ReplaceExisting,
+}
+
 /// Handler for WebSocket connections
 pub struct WebSocketHandler {
     connections: Arc<RwLock<HashMap<String, WebSocketConnection>>>,
     heartbeat: Duration,
+    idle_timeout: Duration,
+    metrics: Arc<Metrics>,
+    duplicate_ids: DuplicateIdPolicy,
 }
 
 impl WebSocketHandler {
@@ -155,35 +670,289 @@ impl WebSocketHandler {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
             heartbeat,
+            idle_timeout: Duration::from_secs(default_websocket_idle_timeout_secs()),
+            metrics: Arc::new(Metrics::new()),
+            duplicate_ids: DuplicateIdPolicy::default(),
+        }
+    }
+    
+    /// Choose how connections reusing an active id are handled
+    pub fn with_duplicate_id_policy(mut self, policy: DuplicateIdPolicy) -> Self {
+        self.duplicate_ids = policy;
+        self
+    }
+    
+    /// Close connections that have been idle for longer than `idle_timeout`
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+    
+    /// Report connection gauges into a shared metrics registry
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+    
+    /// Refresh the active/idle connection gauges
+    fn update_gauges(&self, connections: &HashMap<String, WebSocketConnection>) {
+        let idle = connections.values()
+            .filter(|conn| conn.idle_for() >= self.heartbeat)
+            .count();
+        self.metrics.set_gauge(WS_ACTIVE_GAUGE, connections.len() as i64);
+        self.metrics.set_gauge(WS_IDLE_GAUGE, idle as i64);
+    }
+    
+    /// Close and remove every connection idle beyond the threshold, returning how many were reaped
+    pub async fn reap_idle(&self) -> usize {
+        let mut connections = self.connections.write().await;
+        let expired: Vec<String> = connections.iter()
+            .filter(|(_, conn)| conn.idle_for() >= self.idle_timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+        
+        for id in &expired {
+            if let Some(conn) = connections.remove(id) {
+                info!("Reaping idle WebSocket connection {} after {:?}", id, conn.idle_for());
+                if let Err(e) = conn.close(CloseCode::Away, "Idle timeout") {
+                    debug!("Failed to close idle connection {}: {}", id, e);
+                }
+            }
         }
+        
+        self.update_gauges(&connections);
+        expired.len()
     }
+    
+    /// Send a message to the connection registered under `id`.
+    ///
+    /// A connection that fails to take the message is dropped.
+    pub async fn send_to(&self, id: &str, msg: serde_json::Value) -> Result<()> {
+        let mut connections = self.connections.write().await;
+        let conn = connections.get(id)
+            .ok_or_else(|| ServiceError::not_found(format!("No WebSocket connection {}", id)))?;
 
-    pub async fn handle_connection(&self, socket: WebSocketStream<TcpStream>, id: String) {
-        debug!("New WebSocket connection: {}", id);
+        if let Err(e) = conn.send(msg) {
+            warn!("Dropping WebSocket connection {}: {}", id, e);
+            connections.remove(id);
+            self.update_gauges(&connections);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Send a message to every connection, returning how many received it.
+    ///
+    /// Connections that fail to take the message are dropped.
+    pub async fn broadcast(&self, msg: serde_json::Value) -> usize {
+        let mut connections = self.connections.write().await;
+        let mut sent = 0;
+        let mut failed = Vec::new();
+        for (id, conn) in connections.iter() {
+            match conn.send(msg.clone()) {
+                Ok(()) => sent += 1,
+                Err(e) => {
+                    warn!("Dropping WebSocket connection {}: {}", id, e);
+                    failed.push(id.clone());
+                },
+            }
+        }
+
+        for id in &failed {
+            connections.remove(id);
+        }
+        if !failed.is_empty() {
+            self.update_gauges(&connections);
+        }
+        sent
+    }
+
+    /// Push a bus event to the connections subscribed to its topic that were
+    /// opened by the user it concerns, returning how many received it.
+    ///
+    /// Events without a `tenant_id` and `user_id` in their payload are never
+    /// pushed, so nothing crosses tenants or reaches another user's socket.
+    /// Connections that fail to take the event are dropped.
+    pub async fn push_event(&self, event: &Event) -> usize {
+        let owner = event.payload.get("tenant_id").and_then(|t| t.as_str())
+            .zip(event.payload.get("user_id").and_then(|u| u.as_str()));
+        let (tenant_id, user_id) = match owner {
+            Some(owner) => owner,
+            None => return 0,
+        };
+        let msg = serde_json::json!({
+            "type": "event",
+            "topic": event.topic,
+            "kind": event.kind,
+            "payload": event.payload,
+        });
         
-        // Store connection
-        {
-            let conn = WebSocketConnection::new(id.clone(), socket);
-            self.connections.write().await.insert(id.clone(), conn);
+        let mut connections = self.connections.write().await;
+        let mut sent = 0;
+        let mut failed = Vec::new();
+        for (id, conn) in connections.iter() {
+            if conn.tenant_id != tenant_id || conn.user_id.as_deref() != Some(user_id) || !conn.topics.contains(&event.topic) {
+                continue;
+            }
+            match conn.send(msg.clone()) {
+                Ok(()) => sent += 1,
+                Err(e) => {
+                    warn!("Dropping WebSocket connection {}: {}", id, e);
+                    failed.push(id.clone());
+                },
+            }
+        }
+        
+        for id in &failed {
+            connections.remove(id);
+        }
+        if !failed.is_empty() {
+            self.update_gauges(&connections);
+        }
+        sent
+    }
+    
+    /// Push events published on `topics` to subscribed connections for as long
+    /// as the handler is alive
+    pub fn spawn_event_forwarder(self: &Arc<Self>, bus: &EventBus, topics: &[String]) {
+        for topic in topics {
+            let handler = Arc::downgrade(self);
+            let mut events = bus.subscribe(topic);
+            tokio::spawn(async move {
+                while let Some(event) = events.recv().await {
+                    match handler.upgrade() {
+                        Some(handler) => {
+                            handler.push_event(&event).await;
+                        },
+                        None => break,
+                    }
+                }
+            });
+        }
+    }
+
+    /// Ping every connection, closing and removing those that missed too many
+    /// heartbeats or can't take the ping; returns how many were removed
+    pub async fn heartbeat(&self) -> usize {
+        let mut connections = self.connections.write().await;
+        let mut dead = Vec::new();
+        for (id, conn) in connections.iter() {
+            if conn.missed_heartbeats(self.heartbeat) {
+                info!("Closing WebSocket connection {} after {} missed heartbeats", id, MAX_MISSED_HEARTBEATS);
+                if let Err(e) = conn.close(CloseCode::Away, "Heartbeat timeout") {
+                    debug!("Failed to close dead connection {}: {}", id, e);
+                }
+                dead.push(id.clone());
+            } else if let Err(e) = conn.ping() {
+                warn!("Dropping WebSocket connection {}: {}", id, e);
+                dead.push(id.clone());
+            }
         }
         
-        // Start a background task to handle the connection
-        let connections = self.connections.clone();
+        for id in &dead {
+            connections.remove(id);
+        }
+        self.update_gauges(&connections);
+        dead.len()
+    }
+    
+    /// Every heartbeat interval, ping connections and reap dead or idle ones
+    /// for as long as the handler is alive
+    pub fn spawn_reaper(self: &Arc<Self>) {
+        let handler = Arc::downgrade(self);
+        let interval = self.heartbeat;
         tokio::spawn(async move {
             loop {
-                let conn_guard = connections.read().await;
-                if let Some(_conn) = conn_guard.get(&id) {
-                    // Handle messages
-                    // This is a simplified example - in a real system you would
-                    // have proper message handling here
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                } else {
-                    break;
+                tokio::time::sleep(interval).await;
+                match handler.upgrade() {
+                    Some(handler) => {
+                        handler.heartbeat().await;
+                        handler.reap_idle().await;
+                    },
+                    None => break,
                 }
             }
         });
     }
 
+    /// Store a connection under its id, never leaving a displaced socket open;
+    /// returns the connection's session
+    async fn register(&self, conn: WebSocketConnection) -> Result<uuid::Uuid> {
+        let session = conn.session;
+        let mut connections = self.connections.write().await;
+        if connections.contains_key(&conn.id) {
+            match self.duplicate_ids {
+                DuplicateIdPolicy::Reject => {
+                    warn!("Rejecting WebSocket connection with duplicate id {}", conn.id);
+                    conn.close(CloseCode::Policy, "Connection id already in use")?;
+                    return Err(anyhow!("WebSocket connection id {} is already in use", conn.id));
+                },
+                DuplicateIdPolicy::ReplaceExisting => {
+                    if let Some(old) = connections.remove(&conn.id) {
+                        info!("Replacing WebSocket connection {} with a new one", conn.id);
+                        if let Err(e) = old.close(CloseCode::Normal, "Replaced by a new connection") {
+                            debug!("Failed to close replaced connection {}: {}", conn.id, e);
+                        }
+                    }
+                },
+            }
+        }
+        connections.insert(conn.id.clone(), conn);
+        self.update_gauges(&connections);
+        Ok(session)
+    }
+
+    /// Register an upgraded socket and serve it until the peer closes it or it fails.
+    ///
+    /// `user_id` is the user the upgrade request authenticated as, if any.
+    pub async fn handle_connection(&self, socket: WsStream, id: String, tenant_id: String, user_id: Option<String>) -> Result<()> {
+        debug!("New WebSocket connection: {}", id);
+        let (sink, mut stream) = socket.split();
+        let (outbox, queued) = mpsc::channel(WS_OUTBOX_CAPACITY);
+        spawn_writer(sink, queued);
+        let mut conn = WebSocketConnection::new(id.clone(), tenant_id, outbox);
+        if let Some(user_id) = user_id {
+            conn = conn.authenticated_as(user_id);
+        }
+        let session = self.register(conn).await?;
+        
+        // Read loop: runs until the peer closes, the socket errors, or we are replaced
+        let result = loop {
+            match stream.next().await {
+                Some(Ok(Message::Text(text))) => {
+                    if let Err(e) = self.handle_message(&id, text).await {
+                        warn!("Failed to handle WebSocket message from {}: {}", id, e);
+                    }
+                },
+                Some(Ok(Message::Close(_))) | None => break Ok(()),
+                Some(Ok(Message::Pong(_))) => {
+                    if let Some(conn) = self.connections.write().await.get_mut(&id) {
+                        conn.record_pong();
+                    }
+                },
+                Some(Ok(_)) => {
+                    // Pings are answered by tungstenite; any frame counts as activity
+                    if let Some(conn) = self.connections.write().await.get_mut(&id) {
+                        conn.touch();
+                    }
+                },
+                Some(Err(e)) => break Err(anyhow!("WebSocket error on {}: {}", id, e)),
+            }
+        };
+        
+        // Only forget the connection if it hasn't been replaced under the same id
+        {
+            let mut connections = self.connections.write().await;
+            if connections.get(&id).map_or(false, |conn| conn.session == session) {
+                connections.remove(&id);
+            }
+            self.update_gauges(&connections);
+        }
+        debug!("WebSocket connection closed: {}", id);
+        
+        result
+    }
+
     async fn handle_message(&self, id: &str, text: String) -> Result<()> {
         debug!("Received WebSocket message from {}: {}", id, text);
         
@@ -193,11 +962,49 @@ impl WebSocketHandler {
         // Get the message type
         let message_type = message.get("type").and_then(|v| v.as_str());
         
+        // Application-level pings are heartbeats, not activity
+        if message_type != Some("ping") {
+            if let Some(conn) = self.connections.write().await.get_mut(id) {
+                conn.touch();
+            }
+        }
+        
         match message_type {
             Some("ping") => {
-                // Send a pong response
-                if let Some(mut conn) = self.connections.write().await.get_mut(id) {
-                    conn.send(serde_json::json!({ "type": "pong" })).await?;
+                // An application-level ping proves the peer is alive just like a pong frame
+                if let Some(conn) = self.connections.write().await.get_mut(id) {
+                    conn.record_pong();
+                    conn.send(serde_json::json!({ "type": "pong" }))?;
+                }
+            },
+            Some(kind @ ("subscribe" | "unsubscribe")) => {
+                // Choose which bus topics get pushed to this connection
+                let topics: Vec<String> = message.get("topics")
+                    .and_then(|topics| serde_json::from_value(topics.clone()).ok())
+                    .unwrap_or_default();
+                if let Some(conn) = self.connections.write().await.get_mut(id) {
+                    if kind == "subscribe" && conn.user_id.is_none() {
+                        // Events are only pushed to the user they concern, so an anonymous socket has none
+                        conn.send(serde_json::json!({
+                            "type": "error",
+                            "error": {
+                                "message": "Subscribing requires an authenticated connection",
+                                "code": 401
+                            }
+                        }))?;
+                        return Ok(());
+                    }
+                    if kind == "subscribe" {
+                        conn.topics.extend(topics);
+                    } else {
+                        for topic in &topics {
+                            conn.topics.remove(topic);
+                        }
+                    }
+                    let mut subscribed: Vec<&String> = conn.topics.iter().collect();
+                    subscribed.sort();
+                    let reply = serde_json::json!({ "type": "subscriptions", "topics": subscribed });
+                    conn.send(reply)?;
                 }
             },
             Some("action") => {
@@ -209,16 +1016,16 @@ impl WebSocketHandler {
                 if let (Some(action_id), Some(_action), Some(params)) = (action_id, action, params) {
                     // Here you would dispatch the action to the appropriate service
                     // For now, just echo back the parameters
-                    if let Some(mut conn) = self.connections.write().await.get_mut(id) {
+                    if let Some(conn) = self.connections.read().await.get(id) {
                         conn.send(serde_json::json!({
                             "id": action_id,
                             "success": true,
                             "data": params
-                        })).await?;
+                        }))?;
                     }
                 } else {
                     // Invalid action request
-                    if let Some(mut conn) = self.connections.write().await.get_mut(id) {
+                    if let Some(conn) = self.connections.read().await.get(id) {
                         conn.send(serde_json::json!({
                             "id": action_id.unwrap_or("unknown"),
                             "success": false,
@@ -226,7 +1033,7 @@ impl WebSocketHandler {
                                 "message": "Invalid action request",
                                 "code": 400
                             }
-                        })).await?;
+                        }))?;
                     }
                 }
             },
@@ -239,111 +1046,344 @@ impl WebSocketHandler {
     }
 }
 
-/// Start the gateway service
+/// Wrap `gateway` in the internal authentication and circuit breaking `config` asks for
+pub(crate) fn layered_gateway(
+    mut gateway: Arc<dyn Gateway + Send + Sync>,
+    config: &GatewayConfig,
+) -> Arc<dyn Gateway + Send + Sync> {
+    if let Some(secret) = &config.internal_auth_secret {
+        gateway = Arc::new(internal::InternallyAuthenticated::new(gateway, kagi_shared::InternalAuth::new(secret)));
+    }
+    if let Some(breaker) = &config.circuit_breaker {
+        let breakers = Arc::new(circuit_breaker::CircuitBreakers::new(breaker));
+        gateway = Arc::new(circuit_breaker::CircuitBreaking::new(gateway, breakers));
+    }
+    gateway
+}
+
+/// Start the gateway service and run it until a listener fails
 pub async fn start_gateway<G: Gateway + Send + Sync + 'static>(gateway: G, config: GatewayConfig) -> Result<()> {
-    let routes = build_routes()?;
-    let middlewares = build_middleware(&config)?;
+    start_gateway_with_handle(gateway, config).await?.wait().await
+}
+
+/// Start the gateway service in the background.
+///
+/// Returns once every listener is bound; the handle shuts the gateway down gracefully.
+pub async fn start_gateway_with_handle<G: Gateway + Send + Sync + 'static>(gateway: G, config: GatewayConfig) -> Result<shutdown::GatewayHandle> {
+    let metrics = Arc::new(Metrics::new());
+    let gateway = layered_gateway(Arc::new(gateway), &config);
     
     // Create shared state
-    let state = Arc::new(GatewayState {
-        routes: Arc::new(routes),
-        middlewares: Arc::new(middlewares),
-        config: config.clone(),
-        gateway: Some(Arc::new(gateway)),
-    });
+    let state = Arc::new(GatewayState::new(gateway, &config, metrics.clone())?);
     
     // Create WebSocket handler
-    let ws_handler = Arc::new(WebSocketHandler::new(Duration::from_secs(30)));
+    let ws_handler = Arc::new(
+        WebSocketHandler::new(Duration::from_secs(30))
+            .with_idle_timeout(Duration::from_secs(config.websocket_idle_timeout_secs))
+            .with_metrics(metrics),
+    );
+    ws_handler.spawn_reaper();
+    if let Some(bus) = &config.event_bus {
+        ws_handler.spawn_event_forwarder(bus, &config.event_topics);
+    }
+    reload::spawn_sighup_listener(state.clone());
     
-    // Create address
-    let addr = format!("{}:{}", config.host, config.port)
-        .parse::<SocketAddr>()
-        .map_err(|e| anyhow!("Invalid address: {}", e))?;
+    let addresses = config.listen_addresses()?;
     
-    info!("Starting gateway server on {}", addr);
+    // One limiter across all listeners, so the cap holds however connections are spread
+    let limiter = config.connection_limit.as_ref().map(|limit| {
+        Arc::new(connections::ConnectionLimiter::new(limit).with_metrics(state.metrics.clone()))
+    });
     
-    // Use tower Service-based approach
-    let service = tower::service_fn(move |req: Request<Body>| {
+    // Each connection stamps its remote address on its requests so middleware can identify the client
+    let serve = {
         let state = state.clone();
         let ws_handler = ws_handler.clone();
-        
-        async move {
-            if is_websocket_request(&req) {
-                handle_websocket_request(req, ws_handler).await
-            } else {
-                handle_http_request(req, state).await
-            }
+        move |remote_addr: Option<SocketAddr>, admission: connections::Admission| {
+            let state = state.clone();
+            let ws_handler = ws_handler.clone();
+            // The service owns the admission, so the connection's slot is held until it closes
+            service_fn(move |mut req: Request<Body>| {
+                if let Some(remote_addr) = remote_addr {
+                    req.extensions_mut().insert(remote_addr);
+                }
+                let rejected = admission.is_rejected();
+                let state = state.clone();
+                let ws_handler = ws_handler.clone();
+                
+                async move {
+                    if rejected {
+                        Ok(connections::rejected_response(&state.settings().config))
+                    } else if is_websocket_request(&req) {
+                        handle_websocket_request(req, ws_handler, state.token_validator.clone()).await
+                    } else {
+                        handle_http_request(req, state).await
+                    }
+                }
+            })
         }
-    });
+    };
+    
+    let tls_config = if config.ssl.enabled {
+        Some(tls::server_config(&config.ssl)?)
+    } else {
+        None
+    };
     
-    // Create the server with the tower service
-    let server = hyper::Server::bind(&addr)
-        .serve(tower::make::Shared::new(service));
+    // Every listener serves the same routes and shares the same state
+    let trigger = shutdown::ShutdownTrigger::new();
+    let mut servers: Vec<futures::future::BoxFuture<'static, Result<()>>> = Vec::with_capacity(addresses.len());
+    for addr in addresses {
+        let serve = serve.clone();
+        let limiter = limiter.clone();
+        match &tls_config {
+            Some(tls_config) => {
+                let listener = TcpListener::bind(addr).await
+                    .map_err(|e| anyhow!("Failed to bind {}: {}", addr, e))?;
+                let server = hyper::Server::builder(tls::incoming(listener, tls_config.clone()))
+                    .serve(make_service_fn(move |conn: &tokio_rustls::server::TlsStream<tokio::net::TcpStream>| {
+                        let remote_addr = conn.get_ref().0.peer_addr().ok();
+                        let (serve, limiter) = (serve.clone(), limiter.clone());
+                        async move {
+                            let admission = connections::admit(limiter.as_ref()).await;
+                            Ok::<_, Infallible>(serve(remote_addr, admission))
+                        }
+                    }))
+                    .with_graceful_shutdown(trigger.signal().recv());
+                info!("Starting gateway server on https://{}", addr);
+                servers.push(Box::pin(async move {
+                    server.await.map_err(|e| anyhow!("Server error on {}: {}", addr, e))
+                }));
+            },
+            None => {
+                let server = hyper::Server::try_bind(&addr)
+                    .map_err(|e| anyhow!("Failed to bind {}: {}", addr, e))?
+                    .serve(make_service_fn(move |conn: &hyper::server::conn::AddrStream| {
+                        let remote_addr = conn.remote_addr();
+                        let (serve, limiter) = (serve.clone(), limiter.clone());
+                        async move {
+                            let admission = connections::admit(limiter.as_ref()).await;
+                            Ok::<_, Infallible>(serve(Some(remote_addr), admission))
+                        }
+                    }))
+                    .with_graceful_shutdown(trigger.signal().recv());
+                info!("Starting gateway server on http://{}", addr);
+                servers.push(Box::pin(async move {
+                    server.await.map_err(|e| anyhow!("Server error on {}: {}", addr, e))
+                }));
+            },
+        }
+    }
     
-    // Run the server
-    server.await.map_err(|e| anyhow!("Server error: {}", e))?;
+    // The gateway stops as soon as any listener fails, or once shutdown has drained them
+    let grace = Duration::from_millis(config.shutdown_grace_period_ms);
+    let task = tokio::spawn(shutdown::serve_until_shutdown(servers, trigger.signal(), grace));
     
-    Ok(())
+    Ok(shutdown::GatewayHandle::new(trigger, task))
+}
+
+/// Settings that can be swapped while the gateway is running
+pub(crate) struct GatewaySettings {
+    pub(crate) config: GatewayConfig,
+    pub(crate) middlewares: Vec<Box<dyn Middleware>>,
+    /// Middleware declared by individual routes, keyed by method and path pattern
+    pub(crate) route_middlewares: HashMap<(String, String), Vec<Box<dyn Middleware>>>,
+    pub(crate) sampler: sampling::TraceSampler,
 }
 
 /// Gateway state shared across HTTP handlers
-struct GatewayState {
-    routes: Arc<HashMap<(String, String), RouteHandler>>,
-    middlewares: Arc<Vec<Box<dyn Middleware>>>,
-    config: GatewayConfig,
+pub(crate) struct GatewayState {
+    /// Built from `ROUTES` by `initialize_routes`
+    routes: std::sync::RwLock<Arc<routing::RouteMatcher<RouteHandler>>>,
+    /// Set once `routes` has been built; readiness fails until then
+    routes_initialized: std::sync::atomic::AtomicBool,
+    /// Replaced wholesale on reload; requests keep the snapshot they started with
+    settings: std::sync::RwLock<Arc<GatewaySettings>>,
     gateway: Option<Arc<dyn Gateway + Send + Sync>>,
+    /// Used by the `auth` middleware, kept here so reloads can rebuild it
+    token_validator: Option<Arc<dyn auth::TokenValidator>>,
+    metrics: Arc<Metrics>,
+    jobs: Arc<jobs::JobStore>,
 }
 
 /// Type alias for route handlers
-type RouteHandler = Box<dyn Fn(&Request<Body>) -> Result<Response<Body>> + Send + Sync>;
+type RouteHandler = Box<HandlerFn>;
 
-/// Build routes from the ROUTES static vector
-fn build_routes() -> Result<HashMap<(String, String), RouteHandler>> {
-    let mut routes = HashMap::new();
+/// Build routes from the ROUTES static vector, each forwarding to the
+/// `service.action` named by its `handler_name`
+fn build_routes(
+    gateway: Arc<dyn Gateway + Send + Sync>,
+    metrics: Arc<Metrics>,
+    retry: Option<retry::RetryConfig>,
+) -> Result<routing::RouteMatcher<RouteHandler>> {
+    let retry = retry.map(Arc::new);
+    let mut routes = routing::RouteMatcher::new();
     
     // Access the static vector safely
-    let route_infos = unsafe { &ROUTES };
+    let route_infos = read_recover(&ROUTES);
     
     for route_info in route_infos.iter() {
-        let method = route_info.method.to_string();
-        let path = route_info.path.to_string();
-        
-        // Create a handler function for this route
-        let handler: RouteHandler = Box::new(move |_req: &Request<Body>| {
-            // This is a placeholder - in a real implementation, you would:
-            // 1. Extract parameters from the path
-            // 2. Parse the request body
-            // 3. Forward to the appropriate service/handler
-            // 4. Return the response
-            
-            Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "application/json")
-                .body(Body::from(format!("{{\"route\":\"{}\"}}", route_info.path)))
-                .unwrap())
+        let target = forwarding::RouteTarget::from_route(route_info)?;
+        debug!("Routing {} {} to {}.{}", route_info.method, route_info.path, target.service, target.action);
+        
+        let gateway = gateway.clone();
+        let metrics = metrics.clone();
+        let retry = retry.clone();
+        let handler: RouteHandler = Box::new(move |req: &Request<Body>| {
+            forwarding::forward(gateway.clone(), metrics.clone(), retry.clone(), target.clone(), req)
         });
         
-        routes.insert((method, path), handler);
+        routes.insert(route_info.method, route_info.path, handler);
     }
     
     Ok(routes)
 }
 
-/// Build middleware chain from configuration
-fn build_middleware(config: &GatewayConfig) -> Result<Vec<Box<dyn Middleware>>> {
-    let mut middlewares = Vec::new();
+impl GatewayState {
+    /// State serving the registered routes through `gateway` with `config`'s settings
+    pub(crate) fn new(
+        gateway: Arc<dyn Gateway + Send + Sync>,
+        config: &GatewayConfig,
+        metrics: Arc<Metrics>,
+    ) -> Result<Self> {
+        let state = Self::without_routes(gateway, config, metrics)?;
+        state.initialize_routes()?;
+        Ok(state)
+    }
     
-    // Add CORS middleware
-    let cors = CorsMiddleware::new(config.cors.clone());
-    middlewares.push(Box::new(cors) as Box<dyn Middleware>);
+    /// State that answers readiness checks with `503` until `initialize_routes` runs
+    pub(crate) fn without_routes(
+        gateway: Arc<dyn Gateway + Send + Sync>,
+        config: &GatewayConfig,
+        metrics: Arc<Metrics>,
+    ) -> Result<Self> {
+        let token_validator: Arc<dyn auth::TokenValidator> = Arc::new(auth::ServiceTokenValidator::new(gateway.clone()));
+        let middlewares = build_middleware(config, Some(&token_validator))?;
+        
+        Ok(Self {
+            routes: std::sync::RwLock::new(Arc::new(routing::RouteMatcher::new())),
+            routes_initialized: std::sync::atomic::AtomicBool::new(false),
+            settings: std::sync::RwLock::new(Arc::new(GatewaySettings {
+                config: config.clone(),
+                middlewares,
+                route_middlewares: build_route_middleware(config)?,
+                sampler: sampling::TraceSampler::new(config.trace_sample_rate),
+            })),
+            gateway: Some(gateway),
+            token_validator: Some(token_validator),
+            metrics,
+            jobs: Arc::new(jobs::JobStore::new(config.max_concurrent_jobs)),
+        })
+    }
     
-    // Add other middleware here based on config.middleware
+    /// Build the route table from `ROUTES`, forwarding each route through the gateway
+    pub(crate) fn initialize_routes(&self) -> Result<()> {
+        let gateway = self.gateway.clone()
+            .ok_or_else(|| anyhow!("No gateway available to dispatch calls"))?;
+        let retry = self.settings().config.retry.clone();
+        let routes = build_routes(gateway, self.metrics.clone(), retry)?;
+        *write_recover(&self.routes) = Arc::new(routes);
+        self.routes_initialized.store(true, std::sync::atomic::Ordering::SeqCst);
+        Ok(())
+    }
     
-    Ok(middlewares)
-}
-
-/// Check if a request is a WebSocket upgrade request
-fn is_websocket_request(req: &Request<Body>) -> bool {
+    /// Whether the route table has been built
+    pub(crate) fn routes_initialized(&self) -> bool {
+        self.routes_initialized.load(std::sync::atomic::Ordering::SeqCst)
+    }
+    
+    /// Current settings snapshot
+    pub(crate) fn settings(&self) -> Arc<GatewaySettings> {
+        read_recover(&self.settings).clone()
+    }
+    
+    /// Atomically replace the settings used by new requests
+    pub(crate) fn replace_settings(&self, settings: GatewaySettings) {
+        *write_recover(&self.settings) = Arc::new(settings);
+    }
+}
+
+/// Build middleware chain from configuration
+pub(crate) fn build_middleware(
+    config: &GatewayConfig,
+    token_validator: Option<&Arc<dyn auth::TokenValidator>>,
+) -> Result<Vec<Box<dyn Middleware>>> {
+    let mut middlewares = Vec::new();
+    
+    // Outermost, so the logged latency and status cover the whole chain
+    if config.middleware.iter().any(|name| name == logging::LOGGING_MIDDLEWARE) {
+        middlewares.push(Box::new(logging::LoggingMiddleware) as Box<dyn Middleware>);
+    }
+    
+    // The timeout comes next so it bounds every other middleware as well
+    if let Some(timeout_ms) = config.request_timeout_ms {
+        let timeout = TimeoutMiddleware::new(Duration::from_millis(timeout_ms));
+        middlewares.push(Box::new(timeout) as Box<dyn Middleware>);
+    }
+    
+    // Outside CORS, rate limiting and auth so error and throttled responses are compressed too
+    if let Some(compression) = &config.compression {
+        let compression = compression::CompressionMiddleware::new(compression);
+        middlewares.push(Box::new(compression) as Box<dyn Middleware>);
+    }
+    
+    // Add CORS middleware
+    let cors = CorsMiddleware::new(config.cors.clone());
+    middlewares.push(Box::new(cors) as Box<dyn Middleware>);
+    
+    // Inside CORS so throttled responses still carry CORS headers
+    let rate_limit = rate_limit::RateLimitMiddleware::new(&config.rate_limit);
+    middlewares.push(Box::new(rate_limit) as Box<dyn Middleware>);
+    
+    if let Some(replay_protection) = &config.replay_protection {
+        let nonces = nonce::NonceMiddleware::new(replay_protection.clone());
+        middlewares.push(Box::new(nonces) as Box<dyn Middleware>);
+    }
+    
+    if config.middleware.iter().any(|name| name == auth::AUTH_MIDDLEWARE) {
+        let validator = token_validator
+            .ok_or_else(|| anyhow!("The '{}' middleware needs a token validator", auth::AUTH_MIDDLEWARE))?;
+        middlewares.push(Box::new(auth::AuthMiddleware::new(validator.clone())) as Box<dyn Middleware>);
+    }
+    
+    // Innermost, so only authenticated requests are answered from the cache
+    if let Some(response_cache) = &config.response_cache {
+        middlewares.push(Box::new(cache::CacheMiddleware::new(response_cache)) as Box<dyn Middleware>);
+    }
+    
+    // Add other middleware here based on config.middleware
+    
+    Ok(middlewares)
+}
+
+/// Build the middleware each route declares in `RouteInfo::middleware`
+pub(crate) fn build_route_middleware(config: &GatewayConfig) -> Result<HashMap<(String, String), Vec<Box<dyn Middleware>>>> {
+    let mut route_middlewares = HashMap::new();
+    
+    let route_infos = read_recover(&ROUTES);
+    for route_info in route_infos.iter() {
+        let mut middlewares = Vec::new();
+        for name in route_info.middleware.iter().flatten() {
+            // Read by the global auth middleware rather than built per route
+            if *name == auth::PUBLIC_ROUTE {
+                continue;
+            }
+            match scopes::ScopeMiddleware::from_name(name, &config.auth)? {
+                Some(scope) => middlewares.push(Box::new(scope) as Box<dyn Middleware>),
+                None => warn!("Unknown middleware '{}' on route {} {}", name, route_info.method, route_info.path),
+            }
+        }
+        
+        if !middlewares.is_empty() {
+            route_middlewares.insert((route_info.method.to_uppercase(), route_info.path.to_string()), middlewares);
+        }
+    }
+    
+    Ok(route_middlewares)
+}
+
+/// Check if a request is a WebSocket upgrade request
+fn is_websocket_request(req: &Request<Body>) -> bool {
     req.headers().contains_key(header::UPGRADE) &&
     req.headers().get(header::UPGRADE)
         .and_then(|v| v.to_str().ok())
@@ -351,111 +1391,399 @@ fn is_websocket_request(req: &Request<Body>) -> bool {
         .unwrap_or(false)
 }
 
+/// Header letting clients behind restrictive proxies tunnel other methods through POST
+pub const METHOD_OVERRIDE_HEADER: &str = "x-http-method-override";
+
+/// Methods a POST request may be overridden to
+const OVERRIDABLE_METHODS: [Method; 3] = [Method::PUT, Method::PATCH, Method::DELETE];
+
+/// Resolve the method used for routing.
+///
+/// A POST carrying `X-HTTP-Method-Override` with an allowlisted method is routed
+/// as that method; the header is ignored on every other method.
+pub fn effective_method(req: &Request<Body>) -> Method {
+    if req.method() != Method::POST {
+        return req.method().clone();
+    }
+    
+    req.headers()
+        .get(METHOD_OVERRIDE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| Method::from_bytes(v.trim().to_uppercase().as_bytes()).ok())
+        .filter(|m| OVERRIDABLE_METHODS.contains(m))
+        .unwrap_or(Method::POST)
+}
+
+/// Tenant used when the request host carries no tenant subdomain
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Resolve the tenant for a request from its `Host` header.
+///
+/// The first label of a host with at least three labels is the tenant
+/// (e.g. `acme.api.example.com` -> `acme`); anything else maps to the default tenant.
+pub fn resolve_tenant(req: &Request<Body>) -> String {
+    let host = req.headers()
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    
+    // Strip the port, if any
+    let host = host.split(':').next().unwrap_or("");
+    let labels: Vec<&str> = host.split('.').filter(|l| !l.is_empty()).collect();
+    
+    if labels.len() >= 3 {
+        labels[0].to_lowercase()
+    } else {
+        DEFAULT_TENANT.to_string()
+    }
+}
+
+/// Stamp the resolved tenant onto forwarded parameters, overwriting any client-supplied value
+pub(crate) fn stamp_tenant(params: Option<serde_json::Value>, tenant_id: &str) -> serde_json::Value {
+    let mut params = match params {
+        Some(serde_json::Value::Object(map)) => serde_json::Value::Object(map),
+        _ => serde_json::json!({}),
+    };
+    params["tenant_id"] = serde_json::Value::String(tenant_id.to_string());
+    params
+}
+
+/// JSON error answering a WebSocket upgrade request that is refused
+fn websocket_refusal(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::json!({ "error": message }).to_string()))
+        .unwrap()
+}
+
+/// User id for the upgrade request's token, if it carries one.
+///
+/// Browsers can't set headers on WebSocket requests, so a `?token=` query
+/// parameter is accepted as well as a bearer token. An invalid token refuses
+/// the upgrade rather than falling back to an anonymous connection.
+async fn websocket_user(
+    req: &Request<Body>,
+    query_token: Option<String>,
+    token_validator: Option<&Arc<dyn auth::TokenValidator>>,
+) -> Result<Option<String>, Response<Body>> {
+    let token = match auth::bearer_token(req.headers()).map(str::to_string).or(query_token) {
+        Some(token) => token,
+        None => return Ok(None),
+    };
+    let validator = token_validator
+        .ok_or_else(|| websocket_refusal(StatusCode::UNAUTHORIZED, "Tokens can't be validated"))?;
+    
+    let tenant_id = resolve_tenant(req);
+    let user = match validator.validate_token(&tenant_id, &token).await {
+        Ok(user) => user,
+        Err(e) => {
+            return Err(match ServiceError::from_anyhow(&e) {
+                ServiceError::Internal(_) | ServiceError::Unavailable(_) => {
+                    warn!("WebSocket token validation failed: {}", e);
+                    websocket_refusal(StatusCode::SERVICE_UNAVAILABLE, "Token validation unavailable")
+                },
+                rejected => websocket_refusal(StatusCode::UNAUTHORIZED, rejected.message()),
+            });
+        },
+    };
+    user.get("id")
+        .and_then(|id| id.as_str())
+        .map(|id| Some(id.to_string()))
+        .ok_or_else(|| websocket_refusal(StatusCode::UNAUTHORIZED, "Token has no user"))
+}
+
 /// Handle WebSocket connection
 async fn handle_websocket_request(
     req: Request<Body>,
     ws_handler: Arc<WebSocketHandler>,
+    token_validator: Option<Arc<dyn auth::TokenValidator>>,
 ) -> Result<Response<Body>, Infallible> {
-    let uri = req.uri();
-    
-    // Generate a unique ID for this connection
-    let id = if let Some(query) = uri.query() {
-        // Extract ID from query params if present
-        query.to_string()
-    } else {
-        // Generate a random ID
-        format!("ws-{}", uuid::Uuid::new_v4())
-    };
+    let mut query = req.uri()
+        .query()
+        .and_then(|query| serde_urlencoded::from_str::<HashMap<String, String>>(query).ok())
+        .unwrap_or_default();
+    // Use the `?id=` query param if present, otherwise generate a unique ID
+    let id = query.remove("id")
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| format!("ws-{}", uuid::Uuid::new_v4()));
     
     // Get remote address for logging
     if let Some(addr) = req.extensions().get::<SocketAddr>() {
         debug!("WebSocket connection from {}: {}", addr, id);
     }
     
-    // For simplicity, we'll just return a response for now
-    // WebSocket implementation would require more complex handling
-    let response = Response::builder()
-        .status(StatusCode::NOT_IMPLEMENTED)
-        .body(Body::from("WebSocket support not fully implemented"))
-        .unwrap();
-        
-    Ok(response)
+    let key = match req.headers().get(header::SEC_WEBSOCKET_KEY) {
+        Some(key) => key.clone(),
+        None => return Ok(websocket_refusal(StatusCode::BAD_REQUEST, "Missing Sec-WebSocket-Key header")),
+    };
+    let user_id = match websocket_user(&req, query.remove("token"), token_validator.as_ref()).await {
+        Ok(user_id) => user_id,
+        Err(refusal) => return Ok(refusal),
+    };
+    let accept = derive_accept_key(key.as_bytes());
+    let tenant_id = resolve_tenant(&req);
+    
+    // hyper completes the HTTP handshake, so the upgraded IO is wrapped as an
+    // already-negotiated server-side socket rather than going through `accept_async`
+    tokio::spawn(async move {
+        match hyper::upgrade::on(req).await {
+            Ok(upgraded) => {
+                let socket = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                if let Err(e) = ws_handler.handle_connection(socket, id, tenant_id, user_id).await {
+                    debug!("WebSocket connection ended with error: {}", e);
+                }
+            },
+            Err(e) => warn!("WebSocket upgrade failed for {}: {}", id, e),
+        }
+    });
+    
+    Ok(Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, "Upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept)
+        .body(Body::empty())
+        .unwrap())
+}
+
+/// Build an error response, using the configured template for the status if there is one
+pub fn error_response(config: &GatewayConfig, status: StatusCode, message: &str) -> Response<Body> {
+    let (content_type, body) = match config.error_bodies.get(&status.as_u16()) {
+        Some(template) => (template.content_type.clone(), template.render(status, message)),
+        None => (
+            "application/json".to_string(),
+            serde_json::json!({ "error": message }).to_string(),
+        ),
+    };
+    
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Map an error to the HTTP status returned to clients.
+///
+/// Structured `ServiceError`s map directly; other errors fall back to matching
+/// common message phrasing, and anything unrecognised is a `500`. Every path
+/// that turns an error into a response goes through here.
+pub fn status_for_error(err: &anyhow::Error) -> StatusCode {
+    if let Some(service_error) = err.downcast_ref::<ServiceError>() {
+        return match service_error {
+            ServiceError::NotFound(_) => StatusCode::NOT_FOUND,
+            ServiceError::Validation(_) => StatusCode::BAD_REQUEST,
+            ServiceError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ServiceError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ServiceError::Conflict(_) => StatusCode::CONFLICT,
+            ServiceError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ServiceError::Gone(_) => StatusCode::GONE,
+            ServiceError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ServiceError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+    }
+    
+    let message = err.to_string().to_lowercase();
+    if message.contains("not found") {
+        StatusCode::NOT_FOUND
+    } else if message.contains("invalid token") || message.contains("unauthorized") {
+        StatusCode::UNAUTHORIZED
+    } else if message.contains("forbidden") || message.contains("permission denied") {
+        StatusCode::FORBIDDEN
+    } else if message.contains("already exists") || message.contains("conflict") {
+        StatusCode::CONFLICT
+    } else if message.contains("invalid") || message.contains("must be") {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
 }
 
-/// Handle HTTP request
+/// Build the response for an error, hiding the details of internal failures
+pub fn error_response_for(config: &GatewayConfig, err: &anyhow::Error) -> Response<Body> {
+    let status = status_for_error(err);
+    if status == StatusCode::INTERNAL_SERVER_ERROR {
+        error!("Error processing request: {}", err);
+        error_response(config, status, "Internal server error")
+    } else {
+        error_response(config, status, &err.to_string())
+    }
+}
+
+/// Handle HTTP request, tracing it when it is sampled
 async fn handle_http_request(
     req: Request<Body>,
     state: Arc<GatewayState>,
 ) -> Result<Response<Body>, Infallible> {
-    // Create a response for when things go wrong
+    let trace = state.settings().sampler.context_for(&req);
+    let traceparent = trace.traceparent();
+    let sampled = trace.sampled;
+    let (method, path) = (req.method().clone(), req.uri().path().to_string());
+    let started = Instant::now();
+    
+    if sampled {
+        info!("trace={} span={} started {} {}", trace.trace_id, trace.span_id, method, path);
+    }
+    let trace_id = trace.trace_id.clone();
+    let mut response = match trace.scope(route_http_request(req, state)).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    };
+    if sampled {
+        info!("trace={} finished {} {} -> {} in {:?}", trace_id, method, path, response.status(), started.elapsed());
+    }
+    
+    if let Ok(value) = header::HeaderValue::from_str(&traceparent) {
+        response.headers_mut().insert(sampling::TRACEPARENT_HEADER, value);
+    }
+    Ok(response)
+}
+
+/// Route an HTTP request to a built-in endpoint or a registered handler
+async fn route_http_request(
+    req: Request<Body>,
+    state: Arc<GatewayState>,
+) -> Result<Response<Body>, Infallible> {
+    let settings = state.settings();
     let error_response = |status: StatusCode, message: &str| {
-        let body = format!("{{\"error\":\"{}\"}}", message);
-        Response::builder()
-            .status(status)
-            .header(header::CONTENT_TYPE, "application/json")
-            .body(Body::from(body))
-            .unwrap()
+        error_response(&settings.config, status, message)
     };
     
     // Check if we have a route for this request
-    let method = req.method().to_string();
+    let method = effective_method(&req).to_string();
     let path = req.uri().path().to_string();
     
-    let response = match state.routes.get(&(method.clone(), path.clone())) {
-        Some(handler) => {
+    // Built-in endpoints bypass the route table
+    if req.method() == Method::GET && path == health::HEALTH_PATH {
+        return Ok(health::liveness_response(health::GATEWAY_VERSION));
+    }
+    if req.method() == Method::GET && path == openapi::OPENAPI_PATH {
+        return Ok(openapi::openapi_response(&read_recover(&ROUTES)));
+    }
+    if req.method() == Method::GET && health::is_readiness_path(&path) {
+        let timeout = Duration::from_millis(settings.config.readiness_timeout_ms);
+        let gateway = state.gateway.as_deref().map(|g| g as &dyn Gateway);
+        let initialized = state.routes_initialized();
+        return Ok(health::readiness_response(gateway, &settings.config.services, timeout, health::GATEWAY_VERSION, initialized).await);
+    }
+    if jobs::is_jobs_request(req.method(), &path) {
+        return Ok(jobs::jobs_response(state.clone(), settings.clone(), req).await);
+    }
+    if req.method() == Method::POST && path == batch::BATCH_PATH {
+        let (parts, body) = req.into_parts();
+        let body = match body::read_limited(&parts.headers, body, settings.config.max_body_bytes).await {
+            Ok(body) => body,
+            Err(e) => return Ok(error_response(e.status(), &e.to_string())),
+        };
+        let calls: Vec<serde_json::Value> = match serde_json::from_slice(&body) {
+            Ok(calls) => calls,
+            Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, &format!("Batch body must be a JSON array: {}", e))),
+        };
+        if calls.len() > settings.config.max_batch_size {
+            let message = format!("Batch has {} calls; at most {} are allowed", calls.len(), settings.config.max_batch_size);
+            return Ok(error_response(StatusCode::BAD_REQUEST, &message));
+        }
+        
+        // Each call runs through the middleware chain with the batch request's credentials
+        let caller = batch::Caller::from_parts(&parts);
+        let result = batch::execute_batch(&state, &settings, &caller, calls).await;
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::json!(result).to_string()))
+            .unwrap());
+    }
+    if req.method() == Method::GET && path.starts_with(downloads::DOWNLOADS_PREFIX) {
+        let tenant_id = resolve_tenant(&req);
+        let gateway = state.gateway.as_deref().map(|g| g as &dyn Gateway);
+        return Ok(downloads::download_response(
+            gateway,
+            &state.metrics,
+            &settings.config,
+            &tenant_id,
+            &path,
+            req.uri().query(),
+        ).await);
+    }
+    if req.method() == Method::POST && path == reload::ADMIN_RELOAD_PATH {
+        return Ok(reload::reload_response(&state, &settings.config, &req));
+    }
+    if req.method() == Method::GET && path == metrics::METRICS_PATH {
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(Body::from(state.metrics.render()))
+            .unwrap());
+    }
+    
+    let routes = read_recover(&state.routes).clone();
+    let response = match routes.find(&method, &path) {
+        Some(matched) => {
+            // Buffer the body up front: middleware and handlers only see `&Request`
+            let (parts, body) = req.into_parts();
+            let body = match body::read_limited(&parts.headers, body, settings.config.max_body_bytes).await {
+                Ok(body) => body,
+                Err(e) => return Ok(error_response(e.status(), &e.to_string())),
+            };
+            let form = match multipart::form_fields(&parts.headers, body.clone(), settings.config.max_part_bytes).await {
+                Ok(form) => form,
+                Err(e) => return Ok(error_response(e.status(), &e.to_string())),
+            };
+            let mut req = Request::from_parts(parts, Body::from(body.clone()));
+            req.extensions_mut().insert(forwarding::RequestBody(body));
+            if let Some(form) = form {
+                req.extensions_mut().insert(forwarding::FormFields(form));
+            }
+            
+            // Handlers and middleware read path parameters, the matched route,
+            // the authenticated caller and the request id from the extensions
+            req.extensions_mut().insert(routing::PathParams(matched.params));
+            req.extensions_mut().insert(routing::MatchedRoute {
+                method: matched.method.to_string(),
+                pattern: matched.pattern.to_string(),
+            });
+            req.extensions_mut().insert(auth::Authentication::default());
+            let request_id = logging::RequestId::for_request(&req);
+            req.extensions_mut().insert(request_id);
+            let handler = matched.value;
+            
             // Apply middleware chain
-            let next = Next::new(&state.middlewares, handler);
+            let route_middlewares = settings.route_middlewares
+                .get(&(matched.method.to_string(), matched.pattern.to_string()))
+                .map(Vec::as_slice)
+                .unwrap_or(&[]);
+            let trace = introspection::MiddlewareTrace::new();
+            let mut next = Next::with_route(&settings.middlewares, route_middlewares, handler);
+            if settings.config.debug_middleware {
+                next = next.with_trace(&trace);
+            }
             
-            match next.run(&req).await {
+            let mut response = match next.run(&req).await {
                 Ok(response) => response,
-                Err(e) => {
-                    error!("Error processing request: {}", e);
-                    error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+                Err(e) => error_response_for(&settings.config, &e),
+            };
+            if settings.config.debug_middleware {
+                let chain = trace.header_value();
+                debug!("Middleware for {} {}: {}", method, path, chain);
+                if let Ok(value) = header::HeaderValue::from_str(&chain) {
+                    response.headers_mut().insert(introspection::DEBUG_MIDDLEWARE_HEADER, value);
                 }
             }
+            response
+        },
+        None => match static_files::serve(&settings.config, &req).await {
+            Some(response) => response,
+            None => {
+                warn!("Route not found: {} {}", method, path);
+                error_response(StatusCode::NOT_FOUND, "Route not found")
+            },
         },
-        None => {
-            // If the route wasn't found, check if we have a pattern match
-            // This would be for routes with parameters like /users/:id
-            // For simplicity, we're not implementing this here
-            
-            warn!("Route not found: {} {}", method, path);
-            error_response(StatusCode::NOT_FOUND, "Route not found")
-        }
     };
     
     Ok(response)
 }
 
-/// Forward a request to a gateway service via the Gateway trait
-async fn forward_to_gateway<G: Gateway + Send + Sync + 'static>(
-    gateway: &G,
-    req_path: String,
-    body_params: Option<serde_json::Value>,
-) -> Result<Response<Body>> {
-    // Get the handler for the path
-    match gateway.handle_request(req_path, body_params).await {
-        Ok(json_response) => {
-            // Convert to HTTP response
-            Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "application/json")
-                .body(Body::from(json_response.to_string()))
-                .unwrap())
-        },
-        Err(e) => {
-            // Return error response
-            error!("Gateway error: {}", e);
-            let error_body = format!("{{\"error\":\"{}\"}}", e);
-            Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .header(header::CONTENT_TYPE, "application/json")
-                .body(Body::from(error_body))
-                .unwrap())
-        }
-    }
-}
-
 /// CORS middleware implementation
 struct CorsMiddleware {
     config: CorsConfig,
@@ -467,29 +1795,47 @@ impl CorsMiddleware {
     }
     
     fn is_origin_allowed(&self, origin: &str) -> bool {
-        // Check if origin matches any allowed origins
-        self.config.allowed_origins.iter()
-            .any(|allowed| {
-                if allowed == "*" {
-                    return true;
-                }
-                
-                // Simple exact match
-                if allowed == origin {
-                    return true;
-                }
-                
-                // Wildcard match (e.g., https://*.example.com)
-                if allowed.starts_with("*.") {
-                    let domain = allowed.trim_start_matches("*.");
-                    origin.ends_with(domain)
-                } else {
-                    false
-                }
-            })
+        self.config.allowed_origins.iter().any(|allowed| origin_matches(allowed, origin))
+    }
+}
+
+/// Split an origin such as `https://app.example.com` into its scheme, if any, and host
+fn split_origin(origin: &str) -> (Option<&str>, &str) {
+    match origin.split_once("://") {
+        Some((scheme, host)) => (Some(scheme), host),
+        None => (None, origin),
     }
 }
 
+/// Whether `origin` matches one configured allowed origin.
+///
+/// `*` allows everything. `*.example.com` allows any subdomain of `example.com`
+/// (but not `example.com` itself or `evilexample.com`) over any scheme, and
+/// `https://*.example.com` only over https. Anything else must match exactly,
+/// ignoring case.
+fn origin_matches(allowed: &str, origin: &str) -> bool {
+    if allowed == "*" || allowed.eq_ignore_ascii_case(origin) {
+        return true;
+    }
+    
+    let (allowed_scheme, allowed_host) = split_origin(allowed);
+    let suffix = match allowed_host.strip_prefix('*') {
+        // Keep the dot so the match stops at a label boundary
+        Some(suffix) if suffix.starts_with('.') && suffix.len() > 1 => suffix.to_ascii_lowercase(),
+        _ => return false,
+    };
+    
+    let (scheme, host) = match split_origin(origin) {
+        (Some(scheme), host) => (scheme, host.to_ascii_lowercase()),
+        // Browsers always send a scheme; anything else isn't a real origin
+        (None, _) => return false,
+    };
+    if allowed_scheme.map_or(false, |allowed| !allowed.eq_ignore_ascii_case(scheme)) {
+        return false;
+    }
+    host.len() > suffix.len() && host.ends_with(&suffix)
+}
+
 #[async_trait]
 impl Middleware for CorsMiddleware {
     async fn process(&self, req: &Request<Body>, next: Next<'_>) -> Result<Response<Body>> {
@@ -504,13 +1850,29 @@ impl Middleware for CorsMiddleware {
             let mut response = Response::builder()
                 .status(StatusCode::OK);
             
+            // The answer depends on the origin even when it isn't allowed
+            response = response.header(header::VARY, "Origin");
+            
             // Add CORS headers if origin is allowed
             if !origin.is_empty() && self.is_origin_allowed(origin) {
+                let requested_headers = req.headers()
+                    .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+                    .and_then(|h| h.to_str().ok());
+                let allowed_headers = match requested_headers {
+                    Some(requested) if self.config.reflect_request_headers => requested.to_string(),
+                    _ => self.config.allowed_headers.join(", "),
+                };
+                
                 response = response
                     .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)
-                    .header(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, POST, PUT, DELETE, OPTIONS")
-                    .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, Authorization")
-                    .header(header::ACCESS_CONTROL_MAX_AGE, "86400"); // 24 hours
+                    .header(header::ACCESS_CONTROL_ALLOW_METHODS, self.config.allowed_methods.join(", "))
+                    .header(header::ACCESS_CONTROL_MAX_AGE, self.config.max_age_secs.to_string());
+                if !allowed_headers.is_empty() {
+                    response = response.header(header::ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers);
+                }
+                if self.config.reflect_request_headers {
+                    response = response.header(header::VARY, "Access-Control-Request-Headers");
+                }
                 
                 if self.config.allow_credentials {
                     response = response.header(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
@@ -522,6 +1884,7 @@ impl Middleware for CorsMiddleware {
         
         // For regular requests
         let mut response = next.run(req).await?;
+        response.headers_mut().append(header::VARY, header::HeaderValue::from_static("Origin"));
         
         // Add CORS headers to the response if origin is allowed
         if !origin.is_empty() && self.is_origin_allowed(origin) {
@@ -537,42 +1900,1235 @@ impl Middleware for CorsMiddleware {
     }
 }
 
+/// Middleware that bounds the time spent in the rest of the chain.
+///
+/// When the deadline elapses the downstream future is dropped, cancelling it,
+/// and a `504 Gateway Timeout` is returned.
+pub struct TimeoutMiddleware {
+    timeout: Duration,
+}
+
+impl TimeoutMiddleware {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+#[async_trait]
+impl Middleware for TimeoutMiddleware {
+    async fn process(&self, req: &Request<Body>, next: Next<'_>) -> Result<Response<Body>> {
+        match tokio::time::timeout(self.timeout, next.run(req)).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!("Request timed out after {:?}: {} {}", self.timeout, req.method(), req.uri().path());
+                let body = serde_json::json!({
+                    "error": "Request timed out",
+                    "timeout_ms": self.timeout.as_millis() as u64,
+                });
+                Ok(Response::builder()
+                    .status(StatusCode::GATEWAY_TIMEOUT)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))?)
+            }
+        }
+    }
+}
+
 /// Gateway request handler trait
 #[async_trait]
 pub trait GatewayRequestHandler {
     async fn handle_request(&self, path: String, params: Option<serde_json::Value>) -> Result<serde_json::Value>;
 }
 
+/// Error value returned to node-to-node callers in place of a response body
+fn request_error(status: StatusCode, message: &str) -> serde_json::Value {
+    serde_json::json!({ "error": message, "status": status.as_u16() })
+}
+
+/// Split `METHOD:PATH`, e.g. `GET:/invoices/42`, checking both halves
+fn parse_request_path(path: &str) -> std::result::Result<(Method, &str), String> {
+    let (method, endpoint) = path
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid path '{}', expected 'METHOD:PATH'", path))?;
+    let method = Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+        .map_err(|_| format!("Invalid method '{}'", method))?;
+    if !endpoint.starts_with('/') {
+        return Err(format!("Invalid endpoint '{}', expected an absolute path", endpoint));
+    }
+    Ok((method, endpoint))
+}
+
+/// Dispatches `METHOD:PATH` requests from other nodes through the route registry.
+///
+/// Path parameters override fields of the same name in `params`. Malformed
+/// paths, unknown routes and failed calls come back as `{"error", "status"}`
+/// values rather than errors.
 #[async_trait]
 impl<G: Gateway + Send + Sync + 'static> GatewayRequestHandler for G {
     async fn handle_request(&self, path: String, params: Option<serde_json::Value>) -> Result<serde_json::Value> {
-        // Convert path to method and endpoint
-        let parts: Vec<&str> = path.split(':').collect();
-        if parts.len() != 2 {
-            return Err(anyhow!("Invalid path format, expected 'METHOD:PATH'"));
+        let (method, endpoint) = match parse_request_path(&path) {
+            Ok(parsed) => parsed,
+            Err(message) => return Ok(request_error(StatusCode::BAD_REQUEST, &message)),
+        };
+        
+        let (target, path_params) = {
+            let route_infos = read_recover(&ROUTES);
+            let mut routes = routing::RouteMatcher::new();
+            for route_info in route_infos.iter() {
+                routes.insert(route_info.method, route_info.path, route_info);
+            }
+            
+            let matched = match routes.find(method.as_str(), endpoint) {
+                Some(matched) => matched,
+                None => {
+                    let message = format!("No route for {} {}", method, endpoint);
+                    return Ok(request_error(StatusCode::NOT_FOUND, &message));
+                },
+            };
+            match forwarding::RouteTarget::from_route(matched.value) {
+                Ok(target) => (target, matched.params),
+                Err(e) => {
+                    error!("{}", e);
+                    return Ok(request_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"));
+                },
+            }
+        };
+        
+        let mut params = match params {
+            Some(serde_json::Value::Object(map)) => serde_json::Value::Object(map),
+            _ => serde_json::json!({}),
+        };
+        for (name, value) in path_params {
+            params[name] = serde_json::Value::String(value);
+        }
+        
+        debug!("Dispatching {} {} to {}.{}", method, endpoint, target.service, target.action);
+        match self.dispatch(&target.service, &target.action, params).await {
+            Ok(body) => Ok(body),
+            Err(e) => {
+                let status = status_for_error(&e);
+                if status == StatusCode::INTERNAL_SERVER_ERROR {
+                    error!("Error dispatching {} {}: {}", method, endpoint, e);
+                    Ok(request_error(status, "Internal server error"))
+                } else {
+                    Ok(request_error(status, &e.to_string()))
+                }
+            },
+        }
+    }
+}
+
+// Re-export the service module
+pub mod service;
+pub mod auth;
+pub mod batch;
+pub mod body;
+pub mod builder;
+pub mod cache;
+pub mod circuit_breaker;
+pub mod compression;
+pub mod conditional;
+pub mod connections;
+pub mod downloads;
+pub mod forwarding;
+pub mod health;
+pub mod internal;
+pub mod introspection;
+pub mod jobs;
+pub mod logging;
+pub mod metrics;
+pub mod multipart;
+pub mod nonce;
+pub mod openapi;
+pub mod operations;
+pub mod query;
+pub mod rate_limit;
+pub mod registry;
+pub mod reload;
+pub mod retry;
+pub mod routing;
+pub mod sampling;
+pub mod scopes;
+pub mod shutdown;
+pub mod static_files;
+pub mod streaming; 
+pub mod tls;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    
+    /// A connection without a socket; its frames land on the returned receiver
+    fn connection(id: &str) -> (WebSocketConnection, mpsc::Receiver<Message>) {
+        let (outbox, frames) = mpsc::channel(WS_OUTBOX_CAPACITY);
+        (WebSocketConnection::new(id.to_string(), DEFAULT_TENANT.to_string(), outbox), frames)
+    }
+    
+    /// The JSON of the next queued text frame
+    fn next_json(frames: &mut mpsc::Receiver<Message>) -> Value {
+        match frames.try_recv() {
+            Ok(Message::Text(text)) => serde_json::from_str(&text).unwrap(),
+            other => panic!("expected a text frame, got {:?}", other),
+        }
+    }
+    
+    #[tokio::test]
+    async fn broadcast_reaches_every_connection_and_send_to_only_one() {
+        let handler = WebSocketHandler::new(Duration::from_secs(30));
+        let (first, mut first_frames) = connection("first");
+        let (second, mut second_frames) = connection("second");
+        handler.register(first).await.unwrap();
+        handler.register(second).await.unwrap();
+        
+        assert_eq!(handler.broadcast(json!({ "n": 1 })).await, 2);
+        assert_eq!(next_json(&mut first_frames), json!({ "n": 1 }));
+        assert_eq!(next_json(&mut second_frames), json!({ "n": 1 }));
+        
+        handler.send_to("second", json!({ "n": 2 })).await.unwrap();
+        assert_eq!(next_json(&mut second_frames), json!({ "n": 2 }));
+        assert!(first_frames.try_recv().is_err());
+    }
+    
+    #[tokio::test]
+    async fn broadcast_counts_only_connections_that_took_the_message() {
+        let handler = WebSocketHandler::new(Duration::from_secs(30));
+        let (gone, gone_frames) = connection("gone");
+        let (live, mut live_frames) = connection("live");
+        handler.register(gone).await.unwrap();
+        handler.register(live).await.unwrap();
+        drop(gone_frames);
+        
+        assert_eq!(handler.broadcast(json!({ "n": 1 })).await, 1);
+        assert_eq!(next_json(&mut live_frames), json!({ "n": 1 }));
+        assert!(handler.send_to("gone", json!({})).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn slow_connection_is_dropped_without_holding_up_the_others() {
+        let handler = WebSocketHandler::new(Duration::from_secs(30));
+        let (slow, _slow_frames) = connection("slow");
+        let (fast, mut fast_frames) = connection("fast");
+        handler.register(slow).await.unwrap();
+        handler.register(fast).await.unwrap();
+        
+        for n in 0..WS_OUTBOX_CAPACITY {
+            assert_eq!(handler.broadcast(json!({ "n": n })).await, 2);
+            next_json(&mut fast_frames);
+        }
+        
+        // The slow peer read nothing, so its outbox is full
+        assert_eq!(handler.broadcast(json!({ "n": "last" })).await, 1);
+        assert_eq!(next_json(&mut fast_frames), json!({ "n": "last" }));
+        assert_eq!(handler.connections.read().await.len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn silent_client_is_closed_after_missed_heartbeats() {
+        let heartbeat = Duration::from_millis(50);
+        let handler = WebSocketHandler::new(heartbeat);
+        let (silent, mut silent_frames) = connection("silent");
+        let (alive, mut alive_frames) = connection("alive");
+        handler.register(silent).await.unwrap();
+        handler.register(alive).await.unwrap();
+        
+        tokio::time::sleep(heartbeat * MAX_MISSED_HEARTBEATS).await;
+        handler.connections.write().await.get_mut("alive").unwrap().record_pong();
+        
+        assert_eq!(handler.heartbeat().await, 1);
+        assert!(matches!(silent_frames.try_recv(), Ok(Message::Close(_))));
+        assert!(matches!(alive_frames.try_recv(), Ok(Message::Ping(_))));
+        let connections = handler.connections.read().await;
+        assert!(!connections.contains_key("silent"));
+        assert!(connections.contains_key("alive"));
+    }
+    
+    #[tokio::test]
+    async fn answering_heartbeats_does_not_keep_an_idle_client_open() {
+        let idle_timeout = Duration::from_millis(50);
+        let handler = WebSocketHandler::new(Duration::from_secs(30)).with_idle_timeout(idle_timeout);
+        let (idle, mut idle_frames) = connection("idle");
+        handler.register(idle).await.unwrap();
+        
+        tokio::time::sleep(idle_timeout).await;
+        handler.handle_message("idle", json!({ "type": "ping" }).to_string()).await.unwrap();
+        handler.connections.write().await.get_mut("idle").unwrap().record_pong();
+        
+        assert_eq!(handler.reap_idle().await, 1);
+        assert_eq!(next_json(&mut idle_frames), json!({ "type": "pong" }));
+        assert!(matches!(idle_frames.try_recv(), Ok(Message::Close(_))));
+        assert!(handler.connections.read().await.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn duplicate_id_is_rejected_by_default() {
+        let handler = WebSocketHandler::new(Duration::from_secs(30));
+        let (original, mut original_frames) = connection("shared");
+        let (duplicate, mut duplicate_frames) = connection("shared");
+        let session = handler.register(original).await.unwrap();
+        
+        assert!(handler.register(duplicate).await.is_err());
+        assert!(matches!(duplicate_frames.try_recv(), Ok(Message::Close(_))));
+        assert!(original_frames.try_recv().is_err());
+        assert_eq!(handler.connections.read().await["shared"].session, session);
+    }
+    
+    #[tokio::test]
+    async fn duplicate_id_can_replace_the_existing_connection() {
+        let handler = WebSocketHandler::new(Duration::from_secs(30))
+            .with_duplicate_id_policy(DuplicateIdPolicy::ReplaceExisting);
+        let (original, mut original_frames) = connection("shared");
+        let (replacement, mut replacement_frames) = connection("shared");
+        handler.register(original).await.unwrap();
+        
+        let session = handler.register(replacement).await.unwrap();
+        assert!(matches!(original_frames.try_recv(), Ok(Message::Close(_))));
+        assert!(replacement_frames.try_recv().is_err());
+        assert_eq!(handler.connections.read().await["shared"].session, session);
+    }
+    
+    fn invoice_event(user_id: Option<&str>) -> Event {
+        let mut payload = json!({ "tenant_id": DEFAULT_TENANT, "invoice_id": "inv-1" });
+        if let Some(user_id) = user_id {
+            payload["user_id"] = json!(user_id);
         }
+        Event {
+            topic: "invoice".to_string(),
+            kind: "invoice_paid".to_string(),
+            payload,
+        }
+    }
+    
+    #[tokio::test]
+    async fn anonymous_connection_cannot_subscribe() {
+        let handler = WebSocketHandler::new(Duration::from_secs(30));
+        let (anonymous, mut frames) = connection("anonymous");
+        handler.register(anonymous).await.unwrap();
+        
+        handler.handle_message("anonymous", json!({ "type": "subscribe", "topics": ["invoice"] }).to_string()).await.unwrap();
         
-        let method = parts[0].to_string();
-        let endpoint = parts[1].to_string();
+        assert_eq!(next_json(&mut frames)["error"]["code"], 401);
+        assert_eq!(handler.push_event(&invoice_event(Some("alice"))).await, 0);
+        assert!(frames.try_recv().is_err());
+    }
+    
+    #[tokio::test]
+    async fn events_reach_only_the_user_they_concern() {
+        let handler = WebSocketHandler::new(Duration::from_secs(30));
+        let (alice, mut alice_frames) = connection("alice-socket");
+        let (bob, mut bob_frames) = connection("bob-socket");
+        handler.register(alice.authenticated_as("alice".to_string())).await.unwrap();
+        handler.register(bob.authenticated_as("bob".to_string())).await.unwrap();
+        for id in ["alice-socket", "bob-socket"] {
+            handler.handle_message(id, json!({ "type": "subscribe", "topics": ["invoice"] }).to_string()).await.unwrap();
+        }
+        assert_eq!(next_json(&mut alice_frames)["type"], "subscriptions");
+        assert_eq!(next_json(&mut bob_frames)["type"], "subscriptions");
         
-        // In a real implementation, you would:
-        // 1. Parse the endpoint to extract path parameters
-        // 2. Call the appropriate service method
-        // 3. Return the response
+        assert_eq!(handler.push_event(&invoice_event(Some("alice"))).await, 1);
+        assert_eq!(next_json(&mut alice_frames)["payload"]["user_id"], "alice");
+        assert!(bob_frames.try_recv().is_err());
         
-        // Simple echo implementation for testing
-        let mut result = serde_json::json!({
-            "method": method,
-            "endpoint": endpoint
+        // Events naming no user go to nobody
+        assert_eq!(handler.push_event(&invoice_event(None)).await, 0);
+    }
+    
+    fn upgrade_request(uri: &str) -> Request<Body> {
+        Request::get(uri)
+            .header(header::CONNECTION, "Upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header(header::SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ==")
+            .body(Body::empty())
+            .unwrap()
+    }
+    
+    #[tokio::test]
+    async fn upgrade_with_a_rejected_token_is_refused() {
+        let validator: Arc<dyn auth::TokenValidator> = Arc::new(|_tenant: String, token: String| async move {
+            match token.as_str() {
+                "good" => Ok(json!({ "id": "alice" })),
+                _ => Err(ServiceError::unauthorized("Invalid token").into()),
+            }
         });
+        let handler = Arc::new(WebSocketHandler::new(Duration::from_secs(30)));
         
-        if let Some(params) = params {
-            result["params"] = params;
+        let refused = handle_websocket_request(upgrade_request("/ws?token=forged"), handler.clone(), Some(validator.clone()))
+            .await
+            .unwrap();
+        assert_eq!(refused.status(), StatusCode::UNAUTHORIZED);
+        
+        let accepted = handle_websocket_request(upgrade_request("/ws?token=good"), handler.clone(), Some(validator.clone()))
+            .await
+            .unwrap();
+        assert_eq!(accepted.status(), StatusCode::SWITCHING_PROTOCOLS);
+        let anonymous = handle_websocket_request(upgrade_request("/ws"), handler, Some(validator)).await.unwrap();
+        assert_eq!(anonymous.status(), StatusCode::SWITCHING_PROTOCOLS);
+    }
+    
+    /// Backend that never has to answer: the tests using it call no actions
+    struct IdleGateway;
+    
+    #[async_trait]
+    impl Gateway for IdleGateway {
+        async fn run(&self) -> Result<()> {
+            Ok(())
         }
+    }
+    
+    async fn readiness(state: &Arc<GatewayState>) -> StatusCode {
+        let req = Request::get(health::READINESS_PATH).body(Body::empty()).unwrap();
+        route_http_request(req, state.clone()).await.unwrap().status()
+    }
+    
+    #[tokio::test]
+    async fn readiness_waits_for_the_route_table() {
+        let state = GatewayState::without_routes(Arc::new(IdleGateway), &GatewayConfig::default(), Arc::new(Metrics::new()));
+        let state = Arc::new(state.unwrap());
+        assert_eq!(readiness(&state).await, StatusCode::SERVICE_UNAVAILABLE);
         
-        Ok(result)
+        state.initialize_routes().unwrap();
+        assert_eq!(readiness(&state).await, StatusCode::OK);
     }
-}
-
-// Re-export the service module
-pub mod service; 
\ No newline at end of file
+    
+    /// Sets its flag when dropped, i.e. once the future owning it completes or is cancelled
+    struct DropFlag(Arc<std::sync::atomic::AtomicBool>);
+    
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+    
+    #[tokio::test]
+    async fn slow_handler_gets_504_and_is_dropped() {
+        let config = GatewayConfig::builder().request_timeout(Duration::from_millis(50)).build();
+        let middlewares = build_middleware(&config, None).unwrap();
+        let dropped = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let flag = dropped.clone();
+        let handler: Box<HandlerFn> = Box::new(move |_: &Request<Body>| {
+            let guard = DropFlag(flag.clone());
+            Box::pin(async move {
+                let _guard = guard;
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                Ok(Response::new(Body::empty()))
+            })
+        });
+        let req = Request::get("/slow").body(Body::empty()).unwrap();
+        
+        let started = Instant::now();
+        let response = Next::new(&middlewares, handler.as_ref()).run(&req).await.unwrap();
+        
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["timeout_ms"], 50);
+        assert!(dropped.load(std::sync::atomic::Ordering::SeqCst), "the timed out handler was not dropped");
+    }
+    
+    #[test]
+    fn service_errors_map_to_their_statuses() {
+        let cases = [
+            (ServiceError::not_found("Invoice not found"), StatusCode::NOT_FOUND),
+            (ServiceError::validation("quantity must be positive"), StatusCode::BAD_REQUEST),
+            (ServiceError::unauthorized("Missing bearer token"), StatusCode::UNAUTHORIZED),
+            (ServiceError::conflict("Username already exists"), StatusCode::CONFLICT),
+            (ServiceError::internal("Invoice not found in the replica"), StatusCode::INTERNAL_SERVER_ERROR),
+        ];
+        
+        for (err, status) in cases {
+            assert_eq!(status_for_error(&err.clone().into()), status, "{:?}", err);
+        }
+    }
+    
+    #[test]
+    fn unrecognised_errors_are_500() {
+        assert_eq!(status_for_error(&anyhow!("connection reset by peer")), StatusCode::INTERNAL_SERVER_ERROR);
+        // Plain errors still map by their phrasing
+        assert_eq!(status_for_error(&anyhow!("Profile not found")), StatusCode::NOT_FOUND);
+    }
+    
+    /// Answers every call with the service, action and params it was routed with
+    struct RoutedGateway;
+    
+    #[async_trait]
+    impl Gateway for RoutedGateway {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn dispatch(&self, service: &str, action: &str, params: Value) -> Result<Value> {
+            Ok(json!({ "service": service, "action": action, "params": params }))
+        }
+    }
+    
+    fn register_test_route(method: &'static str, path: &'static str, handler_name: &'static str) {
+        register_route(RouteInfo {
+            method,
+            path,
+            handler_name,
+            middleware: None,
+        });
+    }
+    
+    fn routed_state(config: &GatewayConfig) -> Arc<GatewayState> {
+        Arc::new(GatewayState::new(Arc::new(RoutedGateway), config, Arc::new(Metrics::new())).unwrap())
+    }
+    
+    async fn send(state: &Arc<GatewayState>, req: Request<Body>) -> (StatusCode, Value) {
+        let response = route_http_request(req, state.clone()).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+    
+    #[tokio::test]
+    async fn post_with_method_override_routes_to_the_put_handler() {
+        register_test_route("PUT", "/override-tests/:id", "overridetest.update");
+        let state = routed_state(&GatewayConfig::default());
+        let req = Request::post("/override-tests/42")
+            .header(METHOD_OVERRIDE_HEADER, "put")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from("{}"))
+            .unwrap();
+        
+        let (status, body) = send(&state, req).await;
+        
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["service"], "overridetest");
+        assert_eq!(body["action"], "update");
+    }
+    
+    #[tokio::test]
+    async fn method_override_is_ignored_on_other_methods() {
+        register_test_route("PUT", "/override-tests/:id", "overridetest.update");
+        let state = routed_state(&GatewayConfig::default());
+        let override_get = Request::get("/override-tests/42")
+            .header(METHOD_OVERRIDE_HEADER, "PUT")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(effective_method(&override_get), Method::GET);
+        let (status, _) = send(&state, override_get).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        
+        // Only PUT, PATCH and DELETE may be requested
+        let override_to_get = Request::post("/override-tests/42")
+            .header(METHOD_OVERRIDE_HEADER, "GET")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(effective_method(&override_to_get), Method::POST);
+    }
+    
+    #[tokio::test]
+    async fn routing_survives_a_poisoned_route_table() {
+        register_test_route("GET", "/poison-tests/:id", "poisontest.get");
+        let state = routed_state(&GatewayConfig::default());
+        let poisoner = state.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.routes.write().unwrap();
+            panic!("panicked while holding the route table");
+        })
+        .join();
+        assert!(state.routes.is_poisoned());
+        
+        let (status, body) = send(&state, Request::get("/poison-tests/1").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["action"], "get");
+        
+        // Rebuilding the table takes the write lock and clears the poison
+        state.initialize_routes().unwrap();
+        assert!(!state.routes.is_poisoned());
+        let (status, _) = send(&state, Request::get("/poison-tests/2").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    
+    #[test]
+    fn route_registry_survives_a_poisoned_lock() {
+        let _ = std::thread::spawn(|| {
+            let _guard = ROUTES.write().unwrap();
+            panic!("panicked while registering a route");
+        })
+        .join();
+        
+        register_test_route("GET", "/poison-tests/registry", "poisontest.registry");
+        
+        assert!(!ROUTES.is_poisoned());
+        assert!(read_recover(&ROUTES).iter().any(|route| route.handler_name == "poisontest.registry"));
+    }
+    
+    async fn body_text(response: Response<Body>) -> String {
+        String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap()
+    }
+    
+    #[tokio::test]
+    async fn configured_404_body_replaces_the_default() {
+        let template = ErrorBodyTemplate {
+            content_type: "text/html".to_string(),
+            body: "<h1>{status}: {message}</h1>".to_string(),
+        };
+        let config = GatewayConfig::builder().error_body(StatusCode::NOT_FOUND, template).build();
+        let state = routed_state(&config);
+        
+        let response = route_http_request(Request::get("/no-such-route").body(Body::empty()).unwrap(), state).await.unwrap();
+        
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html");
+        assert_eq!(body_text(response).await, "<h1>404: Route not found</h1>");
+    }
+    
+    #[tokio::test]
+    async fn unconfigured_status_uses_the_default_body() {
+        let template = ErrorBodyTemplate {
+            content_type: "application/json".to_string(),
+            body: r#"{"code":{status},"detail":"{message}"}"#.to_string(),
+        };
+        let config = GatewayConfig::builder().error_body(StatusCode::NOT_FOUND, template).build();
+        
+        let default = error_response(&config, StatusCode::TOO_MANY_REQUESTS, "Slow down");
+        assert_eq!(default.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(body_text(default).await, r#"{"error":"Slow down"}"#);
+        
+        // Messages are escaped for JSON templates
+        let branded = error_response(&config, StatusCode::NOT_FOUND, r#"No "x""#);
+        let body: Value = serde_json::from_str(&body_text(branded).await).unwrap();
+        assert_eq!(body, json!({ "code": 404, "detail": r#"No "x""# }));
+    }
+    
+    #[tokio::test]
+    async fn idle_connection_is_reaped_and_the_active_gauge_drops() {
+        let idle_timeout = Duration::from_millis(50);
+        let metrics = Arc::new(Metrics::new());
+        let handler = WebSocketHandler::new(Duration::from_secs(30))
+            .with_idle_timeout(idle_timeout)
+            .with_metrics(metrics.clone());
+        let (idle, mut idle_frames) = connection("idle");
+        let (busy, mut busy_frames) = connection("busy");
+        handler.register(idle).await.unwrap();
+        handler.register(busy).await.unwrap();
+        assert_eq!(metrics.gauge(WS_ACTIVE_GAUGE), Some(2));
+        
+        tokio::time::sleep(idle_timeout).await;
+        handler.connections.write().await.get_mut("busy").unwrap().touch();
+        
+        assert_eq!(handler.reap_idle().await, 1);
+        match idle_frames.try_recv() {
+            Ok(Message::Close(Some(frame))) => {
+                assert_eq!(frame.code, CloseCode::Away);
+                assert_eq!(frame.reason, "Idle timeout");
+            },
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+        assert!(busy_frames.try_recv().is_err());
+        assert_eq!(metrics.gauge(WS_ACTIVE_GAUGE), Some(1));
+        assert!(metrics.render().contains(WS_ACTIVE_GAUGE));
+    }
+    
+    fn handler_taking(delay: Duration) -> Box<HandlerFn> {
+        Box::new(move |_: &Request<Body>| {
+            Box::pin(async move {
+                tokio::time::sleep(delay).await;
+                Ok(Response::builder().status(StatusCode::CREATED).header("x-handler", "done").body(Body::from("made")).unwrap())
+            })
+        })
+    }
+    
+    #[tokio::test]
+    async fn timeout_middleware_answers_slow_handlers_with_504() {
+        let timeout = TimeoutMiddleware::new(Duration::from_millis(20));
+        let handler = handler_taking(Duration::from_secs(30));
+        let req = Request::get("/slow").body(Body::empty()).unwrap();
+        
+        let response = timeout.process(&req, Next::new(&[], handler.as_ref())).await.unwrap();
+        
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body: Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(body["error"], "Request timed out");
+    }
+    
+    #[tokio::test]
+    async fn timeout_middleware_passes_fast_responses_through_untouched() {
+        let timeout = TimeoutMiddleware::new(Duration::from_secs(5));
+        let handler = handler_taking(Duration::from_millis(1));
+        let req = Request::get("/fast").body(Body::empty()).unwrap();
+        
+        let response = timeout.process(&req, Next::new(&[], handler.as_ref())).await.unwrap();
+        
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["x-handler"], "done");
+        assert_eq!(body_text(response).await, "made");
+    }
+    
+    #[tokio::test]
+    async fn invoked_action_populates_its_size_histograms() {
+        register_test_route("POST", "/metrics-tests/echo", "metricstest.echo");
+        let state = routed_state(&GatewayConfig::default());
+        let req = Request::post("/metrics-tests/echo")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "payload": "x".repeat(300) }).to_string()))
+            .unwrap();
+        let (status, _) = send(&state, req).await;
+        assert_eq!(status, StatusCode::OK);
+        
+        let labels = [("action", "metricstest.echo")];
+        assert_eq!(state.metrics.histogram_count(metrics::REQUEST_SIZE_HISTOGRAM, &labels), Some(1));
+        assert_eq!(state.metrics.histogram_count(metrics::RESPONSE_SIZE_HISTOGRAM, &labels), Some(1));
+        
+        let response = route_http_request(Request::get(metrics::METRICS_PATH).body(Body::empty()).unwrap(), state).await.unwrap();
+        let exposition = body_text(response).await;
+        let series = format!("{}_count{{action=\"metricstest.echo\"}} 1", metrics::REQUEST_SIZE_HISTOGRAM);
+        assert!(exposition.contains(&series), "{}", exposition);
+        // The 300-byte payload is over the 256-byte bucket
+        let small = format!("{}_bucket{{action=\"metricstest.echo\",le=\"256\"}} 0", metrics::REQUEST_SIZE_HISTOGRAM);
+        assert!(exposition.contains(&small), "{}", exposition);
+    }
+    
+    /// A port nothing is listening on right now
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+    
+    /// Send `GET path` over a fresh connection, returning the raw response
+    async fn http_get(addr: SocketAddr, path: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, addr);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+    
+    #[tokio::test]
+    async fn every_listener_serves_the_routes() {
+        register_test_route("GET", "/listener-tests/ping", "listenertest.ping");
+        let (first, second) = (free_port(), free_port());
+        let config = GatewayConfig::builder()
+            .port(first)
+            .listen_on(format!("127.0.0.1:{}", second))
+            .shutdown_grace_period(Duration::from_millis(100))
+            .build();
+        
+        let handle = start_gateway_with_handle(RoutedGateway, config).await.unwrap();
+        for port in [first, second] {
+            let response = http_get(SocketAddr::from(([127, 0, 0, 1], port)), "/listener-tests/ping").await;
+            assert!(response.starts_with("HTTP/1.1 200"), "port {}: {}", port, response);
+            assert!(response.contains("\"action\":\"ping\""), "port {}: {}", port, response);
+        }
+        
+        tokio::time::timeout(Duration::from_secs(5), handle.shutdown()).await.unwrap().unwrap();
+    }
+    
+    #[tokio::test]
+    async fn debug_header_names_auth_as_the_step_that_rejected_the_request() {
+        register_test_route("GET", "/debug-tests/secure", "debugtest.secure");
+        let mut config = GatewayConfig::builder().middleware(auth::AUTH_MIDDLEWARE).build();
+        config.debug_middleware = true;
+        let state = routed_state(&config);
+        
+        let response = route_http_request(Request::get("/debug-tests/secure").body(Body::empty()).unwrap(), state)
+            .await
+            .unwrap();
+        
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let chain = response.headers()[introspection::DEBUG_MIDDLEWARE_HEADER].to_str().unwrap();
+        assert_eq!(chain, "CorsMiddleware=pass, RateLimitMiddleware=pass, AuthMiddleware=401");
+        
+        // Without debug mode the chain isn't reported
+        let state = routed_state(&GatewayConfig::builder().middleware(auth::AUTH_MIDDLEWARE).build());
+        let response = route_http_request(Request::get("/debug-tests/secure").body(Body::empty()).unwrap(), state)
+            .await
+            .unwrap();
+        assert!(response.headers().get(introspection::DEBUG_MIDDLEWARE_HEADER).is_none());
+    }
+    
+    #[test]
+    fn concurrent_route_registration_keeps_every_route() {
+        const THREADS: usize = 16;
+        const ROUTES_PER_THREAD: usize = 25;
+        
+        let threads: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                std::thread::spawn(move || {
+                    for n in 0..ROUTES_PER_THREAD {
+                        let path: &'static str = Box::leak(format!("/concurrency-tests/{}/{}", thread, n).into_boxed_str());
+                        register_route(RouteInfo {
+                            method: "GET",
+                            path,
+                            handler_name: "concurrencytest.get",
+                            middleware: None,
+                        });
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        
+        // Other tests register routes of their own, so only count ours
+        let registered = read_recover(&ROUTES)
+            .iter()
+            .filter(|route| route.handler_name == "concurrencytest.get")
+            .count();
+        assert_eq!(registered, THREADS * ROUTES_PER_THREAD);
+    }
+    
+    #[tokio::test]
+    async fn post_invoices_reaches_invoice_create_with_merged_arguments() {
+        register_test_route("POST", "/invoices", "invoice.create");
+        let state = routed_state(&GatewayConfig::default());
+        let req = Request::post("/invoices?draft=true")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "customer_name": "Acme Ltd", "total": 110.0 }).to_string()))
+            .unwrap();
+        
+        let (status, body) = send(&state, req).await;
+        
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["service"], "invoice");
+        assert_eq!(body["action"], "create");
+        assert_eq!(body["params"]["customer_name"], "Acme Ltd");
+        assert_eq!(body["params"]["total"], 110.0);
+        assert_eq!(body["params"]["draft"], "true");
+    }
+    
+    /// Fails every call with the given service error
+    struct FailingGateway(ServiceError);
+    
+    #[async_trait]
+    impl Gateway for FailingGateway {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn dispatch(&self, _service: &str, _action: &str, _params: Value) -> Result<Value> {
+            Err(self.0.clone().into())
+        }
+    }
+    
+    #[tokio::test]
+    async fn service_errors_keep_their_status_through_the_gateway() {
+        register_test_route("GET", "/forwarding-tests/:id", "forwardingtest.get");
+        let cases = [
+            (ServiceError::not_found("Invoice not found"), StatusCode::NOT_FOUND),
+            (ServiceError::validation("Tax rate must be between 0 and 1"), StatusCode::BAD_REQUEST),
+            (ServiceError::conflict("Invoice was modified"), StatusCode::CONFLICT),
+        ];
+        
+        for (err, expected) in cases {
+            let gateway = Arc::new(FailingGateway(err.clone()));
+            let state = Arc::new(GatewayState::new(gateway, &GatewayConfig::default(), Arc::new(Metrics::new())).unwrap());
+            let (status, body) = send(&state, Request::get("/forwarding-tests/1").body(Body::empty()).unwrap()).await;
+            assert_eq!(status, expected, "{:?}", err);
+            assert_eq!(body["error"], err.message(), "{:?}", err);
+        }
+    }
+    
+    /// Answers every call after the given delay
+    struct SlowGateway(Duration);
+    
+    #[async_trait]
+    impl Gateway for SlowGateway {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn dispatch(&self, _service: &str, action: &str, _params: Value) -> Result<Value> {
+            tokio::time::sleep(self.0).await;
+            Ok(json!({ "action": action }))
+        }
+    }
+    
+    #[tokio::test]
+    async fn shutdown_lets_the_in_flight_request_finish_then_resolves() {
+        register_test_route("GET", "/shutdown-tests/slow", "shutdowntest.slow");
+        let port = free_port();
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        let config = GatewayConfig::builder()
+            .port(port)
+            .shutdown_grace_period(Duration::from_secs(5))
+            .build();
+        let handle = start_gateway_with_handle(SlowGateway(Duration::from_millis(200)), config).await.unwrap();
+        
+        let in_flight = tokio::spawn(http_get(addr, "/shutdown-tests/slow"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        tokio::time::timeout(Duration::from_secs(5), handle.shutdown()).await.unwrap().unwrap();
+        
+        let response = in_flight.await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn shutdown_stops_waiting_after_the_grace_period() {
+        register_test_route("GET", "/shutdown-tests/stuck", "shutdowntest.stuck");
+        let port = free_port();
+        let config = GatewayConfig::builder()
+            .port(port)
+            .shutdown_grace_period(Duration::from_millis(100))
+            .build();
+        let handle = start_gateway_with_handle(SlowGateway(Duration::from_secs(60)), config).await.unwrap();
+        
+        let _stuck = tokio::spawn(http_get(SocketAddr::from(([127, 0, 0, 1], port)), "/shutdown-tests/stuck"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        
+        tokio::time::timeout(Duration::from_secs(5), handle.shutdown()).await.unwrap().unwrap();
+    }
+    
+    #[tokio::test]
+    async fn access_log_echoes_the_request_id_on_routed_responses() {
+        register_test_route("GET", "/logging-tests/ping", "loggingtest.ping");
+        let state = routed_state(&GatewayConfig::builder().middleware(logging::LOGGING_MIDDLEWARE).build());
+        
+        let response = route_http_request(Request::get("/logging-tests/ping").body(Body::empty()).unwrap(), state.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key(logging::REQUEST_ID_HEADER));
+        
+        let req = Request::get("/logging-tests/ping")
+            .header(logging::REQUEST_ID_HEADER, "client-id-7")
+            .body(Body::empty())
+            .unwrap();
+        let response = route_http_request(req, state).await.unwrap();
+        assert_eq!(response.headers()[logging::REQUEST_ID_HEADER], "client-id-7");
+    }
+    
+    #[tokio::test]
+    async fn openapi_endpoint_lists_the_registered_routes() {
+        register_test_route("GET", "/openapi-tests/items/:item_id", "openapitest.get");
+        register_test_route("POST", "/openapi-tests/items", "openapitest.create");
+        let state = routed_state(&GatewayConfig::default());
+        
+        let (status, document) = send(&state, Request::get(openapi::OPENAPI_PATH).body(Body::empty()).unwrap()).await;
+        
+        assert_eq!(status, StatusCode::OK);
+        assert!(document["openapi"].as_str().unwrap().starts_with("3.0."));
+        let get = &document["paths"]["/openapi-tests/items/{item_id}"]["get"];
+        assert_eq!(get["operationId"], "openapitest.get");
+        assert_eq!(get["parameters"][0]["name"], "item_id");
+        assert_eq!(document["paths"]["/openapi-tests/items"]["post"]["operationId"], "openapitest.create");
+        assert!(document["tags"].as_array().unwrap().contains(&json!({ "name": "openapitest" })));
+    }
+    
+    #[test]
+    fn wildcard_origins_stop_at_a_label_boundary() {
+        let cases = [
+            ("*.example.com", "https://app.example.com", true),
+            ("*.example.com", "http://a.b.example.com", true),
+            ("*.example.com", "https://APP.Example.com", true),
+            ("https://*.example.com", "https://app.example.com", true),
+            ("https://app.example.com", "https://app.example.com", true),
+            // Spoofs the old `ends_with` check let through
+            ("*.example.com", "https://evilexample.com", false),
+            ("*.example.com", "https://notexample.com", false),
+            ("*.example.com", "https://example.com.evil.net", false),
+            ("*.example.com", "https://example.com", false),
+            ("*.example.com", "https://.example.com", false),
+            ("*.example.com", "app.example.com", false),
+            ("https://*.example.com", "http://app.example.com", false),
+            ("https://app.example.com", "https://app.example.com.evil.net", false),
+        ];
+        
+        for (allowed, origin, expected) in cases {
+            assert_eq!(origin_matches(allowed, origin), expected, "{} vs {}", allowed, origin);
+        }
+    }
+    
+    fn cors_for(origins: &[&str]) -> CorsMiddleware {
+        CorsMiddleware::new(CorsConfig {
+            allowed_origins: origins.iter().map(|o| o.to_string()).collect(),
+            ..CorsConfig::default()
+        })
+    }
+    
+    async fn cors_response(middleware: &CorsMiddleware, req: Request<Body>) -> Response<Body> {
+        let handler: Box<HandlerFn> = Box::new(|_: &Request<Body>| Box::pin(async { Ok(Response::new(Body::empty())) }));
+        middleware.process(&req, Next::new(&[], handler.as_ref())).await.unwrap()
+    }
+    
+    #[tokio::test]
+    async fn spoofed_origins_get_no_cors_headers_but_still_vary() {
+        let middleware = cors_for(&["*.example.com"]);
+        
+        for (origin, allowed) in [("https://app.example.com", true), ("https://evilexample.com", false)] {
+            let req = Request::get("/invoices").header(header::ORIGIN, origin).body(Body::empty()).unwrap();
+            let response = cors_response(&middleware, req).await;
+            assert_eq!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_some(), allowed, "{}", origin);
+            assert_eq!(response.headers()[header::VARY], "Origin", "{}", origin);
+            
+            let preflight = Request::options("/invoices").header(header::ORIGIN, origin).body(Body::empty()).unwrap();
+            let response = cors_response(&middleware, preflight).await;
+            assert_eq!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_some(), allowed, "{}", origin);
+            assert_eq!(response.headers()[header::VARY], "Origin", "{}", origin);
+        }
+    }
+    
+    fn preflight(headers: &str) -> Request<Body> {
+        Request::options("/invoices")
+            .header(header::ORIGIN, "https://app.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, headers)
+            .body(Body::empty())
+            .unwrap()
+    }
+    
+    #[tokio::test]
+    async fn preflight_lists_the_configured_methods_headers_and_max_age() {
+        let middleware = CorsMiddleware::new(CorsConfig {
+            allowed_origins: vec!["https://app.example.com".to_string()],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec!["Content-Type".to_string(), "X-Tenant-Id".to_string()],
+            max_age_secs: 600,
+            ..CorsConfig::default()
+        });
+        
+        let response = cors_response(&middleware, preflight("X-Tenant-Id")).await;
+        
+        let headers = response.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "GET, POST");
+        // DELETE wasn't configured, so it is left out even though it was asked for
+        assert!(!headers[header::ACCESS_CONTROL_ALLOW_METHODS].to_str().unwrap().contains("DELETE"));
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_HEADERS], "Content-Type, X-Tenant-Id");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+    }
+    
+    #[tokio::test]
+    async fn preflight_can_reflect_the_requested_headers() {
+        let middleware = CorsMiddleware::new(CorsConfig {
+            allowed_origins: vec!["https://app.example.com".to_string()],
+            reflect_request_headers: true,
+            ..CorsConfig::default()
+        });
+        
+        let response = cors_response(&middleware, preflight("X-Custom-Header, X-Request-Nonce")).await;
+        
+        let headers = response.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_HEADERS], "X-Custom-Header, X-Request-Nonce");
+        let vary: Vec<&str> = headers.get_all(header::VARY).iter().map(|v| v.to_str().unwrap()).collect();
+        assert_eq!(vary, ["Origin", "Access-Control-Request-Headers"]);
+    }
+    
+    /// Fails with an internal error until its `failures` are used up
+    struct FlakyGateway {
+        failures: AtomicUsize,
+        calls: AtomicUsize,
+    }
+    
+    impl FlakyGateway {
+        fn failing(failures: usize) -> Arc<Self> {
+            Arc::new(Self { failures: AtomicUsize::new(failures), calls: AtomicUsize::new(0) })
+        }
+    }
+    
+    #[async_trait]
+    impl Gateway for FlakyGateway {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn dispatch(&self, _service: &str, action: &str, _params: Value) -> Result<Value> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let failed = self.failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1))
+                .is_ok();
+            if failed {
+                return Err(ServiceError::internal("Connection reset by peer").into());
+            }
+            Ok(json!({ "action": action }))
+        }
+    }
+    
+    fn retrying_state(gateway: Arc<FlakyGateway>) -> Arc<GatewayState> {
+        let config = GatewayConfig::builder()
+            .retry(3, Duration::from_millis(1), Duration::from_millis(5))
+            .build();
+        Arc::new(GatewayState::new(gateway, &config, Arc::new(Metrics::new())).unwrap())
+    }
+    
+    #[tokio::test]
+    async fn idempotent_request_succeeds_after_two_transient_failures() {
+        register_test_route("GET", "/retry-tests/:id", "retrytest.get");
+        let gateway = FlakyGateway::failing(2);
+        let state = retrying_state(gateway.clone());
+        
+        let (status, body) = send(&state, Request::get("/retry-tests/1").body(Body::empty()).unwrap()).await;
+        
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["action"], "get");
+        assert_eq!(gateway.calls.load(Ordering::SeqCst), 3);
+    }
+    
+    #[tokio::test]
+    async fn exhausted_retries_return_the_last_error_and_post_is_never_retried() {
+        register_test_route("PUT", "/retry-tests/:id", "retrytest.update");
+        register_test_route("POST", "/retry-tests", "retrytest.create");
+        
+        let gateway = FlakyGateway::failing(5);
+        let state = retrying_state(gateway.clone());
+        let (status, _) = send(&state, Request::put("/retry-tests/1").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(gateway.calls.load(Ordering::SeqCst), 3);
+        
+        let gateway = FlakyGateway::failing(1);
+        let state = retrying_state(gateway.clone());
+        let (status, _) = send(&state, Request::post("/retry-tests").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(gateway.calls.load(Ordering::SeqCst), 1);
+    }
+    
+    #[tokio::test]
+    async fn node_request_dispatches_to_the_routed_action() {
+        register_test_route("GET", "/node-tests/:id", "nodetest.get");
+        
+        let body = RoutedGateway
+            .handle_request("get:/node-tests/42".to_string(), Some(json!({ "id": "ignored", "expand": true })))
+            .await
+            .unwrap();
+        
+        assert_eq!(body["service"], "nodetest");
+        assert_eq!(body["action"], "get");
+        assert_eq!(body["params"], json!({ "id": "42", "expand": true }));
+    }
+    
+    #[tokio::test]
+    async fn malformed_node_requests_come_back_as_error_values() {
+        register_test_route("GET", "/node-tests/:id/lines", "nodetest.lines");
+        assert_eq!(parse_request_path("DELETE:/node-tests/1"), Ok((Method::DELETE, "/node-tests/1")));
+        
+        for (path, status) in [
+            ("GET/node-tests/1/lines", 400),
+            ("G ET:/node-tests/1/lines", 400),
+            ("GET:node-tests/1/lines", 400),
+            ("PATCH:/node-tests/1/lines", 404),
+            ("GET:/node-tests", 404),
+        ] {
+            let body = RoutedGateway.handle_request(path.to_string(), None).await.unwrap();
+            assert_eq!(body["status"], status, "{}", path);
+            assert!(body["error"].is_string(), "{}", path);
+        }
+        
+        let missing = FailingGateway(ServiceError::not_found("Invoice not found"));
+        let body = missing.handle_request("GET:/node-tests/1/lines".to_string(), None).await.unwrap();
+        assert_eq!(body, json!({ "error": "Invoice not found", "status": 404 }));
+    }
+    
+    #[tokio::test]
+    async fn connection_over_the_limit_is_rejected_promptly() {
+        register_test_route("GET", "/connection-tests/ping", "connectiontest.ping");
+        let port = free_port();
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        let config = GatewayConfig::builder()
+            .port(port)
+            .max_connections(1, Duration::from_millis(50))
+            .build();
+        let handle = start_gateway_with_handle(RoutedGateway, config).await.unwrap();
+        
+        // An idle connection holds the only slot
+        let idle = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        
+        let started = Instant::now();
+        let rejected = tokio::time::timeout(Duration::from_secs(2), http_get(addr, "/connection-tests/ping")).await.unwrap();
+        assert!(rejected.starts_with("HTTP/1.1 503"), "{}", rejected);
+        assert!(rejected.to_ascii_lowercase().contains("retry-after: 1"), "{}", rejected);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        
+        // Closing the idle connection frees its slot
+        drop(idle);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let served = http_get(addr, "/connection-tests/ping").await;
+        assert!(served.starts_with("HTTP/1.1 200"), "{}", served);
+        
+        handle.shutdown().await.unwrap();
+    }
+    
+    /// `multipart/form-data` body with a `caption` text part and an `avatar` file part
+    fn avatar_form(image: &[u8]) -> Request<Body> {
+        let mut body = Vec::new();
+        body.extend_from_slice(b"--form-boundary\r\nContent-Disposition: form-data; name=\"caption\"\r\n\r\n");
+        body.extend_from_slice("Holiday photo – août".as_bytes());
+        body.extend_from_slice(b"\r\n--form-boundary\r\n");
+        body.extend_from_slice(b"Content-Disposition: form-data; name=\"avatar\"; filename=\"me.png\"\r\n");
+        body.extend_from_slice(b"Content-Type: image/png\r\n\r\n");
+        body.extend_from_slice(image);
+        body.extend_from_slice(b"\r\n--form-boundary--\r\n");
+        Request::post("/multipart-tests/avatar")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=form-boundary")
+            .body(Body::from(body))
+            .unwrap()
+    }
+    
+    #[tokio::test]
+    async fn text_and_file_parts_of_a_form_arrive_intact() {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+        
+        register_test_route("POST", "/multipart-tests/avatar", "multiparttest.upload");
+        let image = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', 0x00, 0xff, 0x10];
+        let state = routed_state(&GatewayConfig::default());
+        
+        let (status, body) = send(&state, avatar_form(&image)).await;
+        
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["params"]["caption"], "Holiday photo – août");
+        let avatar = &body["params"]["avatar"];
+        assert_eq!(avatar["filename"], "me.png");
+        assert_eq!(avatar["content_type"], "image/png");
+        assert_eq!(STANDARD.decode(avatar["data"].as_str().unwrap()).unwrap(), image);
+    }
+    
+    #[tokio::test]
+    async fn form_part_over_the_limit_is_rejected_with_413() {
+        register_test_route("POST", "/multipart-tests/avatar", "multiparttest.upload");
+        let state = routed_state(&GatewayConfig::builder().max_part_size(64).build());
+        
+        let (status, body) = send(&state, avatar_form(&[0u8; 65])).await;
+        
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["error"], "Form part exceeds the 64 byte limit");
+    }
+    
+    /// Records the path parameters and matched route each request carries
+    struct CaptureRoute(Arc<std::sync::Mutex<Vec<(routing::PathParams, routing::MatchedRoute)>>>);
+    
+    #[async_trait]
+    impl Middleware for CaptureRoute {
+        async fn process(&self, req: &Request<Body>, next: Next<'_>) -> Result<Response<Body>> {
+            let extensions = req.extensions();
+            let params = extensions.get::<routing::PathParams>().cloned().expect("no PathParams extension");
+            let route = extensions.get::<routing::MatchedRoute>().cloned().expect("no MatchedRoute extension");
+            self.0.lock().unwrap().push((params, route));
+            next.run(req).await
+        }
+    }
+    
+    #[tokio::test]
+    async fn path_params_reach_middleware_through_the_request_extensions() {
+        register_test_route("GET", "/path-param-tests/:org/members/:id", "pathparamtest.member");
+        let config = GatewayConfig::default();
+        let state = routed_state(&config);
+        let captured = Arc::new(std::sync::Mutex::new(Vec::new()));
+        state.replace_settings(GatewaySettings {
+            config: config.clone(),
+            middlewares: vec![Box::new(CaptureRoute(captured.clone()))],
+            route_middlewares: HashMap::new(),
+            sampler: sampling::TraceSampler::new(config.trace_sample_rate),
+        });
+        
+        for path in ["/path-param-tests/acme/members/7", "/path-param-tests/acme/members/7/"] {
+            let (status, _) = send(&state, Request::get(path).body(Body::empty()).unwrap()).await;
+            assert_eq!(status, StatusCode::OK, "{}", path);
+        }
+        
+        let captured = captured.lock().unwrap();
+        assert_eq!(captured.len(), 2);
+        for (params, route) in captured.iter() {
+            assert_eq!((params.get("org"), params.get("id")), (Some("acme"), Some("7")));
+            assert_eq!(route.pattern, "/path-param-tests/:org/members/:id");
+            assert_eq!(route.method, "GET");
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,130 @@
+use crate::{Middleware, Next};
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::{header::HeaderValue, Body, Request, Response};
+use log::{info, warn};
+use std::time::Instant;
+use uuid::Uuid;
+
+/// Name in `GatewayConfig::middleware` that turns on the access log
+pub const LOGGING_MIDDLEWARE: &str = "logging";
+
+/// Header carrying the request id, read from callers and echoed on responses
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest caller-supplied request id that is reused
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Id correlating everything done for one request, stored in request extensions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Reuse the caller's `X-Request-Id` when it is well formed, otherwise mint a new one
+    pub fn for_request(req: &Request<Body>) -> Self {
+        let incoming = req.headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|id| is_valid_request_id(id));
+        
+        match incoming {
+            Some(id) => Self(id.to_string()),
+            None => Self(Uuid::new_v4().to_string()),
+        }
+    }
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// Middleware writing one access log line per request and echoing its `X-Request-Id`
+pub struct LoggingMiddleware;
+
+#[async_trait]
+impl Middleware for LoggingMiddleware {
+    async fn process(&self, req: &Request<Body>, next: Next<'_>) -> Result<Response<Body>> {
+        let request_id = req.extensions()
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_else(|| RequestId::for_request(req));
+        let started = Instant::now();
+        
+        let result = next.run(req).await;
+        let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+        
+        match result {
+            Ok(mut response) => {
+                info!(
+                    "request_id={} method={} path={} status={} elapsed_ms={:.1}",
+                    request_id.0, req.method(), req.uri().path(), response.status().as_u16(), elapsed_ms
+                );
+                if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+                    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+                }
+                Ok(response)
+            },
+            Err(e) => {
+                warn!(
+                    "request_id={} method={} path={} error=\"{}\" elapsed_ms={:.1}",
+                    request_id.0, req.method(), req.uri().path(), e, elapsed_ms
+                );
+                Err(e)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HandlerFn;
+    
+    /// Answers with the request id the handler saw in the extensions
+    fn echo_request_id() -> Box<HandlerFn> {
+        Box::new(|req: &Request<Body>| {
+            let seen = req.extensions().get::<RequestId>().map(|id| id.0.clone()).unwrap_or_default();
+            Box::pin(async move { Ok(Response::new(Body::from(seen))) })
+        })
+    }
+    
+    async fn run(req: Request<Body>) -> (String, String) {
+        let handler = echo_request_id();
+        let response = LoggingMiddleware.process(&req, Next::new(&[], handler.as_ref())).await.unwrap();
+        let header = response.headers()[REQUEST_ID_HEADER].to_str().unwrap().to_string();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (header, String::from_utf8(body.to_vec()).unwrap())
+    }
+    
+    #[tokio::test]
+    async fn response_carries_a_minted_request_id() {
+        let (header, _) = run(Request::get("/invoices").body(Body::empty()).unwrap()).await;
+        
+        assert!(Uuid::parse_str(&header).is_ok(), "{}", header);
+    }
+    
+    #[tokio::test]
+    async fn incoming_request_id_is_reused_and_shared_with_the_handler() {
+        let mut req = Request::get("/invoices").header(REQUEST_ID_HEADER, "trace-42").body(Body::empty()).unwrap();
+        let id = RequestId::for_request(&req);
+        req.extensions_mut().insert(id);
+        
+        let (header, seen_by_handler) = run(req).await;
+        
+        assert_eq!(header, "trace-42");
+        assert_eq!(seen_by_handler, "trace-42");
+    }
+    
+    #[test]
+    fn malformed_incoming_ids_are_replaced() {
+        let too_long = "a".repeat(MAX_REQUEST_ID_LEN + 1);
+        for incoming in ["", "has spaces", "quote\"d", too_long.as_str()] {
+            let req = Request::get("/").header(REQUEST_ID_HEADER, incoming).body(Body::empty()).unwrap();
+            let id = RequestId::for_request(&req);
+            assert!(Uuid::parse_str(&id.0).is_ok(), "{:?} kept as {}", incoming, id.0);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{read_recover, write_recover, Gateway};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::RwLock;
+
+/// Path of the metrics endpoint
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Histogram of request body sizes in bytes, labelled by `service.action`
+pub const REQUEST_SIZE_HISTOGRAM: &str = "gateway_action_request_bytes";
+/// Histogram of response body sizes in bytes, labelled by `service.action`
+pub const RESPONSE_SIZE_HISTOGRAM: &str = "gateway_action_response_bytes";
+
+/// Bucket upper bounds used for payload-size histograms
+pub const SIZE_BUCKETS: [f64; 8] = [64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0];
+
+/// Cumulative-bucket histogram
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: Vec<f64>,
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Self {
+        Self {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+    
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Canonical `k="v",...` form of a label set, with values escaped
+fn label_key(labels: &[(&str, &str)]) -> String {
+    labels.iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Render labels as `{k="v",...}`, optionally with an extra `le` label
+fn render_labels(labels: &str, le: Option<&str>) -> String {
+    match (labels.is_empty(), le) {
+        (true, None) => String::new(),
+        (true, Some(le)) => format!("{{le=\"{}\"}}", le),
+        (false, None) => format!("{{{}}}", labels),
+        (false, Some(le)) => format!("{{{},le=\"{}\"}}", labels, le),
+    }
+}
+
+/// In-process metrics registry rendered in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    gauges: RwLock<BTreeMap<String, i64>>,
+    /// Keyed by (metric name, rendered label set)
+    histograms: RwLock<BTreeMap<(String, String), Histogram>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Set a gauge to an absolute value
+    pub fn set_gauge(&self, name: &str, value: i64) {
+        write_recover(&self.gauges).insert(name.to_string(), value);
+    }
+    
+    /// Current value of a gauge, if it has been set
+    pub fn gauge(&self, name: &str) -> Option<i64> {
+        read_recover(&self.gauges).get(name).copied()
+    }
+    
+    /// Record an observation in a histogram, creating it with `bounds` on first use
+    pub fn observe(&self, name: &str, labels: &[(&str, &str)], bounds: &[f64], value: f64) {
+        write_recover(&self.histograms)
+            .entry((name.to_string(), label_key(labels)))
+            .or_insert_with(|| Histogram::new(bounds))
+            .observe(value);
+    }
+    
+    /// Number of observations recorded in a histogram for an exact label set
+    pub fn histogram_count(&self, name: &str, labels: &[(&str, &str)]) -> Option<u64> {
+        read_recover(&self.histograms)
+            .get(&(name.to_string(), label_key(labels)))
+            .map(|h| h.count)
+    }
+    
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        
+        for (name, value) in read_recover(&self.gauges).iter() {
+            out.push_str(&format!("# TYPE {} gauge\n{} {}\n", name, name, value));
+        }
+        
+        let mut last_name: Option<&str> = None;
+        let histograms = read_recover(&self.histograms);
+        for ((name, labels), histogram) in histograms.iter() {
+            if last_name != Some(name.as_str()) {
+                out.push_str(&format!("# TYPE {} histogram\n", name));
+                last_name = Some(name.as_str());
+            }
+            for (bound, count) in histogram.bounds.iter().zip(histogram.counts.iter()) {
+                let le = bound.to_string();
+                out.push_str(&format!("{}_bucket{} {}\n", name, render_labels(labels, Some(&le)), count));
+            }
+            out.push_str(&format!("{}_bucket{} {}\n", name, render_labels(labels, Some("+Inf")), histogram.count));
+            out.push_str(&format!("{}_sum{} {}\n", name, render_labels(labels, None), histogram.sum));
+            out.push_str(&format!("{}_count{} {}\n", name, render_labels(labels, None), histogram.count));
+        }
+        
+        out
+    }
+}
+
+/// Writer that only counts the bytes passed through it
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Serialized size of a JSON value, measured without buffering the output
+pub fn json_size(value: &serde_json::Value) -> usize {
+    let mut counter = ByteCounter(0);
+    // Writing to a counter cannot fail
+    let _ = serde_json::to_writer(&mut counter, value);
+    counter.0
+}
+
+/// Dispatch an action, recording its request and response payload sizes
+pub async fn dispatch_measured(
+    gateway: &dyn Gateway,
+    metrics: &Metrics,
+    service: &str,
+    action: &str,
+    params: serde_json::Value,
+) -> Result<serde_json::Value> {
+    let params = crate::sampling::propagate(params);
+    let label = format!("{}.{}", service, action);
+    let labels = [("action", label.as_str())];
+    metrics.observe(REQUEST_SIZE_HISTOGRAM, &labels, &SIZE_BUCKETS, json_size(&params) as f64);
+    
+    let result = gateway.dispatch(service, action, params).await;
+    if let Ok(response) = &result {
+        metrics.observe(RESPONSE_SIZE_HISTOGRAM, &labels, &SIZE_BUCKETS, json_size(response) as f64);
+    }
+    
+    result
+}
@@ -0,0 +1,69 @@
+use crate::body::BodyError;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::body::Bytes;
+use hyper::{header, HeaderMap};
+use multer::{Constraints, Multipart, SizeLimit};
+use serde_json::{json, Map, Value};
+use std::convert::Infallible;
+
+/// Boundary of a `multipart/form-data` body; `None` for any other content type
+pub fn form_data_boundary(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+    let mime = content_type.split(';').next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    multer::parse_boundary(content_type).ok()
+}
+
+fn body_error(err: multer::Error) -> BodyError {
+    match err {
+        multer::Error::FieldSizeExceeded { limit, .. } => BodyError::PartTooLarge(limit as usize),
+        e => BodyError::InvalidMultipart(e.to_string()),
+    }
+}
+
+/// Fields of a `multipart/form-data` body; `None` when the body is empty or not a form.
+///
+/// Text parts become strings. File parts become objects with the part's
+/// `filename`, `content_type` and base64 `data`, the same shape services return
+/// for downloads. A part larger than `max_part_bytes` fails with `413`.
+pub async fn form_fields(
+    headers: &HeaderMap,
+    body: Bytes,
+    max_part_bytes: usize,
+) -> Result<Option<Map<String, Value>>, BodyError> {
+    let boundary = match form_data_boundary(headers) {
+        Some(boundary) if !body.is_empty() => boundary,
+        _ => return Ok(None),
+    };
+    
+    // The whole body was already held to `max_body_bytes` while buffering it
+    let limits = SizeLimit::new().per_field(max_part_bytes as u64);
+    let stream = futures::stream::once(async move { Ok::<_, Infallible>(body) });
+    let mut multipart = Multipart::with_constraints(stream, boundary, Constraints::new().size_limit(limits));
+    
+    let mut fields = Map::new();
+    while let Some(field) = multipart.next_field().await.map_err(body_error)? {
+        let name = field.name()
+            .ok_or_else(|| BodyError::InvalidMultipart("Form part has no name".to_string()))?
+            .to_string();
+        let value = match field.file_name().map(str::to_string) {
+            Some(filename) => {
+                let content_type = field.content_type()
+                    .map(|mime| mime.to_string())
+                    .unwrap_or_else(|| "application/octet-stream".to_string());
+                let data = field.bytes().await.map_err(body_error)?;
+                json!({
+                    "filename": filename,
+                    "content_type": content_type,
+                    "data": STANDARD.encode(&data),
+                })
+            },
+            None => Value::String(field.text().await.map_err(body_error)?),
+        };
+        fields.insert(name, value);
+    }
+    
+    Ok(Some(fields))
+}
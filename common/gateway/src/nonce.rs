@@ -0,0 +1,232 @@
+use crate::{Middleware, Next};
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::{header, Body, Request, Response, StatusCode};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Header carrying the single-use request nonce
+pub const NONCE_HEADER: &str = "x-request-nonce";
+
+/// Replay protection for routes that opt in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NonceConfig {
+    /// Paths whose requests are checked for replayed nonces
+    pub routes: Vec<String>,
+    /// Reject requests to protected routes that carry no nonce
+    #[serde(default)]
+    pub require_nonce: bool,
+    /// Seconds a nonce is remembered after first use
+    #[serde(default = "default_nonce_ttl_secs")]
+    pub ttl_secs: u64,
+    /// Most nonces remembered at once; the oldest are forgotten first
+    #[serde(default = "default_nonce_max_entries")]
+    pub max_entries: usize,
+}
+
+fn default_nonce_ttl_secs() -> u64 {
+    300
+}
+
+fn default_nonce_max_entries() -> usize {
+    10_000
+}
+
+impl Default for NonceConfig {
+    fn default() -> Self {
+        Self {
+            routes: Vec::new(),
+            require_nonce: false,
+            ttl_secs: default_nonce_ttl_secs(),
+            max_entries: default_nonce_max_entries(),
+        }
+    }
+}
+
+/// Bounded record of recently used nonces
+#[derive(Debug, Default)]
+struct SeenNonces {
+    expires_at: HashMap<String, Instant>,
+    // Insertion order, so expiry and eviction only ever look at the front
+    order: VecDeque<(String, Instant)>,
+}
+
+/// Remembers nonces for a fixed TTL, holding at most `max_entries` of them
+#[derive(Debug)]
+pub struct NonceStore {
+    ttl: Duration,
+    max_entries: usize,
+    seen: Mutex<SeenNonces>,
+}
+
+impl NonceStore {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries: max_entries.max(1),
+            seen: Mutex::new(SeenNonces::default()),
+        }
+    }
+    
+    /// Record `nonce`, returning `false` if it was already used within the TTL
+    pub fn check_and_record(&self, nonce: &str) -> bool {
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        
+        while let Some((_, expires_at)) = seen.order.front() {
+            if *expires_at > now {
+                break;
+            }
+            let (expired, expires_at) = seen.order.pop_front().unwrap();
+            // Only forget the nonce if it wasn't recorded again since
+            if seen.expires_at.get(&expired) == Some(&expires_at) {
+                seen.expires_at.remove(&expired);
+            }
+        }
+        
+        if seen.expires_at.get(nonce).map_or(false, |expires_at| *expires_at > now) {
+            return false;
+        }
+        
+        while seen.expires_at.len() >= self.max_entries {
+            match seen.order.pop_front() {
+                Some((oldest, expires_at)) => {
+                    if seen.expires_at.get(&oldest) == Some(&expires_at) {
+                        seen.expires_at.remove(&oldest);
+                    }
+                },
+                None => break,
+            }
+        }
+        
+        let expires_at = now + self.ttl;
+        seen.expires_at.insert(nonce.to_string(), expires_at);
+        seen.order.push_back((nonce.to_string(), expires_at));
+        true
+    }
+    
+    /// Number of nonces currently remembered
+    pub fn len(&self) -> usize {
+        self.seen.lock().unwrap_or_else(|e| e.into_inner()).expires_at.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Reject replayed requests to opted-in routes with `409 Conflict`.
+///
+/// A request is a replay when its `X-Request-Nonce` was already seen within the
+/// configured TTL. Routes not listed in the config pass straight through.
+pub struct NonceMiddleware {
+    config: NonceConfig,
+    store: NonceStore,
+}
+
+impl NonceMiddleware {
+    pub fn new(config: NonceConfig) -> Self {
+        let store = NonceStore::new(Duration::from_secs(config.ttl_secs), config.max_entries);
+        Self { config, store }
+    }
+    
+    fn protects(&self, path: &str) -> bool {
+        self.config.routes.iter().any(|route| route == path)
+    }
+}
+
+fn json_error(status: StatusCode, message: &str) -> Result<Response<Body>> {
+    let body = serde_json::json!({ "error": message });
+    Ok(Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))?)
+}
+
+#[async_trait]
+impl Middleware for NonceMiddleware {
+    async fn process(&self, req: &Request<Body>, next: Next<'_>) -> Result<Response<Body>> {
+        if !self.protects(req.uri().path()) {
+            return next.run(req).await;
+        }
+        
+        let nonce = req.headers()
+            .get(NONCE_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty());
+        
+        match nonce {
+            Some(nonce) => {
+                if !self.store.check_and_record(nonce) {
+                    warn!("Rejected replayed request: {} {}", req.method(), req.uri().path());
+                    return json_error(StatusCode::CONFLICT, "Request nonce has already been used");
+                }
+            },
+            None if self.config.require_nonce => {
+                return json_error(StatusCode::BAD_REQUEST, "Missing X-Request-Nonce header");
+            },
+            None => {},
+        }
+        
+        next.run(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HandlerFn;
+    
+    fn ok_handler() -> Box<HandlerFn> {
+        Box::new(|_: &Request<Body>| Box::pin(async { Ok(Response::new(Body::from("done"))) }))
+    }
+    
+    fn payment(nonce: &str) -> Request<Body> {
+        Request::post("/payments")
+            .header(NONCE_HEADER, nonce)
+            .body(Body::empty())
+            .unwrap()
+    }
+    
+    #[tokio::test]
+    async fn replayed_nonce_is_rejected_and_a_fresh_one_passes() {
+        let middleware = NonceMiddleware::new(NonceConfig {
+            routes: vec!["/payments".to_string()],
+            ..NonceConfig::default()
+        });
+        let handler = ok_handler();
+        
+        let first = middleware.process(&payment("n-1"), Next::new(&[], handler.as_ref())).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        
+        let replay = middleware.process(&payment("n-1"), Next::new(&[], handler.as_ref())).await.unwrap();
+        assert_eq!(replay.status(), StatusCode::CONFLICT);
+        
+        let fresh = middleware.process(&payment("n-2"), Next::new(&[], handler.as_ref())).await.unwrap();
+        assert_eq!(fresh.status(), StatusCode::OK);
+        
+        // Routes that didn't opt in are never checked
+        let unprotected = Request::post("/other").header(NONCE_HEADER, "n-1").body(Body::empty()).unwrap();
+        let response = middleware.process(&unprotected, Next::new(&[], handler.as_ref())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    
+    #[test]
+    fn store_forgets_expired_and_oldest_nonces() {
+        let store = NonceStore::new(Duration::from_millis(20), 2);
+        assert!(store.check_and_record("a"));
+        assert!(store.check_and_record("b"));
+        assert!(store.check_and_record("c"));
+        assert_eq!(store.len(), 2);
+        // "a" was evicted to stay within the bound
+        assert!(store.check_and_record("a"));
+        
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(store.check_and_record("c"));
+        assert_eq!(store.len(), 1);
+    }
+}
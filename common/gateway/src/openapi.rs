@@ -0,0 +1,197 @@
+use crate::forwarding::RouteTarget;
+use crate::routing::split_path;
+use crate::RouteInfo;
+use hyper::{header, Body, Response, StatusCode};
+use serde_json::{json, Map, Value};
+
+/// Path the generated OpenAPI document is served on
+pub const OPENAPI_PATH: &str = "/openapi.json";
+
+/// Convert a route pattern to OpenAPI form, returning the names of its path parameters.
+///
+/// `/users/:id` becomes `/users/{id}`.
+fn openapi_path(pattern: &str) -> (String, Vec<String>) {
+    let mut params = Vec::new();
+    let segments: Vec<String> = split_path(pattern)
+        .into_iter()
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => {
+                params.push(name.to_string());
+                format!("{{{}}}", name)
+            },
+            None => segment.to_string(),
+        })
+        .collect();
+    
+    (format!("/{}", segments.join("/")), params)
+}
+
+fn operation(route: &RouteInfo, params: &[String]) -> Value {
+    let (tag, action) = match RouteTarget::parse(route.handler_name) {
+        Some(target) => (target.service, target.action),
+        None => ("default".to_string(), route.handler_name.to_string()),
+    };
+    
+    let mut operation = json!({
+        "operationId": route.handler_name,
+        "summary": format!("{}.{}", tag, action),
+        "tags": [tag],
+        "responses": {
+            "200": {
+                "description": "Successful response",
+                "content": { "application/json": { "schema": {} } },
+            },
+            "default": {
+                "description": "Error response",
+                "content": {
+                    "application/json": {
+                        "schema": {
+                            "type": "object",
+                            "properties": { "error": { "type": "string" } },
+                        },
+                    },
+                },
+            },
+        },
+    });
+    
+    if !params.is_empty() {
+        operation["parameters"] = params.iter()
+            .map(|name| json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" },
+            }))
+            .collect();
+    }
+    if matches!(route.method, "POST" | "PUT" | "PATCH") {
+        operation["requestBody"] = json!({
+            "required": false,
+            "content": { "application/json": { "schema": { "type": "object" } } },
+        });
+    }
+    
+    operation
+}
+
+/// Build an OpenAPI 3.0 document describing `routes`.
+///
+/// Each path gets one operation per method, tagged with the service named in
+/// the route's `service.action` handler.
+pub fn generate_openapi(routes: &[RouteInfo]) -> Value {
+    let mut paths = Map::new();
+    let mut tags = Vec::new();
+    
+    for route in routes {
+        let (path, params) = openapi_path(route.path);
+        let operation = operation(route, &params);
+        if let Some(tag) = operation["tags"][0].as_str() {
+            if !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+        
+        let item = paths.entry(path).or_insert_with(|| json!({}));
+        item[route.method.to_ascii_lowercase()] = operation;
+    }
+    tags.sort();
+    
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Kagi Gateway",
+            "version": crate::health::GATEWAY_VERSION,
+        },
+        "tags": tags.iter().map(|name| json!({ "name": name })).collect::<Vec<_>>(),
+        "paths": paths,
+    })
+}
+
+/// Serve the document for `routes`
+pub fn openapi_response(routes: &[RouteInfo]) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(generate_openapi(routes).to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn route(method: &'static str, path: &'static str, handler_name: &'static str) -> RouteInfo {
+        RouteInfo { method, path, handler_name, middleware: None }
+    }
+    
+    fn routes() -> Vec<RouteInfo> {
+        vec![
+            route("GET", "/invoices", "invoice.list"),
+            route("POST", "/invoices", "invoice.create"),
+            route("GET", "/invoices/:id", "invoice.get"),
+            route("DELETE", "/invoices/:id", "invoice.delete"),
+            route("POST", "/invoices/:id/payments/:payment_id", "invoice.record_payment"),
+            route("GET", "/profiles/:user_id", "profile.get_profile"),
+        ]
+    }
+    
+    /// Check the parts of the OpenAPI 3.0 schema the generator is responsible for
+    fn assert_structurally_valid(document: &Value) {
+        assert!(document["openapi"].as_str().unwrap().starts_with("3.0."));
+        assert!(document["info"]["title"].is_string());
+        assert!(document["info"]["version"].is_string());
+        
+        let tags: Vec<&str> = document["tags"].as_array().unwrap().iter().map(|t| t["name"].as_str().unwrap()).collect();
+        for (path, item) in document["paths"].as_object().unwrap() {
+            assert!(path.starts_with('/'), "{}", path);
+            assert!(!path.contains(':'), "{}", path);
+            let templated: Vec<&str> = path.split('/')
+                .filter_map(|segment| segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')))
+                .collect();
+            
+            for (method, operation) in item.as_object().unwrap() {
+                assert!(["get", "put", "post", "delete", "patch"].contains(&method.as_str()), "{}", method);
+                assert!(operation["operationId"].is_string());
+                assert!(tags.contains(&operation["tags"][0].as_str().unwrap()));
+                for (status, response) in operation["responses"].as_object().unwrap() {
+                    assert!(status == "default" || status.parse::<u16>().is_ok(), "{}", status);
+                    assert!(response["description"].is_string());
+                }
+                
+                // Every templated segment is declared as a required path parameter, and nothing else
+                let declared: Vec<&str> = operation["parameters"].as_array().map_or_else(Vec::new, |params| {
+                    params.iter()
+                        .inspect(|p| assert!(p["in"] == "path" && p["required"] == true, "{}", p))
+                        .map(|p| p["name"].as_str().unwrap())
+                        .collect()
+                });
+                assert_eq!(declared, templated, "{} {}", method, path);
+            }
+        }
+    }
+    
+    #[test]
+    fn document_is_structurally_valid_and_lists_every_route() {
+        let document = generate_openapi(&routes());
+        
+        assert_structurally_valid(&document);
+        let paths = document["paths"].as_object().unwrap();
+        let mut listed: Vec<&str> = paths.keys().map(String::as_str).collect();
+        listed.sort();
+        assert_eq!(listed, [
+            "/invoices",
+            "/invoices/{id}",
+            "/invoices/{id}/payments/{payment_id}",
+            "/profiles/{user_id}",
+        ]);
+        for route in routes() {
+            let (path, _) = openapi_path(route.path);
+            let operation = &paths[&path][route.method.to_ascii_lowercase()];
+            assert_eq!(operation["operationId"], route.handler_name);
+        }
+        assert_eq!(document["tags"], json!([{ "name": "invoice" }, { "name": "profile" }]));
+        assert!(paths["/invoices"]["post"]["requestBody"].is_object());
+        assert!(paths["/invoices"]["get"].get("requestBody").is_none());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::read_recover;
+use crate::service::GatewayService;
+use anyhow::Result;
+use kagi_shared::ServiceError;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+type Handler = Box<dyn Fn(&GatewayService, Value) -> Result<Value> + Send + Sync>;
+
+/// An introspection operation exposed by the gateway service
+struct Operation {
+    description: String,
+    handler: Handler,
+}
+
+/// Named operations with typed parameters and a uniform error envelope.
+///
+/// Parameters are deserialized into the handler's own type before it runs, so a
+/// malformed call fails with `ServiceError::Validation` naming the operation, and
+/// unknown operations fail with `ServiceError::NotFound` listing what is supported.
+#[derive(Default)]
+pub struct OperationRegistry {
+    operations: BTreeMap<String, Operation>,
+}
+
+impl OperationRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Registry holding the operations every gateway service supports
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register("ping", "Check the gateway service is responding", |_, _: NoParams| {
+            Ok(Value::from("pong"))
+        });
+        registry.register("getRoutes", "List registered routes, optionally filtered", get_routes);
+        registry.register("getOperations", "List supported operations", |service, _: NoParams| {
+            Ok(serde_json::json!(service.operations.describe()))
+        });
+        registry
+    }
+    
+    /// Add an operation, replacing any existing one with the same name
+    pub fn register<P, F>(&mut self, name: &str, description: &str, handler: F)
+    where
+        P: DeserializeOwned,
+        F: Fn(&GatewayService, P) -> Result<Value> + Send + Sync + 'static,
+    {
+        let operation = name.to_string();
+        let handler = move |service: &GatewayService, params: Value| {
+            // A missing params object means "no filters", not a malformed call
+            let params = if params.is_null() { Value::Object(Default::default()) } else { params };
+            let params: P = serde_json::from_value(params).map_err(|e| {
+                ServiceError::validation(format!("Invalid params for '{}': {}", operation, e))
+            })?;
+            handler(service, params)
+        };
+        
+        self.operations.insert(name.to_string(), Operation {
+            description: description.to_string(),
+            handler: Box::new(handler),
+        });
+    }
+    
+    /// Names of every registered operation, sorted
+    pub fn names(&self) -> Vec<String> {
+        self.operations.keys().cloned().collect()
+    }
+    
+    /// Name and description of every registered operation
+    pub fn describe(&self) -> BTreeMap<&str, &str> {
+        self.operations
+            .iter()
+            .map(|(name, operation)| (name.as_str(), operation.description.as_str()))
+            .collect()
+    }
+    
+    /// Run `name` with `params` against `service`
+    pub fn call(&self, service: &GatewayService, name: &str, params: Value) -> Result<Value> {
+        match self.operations.get(name) {
+            Some(operation) => (operation.handler)(service, params),
+            // Not a server fault: tell the caller what it can call instead
+            None => Err(ServiceError::not_found(format!(
+                "Unknown operation '{}' on {}; supported operations: {}",
+                name,
+                service.name,
+                self.names().join(", ")
+            )).into()),
+        }
+    }
+}
+
+/// Parameters for operations that take none
+#[derive(Debug, Default, Deserialize)]
+pub struct NoParams {}
+
+/// Filters accepted by `getRoutes`; every filter is optional
+#[derive(Debug, Default, Deserialize)]
+pub struct RouteFilter {
+    pub method: Option<String>,
+    pub service: Option<String>,
+    pub path_prefix: Option<String>,
+}
+
+fn get_routes(service: &GatewayService, filter: RouteFilter) -> Result<Value> {
+    let routes = read_recover(&service.routes);
+    let route_data: Vec<Value> = routes
+        .values()
+        .filter(|r| filter.method.as_ref().map_or(true, |m| r.method.eq_ignore_ascii_case(m)))
+        .filter(|r| filter.service.as_ref().map_or(true, |s| &r.service_name == s))
+        .filter(|r| filter.path_prefix.as_ref().map_or(true, |p| r.path_pattern.starts_with(p.as_str())))
+        .map(|r| {
+            serde_json::json!({
+                "method": r.method,
+                "path": r.path_pattern,
+                "service": r.service_name,
+                "action": r.action_name
+            })
+        })
+        .collect();
+    
+    Ok(Value::Array(route_data))
+}
@@ -0,0 +1,72 @@
+use percent_encoding::percent_decode_str;
+use serde_json::{Map, Value};
+
+/// Decode an `application/x-www-form-urlencoded` query string into call arguments.
+///
+/// Values are strings; a key given more than once collects its values into an
+/// array in order. `?x=` yields an empty string and a bare `?flag` yields `true`.
+pub fn parse_query(query: &str) -> Map<String, Value> {
+    let mut params = Map::new();
+    
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = match pair.split_once('=') {
+            Some((key, value)) => (decode(key), Value::String(decode(value))),
+            None => (decode(pair), Value::Bool(true)),
+        };
+        if key.is_empty() {
+            continue;
+        }
+        
+        match params.get_mut(&key) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => {
+                let first = existing.take();
+                *existing = Value::Array(vec![first, value]);
+            },
+            None => {
+                params.insert(key, value);
+            },
+        }
+    }
+    
+    params
+}
+
+/// Percent-decode one key or value, reading `+` as a space
+fn decode(raw: &str) -> String {
+    percent_decode_str(&raw.replace('+', " ")).decode_utf8_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    #[test]
+    fn repeated_keys_collect_into_an_array_in_order() {
+        let params = parse_query("status=Sent&status=Paid&user_id=alice&status=Draft");
+        
+        assert_eq!(params["status"], json!(["Sent", "Paid", "Draft"]));
+        assert_eq!(params["user_id"], "alice");
+    }
+    
+    #[test]
+    fn keys_and_values_are_percent_decoded() {
+        let params = parse_query("customer%20name=Acme+Ltd&note=50%25%20off%2C%20net%2030&caf%C3%A9=cr%C3%A8me");
+        
+        assert_eq!(params["customer name"], "Acme Ltd");
+        assert_eq!(params["note"], "50% off, net 30");
+        assert_eq!(params["café"], "crème");
+        // An encoded `&` or `=` is data, not a separator
+        assert_eq!(parse_query("q=a%26b%3Dc")["q"], "a&b=c");
+    }
+    
+    #[test]
+    fn empty_values_and_bare_flags_are_kept() {
+        let params = parse_query("x=&flag&&=ignored");
+        
+        assert_eq!(params["x"], "");
+        assert_eq!(params["flag"], true);
+        assert_eq!(params.len(), 2);
+    }
+}
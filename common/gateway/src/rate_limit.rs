@@ -0,0 +1,275 @@
+use crate::{Middleware, Next, RateLimitConfig};
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::{header, Body, Request, Response, StatusCode};
+use log::debug;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Header reporting the bucket size
+pub const LIMIT_HEADER: &str = "x-ratelimit-limit";
+/// Header reporting the requests left right now
+pub const REMAINING_HEADER: &str = "x-ratelimit-remaining";
+/// Header reporting the seconds until the bucket is full again
+pub const RESET_HEADER: &str = "x-ratelimit-reset";
+
+/// How often buckets that have refilled completely are dropped
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Token bucket for one client
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Outcome of taking a token from a client's bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the bucket is full again
+    pub reset_secs: u64,
+    /// Seconds until a token is available, when throttled
+    pub retry_after_secs: u64,
+}
+
+/// Per-client token buckets refilling at `default_rate` per second up to `default_burst`
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    by_client: HashMap<String, Bucket>,
+    evicted_at: Instant,
+}
+
+impl Buckets {
+    /// Drop buckets that would be full by now; a fresh bucket behaves identically
+    fn evict_stale(&mut self, now: Instant, rate: f64, burst: f64) {
+        self.by_client.retain(|_, bucket| {
+            let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+            bucket.tokens + elapsed * rate < burst
+        });
+        self.evicted_at = now;
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            rate: f64::from(config.default_rate.max(1)),
+            burst: f64::from(config.default_burst.max(1)),
+            buckets: Mutex::new(Buckets {
+                by_client: HashMap::new(),
+                evicted_at: Instant::now(),
+            }),
+        }
+    }
+    
+    /// Number of clients currently tracked
+    pub fn tracked_clients(&self) -> usize {
+        self.buckets.lock().unwrap_or_else(|e| e.into_inner()).by_client.len()
+    }
+    
+    /// Take one token from `client`'s bucket
+    pub fn acquire(&self, client: &str) -> Quota {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if now.duration_since(buckets.evicted_at) >= EVICTION_INTERVAL {
+            buckets.evict_stale(now, self.rate, self.burst);
+        }
+        let bucket = buckets.by_client.entry(client.to_string()).or_insert_with(|| Bucket {
+            tokens: self.burst,
+            refilled_at: now,
+        });
+        
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.refilled_at = now;
+        
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        
+        Quota {
+            allowed,
+            limit: self.burst as u32,
+            remaining: bucket.tokens.floor() as u32,
+            reset_secs: ((self.burst - bucket.tokens) / self.rate).ceil() as u64,
+            retry_after_secs: if allowed { 0 } else { ((1.0 - bucket.tokens) / self.rate).ceil().max(1.0) as u64 },
+        }
+    }
+}
+
+/// Identify the client a request counts against.
+///
+/// `X-Forwarded-For` is only believed when the connection comes from a trusted
+/// proxy, and then read from the right: the last address no trusted proxy
+/// added is the client, since anything left of it may be forged.
+fn client_key(req: &Request<Body>, trusted_proxies: &[IpAddr]) -> String {
+    let peer = match req.extensions().get::<SocketAddr>() {
+        Some(addr) => addr.ip(),
+        None => return "unknown".to_string(),
+    };
+    if !trusted_proxies.contains(&peer) {
+        return peer.to_string();
+    }
+    
+    let forwarded = req.headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .collect::<Vec<_>>();
+    for hop in forwarded.iter().rev() {
+        match hop.parse::<IpAddr>() {
+            Ok(ip) if trusted_proxies.contains(&ip) => continue,
+            Ok(ip) => return ip.to_string(),
+            // Not an address, so the header can't be trusted past this point
+            Err(_) => break,
+        }
+    }
+    peer.to_string()
+}
+
+/// Add the quota headers to a response
+fn apply_headers(response: &mut Response<Body>, quota: &Quota) {
+    let headers = response.headers_mut();
+    headers.insert(LIMIT_HEADER, quota.limit.into());
+    headers.insert(REMAINING_HEADER, quota.remaining.into());
+    headers.insert(RESET_HEADER, quota.reset_secs.into());
+    if !quota.allowed {
+        headers.insert(header::RETRY_AFTER, quota.retry_after_secs.into());
+    }
+}
+
+/// Throttle clients with `429 Too Many Requests` once their bucket is empty.
+///
+/// Every response it governs carries `X-RateLimit-Limit`, `X-RateLimit-Remaining`
+/// and `X-RateLimit-Reset`; throttled ones also carry `Retry-After`.
+pub struct RateLimitMiddleware {
+    limiter: RateLimiter,
+    trusted_proxies: Vec<IpAddr>,
+}
+
+impl RateLimitMiddleware {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            limiter: RateLimiter::new(config),
+            trusted_proxies: config.trusted_proxies.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for RateLimitMiddleware {
+    async fn process(&self, req: &Request<Body>, next: Next<'_>) -> Result<Response<Body>> {
+        let client = client_key(req, &self.trusted_proxies);
+        let quota = self.limiter.acquire(&client);
+        
+        let mut response = if quota.allowed {
+            next.run(req).await?
+        } else {
+            debug!("Rate limited {} on {} {}", client, req.method(), req.uri().path());
+            let body = serde_json::json!({
+                "error": "Too many requests",
+                "retry_after": quota.retry_after_secs,
+            });
+            Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))?
+        };
+        
+        apply_headers(&mut response, &quota);
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HandlerFn;
+    
+    fn request(peer: &str, forwarded_for: Option<&str>) -> Request<Body> {
+        let mut req = Request::get("/invoices").body(Body::empty()).unwrap();
+        if let Some(forwarded_for) = forwarded_for {
+            req.headers_mut().insert("x-forwarded-for", forwarded_for.parse().unwrap());
+        }
+        req.extensions_mut().insert(SocketAddr::new(peer.parse().unwrap(), 40000));
+        req
+    }
+    
+    async fn status(middleware: &RateLimitMiddleware, req: Request<Body>) -> StatusCode {
+        let handler: Box<HandlerFn> = Box::new(|_: &Request<Body>| Box::pin(async { Ok(Response::new(Body::empty())) }));
+        middleware.process(&req, Next::new(&[], handler.as_ref())).await.unwrap().status()
+    }
+    
+    fn config(burst: u32, trusted_proxies: &[&str]) -> RateLimitConfig {
+        RateLimitConfig {
+            default_rate: 1,
+            default_burst: burst,
+            trusted_proxies: trusted_proxies.iter().map(|ip| ip.parse().unwrap()).collect(),
+        }
+    }
+    
+    #[tokio::test]
+    async fn request_past_the_burst_is_throttled_whatever_it_claims_to_forward() {
+        let middleware = RateLimitMiddleware::new(&config(3, &[]));
+        
+        for n in 0..3 {
+            let spoofed = format!("203.0.113.{}", n);
+            assert_eq!(status(&middleware, request("192.0.2.1", Some(&spoofed))).await, StatusCode::OK);
+        }
+        let spoofed = request("192.0.2.1", Some("203.0.113.99"));
+        assert_eq!(status(&middleware, spoofed).await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(middleware.limiter.tracked_clients(), 1);
+    }
+    
+    #[tokio::test]
+    async fn trusted_proxy_is_limited_per_forwarded_client() {
+        let middleware = RateLimitMiddleware::new(&config(1, &["10.0.0.1"]));
+        
+        assert_eq!(status(&middleware, request("10.0.0.1", Some("198.51.100.7"))).await, StatusCode::OK);
+        assert_eq!(status(&middleware, request("10.0.0.1", Some("198.51.100.8"))).await, StatusCode::OK);
+        // Entries left of the real client are the client's own claims
+        let prefixed = request("10.0.0.1", Some("203.0.113.5, 198.51.100.7"));
+        assert_eq!(status(&middleware, prefixed).await, StatusCode::TOO_MANY_REQUESTS);
+    }
+    
+    #[tokio::test]
+    async fn quota_headers_count_down_and_throttled_responses_say_when_to_retry() {
+        let middleware = RateLimitMiddleware::new(&config(3, &[]));
+        let handler: Box<HandlerFn> = Box::new(|_: &Request<Body>| Box::pin(async { Ok(Response::new(Body::empty())) }));
+        let header_value = |response: &Response<Body>, name: &str| -> u64 {
+            response.headers()[name].to_str().unwrap().parse().unwrap()
+        };
+        
+        for remaining in [2, 1, 0] {
+            let req = request("192.0.2.1", None);
+            let response = middleware.process(&req, Next::new(&[], handler.as_ref())).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(header_value(&response, LIMIT_HEADER), 3);
+            assert_eq!(header_value(&response, REMAINING_HEADER), remaining);
+            assert!(header_value(&response, RESET_HEADER) >= 1);
+            assert!(response.headers().get(header::RETRY_AFTER).is_none());
+        }
+        
+        let req = request("192.0.2.1", None);
+        let throttled = middleware.process(&req, Next::new(&[], handler.as_ref())).await.unwrap();
+        assert_eq!(throttled.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header_value(&throttled, REMAINING_HEADER), 0);
+        assert_eq!(header_value(&throttled, header::RETRY_AFTER.as_str()), 1);
+    }
+}
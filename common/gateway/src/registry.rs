@@ -0,0 +1,178 @@
+use crate::Gateway;
+use anyhow::Result;
+use async_trait::async_trait;
+use kagi_node::services::{AbstractService, RequestContext, ServiceMetadata, ServiceRequest, ServiceResponse, ServiceState};
+use kagi_shared::{ActionParams, ActionService, ServiceRegistry, PING_OPERATION};
+use log::{debug, info};
+use serde_json::Value;
+use std::sync::Arc;
+
+/// The gateway forwards calls to the services registered under their names
+#[async_trait]
+impl Gateway for ServiceRegistry {
+    async fn run(&self) -> Result<()> {
+        Ok(())
+    }
+    
+    async fn dispatch(&self, service: &str, action: &str, params: Value) -> Result<Value> {
+        self.call(service, action, params).await
+    }
+}
+
+/// Node registration for an `ActionService`.
+///
+/// Requests are dispatched by operation, the last segment of the request path,
+/// so every service is added to a node the same way.
+pub struct NodeService {
+    service: Arc<dyn ActionService>,
+    path: String,
+    state: ServiceState,
+}
+
+impl NodeService {
+    pub fn new(service: Arc<dyn ActionService>) -> Self {
+        Self {
+            path: service.name().to_string(),
+            service,
+            state: ServiceState::Stopped,
+        }
+    }
+    
+    /// One node service per service in `registry`
+    pub fn all(registry: &ServiceRegistry) -> Vec<Self> {
+        registry
+            .names()
+            .iter()
+            .filter_map(|name| registry.get(name))
+            .map(Self::new)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl AbstractService for NodeService {
+    fn name(&self) -> &str {
+        self.service.name()
+    }
+    
+    fn path(&self) -> &str {
+        &self.path
+    }
+    
+    fn description(&self) -> &str {
+        self.service.description()
+    }
+    
+    fn state(&self) -> ServiceState {
+        self.state
+    }
+    
+    fn metadata(&self) -> ServiceMetadata {
+        let mut operations = self.service.operations();
+        operations.push(PING_OPERATION.to_string());
+        ServiceMetadata {
+            name: self.service.name().to_string(),
+            path: self.path.clone(),
+            description: self.service.description().to_string(),
+            operations,
+            version: "1.0.0".to_string(),
+            state: self.state,
+        }
+    }
+    
+    async fn init(&mut self, _context: &RequestContext) -> Result<()> {
+        info!("Initializing service: {}", self.path);
+        self.state = ServiceState::Initialized;
+        Ok(())
+    }
+    
+    async fn start(&mut self) -> Result<()> {
+        info!("Starting service: {}", self.path);
+        self.state = ServiceState::Running;
+        Ok(())
+    }
+    
+    async fn stop(&mut self) -> Result<()> {
+        info!("Stopping service: {}", self.path);
+        self.state = ServiceState::Stopped;
+        Ok(())
+    }
+    
+    async fn handle_request(&self, request: ServiceRequest) -> Result<ServiceResponse> {
+        debug!("Processing request: {:?}", request.path);
+        
+        let operation = match request.path.rsplit('/').next() {
+            Some(operation) if !operation.is_empty() => operation,
+            _ => return Ok(ServiceResponse::error("Invalid request path")),
+        };
+        let params = match &request.params {
+            Some(params) => serde_json::to_value(params)?,
+            None => Value::Null,
+        };
+        
+        let params = match ActionParams::new(params) {
+            Ok(params) => params,
+            Err(e) => return Ok(ServiceResponse::error(e.message())),
+        };
+        match self.service.handle(operation, params).await {
+            Ok(data) => Ok(ServiceResponse::success(operation.to_string(), Some(data))),
+            Err(e) => Ok(ServiceResponse::error(&e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kagi_shared::{unknown_operation, ServiceError};
+    use serde_json::json;
+    
+    struct Greeter;
+    
+    #[async_trait]
+    impl ActionService for Greeter {
+        fn name(&self) -> &str {
+            "greeter"
+        }
+        
+        fn description(&self) -> &str {
+            "Greets people"
+        }
+        
+        fn operations(&self) -> Vec<String> {
+            vec!["greet".to_string()]
+        }
+        
+        async fn call(&self, operation: &str, params: ActionParams) -> Result<Value> {
+            match operation {
+                "greet" => Ok(Value::from(format!("Hello, {}", params.get_string("name")?))),
+                _ => Err(unknown_operation(self, operation)),
+            }
+        }
+    }
+    
+    #[tokio::test]
+    async fn gateway_dispatches_to_registered_services() {
+        let registry = ServiceRegistry::new();
+        registry.register(Arc::new(Greeter)).unwrap();
+        let gateway: &dyn Gateway = &registry;
+        
+        let greeting = gateway.dispatch("greeter", "greet", json!({ "name": "Ada" })).await.unwrap();
+        assert_eq!(greeting, json!("Hello, Ada"));
+        let missing = gateway.dispatch("billing", "greet", json!({})).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&missing), ServiceError::NotFound(_)));
+    }
+    
+    #[test]
+    fn node_services_cover_the_registry() {
+        let registry = ServiceRegistry::new();
+        registry.register(Arc::new(Greeter)).unwrap();
+        
+        let services = NodeService::all(&registry);
+        assert_eq!(services.len(), 1);
+        let metadata = services[0].metadata();
+        assert_eq!(metadata.path, "greeter");
+        assert!(metadata.operations.contains(&"greet".to_string()));
+        assert!(metadata.operations.contains(&PING_OPERATION.to_string()));
+    }
+}
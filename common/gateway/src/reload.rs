@@ -0,0 +1,177 @@
+use crate::auth::bearer_token;
+use crate::{build_middleware, build_route_middleware, error_response, sampling, GatewayConfig, GatewaySettings, GatewayState};
+use anyhow::{anyhow, Result};
+use hyper::{header, Body, Request, Response, StatusCode};
+use kagi_shared::constant_time_eq;
+use log::{error, info, warn};
+use std::sync::Arc;
+
+/// Admin endpoint re-reading the configuration file
+pub const ADMIN_RELOAD_PATH: &str = "/_admin/reload";
+
+/// Re-read the configuration file and swap it into the running gateway.
+///
+/// CORS, rate limits, middleware and the rest of the request-time settings take
+/// effect for the next request; in-flight requests and open connections keep the
+/// settings they started with. Listener addresses and TLS can't change without
+/// rebinding, so those keep their startup values. If the new file fails to load
+/// or validate, the running configuration is left untouched.
+pub(crate) fn reload(state: &GatewayState) -> Result<GatewayConfig> {
+    let current = state.settings();
+    let path = current.config.config_file.clone()
+        .ok_or_else(|| anyhow!("Gateway was not started from a config file"))?;
+    
+    let mut config = GatewayConfig::from_file(&path)?;
+    if config.host != current.config.host
+        || config.port != current.config.port
+        || config.additional_listeners != current.config.additional_listeners
+    {
+        warn!("Listener changes in {} require a restart and were ignored", path);
+    }
+    config.host = current.config.host.clone();
+    config.port = current.config.port;
+    config.additional_listeners = current.config.additional_listeners.clone();
+    config.ssl = current.config.ssl.clone();
+    
+    let middlewares = build_middleware(&config, state.token_validator.as_ref())?;
+    let route_middlewares = build_route_middleware(&config)?;
+    let sampler = sampling::TraceSampler::new(config.trace_sample_rate);
+    state.replace_settings(GatewaySettings {
+        config: config.clone(),
+        middlewares,
+        route_middlewares,
+        sampler,
+    });
+    
+    info!("Reloaded gateway configuration from {}", path);
+    Ok(config)
+}
+
+/// Handle `POST /_admin/reload`, which requires the configured admin bearer token
+pub(crate) fn reload_response(state: &GatewayState, config: &GatewayConfig, req: &Request<Body>) -> Response<Body> {
+    let admin_token = match &config.admin_token {
+        Some(token) if !token.is_empty() => token,
+        // Hide the endpoint entirely unless an admin token is configured
+        _ => return error_response(config, StatusCode::NOT_FOUND, "Route not found"),
+    };
+    // Compared in constant time so response timing doesn't reveal the token
+    let authorized = bearer_token(req.headers())
+        .map_or(false, |presented| constant_time_eq(presented.as_bytes(), admin_token.as_bytes()));
+    if !authorized {
+        return error_response(config, StatusCode::UNAUTHORIZED, "Invalid admin token");
+    }
+    
+    match reload(state) {
+        Ok(new_config) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::json!({
+                "reloaded": true,
+                "config_file": new_config.config_file,
+            }).to_string()))
+            .unwrap(),
+        Err(e) => {
+            error!("Configuration reload rejected: {}", e);
+            error_response(config, StatusCode::UNPROCESSABLE_ENTITY, &format!("Reload rejected: {}", e))
+        }
+    }
+}
+
+/// Reload the configuration whenever the process receives `SIGHUP`
+#[cfg(unix)]
+pub(crate) fn spawn_sighup_listener(state: Arc<GatewayState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            warn!("Failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+    
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            if let Err(e) = reload(&state) {
+                error!("Configuration reload rejected: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub(crate) fn spawn_sighup_listener(_state: Arc<GatewayState>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metrics;
+    use crate::Gateway;
+    use async_trait::async_trait;
+    use std::path::PathBuf;
+    
+    const ADMIN_TOKEN: &str = "reload-admin-token";
+    
+    struct IdleGateway;
+    
+    #[async_trait]
+    impl Gateway for IdleGateway {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+    
+    fn write_config(path: &PathBuf, max_batch_size: usize) {
+        let mut config = GatewayConfig::default();
+        config.admin_token = Some(ADMIN_TOKEN.to_string());
+        config.max_batch_size = max_batch_size;
+        std::fs::write(path, serde_json::to_string(&config).unwrap()).unwrap();
+    }
+    
+    /// A gateway started from a config file, and that file
+    fn state_from_file() -> (GatewayState, PathBuf) {
+        let path = std::env::temp_dir().join(format!("gateway-reload-{}.json", uuid::Uuid::new_v4()));
+        write_config(&path, 10);
+        let config = GatewayConfig::from_file(path.to_str().unwrap()).unwrap();
+        let state = GatewayState::new(Arc::new(IdleGateway), &config, Arc::new(Metrics::new())).unwrap();
+        (state, path)
+    }
+    
+    fn reload_with(state: &GatewayState, authorization: Option<&str>) -> StatusCode {
+        let mut req = Request::post(ADMIN_RELOAD_PATH).body(Body::empty()).unwrap();
+        if let Some(authorization) = authorization {
+            req.headers_mut().insert(header::AUTHORIZATION, authorization.parse().unwrap());
+        }
+        reload_response(state, &state.settings().config, &req).status()
+    }
+    
+    #[tokio::test]
+    async fn admin_token_reloads_the_config_file() {
+        let (state, path) = state_from_file();
+        write_config(&path, 25);
+        
+        let status = reload_with(&state, Some(&format!("Bearer {}", ADMIN_TOKEN)));
+        
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.settings().config.max_batch_size, 25);
+        std::fs::remove_file(path).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn wrong_or_missing_admin_token_is_refused() {
+        let (state, path) = state_from_file();
+        write_config(&path, 25);
+        
+        for authorization in [
+            None,
+            Some("Bearer not-the-token".to_string()),
+            Some(format!("Bearer {}x", ADMIN_TOKEN)),
+            Some(format!("Bearer {}", &ADMIN_TOKEN[..ADMIN_TOKEN.len() - 1])),
+            Some(ADMIN_TOKEN.to_string()),
+        ] {
+            assert_eq!(reload_with(&state, authorization.as_deref()), StatusCode::UNAUTHORIZED, "{:?}", authorization);
+        }
+        assert_eq!(state.settings().config.max_batch_size, 10);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,86 @@
+use crate::circuit_breaker::is_backend_failure;
+use anyhow::Result;
+use hyper::Method;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How forwarded idempotent requests are retried after transient backend failures
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Attempts in total, including the first
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Backoff ceiling before the second attempt, in milliseconds; doubles per attempt
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    /// Largest backoff ceiling, in milliseconds
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    100
+}
+
+fn default_max_backoff_ms() -> u64 {
+    2_000
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+        }
+    }
+}
+
+/// Whether requests with `method` can safely be sent more than once.
+///
+/// POST and PATCH are never retried, since a failure may come after the
+/// backend already applied them.
+pub fn is_idempotent(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS)
+}
+
+impl RetryConfig {
+    /// Delay before retry number `retry` (0 for the first retry): a random
+    /// duration up to an exponentially growing ceiling ("full jitter")
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self.initial_backoff_ms
+            .saturating_mul(1u64 << retry.min(32))
+            .min(self.max_backoff_ms);
+        let bytes = Uuid::new_v4().into_bytes();
+        let random = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        Duration::from_millis(random % (ceiling + 1))
+    }
+    
+    /// Run `call` until it succeeds, fails with an error the backend won't
+    /// recover from, or `max_attempts` is used up; the last error is returned
+    pub async fn run<T, F, Fut>(&self, label: &str, mut call: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut retry = 0;
+        loop {
+            match call().await {
+                Err(e) if retry + 1 < self.max_attempts && is_backend_failure(&e) => {
+                    let delay = self.backoff(retry);
+                    debug!("Retrying {} in {:?} after attempt {} failed: {}", label, delay, retry + 1, e);
+                    tokio::time::sleep(delay).await;
+                    retry += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+/// Parameters extracted from the matched route's path, stored in request extensions
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathParams(pub HashMap<String, String>);
+
+impl PathParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+}
+
+/// Method and pattern of the route a request matched, stored in request extensions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedRoute {
+    pub method: String,
+    pub pattern: String,
+}
+
+/// Non-empty segments of a path, so `/users/5` and `/users/5/` compare equal
+pub fn split_path(path: &str) -> Vec<&str> {
+    path.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+/// One segment of a route pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+}
+
+/// A route path such as `/users/:id`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutePattern {
+    pattern: String,
+    segments: Vec<Segment>,
+}
+
+impl RoutePattern {
+    pub fn parse(pattern: &str) -> Self {
+        let segments = split_path(pattern)
+            .into_iter()
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(name) => Segment::Param(name.to_string()),
+                None => Segment::Literal(segment.to_string()),
+            })
+            .collect();
+        
+        Self {
+            pattern: pattern.to_string(),
+            segments,
+        }
+    }
+    
+    /// The pattern as registered
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+    
+    /// Match already-split path segments, returning the extracted parameters
+    pub fn matches(&self, path_segments: &[&str]) -> Option<HashMap<String, String>> {
+        if self.segments.len() != path_segments.len() {
+            return None;
+        }
+        
+        let mut params = HashMap::new();
+        for (segment, value) in self.segments.iter().zip(path_segments) {
+            match segment {
+                Segment::Literal(literal) if literal == value => {},
+                Segment::Literal(_) => return None,
+                Segment::Param(name) => {
+                    params.insert(name.clone(), value.to_string());
+                },
+            }
+        }
+        Some(params)
+    }
+    
+    /// Literal flags per segment; comparing these picks the most specific route,
+    /// so `/users/me` beats `/users/:id` and earlier literals outrank later ones
+    fn specificity(&self) -> Vec<bool> {
+        self.segments.iter().map(|s| matches!(s, Segment::Literal(_))).collect()
+    }
+}
+
+/// Result of a successful route lookup
+#[derive(Debug)]
+pub struct RouteMatch<'a, T> {
+    pub method: &'a str,
+    pub pattern: &'a str,
+    pub value: &'a T,
+    pub params: HashMap<String, String>,
+}
+
+/// Routes keyed by method and path pattern.
+///
+/// When several routes match, literal segments win over parameters and an
+/// exact method wins over the `*` wildcard.
+#[derive(Debug)]
+pub struct RouteMatcher<T> {
+    routes: Vec<(String, RoutePattern, T)>,
+}
+
+impl<T> Default for RouteMatcher<T> {
+    fn default() -> Self {
+        Self { routes: Vec::new() }
+    }
+}
+
+impl<T> RouteMatcher<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Add a route; `method` may be `*` to match any method
+    pub fn insert(&mut self, method: &str, pattern: &str, value: T) {
+        self.routes.push((method.to_uppercase(), RoutePattern::parse(pattern), value));
+    }
+    
+    /// Find the most specific route for a request
+    pub fn find(&self, method: &str, path: &str) -> Option<RouteMatch<'_, T>> {
+        let path_segments = split_path(path);
+        
+        self.routes
+            .iter()
+            .filter(|(route_method, _, _)| route_method == "*" || route_method.eq_ignore_ascii_case(method))
+            .filter_map(|(route_method, pattern, value)| {
+                pattern.matches(&path_segments).map(|params| (route_method, pattern, value, params))
+            })
+            .max_by_key(|(route_method, pattern, _, _)| (pattern.specificity(), route_method.as_str() != "*"))
+            .map(|(route_method, pattern, value, params)| RouteMatch {
+                method: route_method,
+                pattern: pattern.as_str(),
+                value,
+                params,
+            })
+    }
+    
+    /// Every registered route's value, in registration order
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.routes.iter().map(|(_, _, value)| value)
+    }
+    
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+    
+    #[test]
+    fn literal_segment_beats_a_parameter_in_either_registration_order() {
+        let mut me_first = RouteMatcher::new();
+        me_first.insert("GET", "/users/me", "me");
+        me_first.insert("GET", "/users/:id", "by_id");
+        let mut id_first = RouteMatcher::new();
+        id_first.insert("GET", "/users/:id", "by_id");
+        id_first.insert("GET", "/users/me", "me");
+        
+        for routes in [&me_first, &id_first] {
+            let me = routes.find("GET", "/users/me").unwrap();
+            assert_eq!((*me.value, me.pattern), ("me", "/users/me"));
+            assert!(me.params.is_empty());
+            
+            let other = routes.find("GET", "/users/5").unwrap();
+            assert_eq!(*other.value, "by_id");
+            assert_eq!(other.params, params(&[("id", "5")]));
+        }
+    }
+    
+    #[test]
+    fn trailing_slash_matches_the_same_route() {
+        let mut routes = RouteMatcher::new();
+        routes.insert("GET", "/users/:id", "by_id");
+        
+        for path in ["/users/5", "/users/5/", "users/5"] {
+            let matched = routes.find("GET", path).unwrap();
+            assert_eq!(matched.params, params(&[("id", "5")]), "{}", path);
+        }
+        assert!(routes.find("GET", "/users").is_none());
+        assert!(routes.find("GET", "/users/5/posts").is_none());
+    }
+    
+    #[test]
+    fn exact_method_beats_the_wildcard() {
+        let mut routes = RouteMatcher::new();
+        routes.insert("*", "/users/:id", "any");
+        routes.insert("delete", "/users/:id", "delete");
+        
+        let deleted = routes.find("DELETE", "/users/5").unwrap();
+        assert_eq!((*deleted.value, deleted.method), ("delete", "DELETE"));
+        assert_eq!(*routes.find("GET", "/users/5").unwrap().value, "any");
+        assert!(routes.find("get", "/accounts/5").is_none());
+    }
+    
+    #[test]
+    fn every_parameter_is_extracted_by_name() {
+        let mut routes = RouteMatcher::new();
+        routes.insert("GET", "/orgs/:org/members/:id", "member");
+        
+        let matched = routes.find("GET", "/orgs/acme/members/7").unwrap();
+        assert_eq!(matched.params, params(&[("org", "acme"), ("id", "7")]));
+        assert!(routes.find("GET", "/orgs/acme/owners/7").is_none());
+    }
+}
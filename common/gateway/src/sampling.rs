@@ -0,0 +1,169 @@
+use hyper::{Body, Request};
+use uuid::Uuid;
+
+/// W3C trace context header, read from callers and propagated downstream
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Header forcing a request to be traced regardless of the sample rate
+pub const DEBUG_TRACE_HEADER: &str = "x-debug-trace";
+
+/// Param under which the trace context is handed to backend services
+pub const TRACEPARENT_PARAM: &str = "traceparent";
+
+tokio::task_local! {
+    /// Trace context of the request being handled on this task
+    static CURRENT_TRACE: TraceContext;
+}
+
+/// Trace identity and sampling decision for one request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// 32 hex digit trace id, shared with the caller when it sent one
+    pub trace_id: String,
+    /// 16 hex digit id of the gateway's span
+    pub span_id: String,
+    /// Whether spans and detailed logs are emitted for this request
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Render as a `traceparent` header value for downstream calls
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{}", self.trace_id, self.span_id, if self.sampled { "01" } else { "00" })
+    }
+    
+    /// Run `fut` with this context as the current trace
+    pub async fn scope<F: std::future::Future>(self, fut: F) -> F::Output {
+        CURRENT_TRACE.scope(self, fut).await
+    }
+    
+    /// Trace context of the request being handled, if any
+    pub fn current() -> Option<TraceContext> {
+        CURRENT_TRACE.try_with(|trace| trace.clone()).ok()
+    }
+}
+
+/// Parse a `traceparent` value into its trace id and sampled flag
+fn parse_traceparent(value: &str) -> Option<(String, bool)> {
+    let parts: Vec<&str> = value.trim().split('-').collect();
+    if parts.len() < 4 || parts[1].len() != 32 || parts[2].len() != 16 {
+        return None;
+    }
+    if !parts[1].chars().all(|c| c.is_ascii_hexdigit()) || parts[1].chars().all(|c| c == '0') {
+        return None;
+    }
+    let flags = u8::from_str_radix(parts[3], 16).ok()?;
+    Some((parts[1].to_lowercase(), flags & 0x01 == 0x01))
+}
+
+/// Decides which requests are traced.
+///
+/// A request is sampled when its caller sent a sampled `traceparent`, when it
+/// carries `X-Debug-Trace`, or otherwise with probability `rate`. The decision is
+/// made once per request and travels with it in its `TraceContext`.
+#[derive(Debug, Clone)]
+pub struct TraceSampler {
+    rate: f64,
+}
+
+impl TraceSampler {
+    /// Sample `rate` of requests, clamped to `0.0..=1.0`
+    pub fn new(rate: f64) -> Self {
+        Self { rate: if rate.is_nan() { 0.0 } else { rate.clamp(0.0, 1.0) } }
+    }
+    
+    /// Build the trace context for an incoming request
+    pub fn context_for(&self, req: &Request<Body>) -> TraceContext {
+        let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+        
+        let parent = header(TRACEPARENT_HEADER).and_then(parse_traceparent);
+        let forced = header(DEBUG_TRACE_HEADER).map_or(false, |v| v != "0" && !v.eq_ignore_ascii_case("false"));
+        let (trace_id, parent_sampled) = match parent {
+            Some((trace_id, sampled)) => (trace_id, sampled),
+            None => (Uuid::new_v4().simple().to_string(), false),
+        };
+        
+        TraceContext {
+            trace_id,
+            span_id: Uuid::new_v4().simple().to_string()[..16].to_string(),
+            sampled: parent_sampled || forced || self.roll(),
+        }
+    }
+    
+    fn roll(&self) -> bool {
+        if self.rate >= 1.0 {
+            return true;
+        }
+        if self.rate <= 0.0 {
+            return false;
+        }
+        
+        let bytes = Uuid::new_v4().into_bytes();
+        let value = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        (value as f64 / u64::MAX as f64) < self.rate
+    }
+}
+
+/// Add the current request's `traceparent` to the params of a downstream call
+pub fn propagate(mut params: serde_json::Value) -> serde_json::Value {
+    if let (Some(trace), Some(object)) = (TraceContext::current(), params.as_object_mut()) {
+        object.insert(TRACEPARENT_PARAM.to_string(), serde_json::json!(trace.traceparent()));
+    }
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const PARENT_TRACE: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    
+    fn request(headers: &[(&str, &str)]) -> Request<Body> {
+        let mut builder = Request::get("/invoices");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+    
+    #[test]
+    fn zero_rate_samples_only_forced_requests() {
+        let sampler = TraceSampler::new(0.0);
+        for _ in 0..500 {
+            assert!(!sampler.context_for(&request(&[])).sampled);
+        }
+        
+        assert!(sampler.context_for(&request(&[(DEBUG_TRACE_HEADER, "1")])).sampled);
+        assert!(!sampler.context_for(&request(&[(DEBUG_TRACE_HEADER, "false")])).sampled);
+        
+        let parent = format!("00-{}-00f067aa0ba902b7-01", PARENT_TRACE);
+        let trace = sampler.context_for(&request(&[(TRACEPARENT_HEADER, &parent)]));
+        assert!(trace.sampled);
+        assert_eq!(trace.trace_id, PARENT_TRACE);
+        
+        let unsampled_parent = format!("00-{}-00f067aa0ba902b7-00", PARENT_TRACE);
+        assert!(!sampler.context_for(&request(&[(TRACEPARENT_HEADER, &unsampled_parent)])).sampled);
+    }
+    
+    #[test]
+    fn full_rate_samples_every_request() {
+        let sampler = TraceSampler::new(1.0);
+        for _ in 0..500 {
+            let trace = sampler.context_for(&request(&[]));
+            assert!(trace.sampled);
+            assert!(trace.traceparent().ends_with("-01"));
+        }
+    }
+    
+    #[tokio::test]
+    async fn decision_travels_with_downstream_calls() {
+        let trace = TraceSampler::new(0.0).context_for(&request(&[]));
+        let expected = trace.traceparent();
+        
+        let params = trace.scope(async { propagate(serde_json::json!({ "id": 1 })) }).await;
+        
+        assert_eq!(params[TRACEPARENT_PARAM], expected);
+        assert!(expected.ends_with("-00"));
+        assert_eq!(propagate(serde_json::json!({}))[TRACEPARENT_PARAM], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{AuthConfig, Middleware, Next};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use hyper::{header, Body, Request, Response, StatusCode};
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use kagi_shared::has_scope;
+use serde::Deserialize;
+
+/// Prefix of route middleware names requiring a scope, e.g. `scope:invoices:write`
+pub const SCOPE_MIDDLEWARE_PREFIX: &str = "scope:";
+
+/// The claims the gateway needs from an access token
+#[derive(Debug, Deserialize)]
+struct ScopedClaims {
+    #[serde(default)]
+    scopes: Vec<String>,
+}
+
+/// Require the bearer token to grant a scope.
+///
+/// Requests without a valid token get `401`; valid tokens lacking the scope get `403`.
+pub struct ScopeMiddleware {
+    required: String,
+    decoding_key: DecodingKey,
+}
+
+impl ScopeMiddleware {
+    /// Verify tokens with `auth.jwt_secret`, which must be the secret `AuthService`
+    /// signs with; an empty secret is refused rather than accepting tokens anyone can sign
+    pub fn new(required: impl Into<String>, auth: &AuthConfig) -> Result<Self> {
+        if auth.jwt_secret.is_empty() {
+            return Err(anyhow!("Scope checks need auth.jwt_secret, e.g. from AuthConfig::from_env()"));
+        }
+        Ok(Self {
+            required: required.into(),
+            decoding_key: DecodingKey::from_secret(auth.jwt_secret.as_bytes()),
+        })
+    }
+    
+    /// Build from a route middleware name such as `scope:invoices:write`; `None`
+    /// if the name isn't a scope requirement
+    pub fn from_name(name: &str, auth: &AuthConfig) -> Result<Option<Self>> {
+        name.strip_prefix(SCOPE_MIDDLEWARE_PREFIX)
+            .filter(|scope| !scope.is_empty())
+            .map(|scope| Self::new(scope, auth))
+            .transpose()
+    }
+}
+
+pub(crate) fn json_error(status: StatusCode, message: &str) -> Result<Response<Body>> {
+    let body = serde_json::json!({ "error": message });
+    Ok(Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))?)
+}
+
+#[async_trait]
+impl Middleware for ScopeMiddleware {
+    async fn process(&self, req: &Request<Body>, next: Next<'_>) -> Result<Response<Body>> {
+        let token = req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        let token = match token {
+            Some(token) => token,
+            None => return json_error(StatusCode::UNAUTHORIZED, "Missing bearer token"),
+        };
+        
+        let claims = match decode::<ScopedClaims>(token, &self.decoding_key, &Validation::default()) {
+            Ok(data) => data.claims,
+            Err(e) => return json_error(StatusCode::UNAUTHORIZED, &format!("Invalid token: {}", e)),
+        };
+        if !has_scope(&claims.scopes, &self.required) {
+            return json_error(StatusCode::FORBIDDEN, &format!("Token lacks required scope '{}'", self.required));
+        }
+        
+        next.run(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HandlerFn;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    
+    const SECRET: &str = "shared-secret";
+    
+    fn auth_config() -> AuthConfig {
+        AuthConfig {
+            jwt_secret: SECRET.to_string(),
+            ..AuthConfig::default()
+        }
+    }
+    
+    /// An HS256 token like the ones `AuthService` issues, signed with `secret`
+    fn token(secret: &str, scopes: &[&str]) -> String {
+        let exp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() + 600;
+        let claims = serde_json::json!({ "sub": "user-1", "exp": exp, "scopes": scopes });
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes())).unwrap()
+    }
+    
+    async fn status(middleware: &ScopeMiddleware, token: Option<String>) -> StatusCode {
+        let mut req = Request::post("/invoices").body(Body::empty()).unwrap();
+        if let Some(token) = token {
+            req.headers_mut().insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+        }
+        let handler: Box<HandlerFn> = Box::new(|_: &Request<Body>| Box::pin(async { Ok(Response::new(Body::empty())) }));
+        middleware.process(&req, Next::new(&[], handler.as_ref())).await.unwrap().status()
+    }
+    
+    #[tokio::test]
+    async fn token_with_the_required_scope_passes() {
+        let middleware = ScopeMiddleware::from_name("scope:invoices:write", &auth_config()).unwrap().unwrap();
+        
+        assert_eq!(status(&middleware, Some(token(SECRET, &["invoices:read", "invoices:write"]))).await, StatusCode::OK);
+    }
+    
+    #[tokio::test]
+    async fn token_lacking_the_scope_is_forbidden() {
+        let middleware = ScopeMiddleware::new("invoices:write", &auth_config()).unwrap();
+        
+        assert_eq!(status(&middleware, Some(token(SECRET, &["invoices:read"]))).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(&middleware, None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status(&middleware, Some(token("another-secret", &["invoices:write"]))).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+    
+    #[tokio::test]
+    async fn wildcard_scope_covers_the_required_one() {
+        let middleware = ScopeMiddleware::new("invoices:write:bulk", &auth_config()).unwrap();
+        
+        assert_eq!(status(&middleware, Some(token(SECRET, &["invoices:*"]))).await, StatusCode::OK);
+        assert_eq!(status(&middleware, Some(token(SECRET, &["*"]))).await, StatusCode::OK);
+        assert_eq!(status(&middleware, Some(token(SECRET, &["profiles:*"]))).await, StatusCode::FORBIDDEN);
+    }
+    
+    #[test]
+    fn scope_checks_need_a_secret() {
+        assert!(ScopeMiddleware::from_name("scope:invoices:write", &AuthConfig::default()).is_err());
+        assert!(ScopeMiddleware::from_name("audit", &AuthConfig::default()).unwrap().is_none());
+    }
+}
@@ -1,16 +1,30 @@
-use crate::{Gateway, GatewayConfig, RouteInfo, ROUTES};
+use crate::connections::{self, ConnectionLimiter};
+use crate::health;
+use crate::metrics::Metrics;
+use crate::openapi;
+use crate::operations::OperationRegistry;
+use crate::query::parse_query;
+use crate::routing::{split_path, RouteMatcher, RoutePattern};
+use crate::shutdown::{serve_until_shutdown, ShutdownTrigger};
+use crate::static_files;
+use crate::{
+    effective_method, error_response, layered_gateway, read_recover, route_http_request, write_recover, Gateway,
+    GatewayConfig, GatewayState, RouteInfo, ROUTES,
+};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use hyper::{server::conn::AddrStream, service::make_service_fn, service::service_fn, Request, Response, Server, Body, StatusCode, Method, header};
+use hyper::{server::conn::AddrStream, service::make_service_fn, service::service_fn, Request, Response, Server, Body, StatusCode, Method};
 use kagi_node::services::{AbstractService, ServiceState, ServiceMetadata, RequestContext, ServiceRequest, ServiceResponse, ValueType};
+use serde::de::DeserializeOwned;
 use log::{debug, error, info};
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::future::Future;
 use std::net::SocketAddr;
 use std::pin::Pin;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use std::fmt::Debug;
 use serde_json::Value;
 
@@ -50,11 +64,17 @@ pub struct GatewayService {
     /// Service running status
     pub running: bool,
     /// Route registry
-    pub routes: Arc<Mutex<Vec<RouteEntry>>>,
-    /// Service operations
-    pub operations: Vec<String>,
+    pub routes: Arc<RwLock<RouteMatcher<RouteEntry>>>,
+    /// Set once `initialize_routes` has filled the route registry
+    pub routes_initialized: Arc<AtomicBool>,
+    /// Introspection operations served by `handle_request`
+    pub operations: OperationRegistry,
     /// Service version
     pub version: String,
+    /// Stops the servers started by `run`
+    pub shutdown: ShutdownTrigger,
+    /// Backend matched routes are forwarded to
+    pub dispatcher: Option<Arc<dyn Gateway + Send + Sync>>,
 }
 
 impl GatewayService {
@@ -67,112 +87,103 @@ impl GatewayService {
             state: ServiceState::Stopped,
             context: None,
             running: false,
-            routes: Arc::new(Mutex::new(Vec::new())),
-            operations: vec!["process".to_string()],
+            routes: Arc::new(RwLock::new(RouteMatcher::new())),
+            routes_initialized: Arc::new(AtomicBool::new(false)),
+            operations: OperationRegistry::with_builtins(),
             version: "1.0.0".to_string(),
+            shutdown: ShutdownTrigger::new(),
+            dispatcher: None,
         }
     }
-
+    
+    /// Forward matched routes to `dispatcher`; without one they fail with `503`
+    pub fn with_dispatcher(mut self, dispatcher: Arc<dyn Gateway + Send + Sync>) -> Self {
+        self.dispatcher = Some(dispatcher);
+        self
+    }
+    
     /// Initialize routes from the registry
     pub async fn initialize_routes(&self) -> Result<()> {
-        let mut routes = Vec::new();
+        let mut routes = RouteMatcher::new();
         
         // Get the route information from the static registry
-        unsafe {
-            for route_info in ROUTES.iter() {
-                let segments: Vec<String> = route_info.path
-                    .split('/')
-                    .filter(|s| !s.is_empty())
-                    .map(|s| s.to_string())
-                    .collect();
-                
-                let is_param: Vec<bool> = segments
-                    .iter()
-                    .map(|s| s.starts_with(':'))
-                    .collect();
-                
-                // Parse the handler name to extract service and action
-                let handler_parts: Vec<&str> = route_info.handler_name.split('.').collect();
-                if handler_parts.len() != 2 {
-                    error!("Invalid handler name format: {}", route_info.handler_name);
-                    continue;
-                }
-                
-                let entry = RouteEntry {
-                    method: route_info.method.to_string(),
-                    path_pattern: route_info.path.to_string(),
-                    service_name: handler_parts[0].to_string(),
-                    action_name: handler_parts[1].to_string(),
-                    path_segments: segments,
-                    is_parameter: is_param,
-                    middleware: route_info.middleware.clone(),
-                };
-                
-                let service_name = entry.service_name.clone();
-                let action_name = entry.action_name.clone();
-                
-                routes.push(entry);
-                info!("Registered route: {} {} -> {}.{}", 
-                      route_info.method, route_info.path, 
-                      service_name, action_name);
+        for route_info in read_recover(&ROUTES).iter() {
+            let segments: Vec<String> = route_info.path
+                .split('/')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect();
+            
+            let is_param: Vec<bool> = segments
+                .iter()
+                .map(|s| s.starts_with(':'))
+                .collect();
+            
+            // Parse the handler name to extract service and action
+            let handler_parts: Vec<&str> = route_info.handler_name.split('.').collect();
+            if handler_parts.len() != 2 {
+                error!("Invalid handler name format: {}", route_info.handler_name);
+                continue;
             }
+            
+            let entry = RouteEntry {
+                method: route_info.method.to_string(),
+                path_pattern: route_info.path.to_string(),
+                service_name: handler_parts[0].to_string(),
+                action_name: handler_parts[1].to_string(),
+                path_segments: segments,
+                is_parameter: is_param,
+                middleware: route_info.middleware.clone(),
+            };
+            
+            let service_name = entry.service_name.clone();
+            let action_name = entry.action_name.clone();
+            
+            routes.insert(route_info.method, route_info.path, entry);
+            info!("Registered route: {} {} -> {}.{}", 
+                  route_info.method, route_info.path, 
+                  service_name, action_name);
         }
         
         // Update the routes registry
-        let mut routes_lock = self.routes.lock().await;
-        *routes_lock = routes;
+        *write_recover(&self.routes) = routes;
+        self.routes_initialized.store(true, Ordering::SeqCst);
         
         Ok(())
     }
     
+    /// Add an operation served by this gateway service
+    pub fn register_operation<P, F>(&mut self, name: &str, description: &str, handler: F)
+    where
+        P: DeserializeOwned,
+        F: Fn(&GatewayService, P) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.operations.register(name, description, handler);
+    }
+    
     /// Extract parameters from a path based on the route entry
     pub fn extract_parameters(&self, route: &RouteEntry, path: &str) -> HashMap<String, String> {
-        let mut params = HashMap::new();
-        let path_segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-        
-        for (i, segment) in path_segments.iter().enumerate() {
-            if i < route.is_parameter.len() && route.is_parameter[i] {
-                // This is a parameter segment, extract the parameter name from the pattern
-                let param_name = route.path_segments[i].trim_start_matches(':');
-                params.insert(param_name.to_string(), segment.to_string());
-            }
+        RoutePattern::parse(&route.path_pattern)
+            .matches(&split_path(path))
+            .unwrap_or_default()
+    }
+    
+    /// Arguments for a call to `route`: the query string's parameters, with the
+    /// path's parameters taking precedence on collisions
+    pub fn extract_arguments(&self, route: &RouteEntry, path: &str, query: Option<&str>) -> serde_json::Map<String, Value> {
+        let mut arguments = query.map(parse_query).unwrap_or_default();
+        for (name, value) in self.extract_parameters(route, path) {
+            arguments.insert(name, Value::String(value));
         }
-        
-        params
+        arguments
     }
     
-    /// Find a matching route for a request
+    /// Find the most specific matching route for a request
     pub async fn find_route(&self, method: &str, path: &str) -> Option<(RouteEntry, HashMap<String, String>)> {
-        let routes = self.routes.lock().await;
-        let path_segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-        
-        for route in routes.iter() {
-            // First check method
-            if route.method != method && route.method != "*" {
-                continue;
-            }
-            
-            // Then check path segment count
-            if route.path_segments.len() != path_segments.len() {
-                continue;
-            }
-            
-            // Check each segment
-            let mut matches = true;
-            for (i, segment) in path_segments.iter().enumerate() {
-                if !route.is_parameter[i] && route.path_segments[i] != *segment {
-                    matches = false;
-                    break;
-                }
-            }
-            
-            if matches {
-                let params = self.extract_parameters(route, path);
-                return Some((route.clone(), params));
-            }
-        }
-        
-        None
+        let routes = read_recover(&self.routes);
+        routes
+            .find(method, path)
+            .map(|matched| (matched.value.clone(), matched.params))
     }
     
     /// Handle an incoming service request
@@ -184,30 +195,13 @@ impl GatewayService {
         }
         
         let operation = parts[parts.len() - 1];
+        let params = match &req.params {
+            Some(params) => serde_json::to_value(params)?,
+            None => Value::Null,
+        };
         
-        match operation {
-            "ping" => {
-                Ok(ServiceResponse::success("pong".to_string(), Some("pong")))
-            },
-            "getRoutes" => {
-                let routes = self.routes.lock().await;
-                let route_data: Vec<Value> = routes.iter().map(|r| {
-                    serde_json::json!({
-                        "method": r.method,
-                        "path": r.path_pattern,
-                        "service": r.service_name,
-                        "action": r.action_name
-                    })
-                }).collect();
-                
-                // Convert Vec<Value> to a ValueType
-                let routes_json = serde_json::json!(route_data);
-                Ok(ServiceResponse::success("Routes".to_string(), Some(routes_json)))
-            },
-            _ => {
-                Err(anyhow!("Unsupported operation: {}", operation))
-            }
-        }
+        let data = self.operations.call(self, operation, params)?;
+        Ok(ServiceResponse::success(operation.to_string(), Some(data)))
     }
 }
 
@@ -219,37 +213,72 @@ impl Gateway for GatewayService {
         // Initialize routes
         self.initialize_routes().await?;
         
-        // Create the address to bind to
-        let addr = format!("{}:{}", self.config.host, self.config.port);
-        let socket_addr = addr.parse::<SocketAddr>()?;
-        
-        // Create the service factory
-        let routes = self.routes.clone();
-        let _config = self.config.clone();
+        // Every configured address serves the same routes
+        let addresses = self.config.listen_addresses()?;
+        let mut servers: Vec<futures::future::BoxFuture<'static, Result<()>>> = Vec::with_capacity(addresses.len());
         
-        let make_svc = make_service_fn(move |conn: &AddrStream| {
-            let remote_addr = conn.remote_addr();
-            let routes = routes.clone();
-            
-            async move {
-                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
-                    let routes = routes.clone();
-                    handle_request(req, routes, remote_addr)
-                }))
-            }
+        // One limiter across all listeners, so the cap holds however connections are spread
+        let limiter = self.config.connection_limit.as_ref()
+            .map(|limit| Arc::new(ConnectionLimiter::new(limit)));
+        // Matched routes go through the same middleware chain as `start_gateway`
+        let gateway = match &self.dispatcher {
+            Some(dispatcher) => {
+                let dispatcher = layered_gateway(dispatcher.clone(), &self.config);
+                Some(Arc::new(GatewayState::new(dispatcher, &self.config, Arc::new(Metrics::new()))?))
+            },
+            None => None,
+        };
+        let http = Arc::new(HttpState {
+            routes: self.routes.clone(),
+            routes_initialized: self.routes_initialized.clone(),
+            config: self.config.clone(),
+            version: self.version.clone(),
+            dispatcher: self.dispatcher.clone(),
+            gateway,
         });
-        
-        // Create and start the server
-        let server = Server::bind(&socket_addr).serve(make_svc);
-        info!("Gateway service listening on {}", socket_addr);
-        
-        // Run the server
-        if let Err(e) = server.await {
-            error!("Gateway server error: {}", e);
-            return Err(anyhow!("Server error: {}", e));
+        for socket_addr in addresses {
+            // Create the service factory
+            let http = http.clone();
+            let limiter = limiter.clone();
+            let make_svc = make_service_fn(move |conn: &AddrStream| {
+                let remote_addr = conn.remote_addr();
+                let http = http.clone();
+                let limiter = limiter.clone();
+                
+                async move {
+                    // The service owns the admission, so the connection's slot is held until it closes
+                    let admission = connections::admit(limiter.as_ref()).await;
+                    Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                        let http = http.clone();
+                        let rejected = admission.is_rejected();
+                        async move {
+                            if rejected {
+                                return Ok(connections::rejected_response(&http.config));
+                            }
+                            handle_request(req, http, remote_addr).await
+                        }
+                    }))
+                }
+            });
+            
+            // Create the server
+            let server = Server::try_bind(&socket_addr)
+                .map_err(|e| anyhow!("Failed to bind {}: {}", socket_addr, e))?
+                .serve(make_svc)
+                .with_graceful_shutdown(self.shutdown.signal().recv());
+            info!("Gateway service listening on {}", socket_addr);
+            
+            servers.push(Box::pin(async move {
+                server.await.map_err(|e| {
+                    error!("Gateway server error on {}: {}", socket_addr, e);
+                    anyhow!("Server error: {}", e)
+                })
+            }));
         }
         
-        Ok(())
+        // Run the servers until any of them fails or `stop` drains them
+        let grace = Duration::from_millis(self.config.shutdown_grace_period_ms);
+        serve_until_shutdown(servers, self.shutdown.signal(), grace).await
     }
 }
 
@@ -276,7 +305,7 @@ impl AbstractService for GatewayService {
             name: self.name.clone(),
             path: self.path.clone(),
             description: self.description.clone(),
-            operations: self.operations.clone(),
+            operations: self.operations.names(),
             version: self.version.clone(),
             state: self.state,
         }
@@ -300,6 +329,11 @@ impl AbstractService for GatewayService {
     async fn start(&mut self) -> Result<()> {
         info!("Starting gateway service: {}", self.name);
         
+        // A trigger fired by an earlier `stop` would end the next `run` immediately
+        if self.shutdown.is_triggered() {
+            self.shutdown = ShutdownTrigger::new();
+        }
+        
         // Update state
         self.state = ServiceState::Running;
         self.running = true;
@@ -309,6 +343,7 @@ impl AbstractService for GatewayService {
     
     async fn stop(&mut self) -> Result<()> {
         info!("Stopping gateway service: {}", self.name);
+        self.shutdown.trigger();
         
         // Update state
         self.state = ServiceState::Stopped;
@@ -325,20 +360,261 @@ impl AbstractService for GatewayService {
     }
 }
 
+/// What the HTTP servers started by `GatewayService::run` share
+struct HttpState {
+    routes: Arc<RwLock<RouteMatcher<RouteEntry>>>,
+    routes_initialized: Arc<AtomicBool>,
+    config: GatewayConfig,
+    version: String,
+    dispatcher: Option<Arc<dyn Gateway + Send + Sync>>,
+    /// Runs matched routes through the middleware chain before forwarding them
+    /// to `dispatcher`; `None` without a dispatcher
+    gateway: Option<Arc<GatewayState>>,
+}
+
 // Handler for HTTP requests
 async fn handle_request(
     req: Request<Body>,
-    _routes: Arc<Mutex<Vec<RouteEntry>>>,
+    http: Arc<HttpState>,
     _addr: SocketAddr
 ) -> Result<Response<Body>, Infallible> {
-    let method = req.method().to_string();
+    let method = effective_method(&req).to_string();
     let path = req.uri().path().to_string();
+    let config = &http.config;
     
     debug!("Handling HTTP request: {} {}", method, path);
     
-    // Simple 404 response for now
-    Ok(Response::builder()
-        .status(StatusCode::NOT_FOUND)
-        .body(Body::from("Not found"))
-        .unwrap())
-} 
\ No newline at end of file
+    // Health endpoints and the API description bypass the route table
+    if req.method() == Method::GET && path == health::HEALTH_PATH {
+        return Ok(health::liveness_response(&http.version));
+    }
+    if req.method() == Method::GET && path == openapi::OPENAPI_PATH {
+        return Ok(openapi::openapi_response(&read_recover(&ROUTES)));
+    }
+    if req.method() == Method::GET && health::is_readiness_path(&path) {
+        let timeout = Duration::from_millis(config.readiness_timeout_ms);
+        let initialized = http.routes_initialized.load(Ordering::SeqCst);
+        let dispatcher = http.dispatcher.as_ref().map(|dispatcher| dispatcher.as_ref() as &dyn Gateway);
+        return Ok(health::readiness_response(dispatcher, &config.services, timeout, &http.version, initialized).await);
+    }
+    
+    if let Some(gateway) = &http.gateway {
+        return route_http_request(req, gateway.clone()).await;
+    }
+    
+    let matched = read_recover(&http.routes).find(&method, &path).is_some();
+    if matched {
+        return Ok(error_response(config, StatusCode::SERVICE_UNAVAILABLE, "No gateway available to dispatch calls"));
+    }
+    let response = static_files::serve(config, &req).await;
+    Ok(response.unwrap_or_else(|| error_response(config, StatusCode::NOT_FOUND, "Not found")))
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header;
+    use serde_json::json;
+    
+    /// Returns the arguments it was called with; only `alice-token` validates
+    struct EchoDispatcher;
+    
+    #[async_trait]
+    impl Gateway for EchoDispatcher {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn dispatch(&self, service: &str, action: &str, params: Value) -> Result<Value> {
+            if (service, action) == ("auth", "validate_token") && params["token"] != "alice-token" {
+                return Err(kagi_shared::ServiceError::unauthorized("Invalid token").into());
+            }
+            Ok(json!({ "called": format!("{}.{}", service, action), "params": params }))
+        }
+    }
+    
+    fn register_invoice_route() {
+        crate::register_route(RouteInfo {
+            method: "PUT",
+            path: "/service-tests/invoices/:id",
+            handler_name: "invoice.update",
+            middleware: None,
+        });
+    }
+    
+    fn http_state(config: GatewayConfig) -> Arc<HttpState> {
+        register_invoice_route();
+        let mut routes = RouteMatcher::new();
+        routes.insert("PUT", "/service-tests/invoices/:id", RouteEntry {
+            method: "PUT".to_string(),
+            path_pattern: "/service-tests/invoices/:id".to_string(),
+            service_name: "invoice".to_string(),
+            action_name: "update".to_string(),
+            path_segments: vec!["service-tests".to_string(), "invoices".to_string(), ":id".to_string()],
+            is_parameter: vec![false, false, true],
+            middleware: None,
+        });
+        let dispatcher: Arc<dyn Gateway + Send + Sync> = Arc::new(EchoDispatcher);
+        let gateway = GatewayState::new(dispatcher.clone(), &config, Arc::new(Metrics::new())).unwrap();
+        Arc::new(HttpState {
+            routes: Arc::new(RwLock::new(routes)),
+            routes_initialized: Arc::new(AtomicBool::new(true)),
+            config,
+            version: "1.0.0".to_string(),
+            dispatcher: Some(dispatcher),
+            gateway: Some(Arc::new(gateway)),
+        })
+    }
+    
+    async fn put(http: Arc<HttpState>, body: &str) -> (StatusCode, Value) {
+        let req = Request::put("/service-tests/invoices/42")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = handle_request(req, http, "127.0.0.1:9".parse().unwrap()).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+    
+    #[tokio::test]
+    async fn matched_route_is_dispatched_with_its_arguments() {
+        let (status, body) = put(http_state(GatewayConfig::default()), r#"{"notes": "Net 30", "id": "spoofed"}"#).await;
+        
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["called"], "invoice.update");
+        assert_eq!(body["params"]["notes"], "Net 30");
+        assert_eq!(body["params"]["id"], "42");
+        assert_eq!(body["params"]["tenant_id"], crate::DEFAULT_TENANT);
+    }
+    
+    #[tokio::test]
+    async fn oversized_body_is_rejected() {
+        let config = GatewayConfig::builder().max_body_size(16).build();
+        let (status, _) = put(http_state(config), &json!({ "notes": "x".repeat(64) }).to_string()).await;
+        
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    
+    #[tokio::test]
+    async fn malformed_body_is_rejected() {
+        let (status, body) = put(http_state(GatewayConfig::default()), r#"{"notes": "#).await;
+        
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().starts_with("Invalid JSON body"));
+    }
+    
+    #[tokio::test]
+    async fn unknown_operation_is_a_structured_not_found() {
+        let service = GatewayService::new("gateway".to_string(), GatewayConfig::default());
+        
+        let err = service.operations.call(&service, "frobnicate", Value::Null).unwrap_err();
+        
+        match kagi_shared::ServiceError::from_anyhow(&err) {
+            kagi_shared::ServiceError::NotFound(message) => {
+                assert!(message.contains("'frobnicate'"), "{}", message);
+                assert!(message.ends_with("supported operations: getOperations, getRoutes, ping"), "{}", message);
+            },
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+        let response = crate::error_response_for(&service.config, &err);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["error"].as_str().unwrap().contains("getRoutes"));
+    }
+    
+    #[derive(serde::Deserialize)]
+    struct RepeatParams {
+        word: String,
+        times: usize,
+    }
+    
+    #[test]
+    fn new_operations_get_typed_params_and_the_error_envelope() {
+        let mut service = GatewayService::new("gateway".to_string(), GatewayConfig::default());
+        service.register_operation("repeat", "Repeat a word", |_, params: RepeatParams| {
+            Ok(Value::from(vec![params.word; params.times]))
+        });
+        
+        let repeated = service.operations.call(&service, "repeat", json!({ "word": "hi", "times": 3 })).unwrap();
+        assert_eq!(repeated, json!(["hi", "hi", "hi"]));
+        
+        let err = service.operations.call(&service, "repeat", json!({ "word": "hi", "times": "many" })).unwrap_err();
+        match kagi_shared::ServiceError::from_anyhow(&err) {
+            kagi_shared::ServiceError::Validation(message) => assert!(message.contains("'repeat'"), "{}", message),
+            other => panic!("expected Validation, got {:?}", other),
+        }
+        
+        let described = service.operations.call(&service, "getOperations", Value::Null).unwrap();
+        assert_eq!(described["repeat"], "Repeat a word");
+    }
+    
+    #[tokio::test]
+    async fn query_parameters_are_forwarded_but_path_parameters_win() {
+        let req = Request::put("/service-tests/invoices/42?id=spoofed&status=Paid&tag=a&tag=b%20c")
+            .body(Body::empty())
+            .unwrap();
+        let response = handle_request(req, http_state(GatewayConfig::default()), "127.0.0.1:9".parse().unwrap()).await.unwrap();
+        let body: Value = serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        
+        assert_eq!(body["params"]["id"], "42");
+        assert_eq!(body["params"]["status"], "Paid");
+        assert_eq!(body["params"]["tag"], json!(["a", "b c"]));
+    }
+    
+    fn authorized_put(token: Option<&str>) -> Request<Body> {
+        let mut req = Request::put("/service-tests/invoices/42").header(header::CONTENT_TYPE, "application/json");
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        req.body(Body::from(r#"{"notes": "Net 30"}"#)).unwrap()
+    }
+    
+    #[tokio::test]
+    async fn auth_middleware_guards_matched_routes() {
+        let http = http_state(GatewayConfig::builder().middleware(crate::auth::AUTH_MIDDLEWARE).build());
+        
+        for (token, error) in [(None, "Missing bearer token"), (Some("mallory-token"), "Invalid token")] {
+            let response = handle_request(authorized_put(token), http.clone(), "127.0.0.1:9".parse().unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            let body: Value = serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+            assert_eq!(body["error"], error);
+        }
+        
+        let response = handle_request(authorized_put(Some("alice-token")), http, "127.0.0.1:9".parse().unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    
+    #[tokio::test]
+    async fn running_service_rejects_unauthenticated_calls() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        
+        register_invoice_route();
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let config = GatewayConfig::builder()
+            .port(port)
+            .middleware(crate::auth::AUTH_MIDDLEWARE)
+            .shutdown_grace_period(Duration::from_millis(100))
+            .build();
+        let service = Arc::new(GatewayService::new("gateway".to_string(), config).with_dispatcher(Arc::new(EchoDispatcher)));
+        let server = tokio::spawn({
+            let service = service.clone();
+            async move { service.run().await }
+        });
+        
+        let mut stream = loop {
+            match tokio::net::TcpStream::connect(("127.0.0.1", port)).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let request = "PUT /service-tests/invoices/42 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}";
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+        
+        service.shutdown.trigger();
+        server.await.unwrap().unwrap();
+    }
+}
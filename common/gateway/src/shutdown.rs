@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use log::{info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Tells every subscribed listener to stop accepting connections
+#[derive(Debug, Clone)]
+pub struct ShutdownTrigger(Arc<watch::Sender<bool>>);
+
+impl ShutdownTrigger {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        Self(Arc::new(sender))
+    }
+    
+    pub fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+    
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+    
+    pub fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal(self.0.subscribe())
+    }
+}
+
+impl Default for ShutdownTrigger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Receiving end of a `ShutdownTrigger`
+#[derive(Debug, Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    /// Resolve once shutdown is triggered; never resolves if the trigger is dropped first
+    pub async fn recv(mut self) {
+        while !*self.0.borrow() {
+            if self.0.changed().await.is_err() {
+                futures::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// Run `servers` until they all stop or one fails.
+///
+/// Once `signal` fires the servers stop accepting connections and drain the ones
+/// they have; connections still open after `grace` are dropped.
+pub async fn serve_until_shutdown(
+    servers: Vec<BoxFuture<'static, Result<()>>>,
+    signal: ShutdownSignal,
+    grace: Duration,
+) -> Result<()> {
+    let deadline = async move {
+        signal.recv().await;
+        info!("Shutting down gateway, draining connections for up to {}ms", grace.as_millis());
+        tokio::time::sleep(grace).await;
+    };
+    
+    tokio::select! {
+        result = futures::future::try_join_all(servers) => result.map(|_| ()),
+        _ = deadline => {
+            warn!("Closing connections still open after the {}ms shutdown grace period", grace.as_millis());
+            Ok(())
+        }
+    }
+}
+
+/// A gateway running in the background
+pub struct GatewayHandle {
+    trigger: ShutdownTrigger,
+    task: JoinHandle<Result<()>>,
+}
+
+impl GatewayHandle {
+    pub(crate) fn new(trigger: ShutdownTrigger, task: JoinHandle<Result<()>>) -> Self {
+        Self { trigger, task }
+    }
+    
+    /// Trigger that stops this gateway, e.g. to hand to a signal handler
+    pub fn shutdown_trigger(&self) -> ShutdownTrigger {
+        self.trigger.clone()
+    }
+    
+    /// Stop accepting connections and wait for in-flight requests to finish
+    pub async fn shutdown(self) -> Result<()> {
+        self.trigger.trigger();
+        self.wait().await
+    }
+    
+    /// Wait for the gateway to stop on its own or through its trigger
+    pub async fn wait(self) -> Result<()> {
+        self.task.await.map_err(|e| anyhow!("Gateway task failed: {}", e))?
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{conditional, error_response, GatewayConfig};
+use chrono::{DateTime, Utc};
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use log::debug;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// File served for a request naming a directory
+const INDEX_FILE: &str = "index.html";
+
+/// Files under `root` served for requests under the `mount` path prefix
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StaticFiles {
+    /// Path prefix, e.g. `/assets` or `/` for a single-page app
+    pub mount: String,
+    /// Directory the files are read from
+    pub root: PathBuf,
+}
+
+impl StaticFiles {
+    /// Path relative to `root` for a request path under `mount`
+    fn relative_path<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let mount = self.mount.trim_end_matches('/');
+        let rest = path.strip_prefix(mount)?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            // `/assets` mustn't serve `/assetsfoo`
+            return None;
+        }
+        Some(rest.trim_start_matches('/'))
+    }
+}
+
+/// Why a static file couldn't be served
+enum Refusal {
+    /// The path tries to leave the mount's root
+    Forbidden,
+    NotFound,
+}
+
+/// Turn a request path relative to the mount into a path under `root`.
+///
+/// The path is percent-decoded first, so encoded `..` segments are caught too.
+fn resolve(root: &Path, relative: &str) -> Result<PathBuf, Refusal> {
+    let decoded = percent_decode_str(relative)
+        .decode_utf8()
+        .map_err(|_| Refusal::NotFound)?;
+    
+    let mut path = root.to_path_buf();
+    for segment in decoded.split('/').filter(|s| !s.is_empty() && *s != ".") {
+        if segment == ".." || segment.contains('\\') || segment.contains('\0') {
+            return Err(Refusal::Forbidden);
+        }
+        path.push(segment);
+    }
+    Ok(path)
+}
+
+/// Read the file for `relative`, falling back to the directory's `index.html`
+async fn read_file(root: &Path, relative: &str) -> Result<(PathBuf, Vec<u8>, std::fs::Metadata), Refusal> {
+    let mut path = resolve(root, relative)?;
+    let mut metadata = tokio::fs::metadata(&path).await.map_err(|_| Refusal::NotFound)?;
+    if metadata.is_dir() {
+        path.push(INDEX_FILE);
+        metadata = tokio::fs::metadata(&path).await.map_err(|_| Refusal::NotFound)?;
+    }
+    if !metadata.is_file() {
+        return Err(Refusal::NotFound);
+    }
+    
+    // Symlinks are followed, but only to files that stay inside the root
+    let root = tokio::fs::canonicalize(root).await.map_err(|_| Refusal::NotFound)?;
+    let canonical = tokio::fs::canonicalize(&path).await.map_err(|_| Refusal::NotFound)?;
+    if !canonical.starts_with(&root) {
+        return Err(Refusal::Forbidden);
+    }
+    
+    let contents = tokio::fs::read(&canonical).await.map_err(|_| Refusal::NotFound)?;
+    Ok((path, contents, metadata))
+}
+
+/// Entity tag from the file's size and modification time
+fn etag(metadata: &std::fs::Metadata) -> Option<String> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("\"{:x}-{:x}\"", metadata.len(), modified.as_secs()))
+}
+
+fn last_modified(metadata: &std::fs::Metadata) -> Option<String> {
+    let modified: DateTime<Utc> = metadata.modified().ok()?.into();
+    Some(modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
+
+/// Serve a GET or HEAD request from the first mount its path falls under.
+///
+/// Returns `None` when no mount applies, so the caller can answer with its own `404`.
+pub async fn serve(config: &GatewayConfig, req: &Request<Body>) -> Option<Response<Body>> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return None;
+    }
+    let path = req.uri().path();
+    let (mount, relative) = config.static_files
+        .iter()
+        .find_map(|mount| mount.relative_path(path).map(|relative| (mount, relative)))?;
+    
+    let (file, contents, metadata) = match read_file(&mount.root, relative).await {
+        Ok(found) => found,
+        Err(Refusal::Forbidden) => {
+            debug!("Refusing static path outside {}: {}", mount.root.display(), path);
+            return Some(error_response(config, StatusCode::FORBIDDEN, "Forbidden"));
+        },
+        Err(Refusal::NotFound) => return Some(error_response(config, StatusCode::NOT_FOUND, "Not found")),
+    };
+    
+    let etag = etag(&metadata);
+    if let Some(etag) = &etag {
+        if conditional::if_none_match_matches(req.headers(), etag) {
+            return Some(Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header(header::ETAG, etag.as_str())
+                .body(Body::empty())
+                .unwrap());
+        }
+    }
+    
+    let content_type = mime_guess::from_path(&file).first_or_octet_stream();
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type.as_ref())
+        .header(header::CONTENT_LENGTH, contents.len());
+    if let Some(etag) = etag {
+        response = response.header(header::ETAG, etag);
+    }
+    if let Some(last_modified) = last_modified(&metadata) {
+        response = response.header(header::LAST_MODIFIED, last_modified);
+    }
+    
+    let body = if req.method() == Method::HEAD { Body::empty() } else { Body::from(contents) };
+    Some(response.body(body).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// A site directory with a stylesheet and a docs index, next to a file
+    /// outside the mounted root; removed on drop
+    struct Site {
+        dir: PathBuf,
+    }
+    
+    impl Site {
+        fn create() -> Self {
+            let dir = std::env::temp_dir().join(format!("gateway-static-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(dir.join("public/docs")).unwrap();
+            std::fs::write(dir.join("public/app.css"), "body { margin: 0 }").unwrap();
+            std::fs::write(dir.join("public/docs/index.html"), "<h1>Docs</h1>").unwrap();
+            std::fs::write(dir.join("secret.txt"), "api-key").unwrap();
+            Self { dir }
+        }
+        
+        fn config(&self) -> GatewayConfig {
+            GatewayConfig::builder().static_files("/assets", self.dir.join("public")).build()
+        }
+    }
+    
+    impl Drop for Site {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+    
+    async fn fetch(config: &GatewayConfig, path: &str) -> Option<(StatusCode, hyper::HeaderMap, String)> {
+        let response = serve(config, &Request::get(path).body(Body::empty()).unwrap()).await?;
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await.unwrap();
+        Some((parts.status, parts.headers, String::from_utf8(body.to_vec()).unwrap()))
+    }
+    
+    #[tokio::test]
+    async fn serves_a_known_file_and_directory_index() {
+        let site = Site::create();
+        let config = site.config();
+        
+        let (status, headers, body) = fetch(&config, "/assets/app.css").await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "text/css");
+        assert_eq!(body, "body { margin: 0 }");
+        assert!(headers.contains_key(header::LAST_MODIFIED));
+        
+        let revalidate = Request::get("/assets/app.css")
+            .header(header::IF_NONE_MATCH, headers[header::ETAG].clone())
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(serve(&config, &revalidate).await.unwrap().status(), StatusCode::NOT_MODIFIED);
+        
+        let (status, headers, body) = fetch(&config, "/assets/docs/").await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "text/html");
+        assert_eq!(body, "<h1>Docs</h1>");
+    }
+    
+    #[tokio::test]
+    async fn traversal_out_of_the_root_is_refused() {
+        let site = Site::create();
+        let config = site.config();
+        
+        for path in ["/assets/../secret.txt", "/assets/docs/%2e%2e/%2E%2E/secret.txt", "/assets/..%5csecret.txt"] {
+            let (status, _, body) = fetch(&config, path).await.unwrap();
+            assert_eq!(status, StatusCode::FORBIDDEN, "{}", path);
+            assert!(!body.contains("api-key"), "{}", path);
+        }
+        
+        let (status, _, _) = fetch(&config, "/assets/missing.js").await.unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        // Paths merely sharing the prefix fall through to the router
+        assert!(fetch(&config, "/assetsfoo/app.css").await.is_none());
+    }
+}
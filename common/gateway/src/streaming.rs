@@ -0,0 +1,112 @@
+use crate::Gateway;
+use anyhow::Result;
+use futures::stream::{self, BoxStream, StreamExt};
+use hyper::body::Bytes;
+use hyper::{header, Body, HeaderMap, Response, StatusCode};
+
+/// Header a client sets to receive a list response as a chunked JSON array
+pub const STREAM_HEADER: &str = "x-stream-list";
+
+/// Stream of JSON items produced by a list action
+pub type JsonStream = BoxStream<'static, Result<serde_json::Value>>;
+
+/// Whether the client asked for a streamed list response
+pub fn wants_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(STREAM_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true"))
+        .unwrap_or(false)
+}
+
+/// Encode a stream of items as a JSON array body, writing each item as it arrives.
+///
+/// The body has no known length, so hyper sends it with chunked transfer encoding.
+/// An item that fails mid-stream aborts the body, which the client sees as a
+/// truncated (invalid) array rather than a silently shortened list.
+pub fn json_array_body(items: JsonStream) -> Body {
+    let mut first = true;
+    let items = items.map(move |item| -> Result<Bytes> {
+        let item = item?;
+        let mut chunk = if first { Vec::new() } else { b",".to_vec() };
+        first = false;
+        serde_json::to_writer(&mut chunk, &item)?;
+        Ok(Bytes::from(chunk))
+    });
+    
+    let body = stream::once(async { Ok(Bytes::from_static(b"[")) })
+        .chain(items)
+        .chain(stream::once(async { Ok(Bytes::from_static(b"]")) }));
+    
+    Body::wrap_stream(body)
+}
+
+/// Dispatch a list action and stream its items back as a JSON array
+pub async fn streaming_response(
+    gateway: &dyn Gateway,
+    service: &str,
+    action: &str,
+    params: serde_json::Value,
+) -> Result<Response<Body>> {
+    let items = gateway.dispatch_stream(service, action, params).await?;
+    
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(json_array_body(items))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use hyper::body::HttpBody;
+    use serde_json::{json, Value};
+    
+    const ITEMS: usize = 5_000;
+    
+    /// Produces `ITEMS` invoices one at a time
+    struct LargeList;
+    
+    #[async_trait]
+    impl Gateway for LargeList {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn dispatch_stream(&self, _service: &str, _action: &str, _params: Value) -> Result<JsonStream> {
+            Ok(stream::iter(0..ITEMS).map(|n| Ok(json!({ "id": n, "number": format!("INV-{:05}", n) }))).boxed())
+        }
+    }
+    
+    #[tokio::test]
+    async fn large_list_is_chunked_and_reassembles_to_the_full_set() {
+        let response = streaming_response(&LargeList, "invoice", "list_invoices", json!({})).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::CONTENT_LENGTH).is_none());
+        
+        let mut body = response.into_body();
+        // No exact length means hyper sends the body with chunked transfer encoding
+        assert_eq!(body.size_hint().exact(), None);
+        let mut chunks = 0;
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            chunks += 1;
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        assert!(chunks > ITEMS, "only {} chunks", chunks);
+        
+        let items: Vec<Value> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(items.len(), ITEMS);
+        for (n, item) in items.iter().enumerate() {
+            assert_eq!(item["id"], n);
+        }
+    }
+    
+    #[tokio::test]
+    async fn empty_list_streams_an_empty_array() {
+        let body = json_array_body(stream::empty().boxed());
+        let bytes = hyper::body::to_bytes(body).await.unwrap();
+        assert_eq!(&bytes[..], b"[]");
+    }
+}
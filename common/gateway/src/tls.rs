@@ -0,0 +1,215 @@
+use crate::SslConfig;
+use anyhow::{anyhow, Context, Result};
+use hyper::server::accept::Accept;
+use log::{debug, warn};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// How long a client gets to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handshaken connections waiting for the server to pick them up
+const ACCEPT_BACKLOG: usize = 128;
+
+/// Build the rustls server configuration from the gateway's certificate and key files
+pub fn server_config(ssl: &SslConfig) -> Result<Arc<ServerConfig>> {
+    let cert_file = ssl.cert_file.as_deref()
+        .ok_or_else(|| anyhow!("SSL is enabled but no cert_file is configured"))?;
+    let key_file = ssl.key_file.as_deref()
+        .ok_or_else(|| anyhow!("SSL is enabled but no key_file is configured"))?;
+    
+    let certs = load_certs(cert_file)?;
+    let key = load_private_key(key_file)?;
+    
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .with_context(|| format!("Certificate {} can't be used with key {}", cert_file, key_file))?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    
+    Ok(Arc::new(config))
+}
+
+/// Read every certificate in a PEM file, leaf first
+fn load_certs(path: &str) -> Result<Vec<Certificate>> {
+    let file = File::open(path).with_context(|| format!("Failed to open certificate file {}", path))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to parse PEM certificates in {}", path))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in {}", path));
+    }
+    
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// Read the first PKCS#8, PKCS#1 (RSA) or SEC1 (EC) private key in a PEM file
+fn load_private_key(path: &str) -> Result<PrivateKey> {
+    let file = File::open(path).with_context(|| format!("Failed to open key file {}", path))?;
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to parse PEM key in {}", path))?;
+    
+    items.into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("No private key found in {}", path))
+}
+
+/// Accept TLS connections on `listener`.
+///
+/// Handshakes run on their own tasks so a slow or failing client doesn't hold up
+/// the others; failed handshakes are logged and dropped.
+pub fn incoming(listener: TcpListener, config: Arc<ServerConfig>) -> impl Accept<Conn = TlsStream<TcpStream>, Error = io::Error> {
+    let acceptor = TlsAcceptor::from(config);
+    let (tx, rx) = mpsc::channel(ACCEPT_BACKLOG);
+    
+    tokio::spawn(async move {
+        loop {
+            // Stop listening once the server has dropped its end, e.g. after shutdown
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = tx.closed() => break,
+            };
+            let (stream, peer) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept connection: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(tls)) => {
+                        let _ = tx.send(tls).await;
+                    },
+                    Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", peer, e),
+                    Err(_) => debug!("TLS handshake with {} timed out", peer),
+                }
+            });
+        }
+    });
+    
+    hyper::server::accept::from_stream(futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|conn| (Ok::<_, io::Error>(conn), rx))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{start_gateway_with_handle, Gateway, GatewayConfig};
+    use async_trait::async_trait;
+    use rustls::{ClientConfig, RootCertStore, ServerName};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+    
+    struct IdleGateway;
+    
+    #[async_trait]
+    impl Gateway for IdleGateway {
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+    
+    /// A self-signed certificate for `localhost`, written out as PEM files
+    struct SelfSigned {
+        cert_der: Vec<u8>,
+        cert_file: String,
+        key_file: String,
+    }
+    
+    impl SelfSigned {
+        fn generate() -> Self {
+            let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+            let dir = std::env::temp_dir().join(format!("gateway-tls-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let cert_file = dir.join("cert.pem");
+            let key_file = dir.join("key.pem");
+            std::fs::write(&cert_file, cert.serialize_pem().unwrap()).unwrap();
+            std::fs::write(&key_file, cert.serialize_private_key_pem()).unwrap();
+            
+            Self {
+                cert_der: cert.serialize_der().unwrap(),
+                cert_file: cert_file.to_string_lossy().into_owned(),
+                key_file: key_file.to_string_lossy().into_owned(),
+            }
+        }
+    }
+    
+    impl Drop for SelfSigned {
+        fn drop(&mut self) {
+            if let Some(dir) = std::path::Path::new(&self.cert_file).parent() {
+                let _ = std::fs::remove_dir_all(dir);
+            }
+        }
+    }
+    
+    #[tokio::test]
+    async fn serves_https_with_a_self_signed_certificate() {
+        let cert = SelfSigned::generate();
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let config = GatewayConfig::builder()
+            .port(port)
+            .enable_tls(cert.cert_file.clone(), cert.key_file.clone())
+            .shutdown_grace_period(Duration::from_millis(100))
+            .build();
+        let handle = start_gateway_with_handle(IdleGateway, config).await.unwrap();
+        
+        let mut roots = RootCertStore::empty();
+        roots.add(&Certificate(cert.cert_der.clone())).unwrap();
+        let client = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut tls = TlsConnector::from(Arc::new(client))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        
+        tls.write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = Vec::new();
+        tls.read_to_end(&mut response).await.unwrap();
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        
+        tokio::time::timeout(Duration::from_secs(5), handle.shutdown()).await.unwrap().unwrap();
+    }
+    
+    #[test]
+    fn missing_paths_and_bad_pem_are_reported() {
+        let cert = SelfSigned::generate();
+        let ssl = |cert_file: Option<&str>, key_file: Option<&str>| SslConfig {
+            enabled: true,
+            cert_file: cert_file.map(str::to_string),
+            key_file: key_file.map(str::to_string),
+        };
+        
+        assert!(server_config(&ssl(Some(&cert.cert_file), Some(&cert.key_file))).is_ok());
+        
+        let err = server_config(&ssl(None, Some(&cert.key_file))).unwrap_err();
+        assert!(err.to_string().contains("no cert_file"), "{}", err);
+        let err = server_config(&ssl(Some(&cert.cert_file), None)).unwrap_err();
+        assert!(err.to_string().contains("no key_file"), "{}", err);
+        
+        // A certificate file has no private key in it
+        let err = server_config(&ssl(Some(&cert.cert_file), Some(&cert.cert_file))).unwrap_err();
+        assert!(err.to_string().contains("No private key"), "{}", err);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{Profile, ProfileService};
+use anyhow::Result;
+use async_trait::async_trait;
+use auth_service::{AuthService, User};
+use kagi_macros::{action, init, service};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// A user together with their profile, for "my account" style pages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub user: User,
+    /// `None` until the user has created a profile
+    pub profile: Option<Profile>,
+}
+
+/// Thin aggregator over `AuthService` and `ProfileService`
+#[service]
+pub struct AccountService {
+    auth: Arc<AuthService>,
+    profiles: Arc<ProfileService>,
+}
+
+#[init]
+impl AccountService {
+    pub async fn new(auth: Arc<AuthService>, profiles: Arc<ProfileService>) -> Result<Self> {
+        Ok(Self { auth, profiles })
+    }
+}
+
+#[async_trait]
+impl AccountService {
+    /// Fetch the user and their profile, as `viewer_id` sees it, concurrently
+    #[action]
+    pub async fn get_account(&self, tenant_id: String, user_id: Uuid, viewer_id: Option<Uuid>) -> Result<Account> {
+        let (user, profile) = tokio::join!(
+            self.auth.get_user(tenant_id.clone(), user_id),
+            self.profiles.view_profile(&tenant_id, user_id, viewer_id),
+        );
+
+        Ok(Account { user: user?, profile })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ProfileVisibility, UpdateProfileRequest};
+    use auth_service::{AuthConfig, RegisterRequest, DEFAULT_TENANT};
+
+    #[tokio::test]
+    async fn account_profile_follows_its_visibility() {
+        let auth = Arc::new(AuthService::new(AuthConfig::new("test-secret-key")).await.unwrap());
+        let profiles = Arc::new(ProfileService::new().await.unwrap());
+        let registered = auth
+            .register(RegisterRequest {
+                tenant_id: DEFAULT_TENANT.to_string(),
+                username: "alice".to_string(),
+                email: "alice@example.com".to_string(),
+                password: "correct horse battery".to_string(),
+            })
+            .await
+            .unwrap();
+        let owner = registered.user;
+        profiles.create_profile(DEFAULT_TENANT.to_string(), owner.clone()).await.unwrap();
+        let req = UpdateProfileRequest {
+            expected_version: 1,
+            display_name: None,
+            bio: Some("Private bio".to_string()),
+            avatar_url: None,
+            visibility: Some(ProfileVisibility::Private),
+        };
+        profiles.update_profile(DEFAULT_TENANT.to_string(), owner.id, req).await.unwrap();
+        let accounts = AccountService::new(auth, profiles).await.unwrap();
+
+        let own = accounts.get_account(DEFAULT_TENANT.to_string(), owner.id, Some(owner.id)).await.unwrap();
+        assert_eq!(own.profile.unwrap().bio.as_deref(), Some("Private bio"));
+
+        let other = accounts.get_account(DEFAULT_TENANT.to_string(), owner.id, Some(Uuid::new_v4())).await.unwrap();
+        assert!(other.profile.unwrap().bio.is_none());
+    }
+
+    #[tokio::test]
+    async fn account_without_a_profile_has_none() {
+        let auth = Arc::new(AuthService::new(AuthConfig::new("test-secret-key")).await.unwrap());
+        let profiles = Arc::new(ProfileService::new().await.unwrap());
+        let registered = auth
+            .register(RegisterRequest {
+                tenant_id: DEFAULT_TENANT.to_string(),
+                username: "bob".to_string(),
+                email: "bob@example.com".to_string(),
+                password: "correct horse battery".to_string(),
+            })
+            .await
+            .unwrap();
+        let accounts = AccountService::new(auth, profiles).await.unwrap();
+
+        let account = accounts.get_account(DEFAULT_TENANT.to_string(), registered.user.id, None).await.unwrap();
+        assert_eq!(account.user.id, registered.user.id);
+        assert!(account.profile.is_none());
+
+        let missing = accounts.get_account(DEFAULT_TENANT.to_string(), Uuid::new_v4(), None).await;
+        assert!(missing.is_err());
+    }
+}
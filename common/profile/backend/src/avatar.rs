@@ -0,0 +1,49 @@
+use uuid::Uuid;
+
+/// Largest avatar accepted, in bytes
+pub const MAX_AVATAR_BYTES: usize = 2 * 1024 * 1024;
+
+/// Image formats accepted as avatars
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl ImageFormat {
+    /// Identify an image from its leading magic bytes; the declared content
+    /// type is never trusted
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(Self::Jpeg)
+        } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+            Some(Self::Webp)
+        } else {
+            None
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+        }
+    }
+}
+
+/// Blob key an avatar is stored under
+pub fn avatar_key(tenant_id: &str, user_id: Uuid, format: ImageFormat) -> String {
+    format!("avatars/{}/{}.{}", tenant_id, user_id, format.extension())
+}
@@ -0,0 +1,62 @@
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// Who follows whom, kept in both directions so either side is a single lookup
+#[derive(Debug, Default)]
+pub struct FollowGraph {
+    following: HashMap<Uuid, HashSet<Uuid>>,
+    followers: HashMap<Uuid, HashSet<Uuid>>,
+}
+
+impl FollowGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `false` if the edge already existed
+    pub fn follow(&mut self, follower: Uuid, followee: Uuid) -> bool {
+        let added = self.following.entry(follower).or_default().insert(followee);
+        self.followers.entry(followee).or_default().insert(follower);
+        added
+    }
+
+    /// Returns `false` if there was no edge to remove
+    pub fn unfollow(&mut self, follower: Uuid, followee: Uuid) -> bool {
+        let removed = remove_edge(&mut self.following, follower, followee);
+        remove_edge(&mut self.followers, followee, follower);
+        removed
+    }
+
+    pub fn is_following(&self, follower: Uuid, followee: Uuid) -> bool {
+        self.following.get(&follower).map_or(false, |s| s.contains(&followee))
+    }
+
+    pub fn following(&self, user_id: Uuid) -> Vec<Uuid> {
+        self.following.get(&user_id).map(|s| s.iter().copied().collect()).unwrap_or_default()
+    }
+
+    pub fn followers(&self, user_id: Uuid) -> Vec<Uuid> {
+        self.followers.get(&user_id).map(|s| s.iter().copied().collect()).unwrap_or_default()
+    }
+
+    /// Drop every edge touching `user_id`
+    pub fn remove_user(&mut self, user_id: Uuid) {
+        for followee in self.following.remove(&user_id).unwrap_or_default() {
+            remove_edge(&mut self.followers, followee, user_id);
+        }
+        for follower in self.followers.remove(&user_id).unwrap_or_default() {
+            remove_edge(&mut self.following, follower, user_id);
+        }
+    }
+}
+
+fn remove_edge(edges: &mut HashMap<Uuid, HashSet<Uuid>>, from: Uuid, to: Uuid) -> bool {
+    let Some(targets) = edges.get_mut(&from) else {
+        return false;
+    };
+    let removed = targets.remove(&to);
+    if targets.is_empty() {
+        edges.remove(&from);
+    }
+    removed
+}
@@ -1,36 +1,218 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use auth_service::User;
+use auth_service::{RoleChecker, User, UsersMerged, ADMIN_ROLE, USERS_MERGED_EVENT};
 use chrono::{DateTime, Utc};
 use kagi_macros::{action, init, service};
+use kagi_shared::{
+    to_result, unknown_operation, ActionParams, ActionService, Blob, BlobStore, DomainEvent, EventBus, InMemoryBlobStore,
+    ServiceError, RESYNC_KIND,
+};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::warn;
 use uuid::Uuid;
 
+pub mod account;
+pub mod avatar;
+pub mod graph;
+pub mod search;
+
+use avatar::{avatar_key, ImageFormat, MAX_AVATAR_BYTES};
+use graph::FollowGraph;
+use search::SearchIndex;
+
+/// Topic profile events are published on
+pub const EVENT_TOPIC: &str = "profile";
+
+/// Payload of the `profile_followed` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileFollowed {
+    pub tenant_id: String,
+    pub follower_id: Uuid,
+    pub followee_id: Uuid,
+}
+
+impl DomainEvent for ProfileFollowed {
+    const TOPIC: &'static str = EVENT_TOPIC;
+    const KIND: &'static str = "profile_followed";
+}
+
+/// Payload of the `profile_unfollowed` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileUnfollowed {
+    pub tenant_id: String,
+    pub follower_id: Uuid,
+    pub followee_id: Uuid,
+}
+
+impl DomainEvent for ProfileUnfollowed {
+    const TOPIC: &'static str = EVENT_TOPIC;
+    const KIND: &'static str = "profile_unfollowed";
+}
+
+/// Who can see a profile's details; everyone can always see the display name
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileVisibility {
+    #[default]
+    Public,
+    /// Only the owner
+    Private,
+    /// The owner and the users following them
+    FollowersOnly,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Profile {
     pub id: Uuid,
+    pub tenant_id: String,
     pub user_id: Uuid,
     pub display_name: String,
     pub bio: Option<String>,
     pub avatar_url: Option<String>,
+    #[serde(default)]
+    pub visibility: ProfileVisibility,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Incremented on every successful write
+    #[serde(default)]
+    pub version: u64,
+}
+
+impl Profile {
+    /// Whether `viewer_id` may see the full profile; anonymous viewers only see public ones
+    pub fn visible_to(&self, viewer_id: Option<Uuid>, follows: &FollowGraph) -> bool {
+        match (self.visibility, viewer_id) {
+            (ProfileVisibility::Public, _) => true,
+            (_, Some(viewer_id)) if viewer_id == self.user_id => true,
+            (ProfileVisibility::FollowersOnly, Some(viewer_id)) => follows.is_following(viewer_id, self.user_id),
+            _ => false,
+        }
+    }
+
+    /// The view shown to users the profile's visibility excludes
+    pub fn redacted(&self) -> Profile {
+        Profile {
+            bio: None,
+            avatar_url: None,
+            ..self.clone()
+        }
+    }
+
+    /// The profile as `viewer_id` sees it; every read path goes through here
+    pub fn viewed_by(&self, viewer_id: Option<Uuid>, follows: &FollowGraph) -> Profile {
+        if self.visible_to(viewer_id, follows) {
+            self.clone()
+        } else {
+            self.redacted()
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateProfileRequest {
+    /// Version of the profile the caller last read; stale versions are rejected
+    pub expected_version: u64,
     pub display_name: Option<String>,
     pub bio: Option<String>,
     pub avatar_url: Option<String>,
+    pub visibility: Option<ProfileVisibility>,
+}
+
+/// Largest page `list_profiles` returns
+pub const MAX_PAGE_LIMIT: usize = 100;
+
+/// One page of profiles plus the total across all pages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaginatedProfiles {
+    pub items: Vec<Profile>,
+    pub total: usize,
 }
 
-#[service]
+/// Longest display name, in characters
+pub const MAX_DISPLAY_NAME_CHARS: usize = 64;
+
+/// Longest bio, in characters
+pub const MAX_BIO_CHARS: usize = 4096;
+
+impl UpdateProfileRequest {
+    /// Check every supplied field, reporting the first one that is invalid
+    pub fn validate(&self) -> Result<(), ServiceError> {
+        if let Some(display_name) = &self.display_name {
+            let len = display_name.trim().chars().count();
+            if len == 0 {
+                return Err(ServiceError::validation("display_name must not be empty"));
+            }
+            if display_name.chars().count() > MAX_DISPLAY_NAME_CHARS {
+                return Err(ServiceError::validation(format!(
+                    "display_name must be at most {} characters", MAX_DISPLAY_NAME_CHARS
+                )));
+            }
+        }
+        if let Some(bio) = &self.bio {
+            if bio.chars().count() > MAX_BIO_CHARS {
+                return Err(ServiceError::validation(format!(
+                    "bio must be at most {} characters", MAX_BIO_CHARS
+                )));
+            }
+        }
+        if let Some(avatar_url) = &self.avatar_url {
+            let parsed = url::Url::parse(avatar_url)
+                .map_err(|e| ServiceError::validation(format!("avatar_url is not a valid URL: {}", e)))?;
+            if !matches!(parsed.scheme(), "http" | "https") || !parsed.has_host() {
+                return Err(ServiceError::validation("avatar_url must be an http or https URL"));
+            }
+        }
+        Ok(())
+    }
+}
+
+type ProfileMap = Arc<RwLock<HashMap<Uuid, Profile>>>;
+type UserProfileIndex = Arc<RwLock<HashMap<(String, Uuid), Uuid>>>;
+type SearchIndexRef = Arc<RwLock<SearchIndex>>;
+
+/// Move the merged user's profile to the kept user, unless the kept user already has one,
+/// in which case the merged profile is dropped
+async fn reassign_profile(
+    profiles: &ProfileMap,
+    user_profile_index: &UserProfileIndex,
+    search_index: &SearchIndexRef,
+    merged: &UsersMerged,
+) {
+    let mut profiles = profiles.write().await;
+    let mut user_profile_index = user_profile_index.write().await;
+
+    let merge_key = (merged.tenant_id.clone(), merged.merge_id);
+    let keep_key = (merged.tenant_id.clone(), merged.keep_id);
+    let Some(profile_id) = user_profile_index.remove(&merge_key) else {
+        return;
+    };
+
+    if user_profile_index.contains_key(&keep_key) {
+        if let Some(profile) = profiles.remove(&profile_id) {
+            search_index.write().await.remove(&profile.tenant_id, &profile.display_name, profile.id);
+        }
+    } else if let Some(profile) = profiles.get_mut(&profile_id) {
+        profile.user_id = merged.keep_id;
+        profile.updated_at = Utc::now();
+        profile.version += 1;
+        user_profile_index.insert(keep_key, profile_id);
+    }
+}
+
+#[service(name = "profile", description = "User profiles and follows")]
 pub struct ProfileService {
-    profiles: Arc<RwLock<HashMap<Uuid, Profile>>>,
-    user_profile_index: Arc<RwLock<HashMap<Uuid, Uuid>>>,
+    profiles: ProfileMap,
+    user_profile_index: UserProfileIndex,
+    search_index: SearchIndexRef,
+    follows: RwLock<FollowGraph>,
+    events: EventBus,
+    blobs: Arc<dyn BlobStore>,
+    /// Checks the caller of admin-only actions; they're refused without one
+    auth: Option<Arc<dyn RoleChecker>>,
 }
 
 #[init]
@@ -39,63 +221,235 @@ impl ProfileService {
         Ok(Self {
             profiles: Arc::new(RwLock::new(HashMap::new())),
             user_profile_index: Arc::new(RwLock::new(HashMap::new())),
+            search_index: Arc::new(RwLock::new(SearchIndex::new())),
+            follows: RwLock::new(FollowGraph::new()),
+            events: EventBus::new(),
+            blobs: Arc::new(InMemoryBlobStore::new()),
+            auth: None,
+        })
+    }
+}
+
+impl ProfileService {
+    /// Publish events onto a bus shared with other services
+    pub fn with_event_bus(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Store uploaded avatars in `blobs` instead of process memory
+    pub fn with_blob_store(mut self, blobs: Arc<dyn BlobStore>) -> Self {
+        self.blobs = blobs;
+        self
+    }
+
+    /// Check admin-only actions such as `list_profiles` against tokens `auth` issued.
+    ///
+    /// Any `AuthService` works here, whichever user store it runs on.
+    pub fn with_auth(mut self, auth: Arc<dyn RoleChecker>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Reassign profiles when `AuthService` merges duplicate users
+    pub fn spawn_merge_listener(&self) -> JoinHandle<()> {
+        let profiles = self.profiles.clone();
+        let user_profile_index = self.user_profile_index.clone();
+        let search_index = self.search_index.clone();
+        let mut events = self.events.subscribe(auth_service::EVENT_TOPIC);
+
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                match event.kind.as_str() {
+                    USERS_MERGED_EVENT => {
+                        match serde_json::from_value::<UsersMerged>(event.payload) {
+                            Ok(merged) => reassign_profile(&profiles, &user_profile_index, &search_index, &merged).await,
+                            Err(e) => warn!("Ignoring malformed {} event: {}", USERS_MERGED_EVENT, e),
+                        }
+                    },
+                    RESYNC_KIND => {
+                        warn!("Profile merge listener missed {} auth events", event.missed().unwrap_or(0));
+                    },
+                    _ => {},
+                }
+            }
         })
     }
+
+    /// Look up a user's profile, returning `None` when it hasn't been created yet
+    pub async fn find_profile(&self, tenant_id: &str, user_id: Uuid) -> Option<Profile> {
+        let profile_id = {
+            let user_profile_index = self.user_profile_index.read().await;
+            *user_profile_index.get(&(tenant_id.to_string(), user_id))?
+        };
+
+        let profiles = self.profiles.read().await;
+        profiles.get(&profile_id).cloned()
+    }
+
+    async fn require_profile(&self, tenant_id: &str, user_id: Uuid) -> Result<Profile> {
+        self.find_profile(tenant_id, user_id)
+            .await
+            .ok_or_else(|| ServiceError::not_found(format!("Profile not found for user {}", user_id)).into())
+    }
+
+    /// Look up a user's profile as `viewer_id` sees it
+    pub async fn view_profile(&self, tenant_id: &str, user_id: Uuid, viewer_id: Option<Uuid>) -> Option<Profile> {
+        let profile = self.find_profile(tenant_id, user_id).await?;
+        Some(profile.viewed_by(viewer_id, &*self.follows.read().await))
+    }
+
+    /// Profiles for `user_ids` in `tenant_id` as `viewer_id` sees them, ordered by display name
+    async fn profiles_for(&self, tenant_id: &str, user_ids: Vec<Uuid>, viewer_id: Option<Uuid>) -> Vec<Profile> {
+        let mut found = Vec::with_capacity(user_ids.len());
+        for user_id in user_ids {
+            if let Some(profile) = self.view_profile(tenant_id, user_id, viewer_id).await {
+                found.push(profile);
+            }
+        }
+        found.sort_by(|a, b| a.display_name.cmp(&b.display_name).then_with(|| a.id.cmp(&b.id)));
+        found
+    }
 }
 
 #[async_trait]
 impl ProfileService {
+    /// Create `user`'s profile in `tenant_id`, the tenant the gateway resolved
+    /// for the caller; a user from any other tenant is rejected
     #[action]
-    pub async fn create_profile(&self, user: User) -> Result<Profile> {
+    pub async fn create_profile(&self, tenant_id: String, user: User) -> Result<Profile> {
+        if user.tenant_id != tenant_id {
+            return Err(ServiceError::forbidden("User belongs to another tenant").into());
+        }
+
         let now = Utc::now();
         let profile = Profile {
             id: Uuid::new_v4(),
+            tenant_id: tenant_id.clone(),
             user_id: user.id,
             display_name: user.username,
             bio: None,
             avatar_url: None,
+            visibility: ProfileVisibility::default(),
             created_at: now,
             updated_at: now,
+            version: 1,
         };
 
         {
             let mut profiles = self.profiles.write().await;
             let mut user_profile_index = self.user_profile_index.write().await;
 
-            if user_profile_index.contains_key(&user.id) {
+            let key = (tenant_id, user.id);
+            if user_profile_index.contains_key(&key) {
                 return Err(anyhow!("Profile already exists for user"));
             }
 
-            user_profile_index.insert(user.id, profile.id);
+            user_profile_index.insert(key, profile.id);
             profiles.insert(profile.id, profile.clone());
+            self.search_index.write().await.insert(&profile.tenant_id, &profile.display_name, profile.id);
         }
 
+        self.events.publish_json(EVENT_TOPIC, "profile_created", &profile)?;
         Ok(profile)
     }
 
+    /// Fetch a profile as `viewer_id` sees it.
+    ///
+    /// Viewers the profile's visibility excludes, including anonymous ones,
+    /// get the redacted view with only the display name.
     #[action]
-    pub async fn get_profile(&self, user_id: Uuid) -> Result<Profile> {
-        let profile_id = {
-            let user_profile_index = self.user_profile_index.read().await;
-            user_profile_index
-                .get(&user_id)
-                .ok_or_else(|| anyhow!("Profile not found"))?
-                .clone()
-        };
+    pub async fn get_profile(&self, tenant_id: String, user_id: Uuid, viewer_id: Option<Uuid>) -> Result<Profile> {
+        self.view_profile(&tenant_id, user_id, viewer_id)
+            .await
+            .ok_or_else(|| anyhow!("Profile not found"))
+    }
 
+    /// Fetch many profiles at once, as `viewer_id` sees them.
+    ///
+    /// Returns a map keyed by user id; ids without a profile are left out
+    /// rather than failing the batch.
+    #[action]
+    pub async fn get_profiles(&self, tenant_id: String, user_ids: Vec<Uuid>, viewer_id: Option<Uuid>) -> Result<HashMap<Uuid, Profile>> {
+        // Same order as the writers take them
         let profiles = self.profiles.read().await;
-        profiles
-            .get(&profile_id)
-            .cloned()
-            .ok_or_else(|| anyhow!("Profile not found"))
+        let user_profile_index = self.user_profile_index.read().await;
+        let follows = self.follows.read().await;
+
+        let mut found = HashMap::with_capacity(user_ids.len());
+        for user_id in user_ids {
+            let profile = user_profile_index
+                .get(&(tenant_id.clone(), user_id))
+                .and_then(|id| profiles.get(id));
+            if let Some(profile) = profile {
+                found.insert(user_id, profile.viewed_by(viewer_id, &follows));
+            }
+        }
+        Ok(found)
     }
 
+    /// Admin: page through the profiles of the admin's tenant, newest first, as the admin sees them.
+    ///
+    /// `limit` is capped at `MAX_PAGE_LIMIT`; an `offset` past the end yields an empty page.
     #[action]
-    pub async fn update_profile(&self, user_id: Uuid, req: UpdateProfileRequest) -> Result<Profile> {
+    pub async fn list_profiles(&self, token: String, offset: usize, limit: usize) -> Result<PaginatedProfiles> {
+        let auth = self.auth.as_ref()
+            .ok_or_else(|| ServiceError::unavailable("Listing profiles needs an auth service to check the caller is an admin"))?;
+        let admin = auth.require_role(&token, ADMIN_ROLE).await?;
+        let tenant_id = admin.tenant_id;
+        let viewer_id = Some(admin.sub);
+        let limit = limit.min(MAX_PAGE_LIMIT);
+
+        let profiles = self.profiles.read().await;
+        let follows = self.follows.read().await;
+        let mut matching: Vec<&Profile> = profiles
+            .values()
+            .filter(|p| p.tenant_id == tenant_id)
+            .collect();
+        // Tie-break on id so pages stay stable when timestamps collide
+        matching.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.id.cmp(&b.id)));
+
+        let total = matching.len();
+        let items = matching
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|profile| profile.viewed_by(viewer_id, &follows))
+            .collect();
+
+        Ok(PaginatedProfiles { items, total })
+    }
+
+    /// Find profiles whose display name contains `query`, ignoring case, as `viewer_id` sees them.
+    ///
+    /// Names starting with `query` rank ahead of other matches; `limit` is
+    /// capped at `MAX_PAGE_LIMIT`.
+    #[action]
+    pub async fn search_profiles(&self, tenant_id: String, query: String, limit: usize, viewer_id: Option<Uuid>) -> Result<Vec<Profile>> {
+        let ids = self.search_index
+            .read()
+            .await
+            .search(&tenant_id, &query, limit.min(MAX_PAGE_LIMIT));
+
+        let profiles = self.profiles.read().await;
+        let follows = self.follows.read().await;
+        Ok(ids
+            .iter()
+            .filter_map(|id| profiles.get(id))
+            .map(|profile| profile.viewed_by(viewer_id, &follows))
+            .collect())
+    }
+
+    /// Update the profile if it is still at `req.expected_version`; a stale
+    /// version fails with a conflict and leaves the profile unchanged
+    #[action]
+    pub async fn update_profile(&self, tenant_id: String, user_id: Uuid, req: UpdateProfileRequest) -> Result<Profile> {
+        req.validate()?;
+
         let profile_id = {
             let user_profile_index = self.user_profile_index.read().await;
             user_profile_index
-                .get(&user_id)
+                .get(&(tenant_id, user_id))
                 .ok_or_else(|| anyhow!("Profile not found"))?
                 .clone()
         };
@@ -104,8 +458,18 @@ impl ProfileService {
         let profile = profiles
             .get_mut(&profile_id)
             .ok_or_else(|| anyhow!("Profile not found"))?;
+        if req.expected_version != profile.version {
+            return Err(ServiceError::conflict(format!(
+                "Profile was modified concurrently: expected version {}, current version {}",
+                req.expected_version,
+                profile.version
+            )).into());
+        }
 
         if let Some(display_name) = req.display_name {
+            let mut search_index = self.search_index.write().await;
+            search_index.remove(&profile.tenant_id, &profile.display_name, profile.id);
+            search_index.insert(&profile.tenant_id, &display_name, profile.id);
             profile.display_name = display_name;
         }
         if let Some(bio) = req.bio {
@@ -114,25 +478,706 @@ impl ProfileService {
         if let Some(avatar_url) = req.avatar_url {
             profile.avatar_url = Some(avatar_url);
         }
+        if let Some(visibility) = req.visibility {
+            profile.visibility = visibility;
+        }
         profile.updated_at = Utc::now();
+        profile.version += 1;
 
+        self.events.publish_json(EVENT_TOPIC, "profile_updated", &*profile)?;
         Ok(profile.clone())
     }
 
+    /// Upload an avatar image and point `avatar_url` at it.
+    ///
+    /// The format is sniffed from the data, which must be a PNG, JPEG or WebP
+    /// image of at most `MAX_AVATAR_BYTES`; `content_type` has to agree with it.
+    #[action]
+    pub async fn upload_avatar(&self, tenant_id: String, user_id: Uuid, bytes: Vec<u8>, content_type: String) -> Result<Profile> {
+        if bytes.len() > MAX_AVATAR_BYTES {
+            return Err(ServiceError::validation(format!(
+                "Avatar must be at most {} bytes", MAX_AVATAR_BYTES
+            )).into());
+        }
+        let format = ImageFormat::sniff(&bytes)
+            .ok_or_else(|| ServiceError::validation("Avatar must be a PNG, JPEG or WebP image"))?;
+        let declared = match content_type.trim().to_ascii_lowercase().as_str() {
+            "image/jpg" => "image/jpeg".to_string(),
+            declared => declared.to_string(),
+        };
+        if declared != format.content_type() {
+            return Err(ServiceError::validation(format!(
+                "content_type {} does not match the {} image data", content_type, format.content_type()
+            )).into());
+        }
+
+        let profile = self.require_profile(&tenant_id, user_id).await?;
+        let key = avatar_key(&tenant_id, user_id, format);
+        self.blobs.put(&key, Blob {
+            content_type: format.content_type().to_string(),
+            data: bytes,
+        }).await?;
+
+        let avatar_url = format!("/{}", key);
+        let updated = {
+            let mut profiles = self.profiles.write().await;
+            let profile = profiles
+                .get_mut(&profile.id)
+                .ok_or_else(|| anyhow!("Profile not found"))?;
+            profile.avatar_url = Some(avatar_url.clone());
+            profile.updated_at = Utc::now();
+            profile.version += 1;
+            profile.clone()
+        };
+
+        // An earlier upload in another format is left under a different key
+        if let Some(old_key) = profile.avatar_url.as_deref().and_then(|url| url.strip_prefix('/')) {
+            if old_key != key {
+                if let Err(e) = self.blobs.delete(old_key).await {
+                    warn!("Failed to delete old avatar {}: {}", old_key, e);
+                }
+            }
+        }
+
+        self.events.publish_json(EVENT_TOPIC, "profile_updated", &updated)?;
+        Ok(updated)
+    }
+
     #[action]
-    pub async fn delete_profile(&self, user_id: Uuid) -> Result<()> {
+    pub async fn delete_profile(&self, tenant_id: String, user_id: Uuid) -> Result<()> {
         let profile_id = {
             let mut user_profile_index = self.user_profile_index.write().await;
             user_profile_index
-                .remove(&user_id)
+                .remove(&(tenant_id, user_id))
                 .ok_or_else(|| anyhow!("Profile not found"))?
         };
 
         let mut profiles = self.profiles.write().await;
-        profiles
+        let profile = profiles
             .remove(&profile_id)
             .ok_or_else(|| anyhow!("Profile not found"))?;
+        self.search_index.write().await.remove(&profile.tenant_id, &profile.display_name, profile.id);
+        self.follows.write().await.remove_user(profile.user_id);
+
+        self.events.publish_json(EVENT_TOPIC, "profile_deleted", &profile)?;
+        Ok(())
+    }
+
+    /// Make `follower_id` follow `followee_id`; following twice is a no-op
+    #[action]
+    pub async fn follow(&self, tenant_id: String, follower_id: Uuid, followee_id: Uuid) -> Result<()> {
+        if follower_id == followee_id {
+            return Err(ServiceError::validation("Users cannot follow themselves").into());
+        }
+        self.require_profile(&tenant_id, follower_id).await?;
+        self.require_profile(&tenant_id, followee_id).await?;
 
+        if self.follows.write().await.follow(follower_id, followee_id) {
+            self.events.emit(&ProfileFollowed {
+                tenant_id,
+                follower_id,
+                followee_id,
+            })?;
+        }
         Ok(())
     }
-} 
\ No newline at end of file
+
+    /// Stop `follower_id` following `followee_id`; unfollowing twice is a no-op
+    #[action]
+    pub async fn unfollow(&self, tenant_id: String, follower_id: Uuid, followee_id: Uuid) -> Result<()> {
+        self.require_profile(&tenant_id, follower_id).await?;
+        self.require_profile(&tenant_id, followee_id).await?;
+
+        if self.follows.write().await.unfollow(follower_id, followee_id) {
+            self.events.emit(&ProfileUnfollowed {
+                tenant_id,
+                follower_id,
+                followee_id,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The user's followers as `viewer_id` sees them
+    #[action]
+    pub async fn get_followers(&self, tenant_id: String, user_id: Uuid, viewer_id: Option<Uuid>) -> Result<Vec<Profile>> {
+        self.require_profile(&tenant_id, user_id).await?;
+        let ids = self.follows.read().await.followers(user_id);
+        Ok(self.profiles_for(&tenant_id, ids, viewer_id).await)
+    }
+
+    /// The users the user follows, as `viewer_id` sees them
+    #[action]
+    pub async fn get_following(&self, tenant_id: String, user_id: Uuid, viewer_id: Option<Uuid>) -> Result<Vec<Profile>> {
+        self.require_profile(&tenant_id, user_id).await?;
+        let ids = self.follows.read().await.following(user_id);
+        Ok(self.profiles_for(&tenant_id, ids, viewer_id).await)
+    }
+}
+
+/// Actions by operation name, for registration and gateway dispatch
+#[async_trait]
+impl ActionService for ProfileService {
+    fn name(&self) -> &str {
+        "profile"
+    }
+
+    fn description(&self) -> &str {
+        "User profiles and follows"
+    }
+
+    fn operations(&self) -> Vec<String> {
+        [
+            "create_profile",
+            "get_profile",
+            "get_profiles",
+            "list_profiles",
+            "search_profiles",
+            "update_profile",
+            "upload_avatar",
+            "delete_profile",
+            "follow",
+            "unfollow",
+            "get_followers",
+            "get_following",
+        ]
+        .iter()
+        .map(|op| op.to_string())
+        .collect()
+    }
+
+    async fn call(&self, operation: &str, params: ActionParams) -> Result<serde_json::Value> {
+        match operation {
+            "create_profile" => to_result(self.create_profile(params.get_string("tenant_id")?, params.get_json("user")?).await?),
+            "get_profile" => to_result(
+                self.get_profile(params.get_string("tenant_id")?, params.get_json("user_id")?, params.get_json_optional("viewer_id")?)
+                    .await?,
+            ),
+            "get_profiles" => to_result(
+                self.get_profiles(params.get_string("tenant_id")?, params.get_json("user_ids")?, params.get_json_optional("viewer_id")?)
+                    .await?,
+            ),
+            "list_profiles" => to_result(
+                self.list_profiles(
+                    params.get_string("token")?,
+                    params.get_json_optional("offset")?.unwrap_or(0),
+                    params.get_json_optional("limit")?.unwrap_or(MAX_PAGE_LIMIT),
+                )
+                .await?,
+            ),
+            "search_profiles" => to_result(
+                self.search_profiles(
+                    params.get_string("tenant_id")?,
+                    params.get_string("query")?,
+                    params.get_json_optional("limit")?.unwrap_or(MAX_PAGE_LIMIT),
+                    params.get_json_optional("viewer_id")?,
+                )
+                .await?,
+            ),
+            "update_profile" => to_result(
+                // The update's fields sit alongside the ids
+                self.update_profile(params.get_string("tenant_id")?, params.get_json("user_id")?, params.parse()?)
+                    .await?,
+            ),
+            "upload_avatar" => to_result(
+                self.upload_avatar(
+                    params.get_string("tenant_id")?,
+                    params.get_json("user_id")?,
+                    params.get_json("bytes")?,
+                    params.get_string("content_type")?,
+                )
+                .await?,
+            ),
+            "delete_profile" => to_result(
+                self.delete_profile(params.get_string("tenant_id")?, params.get_json("user_id")?).await?,
+            ),
+            "follow" => to_result(
+                self.follow(params.get_string("tenant_id")?, params.get_json("follower_id")?, params.get_json("followee_id")?)
+                    .await?,
+            ),
+            "unfollow" => to_result(
+                self.unfollow(params.get_string("tenant_id")?, params.get_json("follower_id")?, params.get_json("followee_id")?)
+                    .await?,
+            ),
+            "get_followers" => to_result(
+                self.get_followers(params.get_string("tenant_id")?, params.get_json("user_id")?, params.get_json_optional("viewer_id")?)
+                    .await?,
+            ),
+            "get_following" => to_result(
+                self.get_following(params.get_string("tenant_id")?, params.get_json("user_id")?, params.get_json_optional("viewer_id")?)
+                    .await?,
+            ),
+            _ => Err(unknown_operation(self, operation)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use auth_service::{AuthConfig, AuthService, Claims, InMemoryUserStore, LoginRequest, PasswordHasher, RegisterRequest, UserStore, DEFAULT_TENANT};
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    fn user(username: &str) -> User {
+        serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(),
+            "tenant_id": DEFAULT_TENANT,
+            "username": username,
+            "email": format!("{}@example.com", username),
+            "password_hash": "",
+            "created_at": Utc::now(),
+            "updated_at": Utc::now(),
+        }))
+        .unwrap()
+    }
+
+    /// Create `owner`'s profile with a bio and the given visibility
+    async fn profile_with(service: &ProfileService, owner: &User, visibility: ProfileVisibility) -> Profile {
+        service.create_profile(DEFAULT_TENANT.to_string(), owner.clone()).await.unwrap();
+        let req = UpdateProfileRequest {
+            expected_version: 1,
+            display_name: None,
+            bio: Some(format!("About {}", owner.username)),
+            avatar_url: None,
+            visibility: Some(visibility),
+        };
+        service.update_profile(DEFAULT_TENANT.to_string(), owner.id, req).await.unwrap()
+    }
+
+    /// Whether `viewer` sees `owner`'s whole profile on every read path
+    async fn sees_whole_profile(service: &ProfileService, owner: &User, viewer: Option<Uuid>) -> bool {
+        let tenant = DEFAULT_TENANT.to_string();
+        let single = service.get_profile(tenant.clone(), owner.id, viewer).await.unwrap();
+        let batch = service.get_profiles(tenant.clone(), vec![owner.id], viewer).await.unwrap();
+        let searched = service.search_profiles(tenant, owner.username.clone(), 10, viewer).await.unwrap();
+        let views = [&single, &batch[&owner.id], &searched[0]];
+
+        let whole = views[0].bio.is_some();
+        assert!(views.iter().all(|p| p.bio.is_some() == whole), "read paths disagree on visibility");
+        assert!(views.iter().all(|p| p.display_name == owner.username));
+        whole
+    }
+
+    #[tokio::test]
+    async fn public_profiles_are_visible_to_everyone() {
+        let service = ProfileService::new().await.unwrap();
+        let owner = user("public_owner");
+        let stranger = user("stranger");
+        profile_with(&service, &owner, ProfileVisibility::Public).await;
+
+        assert!(sees_whole_profile(&service, &owner, None).await);
+        assert!(sees_whole_profile(&service, &owner, Some(stranger.id)).await);
+    }
+
+    #[tokio::test]
+    async fn private_profiles_are_visible_only_to_their_owner() {
+        let service = ProfileService::new().await.unwrap();
+        let owner = user("private_owner");
+        let follower = user("follower");
+        profile_with(&service, &owner, ProfileVisibility::Private).await;
+        profile_with(&service, &follower, ProfileVisibility::Public).await;
+        service.follow(DEFAULT_TENANT.to_string(), follower.id, owner.id).await.unwrap();
+
+        assert!(sees_whole_profile(&service, &owner, Some(owner.id)).await);
+        assert!(!sees_whole_profile(&service, &owner, Some(follower.id)).await);
+        assert!(!sees_whole_profile(&service, &owner, None).await);
+    }
+
+    #[tokio::test]
+    async fn followers_only_profiles_are_visible_to_followers() {
+        let service = ProfileService::new().await.unwrap();
+        let owner = user("guarded_owner");
+        let follower = user("follower");
+        let stranger = user("stranger");
+        for other in [&follower, &stranger] {
+            profile_with(&service, other, ProfileVisibility::Public).await;
+        }
+        profile_with(&service, &owner, ProfileVisibility::FollowersOnly).await;
+        service.follow(DEFAULT_TENANT.to_string(), follower.id, owner.id).await.unwrap();
+        service.follow(DEFAULT_TENANT.to_string(), owner.id, stranger.id).await.unwrap();
+
+        assert!(sees_whole_profile(&service, &owner, Some(owner.id)).await);
+        assert!(sees_whole_profile(&service, &owner, Some(follower.id)).await);
+        assert!(!sees_whole_profile(&service, &owner, Some(stranger.id)).await);
+        assert!(!sees_whole_profile(&service, &owner, None).await);
+
+        // The follow graph lists the owner too, and applies the same rule
+        let tenant = DEFAULT_TENANT.to_string();
+        let followers = service.get_followers(tenant.clone(), stranger.id, Some(stranger.id)).await.unwrap();
+        assert_eq!(followers[0].user_id, owner.id);
+        assert!(followers[0].bio.is_none());
+        let following = service.get_following(tenant, follower.id, Some(follower.id)).await.unwrap();
+        assert_eq!(following[0].user_id, owner.id);
+        assert!(following[0].bio.is_some());
+    }
+
+    #[tokio::test]
+    async fn merged_users_profile_moves_to_the_kept_user() {
+        let events = EventBus::new();
+        let service = ProfileService::new().await.unwrap().with_event_bus(events.clone());
+        service.spawn_merge_listener();
+        let kept = user("alice");
+        let duplicate = user("alice2");
+        let profile = service.create_profile(DEFAULT_TENANT.to_string(), duplicate.clone()).await.unwrap();
+
+        events.emit(&UsersMerged {
+            tenant_id: DEFAULT_TENANT.to_string(),
+            keep_id: kept.id,
+            merge_id: duplicate.id,
+        })
+        .unwrap();
+
+        let mut reassigned = None;
+        for _ in 0..100 {
+            reassigned = service.find_profile(DEFAULT_TENANT, kept.id).await;
+            if reassigned.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let reassigned = reassigned.expect("profile was not reassigned");
+        assert_eq!(reassigned.id, profile.id);
+        assert_eq!(reassigned.user_id, kept.id);
+        assert_eq!(reassigned.version, profile.version + 1);
+        assert!(service.find_profile(DEFAULT_TENANT, duplicate.id).await.is_none());
+    }
+
+    /// Auth with one admin already in the store, and a token for them
+    async fn admin_auth() -> (Arc<AuthService>, String) {
+        let store = InMemoryUserStore::new();
+        let admin: User = serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(),
+            "tenant_id": DEFAULT_TENANT,
+            "username": "admin",
+            "email": "admin@example.com",
+            "password_hash": PasswordHasher::default().hash("correct horse battery").unwrap(),
+            "roles": [ADMIN_ROLE],
+            "created_at": Utc::now(),
+            "updated_at": Utc::now(),
+        }))
+        .unwrap();
+        store.insert_user(admin).await.unwrap();
+        let auth = AuthService::with_store(AuthConfig::new("test-secret-key"), store).await.unwrap();
+        let login = LoginRequest {
+            tenant_id: DEFAULT_TENANT.to_string(),
+            username: "admin".to_string(),
+            password: "correct horse battery".to_string(),
+            totp_code: None,
+        };
+        let token = auth.login(login).await.unwrap().token;
+        (Arc::new(auth), token)
+    }
+
+    async fn service_with_profiles(auth: Arc<AuthService>, count: usize) -> ProfileService {
+        let service = ProfileService::new().await.unwrap().with_auth(auth);
+        for n in 0..count {
+            service.create_profile(DEFAULT_TENANT.to_string(), user(&format!("member_{}", n))).await.unwrap();
+        }
+        service
+    }
+
+    #[tokio::test]
+    async fn listing_profiles_requires_an_admin() {
+        let (auth, _) = admin_auth().await;
+        let registered = auth
+            .register(RegisterRequest {
+                tenant_id: DEFAULT_TENANT.to_string(),
+                username: "member".to_string(),
+                email: "member@example.com".to_string(),
+                password: "correct horse battery".to_string(),
+            })
+            .await
+            .unwrap();
+        let service = service_with_profiles(auth, 1).await;
+
+        let err = service.list_profiles(registered.token, 0, 10).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Forbidden(_)));
+        let err = service.list_profiles("not-a-token".to_string(), 0, 10).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Unauthorized(_)));
+
+        let without_auth = ProfileService::new().await.unwrap();
+        let err = without_auth.list_profiles("any".to_string(), 0, 10).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Unavailable(_)));
+    }
+
+    #[tokio::test]
+    async fn last_page_is_partial_and_pages_past_the_end_are_empty() {
+        let (auth, token) = admin_auth().await;
+        let service = service_with_profiles(auth, 5).await;
+
+        let first = service.list_profiles(token.clone(), 0, 2).await.unwrap();
+        let second = service.list_profiles(token.clone(), 2, 2).await.unwrap();
+        let last = service.list_profiles(token.clone(), 4, 2).await.unwrap();
+        assert_eq!((first.items.len(), second.items.len(), last.items.len()), (2, 2, 1));
+        assert!([&first, &second, &last].iter().all(|page| page.total == 5));
+
+        let mut seen: Vec<Uuid> = [first, second, last].iter().flat_map(|page| page.items.iter().map(|p| p.id)).collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 5, "pages overlapped");
+
+        let past_the_end = service.list_profiles(token, 10, 2).await.unwrap();
+        assert!(past_the_end.items.is_empty());
+        assert_eq!(past_the_end.total, 5);
+    }
+
+    #[tokio::test]
+    async fn limit_is_clamped_to_the_largest_page() {
+        let (auth, token) = admin_auth().await;
+        let service = service_with_profiles(auth, MAX_PAGE_LIMIT + 1).await;
+
+        let page = service.list_profiles(token, 0, 10 * MAX_PAGE_LIMIT).await.unwrap();
+
+        assert_eq!(page.items.len(), MAX_PAGE_LIMIT);
+        assert_eq!(page.total, MAX_PAGE_LIMIT + 1);
+    }
+
+    fn name_change(expected_version: u64, display_name: String) -> UpdateProfileRequest {
+        UpdateProfileRequest {
+            expected_version,
+            display_name: Some(display_name),
+            bio: None,
+            avatar_url: None,
+            visibility: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn display_name_of_64_chars_is_accepted_and_65_rejected() {
+        let service = ProfileService::new().await.unwrap();
+        let owner = user("long_names");
+        service.create_profile(DEFAULT_TENANT.to_string(), owner.clone()).await.unwrap();
+        let tenant = DEFAULT_TENANT.to_string();
+
+        // Counted in characters, not bytes
+        let longest = "é".repeat(MAX_DISPLAY_NAME_CHARS);
+        let updated = service.update_profile(tenant.clone(), owner.id, name_change(1, longest.clone())).await.unwrap();
+        assert_eq!(updated.display_name, longest);
+
+        let too_long = "é".repeat(MAX_DISPLAY_NAME_CHARS + 1);
+        let err = service.update_profile(tenant.clone(), owner.id, name_change(2, too_long)).await.unwrap_err();
+        match ServiceError::from_anyhow(&err) {
+            ServiceError::Validation(message) => assert!(message.starts_with("display_name"), "{}", message),
+            other => panic!("expected Validation, got {:?}", other),
+        }
+        let err = service.update_profile(tenant.clone(), owner.id, name_change(2, "   ".to_string())).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Validation(_)));
+
+        // A request with one bad field changes nothing
+        let req = UpdateProfileRequest {
+            bio: Some("New bio".to_string()),
+            avatar_url: Some("ftp://example.com/me.png".to_string()),
+            ..name_change(2, "Renamed".to_string())
+        };
+        let err = service.update_profile(tenant.clone(), owner.id, req).await.unwrap_err();
+        assert!(ServiceError::from_anyhow(&err).message().starts_with("avatar_url"));
+        let stored = service.get_profile(tenant, owner.id, Some(owner.id)).await.unwrap();
+        assert_eq!(stored.display_name, longest);
+        assert_eq!(stored.bio, None);
+        assert_eq!(stored.version, 2);
+    }
+
+    #[tokio::test]
+    async fn follow_graph_stays_consistent_in_both_directions_after_unfollow() {
+        let service = ProfileService::new().await.unwrap();
+        let tenant = DEFAULT_TENANT.to_string();
+        let (ann, ben, cat) = (user("ann"), user("ben"), user("cat"));
+        for owner in [&ann, &ben, &cat] {
+            service.create_profile(DEFAULT_TENANT.to_string(), owner.clone()).await.unwrap();
+        }
+        let ids = |profiles: Vec<Profile>| -> HashSet<Uuid> { profiles.iter().map(|p| p.user_id).collect() };
+        let following = |user_id| service.get_following(tenant.clone(), user_id, None);
+        let followers = |user_id| service.get_followers(tenant.clone(), user_id, None);
+
+        for (from, to) in [(&ann, &ben), (&ann, &cat), (&ben, &cat), (&cat, &ann), (&ann, &ben)] {
+            service.follow(tenant.clone(), from.id, to.id).await.unwrap();
+        }
+        let err = service.follow(tenant.clone(), ann.id, ann.id).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Validation(_)));
+        assert!(service.follow(tenant.clone(), ann.id, Uuid::new_v4()).await.is_err());
+
+        service.unfollow(tenant.clone(), ann.id, cat.id).await.unwrap();
+        service.unfollow(tenant.clone(), ann.id, cat.id).await.unwrap();
+
+        assert_eq!(ids(following(ann.id).await.unwrap()), HashSet::from([ben.id]));
+        assert_eq!(ids(followers(ann.id).await.unwrap()), HashSet::from([cat.id]));
+        assert_eq!(ids(following(ben.id).await.unwrap()), HashSet::from([cat.id]));
+        assert_eq!(ids(followers(ben.id).await.unwrap()), HashSet::from([ann.id]));
+        assert_eq!(ids(following(cat.id).await.unwrap()), HashSet::from([ann.id]));
+        assert_eq!(ids(followers(cat.id).await.unwrap()), HashSet::from([ben.id]));
+    }
+
+    /// Start of a 1x1 PNG: the signature and IHDR chunk
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0\x1f\x15\xc4\x89";
+
+    #[tokio::test]
+    async fn png_avatar_is_stored_and_linked_from_the_profile() {
+        let blobs = InMemoryBlobStore::new();
+        let service = ProfileService::new().await.unwrap().with_blob_store(Arc::new(blobs.clone()));
+        let owner = user("avatar_owner");
+        let created = service.create_profile(DEFAULT_TENANT.to_string(), owner.clone()).await.unwrap();
+
+        let updated = service
+            .upload_avatar(DEFAULT_TENANT.to_string(), owner.id, PNG.to_vec(), "image/png".to_string())
+            .await
+            .unwrap();
+
+        let key = format!("avatars/{}/{}.png", DEFAULT_TENANT, owner.id);
+        assert_eq!(updated.avatar_url, Some(format!("/{}", key)));
+        assert_eq!(updated.version, created.version + 1);
+        let stored = blobs.get(&key).await.unwrap().unwrap();
+        assert_eq!(stored.content_type, "image/png");
+        assert_eq!(stored.data, PNG);
+    }
+
+    #[tokio::test]
+    async fn disguised_non_images_are_rejected_by_their_bytes() {
+        let blobs = InMemoryBlobStore::new();
+        let service = ProfileService::new().await.unwrap().with_blob_store(Arc::new(blobs.clone()));
+        let owner = user("avatar_owner");
+        service.create_profile(DEFAULT_TENANT.to_string(), owner.clone()).await.unwrap();
+        let (service, owner_id) = (&service, owner.id);
+        let upload = move |bytes: &[u8], content_type: &str| {
+            service.upload_avatar(DEFAULT_TENANT.to_string(), owner_id, bytes.to_vec(), content_type.to_string())
+        };
+
+        for (bytes, content_type) in [
+            (&b"<script>alert(1)</script>"[..], "image/png"),
+            (&b"GIF89a\x01\0\x01\0"[..], "image/gif"),
+            (&b"MZ\x90\0\x03\0\0\0"[..], "image/jpeg"),
+        ] {
+            let err = upload(bytes, content_type).await.unwrap_err();
+            assert_eq!(
+                ServiceError::from_anyhow(&err),
+                ServiceError::validation("Avatar must be a PNG, JPEG or WebP image"),
+                "{}",
+                content_type
+            );
+        }
+
+        // A real PNG declared as something else is rejected as well
+        let err = upload(PNG, "image/jpeg").await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Validation(_)));
+
+        let key = format!("avatars/{}/{}.png", DEFAULT_TENANT, owner.id);
+        assert!(blobs.get(&key).await.unwrap().is_none());
+        let profile = service.get_profile(DEFAULT_TENANT.to_string(), owner.id, Some(owner.id)).await.unwrap();
+        assert_eq!(profile.avatar_url, None);
+    }
+
+    #[tokio::test]
+    async fn batch_fetch_returns_the_existing_profiles_and_skips_missing_ids() {
+        let service = ProfileService::new().await.unwrap();
+        let alice = user("alice");
+        let bob = user("bob");
+        let carol = user("carol");
+        profile_with(&service, &alice, ProfileVisibility::Public).await;
+        profile_with(&service, &bob, ProfileVisibility::Private).await;
+        profile_with(&service, &carol, ProfileVisibility::Public).await;
+        let mut elsewhere = user("elsewhere");
+        elsewhere.tenant_id = "globex".to_string();
+        service.create_profile("globex".to_string(), elsewhere.clone()).await.unwrap();
+        let never_created = Uuid::new_v4();
+
+        let ids = vec![alice.id, never_created, bob.id, elsewhere.id, carol.id, alice.id];
+        let found = service.get_profiles(DEFAULT_TENANT.to_string(), ids, None).await.unwrap();
+
+        assert_eq!(found.keys().copied().collect::<HashSet<_>>(), HashSet::from([alice.id, bob.id, carol.id]));
+        assert_eq!(found[&alice.id].display_name, "alice");
+        assert_eq!(found[&carol.id].bio.as_deref(), Some("About carol"));
+        // Each profile is still seen as the viewer would see it on its own
+        assert_eq!(found[&bob.id].bio, None);
+
+        assert!(service.get_profiles(DEFAULT_TENANT.to_string(), Vec::new(), None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn second_update_from_the_same_base_version_conflicts() {
+        let service = ProfileService::new().await.unwrap();
+        let owner = user("racing_editor");
+        let created = service.create_profile(DEFAULT_TENANT.to_string(), owner.clone()).await.unwrap();
+        let tenant = DEFAULT_TENANT.to_string();
+        let base = created.version;
+
+        let first = service.update_profile(tenant.clone(), owner.id, name_change(base, "Quick Editor".to_string())).await.unwrap();
+        assert_eq!(first.version, base + 1);
+
+        let stale = UpdateProfileRequest {
+            bio: Some("Overwritten".to_string()),
+            ..name_change(base, "Slow Editor".to_string())
+        };
+        let err = service.update_profile(tenant.clone(), owner.id, stale).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Conflict(_)));
+
+        // The losing write left no trace, in the profile or the search index
+        let stored = service.get_profile(tenant.clone(), owner.id, Some(owner.id)).await.unwrap();
+        assert_eq!(stored.display_name, "Quick Editor");
+        assert_eq!(stored.bio, None);
+        assert_eq!(stored.version, first.version);
+        assert!(service.search_profiles(tenant.clone(), "slow".to_string(), 10, None).await.unwrap().is_empty());
+
+        // Re-reading and retrying at the current version succeeds
+        let retried = service.update_profile(tenant, owner.id, name_change(stored.version, "Slow Editor".to_string())).await.unwrap();
+        assert_eq!(retried.version, base + 2);
+    }
+
+    #[tokio::test]
+    async fn profiles_are_created_in_the_stamped_tenant_only() {
+        let service = ProfileService::new().await.unwrap();
+        let mut intruder = user("intruder");
+        intruder.tenant_id = "globex".to_string();
+        let mut body = serde_json::to_value(&intruder).unwrap();
+        body["password_hash"] = "".into();
+
+        // The gateway stamps the caller's tenant; the body claims another one
+        let params = serde_json::json!({ "tenant_id": DEFAULT_TENANT, "user": body.clone() });
+        let err = service.handle("create_profile", ActionParams::new(params).unwrap()).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Forbidden(_)));
+        assert!(service.find_profile(DEFAULT_TENANT, intruder.id).await.is_none());
+        assert!(service.find_profile("globex", intruder.id).await.is_none());
+
+        let params = serde_json::json!({ "tenant_id": "globex", "user": body });
+        let created: Profile = serde_json::from_value(
+            service.handle("create_profile", ActionParams::new(params).unwrap()).await.unwrap(),
+        )
+        .unwrap();
+        assert_eq!(created.tenant_id, "globex");
+        assert!(service.find_profile("globex", intruder.id).await.is_some());
+    }
+
+    /// Treats `admin-token` as an admin of the default tenant and refuses anything else
+    struct StaticAdmin(Uuid);
+
+    #[async_trait]
+    impl RoleChecker for StaticAdmin {
+        async fn require_role(&self, token: &str, role: &str) -> Result<Claims> {
+            if token != "admin-token" {
+                return Err(ServiceError::forbidden(format!("Requires the '{}' role", role)).into());
+            }
+            Ok(Claims {
+                sub: self.0,
+                tenant_id: DEFAULT_TENANT.to_string(),
+                exp: (Utc::now() + chrono::Duration::hours(1)).timestamp(),
+                iat: Utc::now().timestamp(),
+                jti: Uuid::new_v4(),
+                roles: vec![role.to_string()],
+                scopes: Vec::new(),
+                session_version: 0,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn listing_profiles_checks_admins_with_any_role_checker() {
+        let service = ProfileService::new().await.unwrap().with_auth(Arc::new(StaticAdmin(Uuid::new_v4())));
+        for n in 0..3 {
+            service.create_profile(DEFAULT_TENANT.to_string(), user(&format!("member_{}", n))).await.unwrap();
+        }
+
+        let page = service.list_profiles("admin-token".to_string(), 0, 10).await.unwrap();
+        assert_eq!(page.total, 3);
+        let err = service.list_profiles("member-token".to_string(), 0, 10).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Forbidden(_)));
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::BTreeSet;
+use std::ops::Bound;
+use uuid::Uuid;
+
+/// Unicode case-folded form of a display name, used for matching
+pub fn fold(name: &str) -> String {
+    caseless::default_case_fold_str(name.trim())
+}
+
+/// Display names folded and ordered per tenant for `search_profiles`.
+///
+/// Prefix matches are a range scan; substring matches only walk the tenant's
+/// folded names rather than every stored profile.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    // (tenant, folded display name, profile id)
+    entries: BTreeSet<(String, String, Uuid)>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, tenant_id: &str, display_name: &str, profile_id: Uuid) {
+        self.entries.insert((tenant_id.to_string(), fold(display_name), profile_id));
+    }
+
+    pub fn remove(&mut self, tenant_id: &str, display_name: &str, profile_id: Uuid) {
+        self.entries.remove(&(tenant_id.to_string(), fold(display_name), profile_id));
+    }
+
+    /// Profile ids whose display name contains `query`: prefix matches first,
+    /// then other substring matches, each group in name order
+    pub fn search(&self, tenant_id: &str, query: &str, limit: usize) -> Vec<Uuid> {
+        let query = fold(query);
+        if query.is_empty() || limit == 0 {
+            return Vec::new();
+        }
+
+        let tenant = tenant_id.to_string();
+        let start = (tenant.clone(), query.clone(), Uuid::nil());
+        let mut results: Vec<Uuid> = self
+            .entries
+            .range((Bound::Included(start), Bound::Unbounded))
+            .take_while(|(t, name, _)| *t == tenant && name.starts_with(&query))
+            .take(limit)
+            .map(|(_, _, id)| *id)
+            .collect();
+
+        if results.len() < limit {
+            let tenant_start = (tenant.clone(), String::new(), Uuid::nil());
+            let substring = self
+                .entries
+                .range((Bound::Included(tenant_start), Bound::Unbounded))
+                .take_while(|(t, _, _)| *t == tenant)
+                .filter(|(_, name, _)| !name.starts_with(&query) && name.contains(&query))
+                .take(limit - results.len())
+                .map(|(_, _, id)| *id);
+            results.extend(substring);
+        }
+
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index_of(names: &[&str]) -> (SearchIndex, Vec<Uuid>) {
+        let mut index = SearchIndex::new();
+        let ids: Vec<Uuid> = names.iter().map(|_| Uuid::new_v4()).collect();
+        for (name, id) in names.iter().zip(&ids) {
+            index.insert("acme", name, *id);
+        }
+        (index, ids)
+    }
+
+    #[test]
+    fn prefix_matches_rank_ahead_of_substring_matches() {
+        let (index, ids) = index_of(&["Joanna", "Hanna Lind", "Annabel Lee", "anna berg", "Bob"]);
+
+        let results = index.search("acme", "ANNA", 10);
+
+        // Prefix matches in name order, then substring matches in name order
+        assert_eq!(results, vec![ids[3], ids[2], ids[1], ids[0]]);
+        assert_eq!(index.search("acme", "anna", 3), vec![ids[3], ids[2], ids[1]]);
+        assert!(index.search("other-tenant", "anna", 10).is_empty());
+    }
+
+    #[test]
+    fn matching_folds_unicode_case() {
+        let (mut index, ids) = index_of(&["Straße Müller", "ÉMILE ZOLA"]);
+
+        assert_eq!(index.search("acme", "STRASSE", 10), vec![ids[0]]);
+        assert_eq!(index.search("acme", "MÜLLER", 10), vec![ids[0]]);
+        assert_eq!(index.search("acme", "émile", 10), vec![ids[1]]);
+
+        // Entries are keyed on the folded name, so any casing removes them
+        index.remove("acme", "STRASSE MÜLLER", ids[0]);
+        assert!(index.search("acme", "strasse", 10).is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+use crate::ServiceError;
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Operation every `ActionService` answers, so callers can check a service is reachable
+pub const PING_OPERATION: &str = "ping";
+
+/// Named parameters of an action call
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActionParams(Map<String, Value>);
+
+impl ActionParams {
+    /// Parameters from a JSON object; `null` means no parameters
+    pub fn new(params: Value) -> Result<Self, ServiceError> {
+        match params {
+            Value::Object(fields) => Ok(Self(fields)),
+            Value::Null => Ok(Self::default()),
+            other => Err(ServiceError::validation(format!("Parameters must be an object, not {}", other))),
+        }
+    }
+    
+    /// Every parameter at once, e.g. a whole request struct
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T, ServiceError> {
+        serde_json::from_value(Value::Object(self.0.clone()))
+            .map_err(|e| ServiceError::validation(format!("Invalid parameters: {}", e)))
+    }
+    
+    /// A parameter that may be left out or `null`
+    pub fn get_json_optional<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>, ServiceError> {
+        match self.0.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => serde_json::from_value(value.clone())
+                .map(Some)
+                .map_err(|e| ServiceError::validation(format!("Invalid parameter '{}': {}", name, e))),
+        }
+    }
+    
+    pub fn get_json<T: DeserializeOwned>(&self, name: &str) -> Result<T, ServiceError> {
+        self.get_json_optional(name)?
+            .ok_or_else(|| ServiceError::validation(format!("Missing parameter '{}'", name)))
+    }
+    
+    pub fn get_string(&self, name: &str) -> Result<String, ServiceError> {
+        self.get_json(name)
+    }
+    
+    pub fn get_string_optional(&self, name: &str) -> Result<Option<String>, ServiceError> {
+        self.get_json_optional(name)
+    }
+    
+    pub fn get_f64(&self, name: &str) -> Result<f64, ServiceError> {
+        self.get_json(name)
+    }
+    
+    pub fn get_f64_optional(&self, name: &str) -> Result<Option<f64>, ServiceError> {
+        self.get_json_optional(name)
+    }
+}
+
+/// A service whose actions are called by operation name with named parameters.
+///
+/// The node registration wrapper and the gateway both call services through
+/// this, so every service registers and dispatches the same way whatever
+/// macros its actions are written with.
+#[async_trait]
+pub trait ActionService: Send + Sync + 'static {
+    /// Name the service is registered and routed under, e.g. `invoice`
+    fn name(&self) -> &str;
+    
+    fn description(&self) -> &str;
+    
+    /// Operations `call` accepts, besides `PING_OPERATION`
+    fn operations(&self) -> Vec<String>;
+    
+    /// Run `operation`; unknown operations fail with `ServiceError::NotFound`
+    async fn call(&self, operation: &str, params: ActionParams) -> anyhow::Result<Value>;
+    
+    /// `call`, answering `PING_OPERATION` the same way for every service
+    async fn handle(&self, operation: &str, params: ActionParams) -> anyhow::Result<Value> {
+        if operation == PING_OPERATION {
+            return Ok(Value::from("pong"));
+        }
+        self.call(operation, params).await
+    }
+}
+
+/// Error for an operation `service` doesn't have
+pub fn unknown_operation(service: &dyn ActionService, operation: &str) -> anyhow::Error {
+    let mut operations = service.operations();
+    operations.sort();
+    ServiceError::not_found(format!(
+        "Unknown operation '{}' on {}; supported operations: {}",
+        operation,
+        service.name(),
+        operations.join(", ")
+    ))
+    .into()
+}
+
+/// Serialize an action's result as the value `call` returns
+pub fn to_result<T: serde::Serialize>(value: T) -> anyhow::Result<Value> {
+    serde_json::to_value(value).map_err(|e| ServiceError::internal(format!("Failed to serialize result: {}", e)).into())
+}
+
+/// Services reachable by name.
+///
+/// Cloning is cheap and every clone sees the same services, so one registry
+/// can back both node registration and the gateway.
+#[derive(Clone, Default)]
+pub struct ServiceRegistry {
+    services: Arc<RwLock<HashMap<String, Arc<dyn ActionService>>>>,
+}
+
+impl ServiceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Add a service under its name; fails with `Conflict` if the name is taken
+    pub fn register(&self, service: Arc<dyn ActionService>) -> Result<(), ServiceError> {
+        let mut services = self.services.write().unwrap_or_else(|e| e.into_inner());
+        if services.contains_key(service.name()) {
+            return Err(ServiceError::conflict(format!("Service '{}' is already registered", service.name())));
+        }
+        services.insert(service.name().to_string(), service);
+        Ok(())
+    }
+    
+    pub fn get(&self, name: &str) -> Option<Arc<dyn ActionService>> {
+        self.services.read().unwrap_or_else(|e| e.into_inner()).get(name).cloned()
+    }
+    
+    /// Names of the registered services, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.services.read().unwrap_or_else(|e| e.into_inner()).keys().cloned().collect();
+        names.sort();
+        names
+    }
+    
+    /// Call `operation` on the service registered as `service`
+    pub async fn call(&self, service: &str, operation: &str, params: Value) -> anyhow::Result<Value> {
+        let target = self.get(service)
+            .ok_or_else(|| ServiceError::not_found(format!("No service named '{}'", service)))?;
+        target.handle(operation, ActionParams::new(params)?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    struct Echo;
+    
+    #[async_trait]
+    impl ActionService for Echo {
+        fn name(&self) -> &str {
+            "echo"
+        }
+        
+        fn description(&self) -> &str {
+            "Echoes its parameters"
+        }
+        
+        fn operations(&self) -> Vec<String> {
+            vec!["echo".to_string()]
+        }
+        
+        async fn call(&self, operation: &str, params: ActionParams) -> anyhow::Result<Value> {
+            match operation {
+                "echo" => Ok(Value::from(params.get_string("text")?)),
+                _ => Err(unknown_operation(self, operation)),
+            }
+        }
+    }
+    
+    #[tokio::test]
+    async fn registry_dispatches_by_service_and_operation() {
+        let registry = ServiceRegistry::new();
+        registry.register(Arc::new(Echo)).unwrap();
+        
+        assert_eq!(registry.call("echo", PING_OPERATION, Value::Null).await.unwrap(), json!("pong"));
+        assert_eq!(registry.call("echo", "echo", json!({ "text": "hi" })).await.unwrap(), json!("hi"));
+        
+        let missing = registry.call("echo", "echo", json!({})).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&missing), ServiceError::Validation(_)));
+        let unknown = registry.call("echo", "shout", json!({})).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&unknown), ServiceError::NotFound(_)));
+        let unregistered = registry.call("nobody", PING_OPERATION, json!({})).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&unregistered), ServiceError::NotFound(_)));
+    }
+    
+    #[test]
+    fn services_register_once_per_name() {
+        let registry = ServiceRegistry::new();
+        registry.register(Arc::new(Echo)).unwrap();
+        
+        assert!(matches!(registry.register(Arc::new(Echo)), Err(ServiceError::Conflict(_))));
+        assert_eq!(registry.names(), vec!["echo".to_string()]);
+    }
+}
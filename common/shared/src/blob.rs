@@ -0,0 +1,134 @@
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// A stored binary object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blob {
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Pluggable storage for binary objects such as attachments and uploads.
+///
+/// Keys are opaque, `/`-separated paths chosen by the caller.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Store `blob` under `key`, replacing anything already there
+    async fn put(&self, key: &str, blob: Blob) -> anyhow::Result<()>;
+    
+    /// Fetch the blob stored under `key`
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Blob>>;
+    
+    /// Remove the blob stored under `key`, returning whether it existed
+    async fn delete(&self, key: &str) -> anyhow::Result<bool>;
+}
+
+/// `BlobStore` keeping everything in process memory
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryBlobStore {
+    blobs: Arc<RwLock<HashMap<String, Blob>>>,
+}
+
+impl InMemoryBlobStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl BlobStore for InMemoryBlobStore {
+    async fn put(&self, key: &str, blob: Blob) -> anyhow::Result<()> {
+        self.blobs.write().await.insert(key.to_string(), blob);
+        Ok(())
+    }
+    
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Blob>> {
+        Ok(self.blobs.read().await.get(key).cloned())
+    }
+    
+    async fn delete(&self, key: &str) -> anyhow::Result<bool> {
+        Ok(self.blobs.write().await.remove(key).is_some())
+    }
+}
+
+/// `BlobStore` writing each blob to a file under a root directory.
+///
+/// The content type is kept in a `.content-type` file next to the data.
+#[derive(Debug, Clone)]
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+    
+    /// The file holding `key`'s data; keys that would escape the root are rejected
+    fn path_for(&self, key: &str) -> anyhow::Result<PathBuf> {
+        let relative = Path::new(key);
+        let is_plain = !key.is_empty()
+            && relative.components().all(|c| matches!(c, Component::Normal(_)));
+        if !is_plain {
+            return Err(anyhow!("Invalid blob key '{}'", key));
+        }
+        Ok(self.root.join(relative))
+    }
+    
+    fn content_type_path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".content-type");
+        PathBuf::from(name)
+    }
+}
+
+/// Treat a missing file as `None` rather than an error
+fn not_found_as_none<T>(result: std::io::Result<T>) -> std::io::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, key: &str, blob: Blob) -> anyhow::Result<()> {
+        let path = self.path_for(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        tokio::fs::write(&path, &blob.data)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        tokio::fs::write(Self::content_type_path(&path), blob.content_type.as_bytes())
+            .await
+            .with_context(|| format!("Failed to write content type for {}", path.display()))?;
+        Ok(())
+    }
+    
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Blob>> {
+        let path = self.path_for(key)?;
+        let data = match not_found_as_none(tokio::fs::read(&path).await)? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let content_type = not_found_as_none(tokio::fs::read_to_string(Self::content_type_path(&path)).await)?
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        Ok(Some(Blob { content_type, data }))
+    }
+    
+    async fn delete(&self, key: &str) -> anyhow::Result<bool> {
+        let path = self.path_for(key)?;
+        let existed = not_found_as_none(tokio::fs::remove_file(&path).await)?.is_some();
+        not_found_as_none(tokio::fs::remove_file(Self::content_type_path(&path)).await)?;
+        Ok(existed)
+    }
+}
@@ -0,0 +1,75 @@
+use crate::ServiceError;
+use serde::{Deserialize, Serialize};
+
+/// Outcome of one item in a bulk operation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BulkOutcome<T> {
+    Ok { value: T },
+    Error { error: ServiceError },
+}
+
+/// One item of a bulk result, tagged with its position in the input
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkItem<T> {
+    pub index: usize,
+    #[serde(flatten)]
+    pub outcome: BulkOutcome<T>,
+}
+
+/// Per-item results of a bulk operation, in input order.
+///
+/// Serializes as `{ "succeeded": n, "failed": m, "items": [{ "index", "status", "value" | "error" }] }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkResult<T> {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub items: Vec<BulkItem<T>>,
+}
+
+impl<T> Default for BulkResult<T> {
+    fn default() -> Self {
+        Self {
+            succeeded: 0,
+            failed: 0,
+            items: Vec::new(),
+        }
+    }
+}
+
+impl<T> BulkResult<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Record the outcome of the next input item
+    pub fn push(&mut self, result: Result<T, ServiceError>) {
+        let index = self.items.len();
+        let outcome = match result {
+            Ok(value) => {
+                self.succeeded += 1;
+                BulkOutcome::Ok { value }
+            },
+            Err(error) => {
+                self.failed += 1;
+                BulkOutcome::Error { error }
+            },
+        };
+        self.items.push(BulkItem { index, outcome });
+    }
+    
+    /// Whether every item succeeded
+    pub fn all_succeeded(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+impl<T> FromIterator<Result<T, ServiceError>> for BulkResult<T> {
+    fn from_iter<I: IntoIterator<Item = Result<T, ServiceError>>>(iter: I) -> Self {
+        let mut result = Self::new();
+        for item in iter {
+            result.push(item);
+        }
+        result
+    }
+}
@@ -0,0 +1,86 @@
+use crate::signing::HmacSigner;
+use crate::ServiceError;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Signs and verifies opaque pagination cursors.
+///
+/// A cursor is `base64(payload).base64(hmac(payload))`, so clients can pass it
+/// back but cannot alter the offset or scope it encodes without detection.
+#[derive(Clone)]
+pub struct CursorSigner {
+    signer: HmacSigner,
+}
+
+impl CursorSigner {
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self {
+            signer: HmacSigner::new(secret),
+        }
+    }
+    
+    /// Encode and sign a cursor payload
+    pub fn sign<T: Serialize>(&self, payload: &T) -> anyhow::Result<String> {
+        let json = serde_json::to_vec(payload)?;
+        Ok(format!("{}.{}", URL_SAFE_NO_PAD.encode(&json), self.signer.sign(&json)))
+    }
+    
+    /// Verify a cursor's signature and decode its payload.
+    ///
+    /// Malformed or tampered cursors yield `ServiceError::Validation`.
+    pub fn verify<T: DeserializeOwned>(&self, cursor: &str) -> Result<T, ServiceError> {
+        let invalid = || ServiceError::validation("Invalid pagination cursor");
+        
+        let (payload, signature) = cursor.split_once('.').ok_or_else(invalid)?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| invalid())?;
+        if !self.signer.verify(&payload, signature) {
+            return Err(invalid());
+        }
+        
+        serde_json::from_slice(&payload).map_err(|_| invalid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Position {
+        user_id: String,
+        offset: usize,
+    }
+    
+    fn position() -> Position {
+        Position {
+            user_id: "alice".to_string(),
+            offset: 40,
+        }
+    }
+    
+    #[test]
+    fn signed_cursor_round_trips() {
+        let signer = CursorSigner::new("cursor-secret");
+        
+        let cursor = signer.sign(&position()).unwrap();
+        
+        assert_eq!(signer.verify::<Position>(&cursor).unwrap(), position());
+    }
+    
+    #[test]
+    fn tampered_cursor_is_rejected() {
+        let signer = CursorSigner::new("cursor-secret");
+        let cursor = signer.sign(&position()).unwrap();
+        let (_, signature) = cursor.split_once('.').unwrap();
+        
+        let moved = Position { offset: 0, ..position() };
+        let forged_payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&moved).unwrap());
+        let forged = format!("{}.{}", forged_payload, signature);
+        assert!(matches!(signer.verify::<Position>(&forged), Err(ServiceError::Validation(_))));
+        
+        let other_key = CursorSigner::new("another-secret").sign(&moved).unwrap();
+        assert!(signer.verify::<Position>(&other_key).is_err());
+        assert!(signer.verify::<Position>("not-a-cursor").is_err());
+    }
+}
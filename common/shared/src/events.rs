@@ -0,0 +1,227 @@
+use crate::ServiceError;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Default number of events buffered per topic before slow subscribers lag
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// Kind of the control event delivered to a subscriber that fell behind
+pub const RESYNC_KIND: &str = "resync";
+
+/// A typed event with a fixed topic and kind, published with `EventBus::emit`.
+///
+/// Events concerning tenant data carry a `tenant_id` field so the gateway only
+/// pushes them to that tenant's WebSocket clients.
+pub trait DomainEvent: Serialize + DeserializeOwned {
+    const TOPIC: &'static str;
+    const KIND: &'static str;
+}
+
+/// An event published on the bus
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    /// Topic the event was published on, e.g. `invoice`
+    pub topic: String,
+    /// What happened, e.g. `created`
+    pub kind: String,
+    /// Event data
+    pub payload: serde_json::Value,
+}
+
+impl Event {
+    /// Control event telling a subscriber it missed `missed` events on `topic`
+    pub fn resync(topic: &str, missed: u64) -> Self {
+        Self {
+            topic: topic.to_string(),
+            kind: RESYNC_KIND.to_string(),
+            payload: serde_json::json!({ "missed": missed }),
+        }
+    }
+    
+    /// Decode the payload as `E`, or `None` if this is a different kind of event
+    pub fn parse<E: DomainEvent>(&self) -> Option<serde_json::Result<E>> {
+        if self.topic != E::TOPIC || self.kind != E::KIND {
+            return None;
+        }
+        Some(serde_json::from_value(self.payload.clone()))
+    }
+    
+    /// Number of missed events when this is a resync notice
+    pub fn missed(&self) -> Option<u64> {
+        if self.kind != RESYNC_KIND {
+            return None;
+        }
+        self.payload.get("missed").and_then(|missed| missed.as_u64())
+    }
+}
+
+/// A subscriber's view of one topic.
+///
+/// Slow subscribers never get disconnected: once one falls more than the bus
+/// capacity behind, the overwritten events are replaced by a single `resync`
+/// event and delivery carries on from the oldest event still buffered.
+#[derive(Debug)]
+pub struct Subscription {
+    topic: String,
+    receiver: broadcast::Receiver<Event>,
+}
+
+impl Subscription {
+    /// Wait for the next event, returning `None` once the bus has been dropped
+    pub async fn recv(&mut self) -> Option<Event> {
+        match self.receiver.recv().await {
+            Ok(event) => Some(event),
+            Err(RecvError::Lagged(missed)) => Some(Event::resync(&self.topic, missed)),
+            Err(RecvError::Closed) => None,
+        }
+    }
+}
+
+/// In-process pub/sub bus with one `broadcast` channel per topic.
+///
+/// Cloning is cheap and every clone shares the same channels, so one bus can be
+/// handed to all services and to the gateway.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    channels: Arc<RwLock<HashMap<String, broadcast::Sender<Event>>>>,
+    capacity: usize,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+    
+    /// Create a bus buffering up to `capacity` events per topic
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            channels: Arc::new(RwLock::new(HashMap::new())),
+            capacity,
+        }
+    }
+    
+    fn sender(&self, topic: &str) -> broadcast::Sender<Event> {
+        if let Some(sender) = self.channels.read().unwrap_or_else(|e| e.into_inner()).get(topic) {
+            return sender.clone();
+        }
+        
+        self.channels
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(topic.to_string())
+            .or_insert_with(|| broadcast::channel(self.capacity).0)
+            .clone()
+    }
+    
+    /// Publish an event, returning how many subscribers received it
+    pub fn publish(&self, topic: &str, kind: &str, payload: serde_json::Value) -> usize {
+        let event = Event {
+            topic: topic.to_string(),
+            kind: kind.to_string(),
+            payload,
+        };
+        
+        // Sending only fails when nobody is subscribed, which is fine
+        self.sender(topic).send(event).unwrap_or(0)
+    }
+    
+    /// Serialize `payload` and publish it
+    pub fn publish_json<T: Serialize>(&self, topic: &str, kind: &str, payload: &T) -> Result<usize, ServiceError> {
+        let payload = serde_json::to_value(payload)
+            .map_err(|e| ServiceError::internal(format!("Failed to serialize event: {}", e)))?;
+        Ok(self.publish(topic, kind, payload))
+    }
+    
+    /// Publish a typed event on its own topic
+    pub fn emit<E: DomainEvent>(&self, event: &E) -> Result<usize, ServiceError> {
+        self.publish_json(E::TOPIC, E::KIND, event)
+    }
+    
+    /// Subscribe to every event published on `topic` from now on
+    pub fn subscribe(&self, topic: &str) -> Subscription {
+        Subscription {
+            topic: topic.to_string(),
+            receiver: self.sender(topic).subscribe(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct InvoiceSent {
+        invoice_id: String,
+    }
+    
+    impl DomainEvent for InvoiceSent {
+        const TOPIC: &'static str = "invoice";
+        const KIND: &'static str = "invoice_sent";
+    }
+    
+    #[tokio::test]
+    async fn events_reach_every_subscriber_of_their_topic_only() {
+        let bus = EventBus::new();
+        let mut first = bus.subscribe("invoice");
+        let mut second = bus.clone().subscribe("invoice");
+        let mut profiles = bus.subscribe("profile");
+        
+        assert_eq!(bus.publish("invoice", "invoice_created", json!({ "id": 1 })), 2);
+        
+        for subscription in [&mut first, &mut second] {
+            let event = subscription.recv().await.unwrap();
+            assert_eq!(event.topic, "invoice");
+            assert_eq!(event.kind, "invoice_created");
+            assert_eq!(event.payload, json!({ "id": 1 }));
+        }
+        assert!(profiles.receiver.try_recv().is_err());
+    }
+    
+    #[tokio::test]
+    async fn typed_events_round_trip() {
+        let bus = EventBus::new();
+        let mut invoices = bus.subscribe(InvoiceSent::TOPIC);
+        let sent = InvoiceSent { invoice_id: "inv-1".to_string() };
+        
+        assert_eq!(bus.emit(&sent).unwrap(), 1);
+        
+        let event = invoices.recv().await.unwrap();
+        assert_eq!(event.parse::<InvoiceSent>().unwrap().unwrap(), sent);
+        assert_eq!(bus.publish("nobody-listens", "noise", json!(null)), 0);
+    }
+    
+    #[tokio::test]
+    async fn lagging_subscriber_gets_a_resync_notice_and_keeps_receiving() {
+        let bus = EventBus::with_capacity(4);
+        let mut slow = bus.subscribe("invoice");
+        for seq in 0..10 {
+            bus.publish("invoice", "invoice_created", json!({ "seq": seq }));
+        }
+        
+        let notice = slow.recv().await.unwrap();
+        assert_eq!(notice.kind, RESYNC_KIND);
+        assert_eq!(notice.topic, "invoice");
+        assert_eq!(notice.missed(), Some(6));
+        
+        // Delivery resumes from the oldest event still buffered
+        for seq in 6..10 {
+            let event = slow.recv().await.unwrap();
+            assert_eq!(event.payload["seq"], seq);
+            assert_eq!(event.missed(), None);
+        }
+        
+        bus.publish("invoice", "invoice_created", json!({ "seq": 10 }));
+        assert_eq!(slow.recv().await.unwrap().payload["seq"], 10);
+    }
+}
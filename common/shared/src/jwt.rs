@@ -0,0 +1,32 @@
+use crate::ServiceError;
+
+/// Environment variable holding the secret access tokens are signed with
+pub const JWT_SECRET_ENV: &str = "JWT_SECRET";
+
+/// The secret `AuthService` signs access tokens with and the gateway verifies them with.
+///
+/// Both load it from here, so a token the auth service issues is always one
+/// the gateway's scope checks accept.
+pub fn jwt_secret_from_env() -> Result<String, ServiceError> {
+    non_empty_secret(std::env::var(JWT_SECRET_ENV).ok())
+}
+
+/// Refuse a missing or empty secret, which would let anyone sign tokens
+fn non_empty_secret(secret: Option<String>) -> Result<String, ServiceError> {
+    match secret {
+        Some(secret) if !secret.is_empty() => Ok(secret),
+        _ => Err(ServiceError::validation(format!("{} must be set to a non-empty secret", JWT_SECRET_ENV))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn missing_or_empty_secrets_are_refused() {
+        assert_eq!(non_empty_secret(Some("s3cret".to_string())).unwrap(), "s3cret");
+        assert!(matches!(non_empty_secret(Some(String::new())), Err(ServiceError::Validation(_))));
+        assert!(matches!(non_empty_secret(None), Err(ServiceError::Validation(_))));
+    }
+}
@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub mod actions;
+pub mod blob;
+pub mod bulk;
+pub mod cursor;
+pub mod events;
+pub mod internal;
+pub mod jwt;
+pub mod scopes;
+pub mod signing;
+
+pub use actions::{to_result, unknown_operation, ActionParams, ActionService, ServiceRegistry, PING_OPERATION};
+pub use blob::{Blob, BlobStore, InMemoryBlobStore, LocalBlobStore};
+pub use bulk::{BulkItem, BulkOutcome, BulkResult};
+pub use cursor::CursorSigner;
+pub use events::{DomainEvent, Event, EventBus, Subscription, RESYNC_KIND};
+pub use internal::{InternalAuth, INTERNAL_TOKEN_PARAM};
+pub use jwt::{jwt_secret_from_env, JWT_SECRET_ENV};
+pub use scopes::{has_scope, scope_matches};
+pub use signing::{constant_time_eq, HmacSigner};
+
+/// Structured error returned by service actions.
+///
+/// Services wrap these in `anyhow::Error`; the gateway downcasts them to pick
+/// the HTTP status for the response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "message")]
+pub enum ServiceError {
+    /// The requested entity does not exist
+    NotFound(String),
+    /// The request was malformed or failed validation
+    Validation(String),
+    /// The caller is not authenticated
+    Unauthorized(String),
+    /// The caller is authenticated but not allowed to do this
+    Forbidden(String),
+    /// The request conflicts with the current state
+    Conflict(String),
+    /// A conditional request's precondition (e.g. an expected version) did not hold
+    PreconditionFailed(String),
+    /// The resource existed but is no longer available (e.g. an expired link)
+    Gone(String),
+    /// A backend the request depends on is down or refusing calls for now
+    Unavailable(String),
+    /// Anything else
+    Internal(String),
+}
+
+impl ServiceError {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::NotFound(message.into())
+    }
+    
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::Validation(message.into())
+    }
+    
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::Unauthorized(message.into())
+    }
+    
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::Forbidden(message.into())
+    }
+    
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::Conflict(message.into())
+    }
+    
+    pub fn precondition_failed(message: impl Into<String>) -> Self {
+        Self::PreconditionFailed(message.into())
+    }
+    
+    pub fn gone(message: impl Into<String>) -> Self {
+        Self::Gone(message.into())
+    }
+    
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        Self::Unavailable(message.into())
+    }
+    
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::Internal(message.into())
+    }
+    
+    /// Recover the structured error from an `anyhow::Error`, treating anything else as internal
+    pub fn from_anyhow(err: &anyhow::Error) -> Self {
+        match err.downcast_ref::<ServiceError>() {
+            Some(service_error) => service_error.clone(),
+            None => Self::Internal(err.to_string()),
+        }
+    }
+    
+    /// Human-readable message without the variant name
+    pub fn message(&self) -> &str {
+        match self {
+            Self::NotFound(m)
+            | Self::Validation(m)
+            | Self::Unauthorized(m)
+            | Self::Forbidden(m)
+            | Self::Conflict(m)
+            | Self::PreconditionFailed(m)
+            | Self::Gone(m)
+            | Self::Unavailable(m)
+            | Self::Internal(m) => m,
+        }
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for ServiceError {}
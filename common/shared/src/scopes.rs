@@ -0,0 +1,24 @@
+/// Whether a granted scope covers a required one.
+///
+/// Scopes are `:`-separated paths. A trailing `*` segment in the granted scope
+/// matches the rest of the path, so `invoices:*` covers `invoices:write` and
+/// `invoices:write:bulk`, and a bare `*` covers everything.
+pub fn scope_matches(granted: &str, required: &str) -> bool {
+    let granted: Vec<&str> = granted.split(':').collect();
+    let required: Vec<&str> = required.split(':').collect();
+    
+    for (i, segment) in granted.iter().enumerate() {
+        if *segment == "*" && i == granted.len() - 1 {
+            return required.len() > i;
+        }
+        if required.get(i) != Some(segment) {
+            return false;
+        }
+    }
+    granted.len() == required.len()
+}
+
+/// Whether any of the granted scopes covers `required`
+pub fn has_scope<S: AsRef<str>>(granted: &[S], required: &str) -> bool {
+    granted.iter().any(|scope| scope_matches(scope.as_ref(), required))
+}
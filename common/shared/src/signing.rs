@@ -0,0 +1,50 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// HMAC-SHA256 signer producing URL-safe base64 signatures
+#[derive(Clone)]
+pub struct HmacSigner {
+    secret: Vec<u8>,
+}
+
+impl HmacSigner {
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self {
+            secret: secret.as_ref().to_vec(),
+        }
+    }
+    
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length")
+    }
+    
+    /// Sign a message, returning the signature as URL-safe base64
+    pub fn sign(&self, message: &[u8]) -> String {
+        let mut mac = self.mac();
+        mac.update(message);
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    }
+    
+    /// Check a signature produced by `sign`, in constant time
+    pub fn verify(&self, message: &[u8], signature: &str) -> bool {
+        let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+            return false;
+        };
+        
+        let mut mac = self.mac();
+        mac.update(message);
+        mac.verify_slice(&signature).is_ok()
+    }
+}
+
+/// Compare two secrets, such as a presented and a configured token, in constant time.
+///
+/// Fixed-length MACs of the values are compared, so neither the position of the
+/// first difference nor a difference in length shows in the timing.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let signer = HmacSigner::new(b"constant-time-eq");
+    signer.verify(b, &signer.sign(a))
+}
@@ -1,9 +1,14 @@
 use anyhow::Result;
-use runar_macros::main;
-use runar_node::node::{Node, NodeConfig};
-use common::services::auth::AuthService;
-use common::services::profile::ProfileService;
-use crate::services::invoice::InvoiceService;
+use auth_service::{AuthConfig, AuthService};
+use kagi_gateway::registry::NodeService;
+use kagi_gateway::{start_gateway_with_handle, AuthConfig as GatewayAuthConfig, GatewayConfig};
+use kagi_macros::main;
+use kagi_node::node::{Node, NodeConfig};
+use kagi_shared::{EventBus, ServiceRegistry};
+use profile_service::ProfileService;
+use crate::services::email::SmtpMailer;
+use crate::services::invoice::{InvoiceService, EVENT_TOPIC as INVOICE_EVENT_TOPIC};
+use std::sync::Arc;
 
 mod services;
 
@@ -18,17 +23,80 @@ async fn main() -> Result<()> {
     // Create and initialize node
     let mut node = Node::new(config);
 
-    // Register services using the proper add_service method
-    node.add_service(AuthService::new()).await?;
-    node.add_service(ProfileService::new()).await?;
-    node.add_service(InvoiceService::new()).await?;
+    // One bus shared by every service, so auth events such as `users_merged`
+    // reach the services owning per-user data
+    let events = EventBus::new();
+    // Every service is fully constructed, then registered the same way. Auth
+    // signs tokens with the `JWT_SECRET` the gateway verifies them with
+    let auth = Arc::new(AuthService::new(AuthConfig::from_env()?).await?.with_event_bus(events.clone()));
+    // Profiles checks admin-only actions against the tokens auth issues
+    let profiles = ProfileService::new().await?.with_event_bus(events.clone()).with_auth(auth.clone());
+    profiles.spawn_merge_listener();
+    let mut invoices = InvoiceService::new().await?.with_event_bus(events.clone());
+    if let Ok(smtp_host) = std::env::var("SMTP_HOST") {
+        let mailer = SmtpMailer::new(
+            &smtp_host,
+            std::env::var("SMTP_USERNAME").unwrap_or_default(),
+            std::env::var("SMTP_PASSWORD").unwrap_or_default(),
+            &std::env::var("SMTP_FROM").map_err(|_| anyhow::anyhow!("SMTP_FROM must be set with SMTP_HOST"))?,
+        )?;
+        invoices = invoices.with_mailer(Arc::new(mailer));
+    }
+    invoices.spawn_merge_listener();
+
+    let services = register_services(auth, profiles, invoices)?;
+    for service in NodeService::all(&services) {
+        node.add_service(service).await?;
+    }
 
     // Start the node
     node.start().await?;
 
+    // The gateway calls the same services, and pushes invoice events to the
+    // WebSocket clients of the users they concern
+    let gateway_config = GatewayConfig::builder()
+        .port(8080)
+        // Scope checks verify tokens with the secret the auth service signs them with
+        .with_auth_config(GatewayAuthConfig::from_env()?)
+        .push_events(events.clone(), &[INVOICE_EVENT_TOPIC])
+        .build();
+    let gateway = start_gateway_with_handle(services.clone(), gateway_config).await?;
+
     // Keep the application running
     tokio::signal::ctrl_c().await?;
     println!("Shutting down...");
+    gateway.shutdown().await?;
 
     Ok(())
-} 
\ No newline at end of file
+} 
+
+/// Put every service in one registry, which both the node and the gateway call through
+fn register_services(auth: Arc<AuthService>, profiles: ProfileService, invoices: InvoiceService) -> Result<ServiceRegistry> {
+    let services = ServiceRegistry::new();
+    services.register(auth)?;
+    services.register(Arc::new(profiles))?;
+    services.register(Arc::new(invoices))?;
+    Ok(services)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kagi_shared::PING_OPERATION;
+
+    #[tokio::test]
+    async fn every_service_registers_and_answers_ping() {
+        let auth = Arc::new(AuthService::new(AuthConfig::new("test-secret-key")).await.unwrap());
+        let profiles = ProfileService::new().await.unwrap().with_auth(auth.clone());
+        let invoices = InvoiceService::new().await.unwrap();
+
+        let services = register_services(auth, profiles, invoices).unwrap();
+
+        assert_eq!(services.names(), vec!["auth", "invoice", "profile"]);
+        for name in services.names() {
+            let pong = services.call(&name, PING_OPERATION, serde_json::Value::Null).await.unwrap();
+            assert_eq!(pong, "pong", "{} did not answer ping", name);
+        }
+        assert_eq!(NodeService::all(&services).len(), 3);
+    }
+}
@@ -0,0 +1,39 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+
+/// Source of exchange rates used to convert invoice totals
+#[async_trait]
+pub trait ExchangeRateProvider: Send + Sync {
+    /// Rate to multiply an amount in `from` by to get `to`, or `None` if unknown
+    async fn rate(&self, from: &str, to: &str) -> Result<Option<f64>>;
+}
+
+/// Exchange rates from a fixed table, keyed by (from, to) currency codes
+#[derive(Debug, Clone, Default)]
+pub struct StaticExchangeRates {
+    rates: HashMap<(String, String), f64>,
+}
+
+impl StaticExchangeRates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a rate for converting `from` into `to`
+    pub fn with_rate(mut self, from: &str, to: &str, rate: f64) -> Self {
+        self.rates.insert((from.to_uppercase(), to.to_uppercase()), rate);
+        self
+    }
+}
+
+#[async_trait]
+impl ExchangeRateProvider for StaticExchangeRates {
+    async fn rate(&self, from: &str, to: &str) -> Result<Option<f64>> {
+        let (from, to) = (from.to_uppercase(), to.to_uppercase());
+        if from == to {
+            return Ok(Some(1.0));
+        }
+        Ok(self.rates.get(&(from, to)).copied())
+    }
+}
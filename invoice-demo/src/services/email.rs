@@ -0,0 +1,135 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment as MailAttachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use super::invoice::Invoice;
+
+/// A rendered email ready to hand to a `Mailer`
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub html_body: String,
+    pub attachments: Vec<EmailAttachment>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EmailAttachment {
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Delivers emails on behalf of `InvoiceService`
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<()>;
+}
+
+/// Sends email through an SMTP relay over TLS
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(host: &str, username: impl Into<String>, password: impl Into<String>, from: &str) -> Result<Self> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(host)?
+            .credentials(Credentials::new(username.into(), password.into()))
+            .build();
+        let from = from
+            .parse()
+            .map_err(|e| anyhow!("Invalid sender address '{}': {}", from, e))?;
+        Ok(Self { transport, from })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> Result<()> {
+        let to: Mailbox = email
+            .to
+            .parse()
+            .map_err(|e| anyhow!("Invalid recipient address '{}': {}", email.to, e))?;
+
+        let mut body = MultiPart::mixed().singlepart(SinglePart::html(email.html_body));
+        for attachment in email.attachments {
+            let content_type = ContentType::parse(&attachment.content_type)
+                .map_err(|e| anyhow!("Invalid attachment type '{}': {}", attachment.content_type, e))?;
+            body = body.singlepart(MailAttachment::new(attachment.filename).body(attachment.data, content_type));
+        }
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(email.subject)
+            .multipart(body)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// Escape text for inclusion in HTML
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Render the email sent to an invoice's customer: a summary with the line items and totals
+pub fn render_invoice_email(invoice: &Invoice) -> Email {
+    let money = |amount: f64| format!("{:.2} {}", amount, escape_html(&invoice.currency));
+
+    let mut rows = String::new();
+    for item in &invoice.items {
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(&item.description),
+            item.quantity,
+            money(item.unit_price),
+            money(item.amount),
+        ));
+    }
+
+    let notes = invoice
+        .notes
+        .as_deref()
+        .map(|notes| format!("<p>{}</p>", escape_html(notes)))
+        .unwrap_or_default();
+
+    let html_body = format!(
+        "<html><body>\
+         <p>Dear {customer},</p>\
+         <p>Please find invoice #{number} below, due on {due}.</p>\
+         <table><tr><th>Description</th><th>Qty</th><th>Unit price</th><th>Amount</th></tr>{rows}</table>\
+         <p>Subtotal: {subtotal}<br>Tax: {tax}<br><strong>Total: {total}</strong></p>\
+         {notes}\
+         </body></html>",
+        customer = escape_html(&invoice.customer_name),
+        number = invoice.invoice_number,
+        due = invoice.due_date.format("%Y-%m-%d"),
+        rows = rows,
+        subtotal = money(invoice.subtotal),
+        tax = money(invoice.tax_amount),
+        total = money(invoice.total),
+        notes = notes,
+    );
+
+    Email {
+        to: invoice.customer_email.clone(),
+        subject: format!("Invoice #{}", invoice.invoice_number),
+        html_body,
+        attachments: Vec::new(),
+    }
+}
@@ -0,0 +1,29 @@
+use anyhow::{anyhow, Result};
+use super::invoice::Invoice;
+
+/// Columns of the CSV export, in order
+const CSV_HEADER: [&str; 7] = ["id", "customer", "subtotal", "tax", "total", "status", "due_date"];
+
+/// Render invoices as CSV, one row per invoice after a header row.
+///
+/// Amounts are written with two decimals and `due_date` as RFC 3339; the `csv`
+/// writer quotes any field containing commas, quotes or newlines.
+pub fn invoices_to_csv(invoices: &[Invoice]) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(CSV_HEADER)?;
+
+    for invoice in invoices {
+        writer.write_record([
+            invoice.id.clone(),
+            invoice.customer_name.clone(),
+            format!("{:.2}", invoice.subtotal),
+            format!("{:.2}", invoice.tax_amount),
+            format!("{:.2}", invoice.total),
+            format!("{:?}", invoice.status),
+            invoice.due_date.to_rfc3339(),
+        ])?;
+    }
+
+    let bytes = writer.into_inner().map_err(|e| anyhow!("Failed to write CSV: {}", e))?;
+    Ok(String::from_utf8(bytes)?)
+}
@@ -1,40 +1,164 @@
 use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use chrono::{DateTime, Utc};
-use kagi_macros::{service, action};
-use kagi_node::services::{ServiceRequest, ServiceResponse, RequestContext};
+use async_trait::async_trait;
+use auth_service::{UsersMerged, USERS_MERGED_EVENT};
+use kagi_shared::{
+    to_result, unknown_operation, ActionParams, ActionService, Blob, BlobStore, BulkResult, CursorSigner, DomainEvent, EventBus,
+    HmacSigner, InMemoryBlobStore, InternalAuth, ServiceError, INTERNAL_TOKEN_PARAM, RESYNC_KIND,
+};
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use serde_json::Value;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::warn;
 use uuid::Uuid;
+use super::currency::{ExchangeRateProvider, StaticExchangeRates};
+use super::email::{render_invoice_email, EmailAttachment, Mailer};
+use super::export::invoices_to_csv;
+use super::pdf::render_invoice_pdf;
+use super::store::{InMemoryInvoiceStore, InvoiceStore};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InvoiceItem {
     pub description: String,
     pub quantity: f64,
     pub unit_price: f64,
+    /// Reduction applied to `quantity * unit_price` before tax
+    #[serde(default)]
+    pub discount: Option<Discount>,
+    /// Tax rate for this item; the invoice's `tax_rate` applies when unset
+    #[serde(default)]
+    pub tax_rate: Option<f64>,
+    /// Always recomputed as `quantity * unit_price` less the discount; any client-supplied value is ignored
+    #[serde(default)]
     pub amount: f64,
 }
 
+impl InvoiceItem {
+    /// `quantity * unit_price`, before any discount
+    pub fn gross_amount(&self) -> f64 {
+        self.quantity * self.unit_price
+    }
+}
+
+/// Reduction on a line item: a percentage from 0 to 100, or a fixed amount
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Discount {
+    Percent(f64),
+    Fixed(f64),
+}
+
+impl Discount {
+    /// Amount taken off a line item worth `gross`
+    pub fn amount_off(&self, gross: f64) -> f64 {
+        match self {
+            Discount::Percent(percent) => gross * percent / 100.0,
+            Discount::Fixed(amount) => *amount,
+        }
+    }
+}
+
+/// Tax charged at one rate across the items it applies to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaxLine {
+    pub rate: f64,
+    /// Sum of the discounted item amounts taxed at `rate`
+    pub taxable_amount: f64,
+    pub tax_amount: f64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Invoice {
     pub id: String,
+    pub tenant_id: String,
+    /// Sequential number, unique within the tenant
+    pub invoice_number: u64,
     pub user_id: String,
+    /// ISO 4217 code the amounts are denominated in
+    #[serde(default = "default_currency")]
+    pub currency: String,
     pub customer_name: String,
     pub customer_email: String,
     pub items: Vec<InvoiceItem>,
     pub subtotal: f64,
+    /// Default tax rate for items that don't carry their own
     pub tax_rate: f64,
     pub tax_amount: f64,
+    /// Tax per distinct rate, ordered by rate; `tax_amount` is their sum
+    #[serde(default)]
+    pub taxes: Vec<TaxLine>,
     pub total: f64,
     pub notes: Option<String>,
     pub due_date: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub status: InvoiceStatus,
+    /// Incremented on every successful write; surfaced as the HTTP `ETag`
+    #[serde(default)]
+    pub version: u64,
+    /// Files attached to the invoice; the bytes live in the service's `BlobStore`
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// When the invoice's financial fields were locked; set on finalization or payment
+    #[serde(default)]
+    pub finalized_at: Option<DateTime<Utc>>,
+    /// Payments received against the invoice, oldest first
+    #[serde(default)]
+    pub payments: Vec<Payment>,
+}
+
+impl Invoice {
+    /// Finalized invoices keep their customer, items, tax and due date fixed
+    pub fn is_finalized(&self) -> bool {
+        self.finalized_at.is_some() || self.status == InvoiceStatus::Paid
+    }
+
+    /// Sum of the payments received so far
+    pub fn amount_paid(&self) -> f64 {
+        self.payments.iter().map(|payment| payment.amount).sum()
+    }
+}
+
+/// A payment received against an invoice
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Payment {
+    pub id: String,
+    pub amount: f64,
+    /// How the customer paid, e.g. `card` or `bank_transfer`
+    pub method: String,
+    pub paid_at: DateTime<Utc>,
+}
+
+/// Reference to a file attached to an invoice
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: String,
+    pub filename: String,
+    pub content_type: String,
+    /// Size in bytes
+    pub size: usize,
+    pub created_at: DateTime<Utc>,
 }
 
+/// Client-supplied fields for a new invoice
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewInvoice {
+    pub user_id: String,
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    pub customer_name: String,
+    pub customer_email: String,
+    pub items: Vec<InvoiceItem>,
+    pub tax_rate: f64,
+    #[serde(default)]
+    pub notes: Option<String>,
+    pub due_date: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum InvoiceStatus {
     Draft,
     Sent,
@@ -43,93 +167,897 @@ pub enum InvoiceStatus {
     Cancelled,
 }
 
-#[service(name = "invoice", description = "Invoice management service")]
+/// Whether an invoice may move from `from` to `to`.
+///
+/// `Draft → Sent → Paid`, `Sent → Overdue → Paid`, and anything may be
+/// cancelled. Staying in the same status is always allowed.
+pub fn can_transition(from: InvoiceStatus, to: InvoiceStatus) -> bool {
+    use InvoiceStatus::*;
+
+    from == to
+        || to == Cancelled
+        || matches!((from, to), (Draft, Sent) | (Sent, Paid) | (Sent, Overdue) | (Overdue, Paid))
+}
+
+/// Tenant used when the gateway did not stamp one on the request
+const DEFAULT_TENANT: &str = "default";
+
+/// Topic invoice events are published on
+pub const EVENT_TOPIC: &str = "invoice";
+
+/// Published when an invoice moves to `Paid`, whether by payments or by hand
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvoicePaid {
+    pub tenant_id: String,
+    pub invoice_id: String,
+    pub invoice_number: u64,
+    pub user_id: String,
+    pub total: f64,
+    pub currency: String,
+    pub paid_at: DateTime<Utc>,
+}
+
+impl InvoicePaid {
+    fn for_invoice(invoice: &Invoice) -> Self {
+        Self {
+            tenant_id: invoice.tenant_id.clone(),
+            invoice_id: invoice.id.clone(),
+            invoice_number: invoice.invoice_number,
+            user_id: invoice.user_id.clone(),
+            total: invoice.total,
+            currency: invoice.currency.clone(),
+            paid_at: invoice.updated_at,
+        }
+    }
+}
+
+impl DomainEvent for InvoicePaid {
+    const TOPIC: &'static str = EVENT_TOPIC;
+    const KIND: &'static str = "invoice_paid";
+}
+
+/// Currency used when an invoice does not specify one
+const DEFAULT_CURRENCY: &str = "USD";
+
+fn default_currency() -> String {
+    DEFAULT_CURRENCY.to_string()
+}
+
+/// Read the tenant stamped on the request by the gateway
+fn tenant_of(params: &ActionParams) -> Result<String> {
+    Ok(params
+        .get_string_optional("tenant_id")?
+        .unwrap_or_else(|| DEFAULT_TENANT.to_string()))
+}
+
+/// Rounding policy for money fields.
+///
+/// Amounts are rounded half to even (banker's rounding) to the currency's
+/// minor unit (2 decimal places by default) after every computation, so
+/// serialized totals never carry floating-point noise like `19.999999999998`
+/// and rounding doesn't drift upwards across many line items.
+#[derive(Debug, Clone, Copy)]
+pub struct MoneyPolicy {
+    pub minor_units: u32,
+}
+
+impl Default for MoneyPolicy {
+    fn default() -> Self {
+        Self { minor_units: 2 }
+    }
+}
+
+impl MoneyPolicy {
+    pub fn round(&self, value: f64) -> f64 {
+        let factor = 10f64.powi(self.minor_units as i32);
+        // Pre-round to shed representation error (e.g. 1.005 * 100 = 100.49999...)
+        let scaled = (value * factor * 1e6).round() / 1e6;
+        let floor = scaled.floor();
+        let rounded = if (scaled - floor - 0.5).abs() < 1e-9 {
+            // Exactly halfway: pick the even neighbour
+            if floor % 2.0 == 0.0 { floor } else { floor + 1.0 }
+        } else {
+            scaled.round()
+        };
+        rounded / factor
+    }
+}
+
+/// Whether `rate` is a tax rate as a fraction, from 0 (0%) to 1 (100%)
+fn is_valid_tax_rate(rate: f64) -> bool {
+    (0.0..=1.0).contains(&rate)
+}
+
+/// Reject an invoice-level tax rate outside 0 to 1
+pub fn check_tax_rate(rate: f64) -> Result<(), ServiceError> {
+    if !is_valid_tax_rate(rate) {
+        return Err(ServiceError::validation(format!("tax_rate must be between 0 and 1, got {}", rate)));
+    }
+    Ok(())
+}
+
+/// Bounds on the line items a single invoice may carry
+#[derive(Debug, Clone, Copy)]
+pub struct InvoiceLimits {
+    pub max_items: usize,
+    /// Longest item description, in characters
+    pub max_description_len: usize,
+}
+
+impl Default for InvoiceLimits {
+    fn default() -> Self {
+        Self {
+            max_items: 500,
+            max_description_len: 1000,
+        }
+    }
+}
+
+impl InvoiceLimits {
+    /// Reject item lists that are too long or carry oversized or non-numeric fields
+    pub fn check_items(&self, items: &[InvoiceItem]) -> Result<(), ServiceError> {
+        if items.len() > self.max_items {
+            return Err(ServiceError::validation(format!(
+                "Invoice has {} items; the limit is {}",
+                items.len(),
+                self.max_items
+            )));
+        }
+
+        for (index, item) in items.iter().enumerate() {
+            if item.description.chars().count() > self.max_description_len {
+                return Err(ServiceError::validation(format!(
+                    "Item {} description exceeds {} characters",
+                    index,
+                    self.max_description_len
+                )));
+            }
+            if !item.quantity.is_finite() || !item.unit_price.is_finite() || !item.amount.is_finite() {
+                return Err(ServiceError::validation(format!("Item {} has a non-numeric amount", index)));
+            }
+            if let Some(rate) = item.tax_rate {
+                if !is_valid_tax_rate(rate) {
+                    return Err(ServiceError::validation(format!("Item {} tax rate must be between 0 and 1", index)));
+                }
+            }
+            match item.discount {
+                Some(Discount::Percent(percent)) if !(0.0..=100.0).contains(&percent) => {
+                    return Err(ServiceError::validation(format!(
+                        "Item {} discount must be between 0 and 100 percent",
+                        index
+                    )));
+                },
+                Some(Discount::Fixed(amount)) if !amount.is_finite() || amount < 0.0 || amount > item.gross_amount() => {
+                    return Err(ServiceError::validation(format!(
+                        "Item {} discount must be between 0 and the item's amount",
+                        index
+                    )));
+                },
+                _ => {},
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Largest page `list_invoices` will return
+const MAX_PAGE_SIZE: usize = 100;
+
+/// Position in a user's invoice list, signed into an opaque cursor
+#[derive(Debug, Serialize, Deserialize)]
+struct ListCursor {
+    tenant_id: String,
+    user_id: String,
+    offset: usize,
+    /// Filter and sort the cursor was issued for; offsets mean nothing under another
+    #[serde(default)]
+    query: String,
+}
+
+/// Optional `list_invoices` filters; every one that is set must match
+#[derive(Debug, Default, Serialize)]
+struct InvoiceFilter {
+    status: Option<InvoiceStatus>,
+    due_before: Option<DateTime<Utc>>,
+    due_after: Option<DateTime<Utc>>,
+}
+
+impl InvoiceFilter {
+    fn matches(&self, invoice: &Invoice) -> bool {
+        self.status.map_or(true, |status| invoice.status == status)
+            && self.due_before.map_or(true, |before| invoice.due_date < before)
+            && self.due_after.map_or(true, |after| invoice.due_date > after)
+    }
+}
+
+/// Field `list_invoices` can sort by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SortField {
+    DueDate,
+    CreatedAt,
+    Total,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SortDirection {
+    Asc,
+    Desc,
+}
+
+/// Order `invoices` by `field`, falling back to the id so the order is stable
+fn sort_invoices(invoices: &mut [Invoice], field: SortField, direction: SortDirection) {
+    invoices.sort_by(|a, b| {
+        let ordering = match field {
+            SortField::DueDate => a.due_date.cmp(&b.due_date),
+            SortField::CreatedAt => a.created_at.cmp(&b.created_at),
+            SortField::Total => a.total.total_cmp(&b.total),
+        }
+        .then_with(|| a.id.cmp(&b.id));
+        match direction {
+            SortDirection::Asc => ordering,
+            SortDirection::Desc => ordering.reverse(),
+        }
+    });
+}
+
+/// Lifetime of a PDF download link when the caller does not choose one
+const DEFAULT_PDF_LINK_TTL_SECS: i64 = 3600;
+/// Longest lifetime a PDF download link may be given
+const MAX_PDF_LINK_TTL_SECS: i64 = 7 * 24 * 3600;
+
+/// Largest file that may be attached to an invoice
+const MAX_ATTACHMENT_BYTES: usize = 10 * 1024 * 1024;
+/// Most files a single invoice may carry
+const MAX_ATTACHMENTS_PER_INVOICE: usize = 20;
+/// Content types accepted as invoice attachments
+const ALLOWED_ATTACHMENT_TYPES: [&str; 5] = [
+    "application/pdf",
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "text/plain",
+];
+
+/// Blob store key for an invoice attachment
+fn attachment_key(tenant_id: &str, invoice_id: &str, attachment_id: &str) -> String {
+    format!("invoices/{}/{}/{}", tenant_id, invoice_id, attachment_id)
+}
+
+/// Message signed into a PDF download link
+fn pdf_link_message(tenant_id: &str, invoice_id: &str, expires: i64) -> String {
+    format!("{}:{}:{}", tenant_id, invoice_id, expires)
+}
+
+/// Tolerance used when comparing a client-supplied confirmation total
+const CONFIRMATION_EPSILON: f64 = 0.005;
+
+/// Furthest `duplicate` may move a copy's due date, in days
+const MAX_DUE_DATE_SHIFT_DAYS: i64 = 3650;
+
+/// Tolerance when deciding whether payments cover an invoice's total
+const PAYMENT_EPSILON: f64 = 0.005;
+
 pub struct InvoiceService {
-    invoices: Arc<RwLock<HashMap<String, Invoice>>>,
+    store: Arc<dyn InvoiceStore>,
+    /// Serializes read-modify-write cycles against the store
+    writes: Arc<Mutex<()>>,
+    money: MoneyPolicy,
+    limits: InvoiceLimits,
+    cursor_signer: CursorSigner,
+    link_signer: HmacSigner,
+    exchange_rates: Arc<dyn ExchangeRateProvider>,
+    events: EventBus,
+    blobs: Arc<dyn BlobStore>,
+    internal_auth: Option<InternalAuth>,
+    mailer: Option<Arc<dyn Mailer>>,
 }
 
 impl InvoiceService {
-    pub fn new() -> Self {
-        Self {
-            invoices: Arc::new(RwLock::new(HashMap::new())),
+    pub async fn new() -> Result<Self> {
+        Ok(Self {
+            store: Arc::new(InMemoryInvoiceStore::new()),
+            writes: Arc::new(Mutex::new(())),
+            money: MoneyPolicy::default(),
+            limits: InvoiceLimits::default(),
+            // Per-process secret: cursors stop validating after a restart unless one is configured
+            cursor_signer: CursorSigner::new(Uuid::new_v4().as_bytes()),
+            link_signer: HmacSigner::new(Uuid::new_v4().as_bytes()),
+            exchange_rates: Arc::new(StaticExchangeRates::new()),
+            events: EventBus::new(),
+            blobs: Arc::new(InMemoryBlobStore::new()),
+            internal_auth: None,
+            mailer: None,
+        })
+    }
+
+    /// Only accept calls carrying a gateway-issued internal token signed with `secret`
+    pub fn with_internal_auth(mut self, secret: impl AsRef<[u8]>) -> Self {
+        self.internal_auth = Some(InternalAuth::new(secret));
+        self
+    }
+
+    /// Tenant the call acts on, after checking it came through the gateway when required
+    fn caller_tenant(&self, params: &ActionParams) -> Result<String> {
+        let tenant_id = tenant_of(params)?;
+        if let Some(internal_auth) = &self.internal_auth {
+            let token = params
+                .get_string_optional(INTERNAL_TOKEN_PARAM)?
+                .ok_or_else(|| ServiceError::unauthorized("Missing internal token; call through the gateway"))?;
+            internal_auth.verify(&token, "invoice", &tenant_id)?;
+        }
+        Ok(tenant_id)
+    }
+
+    /// Keep invoices somewhere other than process memory
+    pub fn with_store(mut self, store: Arc<dyn InvoiceStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// Load an invoice, failing with `NotFound` if the tenant has no such invoice
+    async fn load_invoice(&self, tenant_id: &str, invoice_id: &str) -> Result<Invoice> {
+        self.store
+            .get(tenant_id, invoice_id)
+            .await?
+            .ok_or_else(|| ServiceError::not_found("Invoice not found").into())
+    }
+
+    /// Store attachment bytes somewhere other than process memory
+    pub fn with_blob_store(mut self, blobs: Arc<dyn BlobStore>) -> Self {
+        self.blobs = blobs;
+        self
+    }
+
+    /// Deliver invoices to customers through `mailer`
+    pub fn with_mailer(mut self, mailer: Arc<dyn Mailer>) -> Self {
+        self.mailer = Some(mailer);
+        self
+    }
+
+    /// Publish events onto a bus shared with other services
+    pub fn with_event_bus(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Move invoices to the kept user when `AuthService` merges duplicate users
+    pub fn spawn_merge_listener(&self) -> JoinHandle<()> {
+        let store = self.store.clone();
+        let writes = self.writes.clone();
+        let mut events = self.events.subscribe(auth_service::EVENT_TOPIC);
+
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                match event.kind.as_str() {
+                    USERS_MERGED_EVENT => {
+                        let merged: UsersMerged = match serde_json::from_value(event.payload) {
+                            Ok(merged) => merged,
+                            Err(e) => {
+                                warn!("Ignoring malformed {} event: {}", USERS_MERGED_EVENT, e);
+                                continue;
+                            }
+                        };
+
+                        let now = Utc::now();
+                        let _writes = writes.lock().await;
+                        let merged_invoices = match store.list_by_user(&merged.tenant_id, &merged.merge_id.to_string()).await {
+                            Ok(invoices) => invoices,
+                            Err(e) => {
+                                warn!("Failed to load invoices of merged user {}: {}", merged.merge_id, e);
+                                continue;
+                            }
+                        };
+                        for mut invoice in merged_invoices {
+                            invoice.user_id = merged.keep_id.to_string();
+                            invoice.updated_at = now;
+                            invoice.version += 1;
+                            if let Err(e) = store.put(invoice).await {
+                                warn!("Failed to reassign invoice of merged user {}: {}", merged.merge_id, e);
+                            }
+                        }
+                    },
+                    RESYNC_KIND => {
+                        warn!("Invoice merge listener missed {} auth events", event.missed().unwrap_or(0));
+                    },
+                    _ => {},
+                }
+            }
+        })
+    }
+
+    /// Use a different source of exchange rates for currency conversion
+    pub fn with_exchange_rates(mut self, exchange_rates: Arc<dyn ExchangeRateProvider>) -> Self {
+        self.exchange_rates = exchange_rates;
+        self
+    }
+
+    /// Sign PDF download links with a fixed secret
+    pub fn with_link_secret(mut self, secret: impl AsRef<[u8]>) -> Self {
+        self.link_signer = HmacSigner::new(secret);
+        self
+    }
+
+    /// Sign pagination cursors with a fixed secret
+    pub fn with_cursor_secret(mut self, secret: impl AsRef<[u8]>) -> Self {
+        self.cursor_signer = CursorSigner::new(secret);
+        self
+    }
+
+    /// Use a different rounding policy for money fields
+    pub fn with_money_policy(mut self, money: MoneyPolicy) -> Self {
+        self.money = money;
+        self
+    }
+
+    /// Use different bounds on invoice line items
+    pub fn with_limits(mut self, limits: InvoiceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Recompute every derived amount from the items, rounding each step.
+    ///
+    /// Each item's `amount` is `quantity * unit_price` less its discount, and
+    /// `subtotal` is the sum of the amounts. Items are grouped by their tax rate
+    /// (the invoice's `tax_rate` when they have none) and each group is taxed on
+    /// its summed amounts; `tax_amount` is the sum of those and `total` adds it to
+    /// the subtotal. Every value is rounded with the service's `MoneyPolicy`
+    /// (banker's rounding to 2 decimal places by default), so client-supplied
+    /// amounts never survive.
+    fn recalculate_totals(&self, invoice: &mut Invoice) {
+        let mut taxes: Vec<TaxLine> = Vec::new();
+        for item in &mut invoice.items {
+            let gross = self.money.round(item.gross_amount());
+            let discount = item.discount.map_or(0.0, |discount| self.money.round(discount.amount_off(gross)));
+            item.amount = self.money.round((gross - discount).max(0.0));
+
+            let rate = item.tax_rate.unwrap_or(invoice.tax_rate);
+            match taxes.iter_mut().find(|line| line.rate == rate) {
+                Some(line) => line.taxable_amount += item.amount,
+                None => taxes.push(TaxLine { rate, taxable_amount: item.amount, tax_amount: 0.0 }),
+            }
+        }
+
+        taxes.sort_by(|a, b| a.rate.total_cmp(&b.rate));
+        for line in &mut taxes {
+            line.taxable_amount = self.money.round(line.taxable_amount);
+            line.tax_amount = self.money.round(line.taxable_amount * line.rate);
         }
+
+        invoice.subtotal = self.money.round(invoice.items.iter().map(|item| item.amount).sum());
+        invoice.tax_amount = self.money.round(taxes.iter().map(|line| line.tax_amount).sum());
+        invoice.total = self.money.round(invoice.subtotal + invoice.tax_amount);
+        invoice.taxes = taxes;
     }
 
-    #[action(operation = "create", description = "Create a new invoice")]
-    async fn create_invoice(&self, _context: &RequestContext, request: ServiceRequest) -> Result<ServiceResponse> {
-        let user_id = request.get_string("user_id")?;
-        let customer_name = request.get_string("customer_name")?;
-        let customer_email = request.get_string("customer_email")?;
-        let items: Vec<InvoiceItem> = request.get_json("items")?;
-        let tax_rate = request.get_f64("tax_rate")?;
-        let notes = request.get_string_optional("notes")?;
-        let due_date: DateTime<Utc> = request.get_datetime("due_date")?;
+    /// Store a new draft invoice for the tenant, assigning its number and totals
+    async fn insert_invoice(&self, tenant_id: String, new_invoice: NewInvoice) -> Result<Invoice> {
+        let NewInvoice {
+            user_id,
+            currency,
+            customer_name,
+            customer_email,
+            items,
+            tax_rate,
+            notes,
+            due_date,
+        } = new_invoice;
+        self.limits.check_items(&items)?;
+        check_tax_rate(tax_rate)?;
+        let currency = currency.to_uppercase();
 
-        let subtotal: f64 = items.iter().map(|item| item.amount).sum();
-        let tax_amount = subtotal * tax_rate;
-        let total = subtotal + tax_amount;
+        let invoice_number = self.store.next_invoice_number(&tenant_id).await?;
 
         let now = Utc::now();
-        let invoice = Invoice {
+        let mut invoice = Invoice {
             id: Uuid::new_v4().to_string(),
+            tenant_id,
+            invoice_number,
             user_id,
+            currency,
             customer_name,
             customer_email,
             items,
-            subtotal,
+            subtotal: 0.0,
             tax_rate,
-            tax_amount,
-            total,
+            tax_amount: 0.0,
+            taxes: Vec::new(),
+            total: 0.0,
             notes,
             due_date,
             created_at: now,
             updated_at: now,
             status: InvoiceStatus::Draft,
+            version: 1,
+            attachments: Vec::new(),
+            finalized_at: None,
+            payments: Vec::new(),
+        };
+        self.recalculate_totals(&mut invoice);
+
+        self.store.put(invoice.clone()).await?;
+
+        self.events.publish_json(EVENT_TOPIC, "invoice_created", &invoice)?;
+
+        Ok(invoice)
+    }
+
+    /// Create a new invoice
+    async fn create_invoice(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let new_invoice = NewInvoice {
+            user_id: params.get_string("user_id")?,
+            currency: params
+                .get_string_optional("currency")?
+                .unwrap_or_else(default_currency),
+            customer_name: params.get_string("customer_name")?,
+            customer_email: params.get_string("customer_email")?,
+            items: params.get_json("items")?,
+            tax_rate: params.get_f64("tax_rate")?,
+            notes: params.get_string_optional("notes")?,
+            due_date: params.get_json("due_date")?,
+        };
+
+        let invoice = self.insert_invoice(tenant_id, new_invoice).await?;
+
+        to_result(invoice)
+    }
+
+    /// Copy an invoice into a new draft with its due date moved by `shift_days`.
+    ///
+    /// The copy gets a fresh id and number and starts without payments,
+    /// attachments or a finalization lock; the original is left as it was.
+    async fn duplicate_invoice(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let invoice_id = params.get_string("invoice_id")?;
+        let shift_days: i64 = params.get_json_optional("shift_days")?.unwrap_or(0);
+        if shift_days.abs() > MAX_DUE_DATE_SHIFT_DAYS {
+            return Err(ServiceError::validation(format!(
+                "shift_days must be within ±{}",
+                MAX_DUE_DATE_SHIFT_DAYS
+            )).into());
+        }
+
+        let original = self.load_invoice(&tenant_id, &invoice_id).await?;
+        let new_invoice = NewInvoice {
+            user_id: original.user_id,
+            currency: original.currency,
+            customer_name: original.customer_name,
+            customer_email: original.customer_email,
+            items: original.items,
+            tax_rate: original.tax_rate,
+            notes: original.notes,
+            due_date: original.due_date + chrono::Duration::days(shift_days),
         };
 
-        let mut invoices = self.invoices.write().await;
-        invoices.insert(invoice.id.clone(), invoice.clone());
+        let invoice = self.insert_invoice(tenant_id, new_invoice).await?;
 
-        Ok(ServiceResponse::json(serde_json::json!(invoice)))
+        to_result(invoice)
     }
 
-    #[action(operation = "get", description = "Get invoice by ID")]
-    async fn get_invoice(&self, _context: &RequestContext, request: ServiceRequest) -> Result<ServiceResponse> {
-        let invoice_id = request.get_string("invoice_id")?;
+    /// Create several invoices, reporting each one's outcome in input order.
+    ///
+    /// One malformed or rejected invoice does not prevent the others from being created.
+    async fn bulk_create_invoices(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let drafts: Vec<serde_json::Value> = params.get_json("invoices")?;
 
-        let invoices = self.invoices.read().await;
-        match invoices.get(&invoice_id) {
-            Some(invoice) => Ok(ServiceResponse::json(serde_json::json!(invoice))),
-            None => Ok(ServiceResponse::error("Invoice not found")),
+        let mut result = BulkResult::new();
+        for draft in drafts {
+            let outcome = match serde_json::from_value::<NewInvoice>(draft) {
+                Ok(new_invoice) => self
+                    .insert_invoice(tenant_id.clone(), new_invoice)
+                    .await
+                    .map_err(|e| ServiceError::from_anyhow(&e)),
+                Err(e) => Err(ServiceError::validation(format!("Invalid invoice: {}", e))),
+            };
+            result.push(outcome);
+        }
+
+        to_result(result)
+    }
+
+    /// Get invoice by ID
+    async fn get_invoice(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let invoice_id = params.get_string("invoice_id")?;
+
+        match self.store.get(&tenant_id, &invoice_id).await? {
+            Some(invoice) => to_result(invoice),
+            None => Err(ServiceError::not_found("Invoice not found").into()),
         }
     }
 
-    #[action(operation = "list", description = "List user's invoices")]
-    async fn list_invoices(&self, _context: &RequestContext, request: ServiceRequest) -> Result<ServiceResponse> {
-        let user_id = request.get_string("user_id")?;
+    /// List a user's invoices.
+    ///
+    /// Optional `status`, `due_before` and `due_after` filters are combined, and
+    /// `sort_by` (`due_date`, `created_at` or `total`) with `direction` (`asc` or
+    /// `desc`) orders the result; no match yields an empty list.
+    ///
+    /// Without `limit`/`cursor` the full list is returned as an array. With either,
+    /// a page `{ items, next_cursor }` is returned; `next_cursor` is signed and scoped
+    /// to the tenant, user, filters and sort, so a tampered or foreign cursor is rejected.
+    async fn list_invoices(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let user_id = params.get_string("user_id")?;
+        let limit: Option<usize> = params.get_json_optional("limit")?;
+        let cursor = params.get_string_optional("cursor")?;
+        let filter = InvoiceFilter {
+            status: params.get_json_optional("status")?,
+            due_before: params.get_json_optional("due_before")?,
+            due_after: params.get_json_optional("due_after")?,
+        };
+        let sort_by: Option<SortField> = params.get_json_optional("sort_by")?;
+        let direction: SortDirection = params.get_json_optional("direction")?.unwrap_or(SortDirection::Asc);
 
-        let invoices = self.invoices.read().await;
-        let user_invoices: Vec<&Invoice> = invoices
-            .values()
-            .filter(|invoice| invoice.user_id == user_id)
+        let mut user_invoices: Vec<Invoice> = self.store
+            .list_by_user(&tenant_id, &user_id)
+            .await?
+            .into_iter()
+            .filter(|invoice| filter.matches(invoice))
             .collect();
 
-        Ok(ServiceResponse::json(serde_json::json!(user_invoices)))
+        if limit.is_none() && cursor.is_none() {
+            if let Some(field) = sort_by {
+                sort_invoices(&mut user_invoices, field, direction);
+            }
+            return to_result(user_invoices);
+        }
+
+        let query = serde_json::json!({ "filter": filter, "sort_by": sort_by, "direction": direction }).to_string();
+        let offset = match cursor {
+            Some(cursor) => {
+                let position: ListCursor = self.cursor_signer.verify(&cursor)?;
+                if position.tenant_id != tenant_id || position.user_id != user_id || position.query != query {
+                    return Err(ServiceError::validation("Invalid pagination cursor").into());
+                }
+                position.offset
+            },
+            None => 0,
+        };
+        let limit = limit.unwrap_or(MAX_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+        // Stable order so offsets stay meaningful between pages
+        sort_invoices(&mut user_invoices, sort_by.unwrap_or(SortField::CreatedAt), direction);
+        let page: Vec<&Invoice> = user_invoices.iter().skip(offset).take(limit).collect();
+
+        let next_offset = offset + page.len();
+        let next_cursor = if next_offset < user_invoices.len() {
+            Some(self.cursor_signer.sign(&ListCursor {
+                tenant_id,
+                user_id,
+                offset: next_offset,
+                query,
+            })?)
+        } else {
+            None
+        };
+
+        Ok(serde_json::json!({
+            "items": page,
+            "next_cursor": next_cursor,
+        }))
+    }
+
+    /// Create a time-limited, shareable link to the invoice's PDF.
+    ///
+    /// The link is signed over the tenant, invoice id and expiry and is served by the
+    /// gateway's `/downloads/invoice/:id` route, which hands it back to `download`.
+    async fn create_pdf_link(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let invoice_id = params.get_string("invoice_id")?;
+        let ttl_secs: i64 = params.get_json_optional("ttl_secs")?.unwrap_or(DEFAULT_PDF_LINK_TTL_SECS);
+
+        if ttl_secs <= 0 || ttl_secs > MAX_PDF_LINK_TTL_SECS {
+            return Err(ServiceError::validation(format!(
+                "ttl_secs must be between 1 and {}",
+                MAX_PDF_LINK_TTL_SECS
+            )).into());
+        }
+
+        self.load_invoice(&tenant_id, &invoice_id).await?;
+
+        let expires = Utc::now().timestamp() + ttl_secs;
+        let signature = self.link_signer.sign(pdf_link_message(&tenant_id, &invoice_id, expires).as_bytes());
+        let url = format!("/downloads/invoice/{}?expires={}&signature={}", invoice_id, expires, signature);
+
+        Ok(serde_json::json!({
+            "url": url,
+            "expires": expires,
+        }))
+    }
+
+    /// Render an invoice as a PDF, returned base64-encoded
+    async fn render_pdf(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let invoice_id = params.get_string("invoice_id")?;
+
+        let invoice = self.load_invoice(&tenant_id, &invoice_id).await?;
+        let pdf = render_invoice_pdf(&invoice)?;
+
+        Ok(serde_json::json!({
+            "content_type": "application/pdf",
+            "filename": format!("invoice-{}.pdf", invoice.invoice_number),
+            "data": STANDARD.encode(pdf),
+        }))
     }
 
-    #[action(operation = "update", description = "Update invoice")]
-    async fn update_invoice(&self, _context: &RequestContext, request: ServiceRequest) -> Result<ServiceResponse> {
-        let invoice_id = request.get_string("invoice_id")?;
-        let customer_name = request.get_string_optional("customer_name")?;
-        let customer_email = request.get_string_optional("customer_email")?;
-        let items: Option<Vec<InvoiceItem>> = request.get_json_optional("items")?;
-        let tax_rate = request.get_f64_optional("tax_rate")?;
-        let notes = request.get_string_optional("notes")?;
-        let due_date: Option<DateTime<Utc>> = request.get_datetime_optional("due_date")?;
-        let status: Option<InvoiceStatus> = request.get_json_optional("status")?;
+    /// Export a user's invoices as CSV, ordered by invoice number
+    async fn export_csv(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let user_id = params.get_string("user_id")?;
 
-        let mut invoices = self.invoices.write().await;
-        let invoice = invoices.get_mut(&invoice_id).ok_or_else(|| anyhow::anyhow!("Invoice not found"))?;
+        let mut user_invoices = self.store.list_by_user(&tenant_id, &user_id).await?;
+        user_invoices.sort_by_key(|invoice| invoice.invoice_number);
+        let csv = invoices_to_csv(&user_invoices)?;
+
+        Ok(serde_json::json!({
+            "content_type": "text/csv",
+            "filename": format!("invoices-{}.csv", user_id),
+            "data": csv,
+        }))
+    }
+
+    /// Serve the PDF behind a signed download link
+    async fn download(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let invoice_id = params.get_string("id")?;
+        let expires: i64 = params.get_json("expires")?;
+        let signature = params.get_string("signature")?;
+
+        let message = pdf_link_message(&tenant_id, &invoice_id, expires);
+        if !self.link_signer.verify(message.as_bytes(), &signature) {
+            return Err(ServiceError::forbidden("Invalid download signature").into());
+        }
+        if Utc::now().timestamp() > expires {
+            return Err(ServiceError::gone("Download link has expired").into());
+        }
+
+        let invoice = self.load_invoice(&tenant_id, &invoice_id).await?;
+        let pdf = render_invoice_pdf(&invoice)?;
+
+        Ok(serde_json::json!({
+            "content_type": "application/pdf",
+            "filename": format!("invoice-{}.pdf", invoice.invoice_number),
+            "data": STANDARD.encode(pdf),
+        }))
+    }
+
+    /// Report each of a user's invoice totals converted into `target_currency`.
+    ///
+    /// A missing rate is reported on that invoice's entry rather than failing the call.
+    async fn convert_totals(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let user_id = params.get_string("user_id")?;
+        let target_currency = params.get_string("target_currency")?.to_uppercase();
+
+        let user_invoices = self.store.list_by_user(&tenant_id, &user_id).await?;
+
+        let mut results = Vec::with_capacity(user_invoices.len());
+        for invoice in user_invoices {
+            let mut entry = serde_json::json!({
+                "invoice_id": invoice.id,
+                "currency": invoice.currency,
+                "total": invoice.total,
+                "target_currency": target_currency,
+            });
+
+            match self.exchange_rates.rate(&invoice.currency, &target_currency).await {
+                Ok(Some(rate)) => {
+                    entry["converted_total"] = serde_json::json!(self.money.round(invoice.total * rate));
+                    entry["rate"] = serde_json::json!(rate);
+                },
+                Ok(None) => {
+                    entry["error"] = serde_json::json!(format!(
+                        "No exchange rate from {} to {}",
+                        invoice.currency,
+                        target_currency
+                    ));
+                },
+                Err(e) => {
+                    entry["error"] = serde_json::json!(e.to_string());
+                },
+            }
+
+            results.push(entry);
+        }
+
+        to_result(results)
+    }
+
+    /// Update an invoice the caller last read at `expected_version`.
+    ///
+    /// A stale `expected_version` fails with a conflict and leaves the invoice
+    /// untouched; an `If-Match` header may stand in for it, failing with `412`.
+    async fn update_invoice(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let invoice_id = params.get_string("invoice_id")?;
+        let customer_name = params.get_string_optional("customer_name")?;
+        let customer_email = params.get_string_optional("customer_email")?;
+        let items: Option<Vec<InvoiceItem>> = params.get_json_optional("items")?;
+        let tax_rate = params.get_f64_optional("tax_rate")?;
+        let notes = params.get_string_optional("notes")?;
+        let due_date: Option<DateTime<Utc>> = params.get_json_optional("due_date")?;
+        let status: Option<InvoiceStatus> = params.get_json_optional("status")?;
+        let expected_version: Option<u64> = params.get_json_optional("expected_version")?;
+        let if_match_version: Option<u64> = params.get_json_optional("if_match_version")?;
+        if expected_version.is_none() && if_match_version.is_none() {
+            return Err(ServiceError::validation(
+                "expected_version is required to update an invoice"
+            ).into());
+        }
+        if let Some(items) = &items {
+            self.limits.check_items(items)?;
+        }
+        if let Some(rate) = tax_rate {
+            check_tax_rate(rate)?;
+        }
+
+        let _writes = self.writes.lock().await;
+        let mut invoice = self.load_invoice(&tenant_id, &invoice_id).await?;
+
+        if let Some(expected) = if_match_version {
+            if expected != invoice.version {
+                return Err(ServiceError::precondition_failed(format!(
+                    "Invoice has changed: expected version {}, current version {}",
+                    expected,
+                    invoice.version
+                )).into());
+            }
+        }
+        if let Some(expected) = expected_version {
+            if expected != invoice.version {
+                return Err(ServiceError::conflict(format!(
+                    "Invoice was modified concurrently: expected version {}, current version {}",
+                    expected,
+                    invoice.version
+                )).into());
+            }
+        }
+
+        if let Some(new_status) = status {
+            if !can_transition(invoice.status, new_status) {
+                return Err(ServiceError::conflict(format!(
+                    "Cannot move invoice from {:?} to {:?}",
+                    invoice.status,
+                    new_status
+                )).into());
+            }
+        }
+
+        if invoice.is_finalized() {
+            let locked: Vec<&str> = [
+                ("customer_name", customer_name.is_some()),
+                ("customer_email", customer_email.is_some()),
+                ("items", items.is_some()),
+                ("tax_rate", tax_rate.is_some()),
+                ("due_date", due_date.is_some()),
+            ]
+            .iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(field, _)| *field)
+            .collect();
+            if !locked.is_empty() {
+                return Err(ServiceError::conflict(format!(
+                    "Invoice is finalized; cannot change {}",
+                    locked.join(", ")
+                )).into());
+            }
+            if let Some(new_status) = &status {
+                // Once locked, an invoice can only be paid or cancelled
+                if *new_status != invoice.status
+                    && !matches!(new_status, InvoiceStatus::Paid | InvoiceStatus::Cancelled)
+                {
+                    return Err(ServiceError::conflict(format!(
+                        "Invoice is finalized; cannot move it to {:?}",
+                        new_status
+                    )).into());
+                }
+            }
+        }
 
         if let Some(name) = customer_name {
             invoice.customer_name = name;
@@ -139,37 +1067,1216 @@ impl InvoiceService {
         }
         if let Some(new_items) = items {
             invoice.items = new_items;
-            invoice.subtotal = invoice.items.iter().map(|item| item.amount).sum();
-            invoice.tax_amount = invoice.subtotal * invoice.tax_rate;
-            invoice.total = invoice.subtotal + invoice.tax_amount;
         }
         if let Some(rate) = tax_rate {
             invoice.tax_rate = rate;
-            invoice.tax_amount = invoice.subtotal * rate;
-            invoice.total = invoice.subtotal + invoice.tax_amount;
         }
+        self.recalculate_totals(&mut invoice);
         if let Some(new_notes) = notes {
             invoice.notes = Some(new_notes);
         }
         if let Some(new_due_date) = due_date {
             invoice.due_date = new_due_date;
         }
+        let was_paid = invoice.status == InvoiceStatus::Paid;
         if let Some(new_status) = status {
             invoice.status = new_status;
         }
 
-        invoice.updated_at = Utc::now();
+        let now = Utc::now();
+        if invoice.status == InvoiceStatus::Paid && invoice.finalized_at.is_none() {
+            invoice.finalized_at = Some(now);
+        }
+        invoice.updated_at = now;
+        invoice.version += 1;
+        self.store.put(invoice.clone()).await?;
+
+        self.events.publish_json(EVENT_TOPIC, "invoice_updated", &invoice)?;
+        if !was_paid && invoice.status == InvoiceStatus::Paid {
+            self.events.emit(&InvoicePaid::for_invoice(&invoice))?;
+        }
+        to_result(invoice)
+    }
+
+    /// Email an invoice to its customer, attaching the PDF unless `attach_pdf` is false.
+    ///
+    /// A draft moves to `Sent` once the email is handed off; sent invoices can be
+    /// emailed again without changing.
+    async fn send_invoice(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let invoice_id = params.get_string("invoice_id")?;
+        let attach_pdf: bool = params.get_json_optional("attach_pdf")?.unwrap_or(true);
+        let mailer = self
+            .mailer
+            .as_ref()
+            .ok_or_else(|| ServiceError::internal("No mailer is configured for sending invoices"))?;
+
+        let invoice = self.load_invoice(&tenant_id, &invoice_id).await?;
+        if !can_transition(invoice.status, InvoiceStatus::Sent) {
+            return Err(ServiceError::conflict(format!(
+                "Cannot send a {:?} invoice",
+                invoice.status
+            )).into());
+        }
 
-        Ok(ServiceResponse::json(serde_json::json!(invoice)))
+        let mut email = render_invoice_email(&invoice);
+        if attach_pdf {
+            email.attachments.push(EmailAttachment {
+                filename: format!("invoice-{}.pdf", invoice.invoice_number),
+                content_type: "application/pdf".to_string(),
+                data: render_invoice_pdf(&invoice)?,
+            });
+        }
+        // Sent without holding the write lock so a slow mail server doesn't stall other writes
+        mailer.send(email).await?;
+
+        let _writes = self.writes.lock().await;
+        let mut invoice = self.load_invoice(&tenant_id, &invoice_id).await?;
+        if invoice.status == InvoiceStatus::Draft {
+            invoice.status = InvoiceStatus::Sent;
+            invoice.updated_at = Utc::now();
+            invoice.version += 1;
+            self.store.put(invoice.clone()).await?;
+            self.events.publish_json(EVENT_TOPIC, "invoice_updated", &invoice)?;
+        }
+
+        to_result(invoice)
     }
 
-    #[action(operation = "delete", description = "Delete invoice")]
-    async fn delete_invoice(&self, _context: &RequestContext, request: ServiceRequest) -> Result<ServiceResponse> {
-        let invoice_id = request.get_string("invoice_id")?;
+    /// Lock an invoice's financial fields ahead of payment
+    async fn finalize_invoice(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let invoice_id = params.get_string("invoice_id")?;
 
-        let mut invoices = self.invoices.write().await;
-        invoices.remove(&invoice_id);
+        let _writes = self.writes.lock().await;
+        let mut invoice = self.load_invoice(&tenant_id, &invoice_id).await?;
 
-        Ok(ServiceResponse::success("Invoice deleted successfully"))
+        if invoice.status == InvoiceStatus::Cancelled {
+            return Err(ServiceError::conflict("Cannot finalize a cancelled invoice").into());
+        }
+        if invoice.finalized_at.is_none() {
+            let now = Utc::now();
+            invoice.finalized_at = Some(now);
+            invoice.updated_at = now;
+            invoice.version += 1;
+            self.store.put(invoice.clone()).await?;
+            self.events.publish_json(EVENT_TOPIC, "invoice_updated", &invoice)?;
+        }
+
+        to_result(invoice)
     }
-} 
\ No newline at end of file
+
+    /// Record a payment against a sent or overdue invoice.
+    ///
+    /// The invoice becomes `Paid` once its payments cover the total; a partial
+    /// payment leaves the status alone. Payments beyond the outstanding balance
+    /// are rejected. Any payment locks the invoice's financial fields.
+    async fn record_payment(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let invoice_id = params.get_string("invoice_id")?;
+        let amount = params.get_f64("amount")?;
+        let method = params.get_string("method")?.trim().to_string();
+
+        if !amount.is_finite() || amount <= 0.0 {
+            return Err(ServiceError::validation("Payment amount must be a positive number").into());
+        }
+        if method.is_empty() {
+            return Err(ServiceError::validation("Payment method must not be empty").into());
+        }
+        let amount = self.money.round(amount);
+
+        let _writes = self.writes.lock().await;
+        let mut invoice = self.load_invoice(&tenant_id, &invoice_id).await?;
+
+        if invoice.status == InvoiceStatus::Paid || !can_transition(invoice.status, InvoiceStatus::Paid) {
+            return Err(ServiceError::conflict(format!(
+                "Cannot record a payment on a {:?} invoice",
+                invoice.status
+            )).into());
+        }
+
+        let balance = self.money.round(invoice.total - invoice.amount_paid());
+        if amount > balance + PAYMENT_EPSILON {
+            return Err(ServiceError::validation(format!(
+                "Payment of {} exceeds the outstanding balance of {}",
+                amount,
+                balance
+            )).into());
+        }
+
+        let now = Utc::now();
+        let payment = Payment {
+            id: Uuid::new_v4().to_string(),
+            amount,
+            method,
+            paid_at: now,
+        };
+        invoice.payments.push(payment.clone());
+
+        let balance = self.money.round(invoice.total - invoice.amount_paid()).max(0.0);
+        if balance <= PAYMENT_EPSILON {
+            invoice.status = InvoiceStatus::Paid;
+        }
+        if invoice.finalized_at.is_none() {
+            invoice.finalized_at = Some(now);
+        }
+        invoice.updated_at = now;
+        invoice.version += 1;
+        self.store.put(invoice.clone()).await?;
+
+        self.events.publish_json(EVENT_TOPIC, "invoice_updated", &invoice)?;
+        if invoice.status == InvoiceStatus::Paid {
+            self.events.emit(&InvoicePaid::for_invoice(&invoice))?;
+        }
+        Ok(serde_json::json!({
+            "invoice": invoice,
+            "payment": payment,
+            "balance": balance,
+        }))
+    }
+
+    /// Move the tenant's sent invoices whose due date has passed to `Overdue`.
+    ///
+    /// `now` defaults to the current time; returns how many invoices were moved.
+    async fn refresh_overdue(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let now = params.get_json_optional("now")?.unwrap_or_else(Utc::now);
+
+        let _writes = self.writes.lock().await;
+        let mut updated = 0;
+        for mut invoice in self.store.list_by_status(&tenant_id, InvoiceStatus::Sent).await? {
+            if invoice.due_date >= now {
+                continue;
+            }
+
+            invoice.status = InvoiceStatus::Overdue;
+            invoice.updated_at = Utc::now();
+            invoice.version += 1;
+            self.store.put(invoice.clone()).await?;
+            self.events.publish_json(EVENT_TOPIC, "invoice_updated", &invoice)?;
+            updated += 1;
+        }
+
+        Ok(serde_json::json!({ "updated": updated }))
+    }
+
+    /// Delete an invoice.
+    ///
+    /// The caller must echo back the invoice's current `total` as `confirm_total`,
+    /// so a stale or mistaken delete is rejected instead of silently succeeding.
+    async fn delete_invoice(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let invoice_id = params.get_string("invoice_id")?;
+        let confirm_total = params.get_f64("confirm_total")?;
+
+        let writes = self.writes.lock().await;
+        let invoice = self.load_invoice(&tenant_id, &invoice_id).await?;
+
+        if (invoice.total - confirm_total).abs() > CONFIRMATION_EPSILON {
+            return Err(ServiceError::conflict(format!(
+                "Delete confirmation mismatch: expected total {}, got {}",
+                invoice.total,
+                confirm_total
+            )).into());
+        }
+
+        if let Some(invoice) = self.store.delete(&tenant_id, &invoice_id).await? {
+            drop(writes);
+            for attachment in &invoice.attachments {
+                let blob_key = attachment_key(&invoice.tenant_id, &invoice.id, &attachment.id);
+                if let Err(e) = self.blobs.delete(&blob_key).await {
+                    warn!("Failed to remove attachment {} of deleted invoice {}: {}", attachment.id, invoice.id, e);
+                }
+            }
+            self.events.publish_json(EVENT_TOPIC, "invoice_deleted", &invoice)?;
+        }
+
+        Ok(Value::from("Invoice deleted successfully"))
+    }
+
+    /// Attach a file (base64 `data`) to an invoice
+    async fn attach_file(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let invoice_id = params.get_string("invoice_id")?;
+        let filename = params.get_string("filename")?;
+        let content_type = params.get_string("content_type")?.to_lowercase();
+        let data = STANDARD
+            .decode(params.get_string("data")?)
+            .map_err(|e| ServiceError::validation(format!("Attachment data must be base64: {}", e)))?;
+
+        if filename.trim().is_empty() {
+            return Err(ServiceError::validation("filename must not be empty").into());
+        }
+        if !ALLOWED_ATTACHMENT_TYPES.contains(&content_type.as_str()) {
+            return Err(ServiceError::validation(format!(
+                "Unsupported attachment type '{}'; allowed: {}",
+                content_type,
+                ALLOWED_ATTACHMENT_TYPES.join(", ")
+            )).into());
+        }
+        if data.len() > MAX_ATTACHMENT_BYTES {
+            return Err(ServiceError::validation(format!(
+                "Attachment is {} bytes; the limit is {}",
+                data.len(),
+                MAX_ATTACHMENT_BYTES
+            )).into());
+        }
+
+        let _writes = self.writes.lock().await;
+        let mut invoice = self.load_invoice(&tenant_id, &invoice_id).await?;
+        if invoice.attachments.len() >= MAX_ATTACHMENTS_PER_INVOICE {
+            return Err(ServiceError::conflict(format!(
+                "Invoice already has the maximum of {} attachments",
+                MAX_ATTACHMENTS_PER_INVOICE
+            )).into());
+        }
+
+        let attachment = Attachment {
+            id: Uuid::new_v4().to_string(),
+            filename: filename.trim().to_string(),
+            content_type: content_type.clone(),
+            size: data.len(),
+            created_at: Utc::now(),
+        };
+        let blob_key = attachment_key(&tenant_id, &invoice_id, &attachment.id);
+        self.blobs.put(&blob_key, Blob { content_type, data }).await?;
+
+        invoice.attachments.push(attachment.clone());
+        invoice.updated_at = attachment.created_at;
+        invoice.version += 1;
+        self.store.put(invoice.clone()).await?;
+        self.events.publish_json(EVENT_TOPIC, "invoice_updated", &invoice)?;
+
+        to_result(attachment)
+    }
+
+    /// List the files attached to an invoice
+    async fn list_attachments(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let invoice_id = params.get_string("invoice_id")?;
+
+        let invoice = self.load_invoice(&tenant_id, &invoice_id).await?;
+
+        to_result(invoice.attachments)
+    }
+
+    /// Fetch one attachment, returning its bytes base64-encoded
+    async fn get_attachment(&self, params: &ActionParams) -> Result<Value> {
+        let tenant_id = self.caller_tenant(params)?;
+        let invoice_id = params.get_string("invoice_id")?;
+        let attachment_id = params.get_string("attachment_id")?;
+
+        let attachment = self
+            .load_invoice(&tenant_id, &invoice_id)
+            .await?
+            .attachments
+            .into_iter()
+            .find(|a| a.id == attachment_id)
+            .ok_or_else(|| ServiceError::not_found("Attachment not found"))?;
+
+        let blob = self
+            .blobs
+            .get(&attachment_key(&tenant_id, &invoice_id, &attachment_id))
+            .await?
+            .ok_or_else(|| ServiceError::internal(format!("Attachment {} is missing from storage", attachment_id)))?;
+
+        Ok(serde_json::json!({
+            "attachment": attachment,
+            "content_type": blob.content_type,
+            "filename": attachment.filename,
+            "data": STANDARD.encode(blob.data),
+        }))
+    }
+} 
+
+/// Actions by operation name, for registration and gateway dispatch
+#[async_trait]
+impl ActionService for InvoiceService {
+    fn name(&self) -> &str {
+        "invoice"
+    }
+
+    fn description(&self) -> &str {
+        "Invoice management service"
+    }
+
+    fn operations(&self) -> Vec<String> {
+        [
+            "create",
+            "duplicate",
+            "bulk_create",
+            "get",
+            "list",
+            "create_pdf_link",
+            "render_pdf",
+            "export_csv",
+            "download",
+            "convert_totals",
+            "update",
+            "send",
+            "finalize",
+            "record_payment",
+            "refresh_overdue",
+            "delete",
+            "attach_file",
+            "list_attachments",
+            "get_attachment",
+        ]
+        .iter()
+        .map(|op| op.to_string())
+        .collect()
+    }
+
+    async fn call(&self, operation: &str, params: ActionParams) -> Result<Value> {
+        match operation {
+            "create" => self.create_invoice(&params).await,
+            "duplicate" => self.duplicate_invoice(&params).await,
+            "bulk_create" => self.bulk_create_invoices(&params).await,
+            "get" => self.get_invoice(&params).await,
+            "list" => self.list_invoices(&params).await,
+            "create_pdf_link" => self.create_pdf_link(&params).await,
+            "render_pdf" => self.render_pdf(&params).await,
+            "export_csv" => self.export_csv(&params).await,
+            "download" => self.download(&params).await,
+            "convert_totals" => self.convert_totals(&params).await,
+            "update" => self.update_invoice(&params).await,
+            "send" => self.send_invoice(&params).await,
+            "finalize" => self.finalize_invoice(&params).await,
+            "record_payment" => self.record_payment(&params).await,
+            "refresh_overdue" => self.refresh_overdue(&params).await,
+            "delete" => self.delete_invoice(&params).await,
+            "attach_file" => self.attach_file(&params).await,
+            "list_attachments" => self.list_attachments(&params).await,
+            "get_attachment" => self.get_attachment(&params).await,
+            _ => Err(unknown_operation(self, operation)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    async fn call(service: &InvoiceService, operation: &str, params: Value) -> Result<Value> {
+        service.handle(operation, ActionParams::new(params)?).await
+    }
+
+    /// A draft for `user_id` with items worth 100 before 10% tax
+    fn draft(user_id: &str) -> Value {
+        json!({
+            "user_id": user_id,
+            "customer_name": "Acme Ltd",
+            "customer_email": "billing@acme.example",
+            "items": [{ "description": "Consulting", "quantity": 2.0, "unit_price": 50.0 }],
+            "tax_rate": 0.1,
+            "due_date": Utc::now() + chrono::Duration::days(30),
+        })
+    }
+
+    async fn create(service: &InvoiceService, params: Value) -> Invoice {
+        serde_json::from_value(call(service, "create", params).await.unwrap()).unwrap()
+    }
+
+    /// Move a draft to `Sent`, where it can take payments
+    async fn mark_sent(service: &InvoiceService, invoice: &Invoice) -> Invoice {
+        let params = json!({ "invoice_id": invoice.id, "status": "Sent", "expected_version": invoice.version });
+        serde_json::from_value(call(service, "update", params).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn server_computed_totals_win_over_client_values() {
+        let service = InvoiceService::new().await.unwrap();
+        let mut params = draft("alice");
+        params["items"][0]["amount"] = json!(1.0);
+        params["subtotal"] = json!(1.0);
+        params["tax_amount"] = json!(0.0);
+        params["total"] = json!(1.0);
+
+        let invoice = create(&service, params).await;
+
+        assert_eq!(invoice.items[0].amount, 100.0);
+        assert_eq!(invoice.subtotal, 100.0);
+        assert_eq!(invoice.tax_amount, 10.0);
+        assert_eq!(invoice.total, 110.0);
+    }
+
+    #[tokio::test]
+    async fn tax_rate_outside_zero_to_one_is_rejected() {
+        let service = InvoiceService::new().await.unwrap();
+        for rate in [-0.1, 1.5, 10.0] {
+            let mut params = draft("alice");
+            params["tax_rate"] = json!(rate);
+            let err = call(&service, "create", params).await.unwrap_err();
+            assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Validation(_)), "rate {}", rate);
+        }
+
+        let invoice = create(&service, draft("alice")).await;
+        let params = json!({ "invoice_id": invoice.id, "tax_rate": 8.25, "expected_version": invoice.version });
+        let err = call(&service, "update", params).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Validation(_)));
+    }
+
+    #[tokio::test]
+    async fn missing_invoice_is_not_found() {
+        let service = InvoiceService::new().await.unwrap();
+
+        let err = call(&service, "get", json!({ "invoice_id": Uuid::new_v4().to_string() })).await.unwrap_err();
+
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn paying_the_balance_publishes_invoice_paid() {
+        let events = EventBus::new();
+        let mut published = events.subscribe(EVENT_TOPIC);
+        let service = InvoiceService::new().await.unwrap().with_event_bus(events.clone());
+        let invoice = mark_sent(&service, &create(&service, draft("alice")).await).await;
+        assert_eq!(invoice.total, 110.0);
+
+        for amount in [60.0, 50.0] {
+            let params = json!({ "invoice_id": invoice.id, "amount": amount, "method": "card" });
+            call(&service, "record_payment", params).await.unwrap();
+        }
+
+        let mut kinds = Vec::new();
+        let paid = loop {
+            let event = tokio::time::timeout(Duration::from_secs(1), published.recv())
+                .await
+                .expect("no invoice_paid event")
+                .unwrap();
+            kinds.push(event.kind.clone());
+            if event.kind == "invoice_paid" {
+                break event.parse::<InvoicePaid>().unwrap().unwrap();
+            }
+        };
+        // Only the payment settling the balance marks the invoice paid
+        assert_eq!(kinds, ["invoice_created", "invoice_updated", "invoice_updated", "invoice_updated", "invoice_paid"]);
+        assert_eq!(paid.invoice_id, invoice.id);
+        assert_eq!(paid.user_id, "alice");
+        assert_eq!(paid.tenant_id, DEFAULT_TENANT);
+        assert_eq!(paid.total, 110.0);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_missing_invoice_is_not_found() {
+        let service = InvoiceService::new().await.unwrap();
+
+        let params = json!({ "invoice_id": Uuid::new_v4().to_string(), "confirm_total": 0.0 });
+        let err = call(&service, "delete", params).await.unwrap_err();
+
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn delete_with_a_mismatched_total_is_rejected() {
+        let service = InvoiceService::new().await.unwrap();
+        let invoice = create(&service, draft("alice")).await;
+
+        let params = json!({ "invoice_id": invoice.id, "confirm_total": 100.0 });
+        let err = call(&service, "delete", params).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Conflict(_)));
+        assert!(call(&service, "get", json!({ "invoice_id": invoice.id })).await.is_ok());
+
+        let params = json!({ "invoice_id": invoice.id, "confirm_total": invoice.total });
+        call(&service, "delete", params).await.unwrap();
+        assert!(call(&service, "get", json!({ "invoice_id": invoice.id })).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn invoice_numbers_are_per_tenant_and_reads_stay_in_the_tenant() {
+        let service = InvoiceService::new().await.unwrap();
+        let mut acme_draft = draft("alice");
+        acme_draft["tenant_id"] = json!("acme");
+        let mut globex_draft = draft("alice");
+        globex_draft["tenant_id"] = json!("globex");
+
+        let acme = create(&service, acme_draft).await;
+        let globex = create(&service, globex_draft).await;
+        assert_eq!(acme.invoice_number, globex.invoice_number);
+        assert_ne!(acme.id, globex.id);
+
+        let own = call(&service, "get", json!({ "tenant_id": "acme", "invoice_id": acme.id })).await.unwrap();
+        assert_eq!(own["tenant_id"], "acme");
+        let err = call(&service, "get", json!({ "tenant_id": "globex", "invoice_id": acme.id })).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn awkward_tax_rate_rounds_to_two_decimals() {
+        let service = InvoiceService::new().await.unwrap();
+        let mut params = draft("alice");
+        params["tax_rate"] = json!(0.0825);
+        params["items"] = json!([
+            { "description": "Widget", "quantity": 3.0, "unit_price": 12.99 },
+            { "description": "Fee", "quantity": 1.0, "unit_price": 0.1 },
+            { "description": "Fee", "quantity": 1.0, "unit_price": 0.2 },
+        ]);
+
+        let invoice = create(&service, params).await;
+
+        // 39.27 * 0.0825 = 3.239775
+        assert_eq!(invoice.subtotal, 39.27);
+        assert_eq!(invoice.tax_amount, 3.24);
+        assert_eq!(invoice.total, 42.51);
+        let serialized = serde_json::to_value(&invoice).unwrap();
+        assert_eq!(serialized["total"].to_string(), "42.51");
+        assert_eq!(serialized["subtotal"].to_string(), "39.27");
+    }
+
+    #[tokio::test]
+    async fn halfway_tax_rounds_to_even() {
+        let service = InvoiceService::new().await.unwrap();
+        let mut params = draft("alice");
+        params["tax_rate"] = json!(0.0825);
+        params["items"] = json!([{ "description": "Consulting", "quantity": 1.0, "unit_price": 10.0 }]);
+
+        let invoice = create(&service, params).await;
+
+        // 10.00 * 0.0825 = 0.825, halfway between 0.82 and 0.83
+        assert_eq!(invoice.tax_amount, 0.82);
+        assert_eq!(invoice.total, 10.82);
+    }
+
+    /// Knows only the USD to EUR rate
+    struct StubRates;
+
+    #[async_trait]
+    impl ExchangeRateProvider for StubRates {
+        async fn rate(&self, from: &str, to: &str) -> Result<Option<f64>> {
+            Ok(match (from, to) {
+                ("USD", "EUR") => Some(0.9),
+                _ => None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn totals_convert_per_invoice_and_report_missing_rates() {
+        let service = InvoiceService::new().await.unwrap().with_exchange_rates(Arc::new(StubRates));
+        let mut usd = draft("alice");
+        usd["currency"] = json!("usd");
+        let usd = create(&service, usd).await;
+        let mut gbp = draft("alice");
+        gbp["currency"] = json!("GBP");
+        let gbp = create(&service, gbp).await;
+
+        let converted = call(&service, "convert_totals", json!({ "user_id": "alice", "target_currency": "eur" })).await.unwrap();
+
+        let entries = converted.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        let entry = |id: &str| entries.iter().find(|entry| entry["invoice_id"] == id).unwrap().clone();
+        let usd_entry = entry(&usd.id);
+        assert_eq!(usd_entry["total"], 110.0);
+        assert_eq!(usd_entry["converted_total"], 99.0);
+        assert_eq!(usd_entry["target_currency"], "EUR");
+        let gbp_entry = entry(&gbp.id);
+        assert!(gbp_entry.get("converted_total").is_none());
+        assert_eq!(gbp_entry["error"], "No exchange rate from GBP to EUR");
+    }
+
+    #[tokio::test]
+    async fn stale_if_match_version_fails_the_precondition() {
+        let service = InvoiceService::new().await.unwrap();
+        let invoice = create(&service, draft("alice")).await;
+        let invoice = mark_sent(&service, &invoice).await;
+
+        let params = json!({ "invoice_id": invoice.id, "notes": "late", "if_match_version": invoice.version - 1 });
+        let err = call(&service, "update", params).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::PreconditionFailed(_)));
+
+        let params = json!({ "invoice_id": invoice.id, "notes": "on time", "if_match_version": invoice.version });
+        let updated: Invoice = serde_json::from_value(call(&service, "update", params).await.unwrap()).unwrap();
+        assert_eq!(updated.version, invoice.version + 1);
+    }
+
+    #[tokio::test]
+    async fn attachments_are_stored_listed_fetched_and_removed_with_their_invoice() {
+        let blobs = InMemoryBlobStore::new();
+        let service = InvoiceService::new().await.unwrap().with_blob_store(Arc::new(blobs.clone()));
+        let invoice = create(&service, draft("alice")).await;
+        let receipt = b"Paid in full, thanks".to_vec();
+
+        let params = json!({
+            "invoice_id": invoice.id,
+            "filename": "receipt.txt",
+            "content_type": "text/plain",
+            "data": STANDARD.encode(&receipt),
+        });
+        let attachment: Attachment = serde_json::from_value(call(&service, "attach_file", params).await.unwrap()).unwrap();
+        assert_eq!(attachment.filename, "receipt.txt");
+        assert_eq!(attachment.size, receipt.len());
+
+        let listed: Vec<Attachment> =
+            serde_json::from_value(call(&service, "list_attachments", json!({ "invoice_id": invoice.id })).await.unwrap()).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, attachment.id);
+
+        // The invoice carries a reference, not the bytes
+        let stored = call(&service, "get", json!({ "invoice_id": invoice.id })).await.unwrap();
+        assert_eq!(stored["attachments"][0]["id"], attachment.id.as_str());
+        assert!(stored["attachments"][0].get("data").is_none());
+
+        let params = json!({ "invoice_id": invoice.id, "attachment_id": attachment.id });
+        let fetched = call(&service, "get_attachment", params).await.unwrap();
+        assert_eq!(fetched["content_type"], "text/plain");
+        assert_eq!(STANDARD.decode(fetched["data"].as_str().unwrap()).unwrap(), receipt);
+
+        let blob_key = attachment_key(DEFAULT_TENANT, &invoice.id, &attachment.id);
+        assert!(blobs.get(&blob_key).await.unwrap().is_some());
+        let current: Invoice = serde_json::from_value(stored).unwrap();
+        call(&service, "delete", json!({ "invoice_id": invoice.id, "confirm_total": current.total })).await.unwrap();
+        assert!(blobs.get(&blob_key).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn attachments_of_unsupported_types_are_rejected() {
+        let service = InvoiceService::new().await.unwrap();
+        let invoice = create(&service, draft("alice")).await;
+
+        let params = json!({
+            "invoice_id": invoice.id,
+            "filename": "setup.exe",
+            "content_type": "application/x-msdownload",
+            "data": STANDARD.encode(b"MZ"),
+        });
+        let err = call(&service, "attach_file", params).await.unwrap_err();
+
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Validation(_)));
+    }
+
+    #[tokio::test]
+    async fn finalized_invoice_rejects_item_edits_but_takes_notes() {
+        let service = InvoiceService::new().await.unwrap();
+        let invoice = create(&service, draft("alice")).await;
+        let finalized: Invoice =
+            serde_json::from_value(call(&service, "finalize", json!({ "invoice_id": invoice.id })).await.unwrap()).unwrap();
+        assert!(finalized.is_finalized());
+
+        let params = json!({
+            "invoice_id": invoice.id,
+            "items": [{ "description": "Consulting", "quantity": 20.0, "unit_price": 50.0 }],
+            "expected_version": finalized.version,
+        });
+        let err = call(&service, "update", params).await.unwrap_err();
+        match ServiceError::from_anyhow(&err) {
+            ServiceError::Conflict(message) => assert!(message.contains("items"), "{}", message),
+            other => panic!("expected Conflict, got {:?}", other),
+        }
+
+        let params = json!({ "invoice_id": invoice.id, "notes": "Paid by wire", "expected_version": finalized.version });
+        let noted: Invoice = serde_json::from_value(call(&service, "update", params).await.unwrap()).unwrap();
+        assert_eq!(noted.notes.as_deref(), Some("Paid by wire"));
+        assert_eq!(noted.total, finalized.total);
+        assert_eq!(noted.items.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn only_calls_stamped_by_the_gateway_are_served() {
+        let service = InvoiceService::new().await.unwrap().with_internal_auth("internal-secret");
+        let gateway_auth = InternalAuth::new("internal-secret");
+        let mut params = draft("alice");
+        params["tenant_id"] = json!("acme");
+
+        let err = call(&service, "create", params.clone()).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Unauthorized(_)));
+
+        let created = call(&service, "create", gateway_auth.stamp("invoice", params)).await.unwrap();
+        assert_eq!(created["tenant_id"], "acme");
+    }
+
+    fn items(count: usize) -> Value {
+        Value::from(
+            (0..count)
+                .map(|n| json!({ "description": format!("Line {}", n), "quantity": 1.0, "unit_price": 2.0 }))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[tokio::test]
+    async fn item_lists_over_the_limit_are_rejected() {
+        let limits = InvoiceLimits { max_items: 50, max_description_len: 20 };
+        let service = InvoiceService::new().await.unwrap().with_limits(limits);
+
+        let mut params = draft("alice");
+        params["items"] = items(51);
+        let err = call(&service, "create", params).await.unwrap_err();
+        match ServiceError::from_anyhow(&err) {
+            ServiceError::Validation(message) => assert!(message.contains("limit is 50"), "{}", message),
+            other => panic!("expected Validation, got {:?}", other),
+        }
+
+        let mut params = draft("alice");
+        params["items"][0]["description"] = json!("x".repeat(21));
+        let err = call(&service, "create", params).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Validation(_)));
+    }
+
+    #[tokio::test]
+    async fn reasonably_sized_item_list_is_accepted() {
+        let service = InvoiceService::new().await.unwrap();
+        let mut params = draft("alice");
+        params["items"] = items(InvoiceLimits::default().max_items);
+
+        let invoice = create(&service, params).await;
+
+        assert_eq!(invoice.items.len(), 500);
+        assert_eq!(invoice.subtotal, 1000.0);
+    }
+
+    /// Store recording each call before handing it to an in-memory store
+    #[derive(Default)]
+    struct RecordingStore {
+        inner: InMemoryInvoiceStore,
+        calls: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl RecordingStore {
+        fn record(&self, call: String) {
+            self.calls.lock().unwrap().push(call);
+        }
+
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.calls.lock().unwrap())
+        }
+    }
+
+    #[async_trait]
+    impl InvoiceStore for RecordingStore {
+        async fn put(&self, invoice: Invoice) -> Result<()> {
+            self.record(format!("put {}", invoice.id));
+            self.inner.put(invoice).await
+        }
+
+        async fn get(&self, tenant_id: &str, invoice_id: &str) -> Result<Option<Invoice>> {
+            self.record(format!("get {}/{}", tenant_id, invoice_id));
+            self.inner.get(tenant_id, invoice_id).await
+        }
+
+        async fn list_by_user(&self, tenant_id: &str, user_id: &str) -> Result<Vec<Invoice>> {
+            self.record(format!("list_by_user {}/{}", tenant_id, user_id));
+            self.inner.list_by_user(tenant_id, user_id).await
+        }
+
+        async fn list_by_status(&self, tenant_id: &str, status: InvoiceStatus) -> Result<Vec<Invoice>> {
+            self.record(format!("list_by_status {}/{:?}", tenant_id, status));
+            self.inner.list_by_status(tenant_id, status).await
+        }
+
+        async fn delete(&self, tenant_id: &str, invoice_id: &str) -> Result<Option<Invoice>> {
+            self.record(format!("delete {}/{}", tenant_id, invoice_id));
+            self.inner.delete(tenant_id, invoice_id).await
+        }
+
+        async fn next_invoice_number(&self, tenant_id: &str) -> Result<u64> {
+            self.record(format!("next_invoice_number {}", tenant_id));
+            self.inner.next_invoice_number(tenant_id).await
+        }
+    }
+
+    #[tokio::test]
+    async fn handlers_delegate_to_the_store() {
+        let store = Arc::new(RecordingStore::default());
+        let service = InvoiceService::new().await.unwrap().with_store(store.clone());
+
+        let invoice = create(&service, draft("alice")).await;
+        assert_eq!(store.take(), vec![
+            format!("next_invoice_number {}", DEFAULT_TENANT),
+            format!("put {}", invoice.id),
+        ]);
+
+        call(&service, "get", json!({ "invoice_id": invoice.id })).await.unwrap();
+        assert_eq!(store.take(), vec![format!("get {}/{}", DEFAULT_TENANT, invoice.id)]);
+
+        call(&service, "list", json!({ "user_id": "alice" })).await.unwrap();
+        assert_eq!(store.take(), vec![format!("list_by_user {}/alice", DEFAULT_TENANT)]);
+
+        call(&service, "delete", json!({ "invoice_id": invoice.id, "confirm_total": invoice.total })).await.unwrap();
+        assert_eq!(store.take(), vec![
+            format!("get {}/{}", DEFAULT_TENANT, invoice.id),
+            format!("delete {}/{}", DEFAULT_TENANT, invoice.id),
+        ]);
+        assert!(store.inner.get(DEFAULT_TENANT, &invoice.id).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn status_transitions_follow_the_state_machine() {
+        use InvoiceStatus::*;
+
+        assert!(can_transition(Draft, Sent));
+        assert!(can_transition(Paid, Cancelled));
+        assert!(!can_transition(Paid, Draft));
+        assert!(!can_transition(Draft, Paid));
+        assert!(!can_transition(Cancelled, Sent));
+    }
+
+    #[tokio::test]
+    async fn illegal_status_changes_are_rejected_naming_both_states() {
+        let service = InvoiceService::new().await.unwrap();
+        let invoice = create(&service, draft("alice")).await;
+
+        let params = json!({ "invoice_id": invoice.id, "status": "Paid", "expected_version": invoice.version });
+        let err = call(&service, "update", params).await.unwrap_err();
+        match ServiceError::from_anyhow(&err) {
+            ServiceError::Conflict(message) => assert_eq!(message, "Cannot move invoice from Draft to Paid"),
+            other => panic!("expected Conflict, got {:?}", other),
+        }
+
+        let sent = mark_sent(&service, &invoice).await;
+        assert_eq!(sent.status, InvoiceStatus::Sent);
+
+        let params = json!({ "invoice_id": invoice.id, "status": "Draft", "expected_version": sent.version });
+        let err = call(&service, "update", params).await.unwrap_err();
+        assert_eq!(ServiceError::from_anyhow(&err).message(), "Cannot move invoice from Sent to Draft");
+    }
+
+    #[tokio::test]
+    async fn rendered_pdf_starts_with_the_pdf_magic() {
+        let service = InvoiceService::new().await.unwrap();
+        let mut invoice = create(&service, draft("alice")).await;
+
+        let rendered = call(&service, "render_pdf", json!({ "invoice_id": invoice.id })).await.unwrap();
+        assert_eq!(rendered["content_type"], "application/pdf");
+        let pdf = STANDARD.decode(rendered["data"].as_str().unwrap()).unwrap();
+        assert!(pdf.starts_with(b"%PDF"));
+
+        // An invoice without items still renders
+        invoice.items.clear();
+        assert!(render_invoice_pdf(&invoice).unwrap().starts_with(b"%PDF"));
+    }
+
+    async fn listed(service: &InvoiceService, params: Value) -> Vec<String> {
+        let invoices: Vec<Invoice> = serde_json::from_value(call(service, "list", params).await.unwrap()).unwrap();
+        invoices.into_iter().map(|invoice| invoice.id).collect()
+    }
+
+    #[tokio::test]
+    async fn list_filters_combine_and_an_empty_match_is_an_empty_list() {
+        let service = InvoiceService::new().await.unwrap();
+        let now = Utc::now();
+        let mut ids = Vec::new();
+        for days in [10, 20, 30] {
+            let mut params = draft("alice");
+            params["due_date"] = json!(now + chrono::Duration::days(days));
+            let invoice = create(&service, params).await;
+            // Only the first two are sent
+            let invoice = if days < 30 { mark_sent(&service, &invoice).await } else { invoice };
+            ids.push(invoice.id);
+        }
+
+        let sent_before = json!({ "user_id": "alice", "status": "Sent", "due_before": now + chrono::Duration::days(15) });
+        assert_eq!(listed(&service, sent_before).await, vec![ids[0].clone()]);
+
+        let sent_after = json!({ "user_id": "alice", "status": "Sent", "due_after": now + chrono::Duration::days(15) });
+        assert_eq!(listed(&service, sent_after).await, vec![ids[1].clone()]);
+
+        let draft_window = json!({
+            "user_id": "alice",
+            "status": "Draft",
+            "due_after": now + chrono::Duration::days(5),
+            "due_before": now + chrono::Duration::days(35),
+        });
+        assert_eq!(listed(&service, draft_window).await, vec![ids[2].clone()]);
+
+        let all_by_due_desc = json!({ "user_id": "alice", "sort_by": "due_date", "direction": "desc" });
+        assert_eq!(listed(&service, all_by_due_desc).await, vec![ids[2].clone(), ids[1].clone(), ids[0].clone()]);
+
+        let paid = json!({ "user_id": "alice", "status": "Paid" });
+        assert_eq!(call(&service, "list", paid).await.unwrap(), json!([]));
+        let draft_too_soon = json!({ "user_id": "alice", "status": "Draft", "due_before": now + chrono::Duration::days(15) });
+        assert_eq!(call(&service, "list", draft_too_soon).await.unwrap(), json!([]));
+    }
+
+    async fn pay(service: &InvoiceService, invoice: &Invoice, amount: f64) -> Result<Value> {
+        call(service, "record_payment", json!({ "invoice_id": invoice.id, "amount": amount, "method": "card" })).await
+    }
+
+    #[tokio::test]
+    async fn partial_payment_leaves_the_invoice_sent_with_a_balance() {
+        let service = InvoiceService::new().await.unwrap();
+        let invoice = mark_sent(&service, &create(&service, draft("alice")).await).await;
+
+        let result = pay(&service, &invoice, 40.0).await.unwrap();
+
+        assert_eq!(result["balance"], 70.0);
+        assert_eq!(result["payment"]["amount"], 40.0);
+        let updated: Invoice = serde_json::from_value(result["invoice"].clone()).unwrap();
+        assert_eq!(updated.status, InvoiceStatus::Sent);
+        assert_eq!(updated.payments.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn payment_settling_the_balance_marks_the_invoice_paid() {
+        let service = InvoiceService::new().await.unwrap();
+        let invoice = mark_sent(&service, &create(&service, draft("alice")).await).await;
+        pay(&service, &invoice, 40.0).await.unwrap();
+
+        let result = pay(&service, &invoice, 70.0).await.unwrap();
+
+        assert_eq!(result["balance"], 0.0);
+        let updated: Invoice = serde_json::from_value(result["invoice"].clone()).unwrap();
+        assert_eq!(updated.status, InvoiceStatus::Paid);
+        assert_eq!(updated.payments.iter().map(|p| p.amount).collect::<Vec<_>>(), [40.0, 70.0]);
+        // Nothing more can be paid once settled
+        assert!(pay(&service, &invoice, 1.0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn overpayments_and_non_positive_amounts_are_rejected() {
+        let service = InvoiceService::new().await.unwrap();
+        let invoice = mark_sent(&service, &create(&service, draft("alice")).await).await;
+
+        let err = pay(&service, &invoice, 110.5).await.unwrap_err();
+        assert_eq!(
+            ServiceError::from_anyhow(&err).message(),
+            "Payment of 110.5 exceeds the outstanding balance of 110"
+        );
+        for amount in [0.0, -10.0] {
+            let err = pay(&service, &invoice, amount).await.unwrap_err();
+            assert_eq!(ServiceError::from_anyhow(&err).message(), "Payment amount must be a positive number");
+        }
+    }
+
+    #[tokio::test]
+    async fn discounts_and_mixed_tax_rates_are_totalled_per_rate() {
+        let service = InvoiceService::new().await.unwrap();
+        let mut params = draft("alice");
+        params["items"] = json!([
+            { "description": "Design", "quantity": 2.0, "unit_price": 50.0,
+              "discount": { "type": "percent", "value": 10.0 }, "tax_rate": 0.2 },
+            { "description": "Hosting", "quantity": 1.0, "unit_price": 40.0,
+              "discount": { "type": "fixed", "value": 5.0 } },
+            { "description": "Support", "quantity": 3.0, "unit_price": 10.0, "tax_rate": 0.2 },
+        ]);
+
+        let invoice = create(&service, params).await;
+
+        let amounts: Vec<f64> = invoice.items.iter().map(|item| item.amount).collect();
+        assert_eq!(amounts, [90.0, 35.0, 30.0]);
+        assert_eq!(invoice.subtotal, 155.0);
+        // Hosting falls back to the invoice's 10%; the other two share the 20% line
+        let lines: Vec<(f64, f64, f64)> = invoice.taxes.iter()
+            .map(|line| (line.rate, line.taxable_amount, line.tax_amount))
+            .collect();
+        assert_eq!(lines, [(0.1, 35.0, 3.5), (0.2, 120.0, 24.0)]);
+        assert_eq!(invoice.tax_amount, 27.5);
+        assert_eq!(invoice.total, 182.5);
+    }
+
+    async fn status_of(service: &InvoiceService, invoice: &Invoice) -> InvoiceStatus {
+        let fetched: Invoice = serde_json::from_value(call(service, "get", json!({ "invoice_id": invoice.id })).await.unwrap()).unwrap();
+        fetched.status
+    }
+
+    #[tokio::test]
+    async fn only_sent_invoices_past_their_due_date_become_overdue() {
+        let service = InvoiceService::new().await.unwrap();
+        let now = Utc::now();
+        let mut sent = Vec::new();
+        for days in [5, 15, 25] {
+            let mut params = draft("alice");
+            params["due_date"] = json!(now + chrono::Duration::days(days));
+            sent.push(mark_sent(&service, &create(&service, params).await).await);
+        }
+        // Past due as well, but not in a state that can become overdue
+        let mut params = draft("alice");
+        params["due_date"] = json!(now + chrono::Duration::days(1));
+        let still_draft = create(&service, params.clone()).await;
+        let paid = mark_sent(&service, &create(&service, params).await).await;
+        pay(&service, &paid, paid.total).await.unwrap();
+
+        let sweep_at = now + chrono::Duration::days(10);
+        let result = call(&service, "refresh_overdue", json!({ "now": sweep_at })).await.unwrap();
+
+        assert_eq!(result["updated"], 1);
+        assert_eq!(status_of(&service, &sent[0]).await, InvoiceStatus::Overdue);
+        assert_eq!(status_of(&service, &sent[1]).await, InvoiceStatus::Sent);
+        assert_eq!(status_of(&service, &sent[2]).await, InvoiceStatus::Sent);
+        assert_eq!(status_of(&service, &still_draft).await, InvoiceStatus::Draft);
+        assert_eq!(status_of(&service, &paid).await, InvoiceStatus::Paid);
+
+        // A second sweep at the same time has nothing left to move
+        let result = call(&service, "refresh_overdue", json!({ "now": sweep_at })).await.unwrap();
+        assert_eq!(result["updated"], 0);
+    }
+
+    #[tokio::test]
+    async fn csv_export_round_trips_through_a_reader() {
+        let service = InvoiceService::new().await.unwrap();
+        let mut params = draft("alice");
+        params["customer_name"] = json!("Smith, \"Jones\" & Co");
+        let quoted = create(&service, params).await;
+        let plain = mark_sent(&service, &create(&service, draft("alice")).await).await;
+
+        let export = call(&service, "export_csv", json!({ "user_id": "alice" })).await.unwrap();
+        assert_eq!(export["content_type"], "text/csv");
+
+        let mut reader = csv::Reader::from_reader(export["data"].as_str().unwrap().as_bytes());
+        assert_eq!(reader.headers().unwrap(), vec!["id", "customer", "subtotal", "tax", "total", "status", "due_date"]);
+        let rows: Vec<csv::StringRecord> = reader.records().map(|row| row.unwrap()).collect();
+        assert_eq!(rows.len(), 2);
+        for (row, invoice, status) in [(&rows[0], &quoted, "Draft"), (&rows[1], &plain, "Sent")] {
+            assert_eq!(&row[0], invoice.id);
+            assert_eq!(&row[1], invoice.customer_name);
+            assert_eq!(&row[2], "100.00");
+            assert_eq!(&row[3], "10.00");
+            assert_eq!(&row[4], "110.00");
+            assert_eq!(&row[5], status);
+            assert_eq!(DateTime::parse_from_rfc3339(&row[6]).unwrap(), invoice.due_date);
+        }
+    }
+
+    #[tokio::test]
+    async fn csv_export_of_no_invoices_is_just_the_header() {
+        let service = InvoiceService::new().await.unwrap();
+
+        let export = call(&service, "export_csv", json!({ "user_id": "nobody" })).await.unwrap();
+
+        assert_eq!(export["data"], "id,customer,subtotal,tax,total,status,due_date\n");
+    }
+
+    #[tokio::test]
+    async fn duplicate_is_a_fresh_draft_with_the_same_items() {
+        let service = InvoiceService::new().await.unwrap();
+        let mut params = draft("alice");
+        params["items"] = items(3);
+        let original = mark_sent(&service, &create(&service, params).await).await;
+        pay(&service, &original, 2.0).await.unwrap();
+        let original: Invoice = serde_json::from_value(call(&service, "get", json!({ "invoice_id": original.id })).await.unwrap()).unwrap();
+
+        let copy: Invoice = serde_json::from_value(
+            call(&service, "duplicate", json!({ "invoice_id": original.id, "shift_days": 30 })).await.unwrap(),
+        )
+        .unwrap();
+
+        assert_ne!(copy.id, original.id);
+        assert_ne!(copy.invoice_number, original.invoice_number);
+        assert_eq!(copy.status, InvoiceStatus::Draft);
+        assert_eq!(serde_json::to_value(&copy.items).unwrap(), serde_json::to_value(&original.items).unwrap());
+        assert_eq!(copy.total, original.total);
+        assert!(copy.payments.is_empty());
+        assert!(copy.created_at > original.created_at);
+        assert_eq!(copy.due_date, original.due_date + chrono::Duration::days(30));
+
+        // The original is left as it was
+        let after: Invoice = serde_json::from_value(call(&service, "get", json!({ "invoice_id": original.id })).await.unwrap()).unwrap();
+        assert_eq!(after.version, original.version);
+        assert_eq!(after.status, InvoiceStatus::Sent);
+        assert_eq!(after.payments.len(), 1);
+    }
+
+    /// Mailer keeping every email it is asked to send
+    #[derive(Default)]
+    struct CapturingMailer {
+        sent: std::sync::Mutex<Vec<super::super::email::Email>>,
+    }
+
+    #[async_trait]
+    impl Mailer for CapturingMailer {
+        async fn send(&self, email: super::super::email::Email) -> Result<()> {
+            self.sent.lock().unwrap().push(email);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn sending_mails_the_customer_and_marks_the_invoice_sent() {
+        let mailer = Arc::new(CapturingMailer::default());
+        let service = InvoiceService::new().await.unwrap().with_mailer(mailer.clone());
+        let invoice = create(&service, draft("alice")).await;
+
+        let sent: Invoice = serde_json::from_value(call(&service, "send", json!({ "invoice_id": invoice.id })).await.unwrap()).unwrap();
+
+        assert_eq!(sent.status, InvoiceStatus::Sent);
+        assert_eq!(sent.version, invoice.version + 1);
+        let emails = mailer.sent.lock().unwrap();
+        assert_eq!(emails.len(), 1);
+        assert_eq!(emails[0].to, "billing@acme.example");
+        assert!(emails[0].html_body.contains("Acme Ltd"), "{}", emails[0].html_body);
+        assert_eq!(emails[0].attachments.len(), 1);
+        assert!(emails[0].attachments[0].data.starts_with(b"%PDF"));
+    }
+
+    #[tokio::test]
+    async fn sending_a_paid_invoice_is_rejected_without_mailing() {
+        let mailer = Arc::new(CapturingMailer::default());
+        let service = InvoiceService::new().await.unwrap().with_mailer(mailer.clone());
+        let invoice = mark_sent(&service, &create(&service, draft("alice")).await).await;
+        pay(&service, &invoice, invoice.total).await.unwrap();
+
+        let err = call(&service, "send", json!({ "invoice_id": invoice.id })).await.unwrap_err();
+
+        assert_eq!(ServiceError::from_anyhow(&err).message(), "Cannot send a Paid invoice");
+        assert!(mailer.sent.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stale_expected_version_is_rejected_and_keeps_the_first_write() {
+        let service = InvoiceService::new().await.unwrap();
+        let invoice = create(&service, draft("alice")).await;
+
+        // Two clients read the same version; the first to write wins
+        let first = json!({ "invoice_id": invoice.id, "notes": "Net 15", "expected_version": invoice.version });
+        let updated: Invoice = serde_json::from_value(call(&service, "update", first).await.unwrap()).unwrap();
+        assert_eq!(updated.version, invoice.version + 1);
+
+        let stale = json!({ "invoice_id": invoice.id, "notes": "Net 60", "expected_version": invoice.version });
+        let err = call(&service, "update", stale).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Conflict(_)));
+
+        let stored: Invoice = serde_json::from_value(call(&service, "get", json!({ "invoice_id": invoice.id })).await.unwrap()).unwrap();
+        assert_eq!(stored.notes.as_deref(), Some("Net 15"));
+        assert_eq!(stored.version, updated.version);
+
+        let unversioned = json!({ "invoice_id": invoice.id, "notes": "Net 60" });
+        let err = call(&service, "update", unversioned).await.unwrap_err();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Validation(_)));
+    }
+
+    #[tokio::test]
+    async fn concurrent_updates_from_one_version_let_exactly_one_through() {
+        let service = InvoiceService::new().await.unwrap();
+        let invoice = create(&service, draft("alice")).await;
+        let update = |notes: &str| json!({ "invoice_id": invoice.id, "notes": notes, "expected_version": invoice.version });
+
+        let (a, b) = tokio::join!(call(&service, "update", update("A")), call(&service, "update", update("B")));
+
+        assert_eq!([&a, &b].iter().filter(|result| result.is_ok()).count(), 1);
+        let err = a.err().or(b.err()).unwrap();
+        assert!(matches!(ServiceError::from_anyhow(&err), ServiceError::Conflict(_)));
+    }
+
+    #[tokio::test]
+    async fn merged_users_invoices_move_to_the_kept_user() {
+        let events = EventBus::new();
+        let service = InvoiceService::new().await.unwrap().with_event_bus(events.clone());
+        let (keep_id, merge_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let invoice = create(&service, draft(&merge_id.to_string())).await;
+        let _listener = service.spawn_merge_listener();
+
+        events
+            .emit(&UsersMerged {
+                tenant_id: DEFAULT_TENANT.to_string(),
+                keep_id,
+                merge_id,
+            })
+            .unwrap();
+
+        let moved = tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                let invoices = service.store.list_by_user(DEFAULT_TENANT, &keep_id.to_string()).await.unwrap();
+                if !invoices.is_empty() {
+                    break invoices;
+                }
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("invoice was not reassigned");
+        assert_eq!(moved.len(), 1);
+        assert_eq!(moved[0].id, invoice.id);
+        assert_eq!(moved[0].version, invoice.version + 1);
+        assert!(service.store.list_by_user(DEFAULT_TENANT, &merge_id.to_string()).await.unwrap().is_empty());
+    }
+}
@@ -1,3 +1,8 @@
+pub mod currency;
+pub mod email;
+pub mod export;
 pub mod invoice;
+pub mod pdf;
+pub mod store;
 
 pub use invoice::*; 
\ No newline at end of file
@@ -0,0 +1,116 @@
+use anyhow::{anyhow, Result};
+use printpdf::{BuiltinFont, IndirectFontRef, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference};
+use super::invoice::Invoice;
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 20.0;
+const LINE_HEIGHT: f32 = 6.0;
+
+/// Column x-offsets of the line-item table: description, quantity, unit price, amount
+const COLUMNS: [f32; 4] = [MARGIN, 115.0, 140.0, 170.0];
+
+/// Writes lines top to bottom, starting a new page when the current one is full
+struct PageWriter<'a> {
+    doc: &'a PdfDocumentReference,
+    layer: PdfLayerReference,
+    font: IndirectFontRef,
+    bold: IndirectFontRef,
+    y: f32,
+}
+
+impl<'a> PageWriter<'a> {
+    fn next_line(&mut self) {
+        self.y -= LINE_HEIGHT;
+        if self.y < MARGIN {
+            let (page, layer) = self.doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+            self.layer = self.doc.get_page(page).get_layer(layer);
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+    }
+
+    fn text(&self, text: &str, size: f32, x: f32, bold: bool) {
+        let font = if bold { &self.bold } else { &self.font };
+        self.layer.use_text(text, size, Mm(x), Mm(self.y), font);
+    }
+
+    fn row(&mut self, cells: [&str; 4], bold: bool) {
+        for (cell, x) in cells.iter().zip(COLUMNS.iter()) {
+            self.text(cell, 10.0, *x, bold);
+        }
+        self.next_line();
+    }
+}
+
+/// Format an amount in the invoice's currency
+fn money(invoice: &Invoice, amount: f64) -> String {
+    format!("{:.2} {}", amount, invoice.currency)
+}
+
+/// Render an invoice as a PDF: header, customer details, line-item table and totals.
+///
+/// An invoice without items still renders, with an empty table.
+pub fn render_invoice_pdf(invoice: &Invoice) -> Result<Vec<u8>> {
+    let title = format!("Invoice {}", invoice.invoice_number);
+    let (doc, page, layer) = PdfDocument::new(&title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica)
+        .map_err(|e| anyhow!("Failed to load PDF font: {}", e))?;
+    let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)
+        .map_err(|e| anyhow!("Failed to load PDF font: {}", e))?;
+
+    let mut writer = PageWriter {
+        doc: &doc,
+        layer: doc.get_page(page).get_layer(layer),
+        font,
+        bold,
+        y: PAGE_HEIGHT - MARGIN,
+    };
+
+    writer.text(&title, 18.0, MARGIN, true);
+    writer.next_line();
+    writer.next_line();
+
+    for line in [
+        format!("Bill to: {} <{}>", invoice.customer_name, invoice.customer_email),
+        format!("Issued: {}", invoice.created_at.format("%Y-%m-%d")),
+        format!("Due: {}", invoice.due_date.format("%Y-%m-%d")),
+        format!("Status: {:?}", invoice.status),
+    ] {
+        writer.text(&line, 11.0, MARGIN, false);
+        writer.next_line();
+    }
+    writer.next_line();
+
+    writer.row(["Description", "Qty", "Unit price", "Amount"], true);
+    for item in &invoice.items {
+        let quantity = item.quantity.to_string();
+        let unit_price = money(invoice, item.unit_price);
+        let amount = money(invoice, item.amount);
+        writer.row([&item.description, &quantity, &unit_price, &amount], false);
+    }
+    writer.next_line();
+
+    let subtotal = money(invoice, invoice.subtotal);
+    let total = money(invoice, invoice.total);
+    writer.row(["", "", "Subtotal", &subtotal], false);
+    // Invoices stored before per-rate taxes were tracked only carry the invoice rate
+    let taxes = if invoice.taxes.is_empty() {
+        vec![(invoice.tax_rate, invoice.tax_amount)]
+    } else {
+        invoice.taxes.iter().map(|line| (line.rate, line.tax_amount)).collect()
+    };
+    for (rate, tax_amount) in taxes {
+        let tax = money(invoice, tax_amount);
+        let tax_label = format!("Tax ({:.2}%)", rate * 100.0);
+        writer.row(["", "", &tax_label, &tax], false);
+    }
+    writer.row(["", "", "Total", &total], true);
+
+    if let Some(notes) = &invoice.notes {
+        writer.next_line();
+        writer.text(&format!("Notes: {}", notes), 10.0, MARGIN, false);
+    }
+
+    drop(writer);
+    doc.save_to_bytes().map_err(|e| anyhow!("Failed to render PDF: {}", e))
+}
@@ -0,0 +1,90 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use super::invoice::{Invoice, InvoiceStatus};
+
+/// Persistence for `InvoiceService`.
+///
+/// Invoices are keyed by `(tenant_id, id)`; implementations must never return
+/// an invoice from a different tenant than the one asked for.
+#[async_trait]
+pub trait InvoiceStore: Send + Sync {
+    /// Insert or replace an invoice
+    async fn put(&self, invoice: Invoice) -> Result<()>;
+
+    async fn get(&self, tenant_id: &str, invoice_id: &str) -> Result<Option<Invoice>>;
+
+    /// A user's invoices in no particular order
+    async fn list_by_user(&self, tenant_id: &str, user_id: &str) -> Result<Vec<Invoice>>;
+
+    /// The tenant's invoices in `status`, in no particular order
+    async fn list_by_status(&self, tenant_id: &str, status: InvoiceStatus) -> Result<Vec<Invoice>>;
+
+    /// Remove an invoice, returning it if it existed
+    async fn delete(&self, tenant_id: &str, invoice_id: &str) -> Result<Option<Invoice>>;
+
+    /// Next sequential invoice number for the tenant, starting at 1
+    async fn next_invoice_number(&self, tenant_id: &str) -> Result<u64>;
+}
+
+/// Process-local store; everything is lost on restart
+#[derive(Debug, Default)]
+pub struct InMemoryInvoiceStore {
+    invoices: RwLock<HashMap<(String, String), Invoice>>,
+    counters: RwLock<HashMap<String, u64>>,
+}
+
+impl InMemoryInvoiceStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl InvoiceStore for InMemoryInvoiceStore {
+    async fn put(&self, invoice: Invoice) -> Result<()> {
+        let key = (invoice.tenant_id.clone(), invoice.id.clone());
+        self.invoices.write().await.insert(key, invoice);
+        Ok(())
+    }
+
+    async fn get(&self, tenant_id: &str, invoice_id: &str) -> Result<Option<Invoice>> {
+        let key = (tenant_id.to_string(), invoice_id.to_string());
+        Ok(self.invoices.read().await.get(&key).cloned())
+    }
+
+    async fn list_by_user(&self, tenant_id: &str, user_id: &str) -> Result<Vec<Invoice>> {
+        Ok(self
+            .invoices
+            .read()
+            .await
+            .values()
+            .filter(|invoice| invoice.tenant_id == tenant_id && invoice.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn list_by_status(&self, tenant_id: &str, status: InvoiceStatus) -> Result<Vec<Invoice>> {
+        Ok(self
+            .invoices
+            .read()
+            .await
+            .values()
+            .filter(|invoice| invoice.tenant_id == tenant_id && invoice.status == status)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete(&self, tenant_id: &str, invoice_id: &str) -> Result<Option<Invoice>> {
+        let key = (tenant_id.to_string(), invoice_id.to_string());
+        Ok(self.invoices.write().await.remove(&key))
+    }
+
+    async fn next_invoice_number(&self, tenant_id: &str) -> Result<u64> {
+        let mut counters = self.counters.write().await;
+        let counter = counters.entry(tenant_id.to_string()).or_insert(0);
+        *counter += 1;
+        Ok(*counter)
+    }
+}